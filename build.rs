@@ -1,10 +1,60 @@
-use std::env;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
     println!("cargo:rerun-if-env-changed=FARCRI_LINK_SEARCH");
-    if let Ok(link_search) = env::var("FARCRI_LINK_SEARCH") {
-        println!("cargo:rustc-link-search={}", link_search);
+    if let Some(link_search) = env::var_os("FARCRI_LINK_SEARCH") {
+        for dir in env::split_paths(&link_search) {
+            println!("cargo:rustc-link-search={}", dir.display());
+        }
     }
+
+    if env::var_os("CARGO_FEATURE_ROLE_PROXY").is_some() {
+        // Used by `--farcri-info`
+        let version = find_lockfile()
+            .and_then(|path| {
+                println!("cargo:rerun-if-changed={}", path.display());
+                let lockfile = fs::read_to_string(path).ok()?;
+                let versions = locked_versions(&lockfile, "probe-rs");
+                if versions.is_empty() {
+                    None
+                } else {
+                    Some(versions.join(", "))
+                }
+            })
+            .unwrap_or_else(|| "(unknown version)".to_owned());
+        println!("cargo:rustc-env=FARCRI_PROBE_RS_VERSION={}", version);
+    }
+}
+
+/// Find the `Cargo.lock` of the workspace being built. It's usually located
+/// in the parent of the target directory, which contains `OUT_DIR`.
+fn find_lockfile() -> Option<PathBuf> {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR")?);
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR")?);
+    (out_dir.ancestors().chain(manifest_dir.ancestors()))
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| Path::is_file(path))
+}
+
+/// Get the versions of the specified package locked in a `Cargo.lock`.
+fn locked_versions(lockfile: &str, package: &str) -> Vec<String> {
+    let mut versions = Vec::new();
+    let mut name = None;
+    for line in lockfile.lines().map(str::trim) {
+        if line == "[[package]]" {
+            name = None;
+        } else if let Some(x) = line.strip_prefix("name = ") {
+            name = Some(x.trim_matches('"'));
+        } else if let Some(x) = line.strip_prefix("version = ") {
+            if name == Some(package) {
+                versions.push(x.trim_matches('"').to_owned());
+            }
+        }
+    }
+    versions
 }
@@ -0,0 +1,25 @@
+#![no_std]
+// Needed for the targets without an operating system. (A macro can't add
+// crate attributes, so this can't be hidden in `bench_harness!`.)
+#![cfg_attr(target_os = "none", no_main)]
+
+use farcri::{black_box, Bencher};
+
+#[farcri::bench]
+fn noop(b: &mut Bencher) {
+    b.iter(|| {});
+}
+
+#[farcri::bench]
+fn sum_256(b: &mut Bencher) {
+    let array = [1u32; 256];
+    b.iter(|| black_box(&array).iter().sum::<u32>());
+}
+
+#[farcri::bench(sample_size = 20, measurement_time = 0.5)]
+fn sum_1024(b: &mut Bencher) {
+    let array = [1u32; 1024];
+    b.iter(|| black_box(&array).iter().sum::<u32>());
+}
+
+farcri::bench_harness!();
@@ -1,23 +1,22 @@
-#![no_std]
-// TODO: Get rid of this conditional attribute; it's FarCri.rs's
-//       implementation detail
-#![cfg_attr(target_os = "none", no_main)]
-
-use farcri::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+// Needed for the targets without an operating system. (A macro can't add
+// crate attributes, so this can't be hidden in `criterion_main!`.)
+#![cfg_attr(target_os = "none", no_std, no_main)]
+farcri::bench_prelude!();
 
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("noop", |b| b.iter(noop));
 
-    let mut array = [0; 256];
+    // Reused by all parameter sizes
+    let mut array = [0usize; 256];
     let mut flip = 0;
     for (i, x) in array.iter_mut().enumerate() {
         *x = i;
     }
 
-    let mut group = c.benchmark_group("sort [i32]");
+    let mut group = c.benchmark_group_fmt(format_args!("sort [{}]", core::any::type_name::<i32>()));
     for &len in &[1, 4, 16, 64, 256] {
         group.throughput(Throughput::Elements(len as _));
-        group.bench_function(BenchmarkId::from_parameter(&len), |b| {
+        group.bench_with_input_mut(BenchmarkId::from_parameter(&len), &mut array, |b, array| {
             b.iter(|| {
                 flip = !flip;
                 array[..len].sort_unstable_by_key(|x| *x ^ flip);
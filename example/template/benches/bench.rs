@@ -0,0 +1,11 @@
+#![cfg_attr(target_os = "none", no_std, no_main)]
+farcri::bench_prelude!();
+
+fn criterion_benchmark(c: &mut Criterion) {
+    c.bench_function("add", |b| {
+        b.iter(|| {{crate_name}}::add(black_box(1), black_box(2)))
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,5 @@
+#![no_std]
+
+pub fn add(x: u32, y: u32) -> u32 {
+    x.wrapping_add(y)
+}
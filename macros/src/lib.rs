@@ -0,0 +1,195 @@
+//! Procedural macros for FarCri.rs. Use them through the re-exports in
+//! `farcri` (requires the `macros_attr` feature).
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+    parse_macro_input, punctuated::Punctuated, spanned::Spanned, ItemFn, Lit, MetaNameValue, Token,
+};
+
+/// Register a function of type `fn(&mut Bencher)` as a benchmark.
+///
+/// The registered benchmarks are collected and run by `farcri::bench_harness!`.
+/// Each benchmark is identified by its path (e.g., `sort::insertion_sort`).
+///
+/// ```rust,ignore
+/// #[farcri::bench]
+/// fn noop(b: &mut farcri::Bencher) {
+///     b.iter(|| {});
+/// }
+///
+/// farcri::bench_harness!();
+/// ```
+///
+/// The benchmark's configuration can be specified as arguments. They are
+/// applied through the corresponding `BenchmarkGroup` methods, so the Proxy
+/// program's command-line options (e.g., `--sample-size`) still take
+/// precedence. Times are specified in seconds.
+///
+/// ```rust,ignore
+/// #[farcri::bench(sample_size = 20, measurement_time = 0.5, warm_up_time = 1)]
+/// fn noop(b: &mut farcri::Bencher) {
+///     b.iter(|| {});
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn bench(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args =
+        parse_macro_input!(attr with Punctuated::<MetaNameValue, Token![,]>::parse_terminated);
+    let item = parse_macro_input!(item as ItemFn);
+
+    if let Err(e) = validate_signature(&item) {
+        return e.to_compile_error().into();
+    }
+
+    let setters = match config_setters(&args) {
+        Ok(x) => x,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let ident = &item.sig.ident;
+
+    let configure = if setters.is_empty() {
+        quote! { ::core::option::Option::None }
+    } else {
+        quote! {
+            ::core::option::Option::Some({
+                fn configure(group: &mut ::farcri::BenchmarkGroup<'_, '_>) {
+                    #( group.#setters; )*
+                }
+                configure
+            })
+        }
+    };
+
+    // The registry entry is placed in a dedicated link section. The linker
+    // gathers all entries into a contiguous array, which is located by
+    // `farcri::__private::bench_entries` through the linker-defined
+    // `__start_*`/`__stop_*` symbols. The section name must be a valid C
+    // identifier for the linker to define these symbols.
+    (quote! {
+        #item
+
+        const _: () = {
+            #[used]
+            #[cfg_attr(
+                any(target_os = "none", target_os = "linux"),
+                link_section = "farcri_benches"
+            )]
+            static ENTRY: ::farcri::__private::BenchEntry = ::farcri::__private::BenchEntry {
+                name: ::core::concat!(::core::module_path!(), "::", ::core::stringify!(#ident)),
+                func: ::core::option::Option::Some(#ident),
+                configure: #configure,
+            };
+
+            // For `--farcri-list-static`
+            #[used]
+            #[cfg_attr(
+                any(target_os = "none", target_os = "linux"),
+                link_section = "farcri_ids"
+            )]
+            static ID: ::farcri::__private::StaticId = ::farcri::__private::StaticId::benchmark(
+                ::core::concat!(::core::module_path!(), "::", ::core::stringify!(#ident)),
+            );
+        };
+    })
+    .into()
+}
+
+fn validate_signature(item: &ItemFn) -> Result<(), syn::Error> {
+    let sig = &item.sig;
+    if sig.asyncness.is_some() {
+        return Err(syn::Error::new(
+            sig.asyncness.span(),
+            "benchmark functions can't be `async`",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "benchmark functions can't be generic",
+        ));
+    }
+    if sig.inputs.len() != 1 {
+        return Err(syn::Error::new(
+            sig.inputs.span(),
+            "benchmark functions must take exactly one parameter of type `&mut Bencher`",
+        ));
+    }
+    Ok(())
+}
+
+/// Convert the arguments of `#[bench(...)]` to calls to `BenchmarkGroup`'s
+/// setter methods (without the receiver).
+fn config_setters(
+    args: &Punctuated<MetaNameValue, Token![,]>,
+) -> Result<Vec<proc_macro2::TokenStream>, syn::Error> {
+    let mut setters = Vec::new();
+    let mut seen = Vec::new();
+
+    for arg in args {
+        let name = match arg.path.get_ident() {
+            Some(x) => x.to_string(),
+            None => return Err(syn::Error::new(arg.path.span(), "expected an identifier")),
+        };
+
+        if seen.contains(&name) {
+            return Err(syn::Error::new(
+                arg.path.span(),
+                format!("`{}` is specified more than once", name),
+            ));
+        }
+
+        let setter = match name.as_str() {
+            "sample_size" | "nresamples" => {
+                let n = parse_count(&arg.lit)?;
+                let ident = &arg.path;
+                quote! { #ident(#n) }
+            }
+            "measurement_time" | "warm_up_time" => {
+                let nanos = parse_seconds_as_nanos(&arg.lit)?;
+                let ident = &arg.path;
+                quote! { #ident(::core::time::Duration::from_nanos(#nanos)) }
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    arg.path.span(),
+                    "unknown benchmark setting; expected one of `sample_size`, \
+                    `nresamples`, `measurement_time`, and `warm_up_time`",
+                ))
+            }
+        };
+
+        setters.push(setter);
+        seen.push(name);
+    }
+
+    Ok(setters)
+}
+
+/// Parse a positive integer.
+fn parse_count(lit: &Lit) -> Result<usize, syn::Error> {
+    let n = match lit {
+        Lit::Int(x) => x.base10_parse::<usize>()?,
+        _ => return Err(syn::Error::new(lit.span(), "expected an integer")),
+    };
+    if n == 0 {
+        return Err(syn::Error::new(lit.span(), "must be non-zero"));
+    }
+    Ok(n)
+}
+
+/// Parse a positive number of seconds and convert it to nanoseconds. The
+/// conversion is done here so that the Target program doesn't have to deal
+/// with floating-point numbers.
+fn parse_seconds_as_nanos(lit: &Lit) -> Result<u64, syn::Error> {
+    let secs = match lit {
+        Lit::Int(x) => x.base10_parse::<f64>()?,
+        Lit::Float(x) => x.base10_parse::<f64>()?,
+        _ => return Err(syn::Error::new(lit.span(), "expected a number of seconds")),
+    };
+    let nanos = (secs * 1e9).round();
+    if !(nanos >= 1.0 && nanos < u64::MAX as f64) {
+        return Err(syn::Error::new(lit.span(), "out of range"));
+    }
+    Ok(nanos as u64)
+}
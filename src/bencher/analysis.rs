@@ -1,21 +1,54 @@
-use super::{func::Function, measurement, protocol, ValueBuf};
+use super::{
+    func::{Function, SamplingOptions},
+    measurement, protocol, summary, ValueBuf,
+};
 
 pub(super) fn common(
     id: &protocol::RawBenchmarkId<&str>,
     routine: &mut Function<'_>,
     config: &protocol::BenchmarkConfig,
+    options: SamplingOptions,
     out_values: &mut ValueBuf,
+    mut out_timestamps: Option<&mut [u64]>,
     measurement: measurement::Measurement<'_>,
 ) {
     log::info!("Benchmarking {}", id);
 
-    let (num_iters_per_sample, mut measurement) = routine.sample(measurement, config, out_values);
+    let (num_iters_per_sample, mut measurement) = routine.sample(
+        measurement,
+        config,
+        options,
+        out_values,
+        out_timestamps.as_deref_mut(),
+    );
+
+    if let Some(message) = routine.error() {
+        log::error!("... Failed: {}", message);
+        measurement
+            .link()
+            .send(&protocol::UpstreamMessage::BenchmarkFailed { id: *id, message });
+        return;
+    }
+
+    // Only the last chunk if the samples were streamed
+    if log::log_enabled!(log::Level::Debug) {
+        if let Some(summary) = summary::summarize(&out_values[..], num_iters_per_sample) {
+            log::debug!("{}: {}", id, summary);
+        }
+    }
+
+    // Not filled if the buffer is too small (see `Function::sample`)
+    if let Some(timestamps) = out_timestamps.and_then(|x| x.get(..out_values.len())) {
+        measurement
+            .link()
+            .send(&protocol::UpstreamMessage::SampleTimestamps { timestamps });
+    }
 
     measurement
         .link()
         .send(&protocol::UpstreamMessage::MeasurementComplete {
             num_iters_per_sample,
             values: &out_values[..],
-            benchmark_config: config.clone(),
+            benchmark_config: *config,
         });
 }
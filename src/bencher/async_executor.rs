@@ -0,0 +1,27 @@
+use core::future::Future;
+
+/// Runs a future to completion. Implement this for the executor of the firmware (e.g., embassy)
+/// to benchmark `async` code with [`Bencher::to_async`](crate::Bencher::to_async).
+///
+/// FarCri.rs doesn't depend on any particular executor. The implementation usually just
+/// forwards to the executor's block-on function.
+///
+/// ```rust,ignore
+/// struct Executor;
+///
+/// impl farcri::AsyncExecutor for Executor {
+///     fn block_on<T>(&self, future: impl core::future::Future<Output = T>) -> T {
+///         embassy_futures::block_on(future)
+///     }
+/// }
+/// ```
+pub trait AsyncExecutor {
+    /// Run `future` to completion on the current thread and return its output.
+    fn block_on<T>(&self, future: impl Future<Output = T>) -> T;
+}
+
+impl<A: AsyncExecutor> AsyncExecutor for &A {
+    fn block_on<T>(&self, future: impl Future<Output = T>) -> T {
+        (**self).block_on(future)
+    }
+}
@@ -1,4 +1,6 @@
-use super::measurement;
+use core::{fmt, future::Future};
+
+use super::{fill_array_string_with_display, measurement, protocol, AsyncExecutor, TestMessageBuf};
 
 /// Timer struct used to iterate a benchmarked function and measure the runtime.
 ///
@@ -7,6 +9,10 @@ use super::measurement;
 ///
 /// * If you want to do the iteration and measurement yourself (eg. passing the iteration count
 ///   to a separate process), use `iter_custom`.
+/// * If your routine needs a fresh input for each iteration, use `iter_with_setup`.
+/// * If your routine takes a constant input that the optimizer shouldn't see through, use
+///   `iter_with_input`.
+/// * If your routine is `async`, use `to_async`.
 /// * Otherwise, use `iter`.
 pub struct Bencher<'link> {
     /// Have we iterated this benchmark?
@@ -21,9 +27,16 @@ pub struct Bencher<'link> {
     pub(super) elapsed_time: measurement::Duration,
     /// Specifies whether `elapsed_time` should be set.
     pub(super) wants_elapsed_time: bool,
+    /// The outcome of the test, reported in test mode
+    pub(super) test_context: TestContext,
+    /// Specifies whether `iter_with_input` passes the input through `black_box`. Cleared for
+    /// one of the runs of `BenchmarkGroup::compare_black_box`.
+    pub(super) black_box_input: bool,
+    /// The first error returned by the routine passed to `iter_result`
+    pub(super) error: Option<TestMessageBuf>,
 }
 
-impl Bencher<'_> {
+impl<'link> Bencher<'link> {
     /// Times a `routine` by executing it many times and timing the total elapsed time.
     ///
     /// Prefer this timing loop when `routine` returns a value that doesn't have a destructor.
@@ -77,34 +90,229 @@ impl Bencher<'_> {
         }
     }
 
+    /// Times a fallible `routine` like `iter`. If `routine` returns `Err`, the benchmark is
+    /// aborted: the remaining iterations and samples are skipped, and the error (formatted with
+    /// `Debug` and truncated to 128 bytes) is reported to the Proxy program, which counts the
+    /// benchmark as failed. In test mode, the error fails the test.
+    ///
+    /// Use this instead of calling `unwrap` in `routine` when an error is expected in some
+    /// configurations (e.g., a peripheral that isn't present on the board).
+    ///
+    /// # Timing model
+    ///
+    /// ```text
+    /// elapsed = Instant::now + iters * (routine + Result::is_err + mem::drop(O) + Range::next)
+    /// ```
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn read_sensor() -> Result<u16, &'static str> {
+    ///     Err("no sensor on this board")
+    /// }
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     c.bench_function("read_sensor", move |b| {
+    ///         b.iter_result(|| read_sensor())
+    ///     });
+    /// }
+    ///
+    /// criterion_group!(benches, bench);
+    /// criterion_main!(benches);
+    /// ```
+    ///
+    #[inline(never)]
+    pub fn iter_result<O, E, R>(&mut self, mut routine: R)
+    where
+        E: fmt::Debug,
+        R: FnMut() -> Result<O, E>,
+    {
+        self.iterated = true;
+        let time_start = self.wants_elapsed_time.then(|| self.measurement.now());
+        let start = self.measurement.value();
+        let mut error = None;
+        for _ in 0..self.iters {
+            match routine() {
+                Ok(output) => {
+                    black_box(output);
+                }
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+        self.value = self.measurement.value().wrapping_sub(start);
+        if let Some(time_start) = time_start {
+            self.elapsed_time = self.measurement.now() - time_start;
+        }
+
+        // Format the error outside the timed region
+        if let Some(e) = error {
+            self.record_error(&format_args!("{:?}", e));
+        }
+    }
+
+    /// Record an error returned by the routine passed to `iter_result`. Only the first one is
+    /// kept.
+    fn record_error(&mut self, message: &dyn fmt::Display) {
+        if self.error.is_none() {
+            let mut buf = TestMessageBuf::new();
+            fill_array_string_with_display(&mut buf, Some(message));
+            self.test_context.fail(buf.as_str());
+            self.error = Some(buf);
+        }
+    }
+
+    /// Times a `routine` that requires some input by generating a new input with `setup` before
+    /// each iteration. Only `routine` is timed.
+    ///
+    /// This is a compatibility shim for benchmarks written for Criterion.rs, where this method
+    /// is deprecated in favor of `iter_batched` with `BatchSize::PerIteration`. FarCri.rs doesn't
+    /// provide `iter_batched`, so this method is the way to keep setup code out of the timed
+    /// region here.
+    ///
+    /// # Timing model
+    ///
+    /// The counter is read around each call to `routine`, so the overhead of reading it is
+    /// included once per iteration. The output of `routine` is dropped outside the timed region.
+    ///
+    /// ```text
+    /// elapsed = iters * (counter read + routine)
+    /// ```
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     c.bench_function("sort", move |b| {
+    ///         b.iter_with_setup(
+    ///             || [5u32, 3, 1, 4, 2],
+    ///             |mut array| {
+    ///                 array.sort_unstable();
+    ///                 array
+    ///             },
+    ///         )
+    ///     });
+    /// }
+    ///
+    /// criterion_group!(benches, bench);
+    /// criterion_main!(benches);
+    /// ```
+    ///
+    #[inline(never)]
+    pub fn iter_with_setup<I, O, S, R>(&mut self, mut setup: S, mut routine: R)
+    where
+        S: FnMut() -> I,
+        R: FnMut(I) -> O,
+    {
+        self.iterated = true;
+        let time_start = self.wants_elapsed_time.then(|| self.measurement.now());
+        let mut value = 0u64;
+        for _ in 0..self.iters {
+            let input = black_box(setup());
+            let start = self.measurement.value();
+            let output = black_box(routine(input));
+            value = value.wrapping_add(self.measurement.value().wrapping_sub(start));
+            drop(output);
+        }
+        self.value = value;
+        if let Some(time_start) = time_start {
+            self.elapsed_time = self.measurement.now() - time_start;
+        }
+    }
+
+    /// Times a `routine` that takes `input` like `iter`, passing `input` through `black_box` on
+    /// each iteration so that the optimizer can't specialize `routine` for it (e.g., by
+    /// constant-folding a computation on a literal input).
+    ///
+    /// If the benchmark group has [`compare_black_box`](crate::BenchmarkGroup::compare_black_box)
+    /// enabled, the benchmark is also run with `input` passed as-is, showing how much of
+    /// `routine` the optimizer can eliminate when it knows the input.
+    ///
+    /// # Timing model
+    ///
+    /// ```text
+    /// elapsed = Instant::now + iters * (black_box(input) + routine + mem::drop(O) + Range::next)
+    /// ```
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn fibonacci(n: u32) -> u32 {
+    ///     (0..n).fold((0, 1), |(a, b), _| (b, a + b)).0
+    /// }
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     let mut group = c.benchmark_group("fibonacci");
+    ///     group.compare_black_box(true);
+    ///     group.bench_function("20", |b| b.iter_with_input(20, fibonacci));
+    ///     group.finish();
+    /// }
+    ///
+    /// criterion_group!(benches, bench);
+    /// criterion_main!(benches);
+    /// ```
+    ///
+    #[inline(never)]
+    pub fn iter_with_input<I, O, R>(&mut self, input: I, mut routine: R)
+    where
+        I: Copy,
+        R: FnMut(I) -> O,
+    {
+        if self.black_box_input {
+            self.iter(|| routine(black_box(input)))
+        } else {
+            self.iter(|| routine(input))
+        }
+    }
+
     /// Times a `routine` by executing it many times and relying on `routine` to measure its own execution time.
     ///
     /// Prefer this timing loop in cases where `routine` has to do its own measurements to
-    /// get accurate timing information (for example in multi-threaded scenarios where you spawn
-    /// and coordinate with multiple threads).
+    /// get accurate timing information (for example, when only a part of each iteration should
+    /// be measured).
     ///
     /// # Timing model
-    /// Custom, the timing model is whatever is returned as the Duration from `routine`.
+    /// Custom, the timing model is whatever is returned from `routine`.
+    ///
+    /// `routine` must return the *elapsed* cycle count for all `iters` iterations, i.e., the
+    /// difference between two counter readings (computed with `wrapping_sub` in case the counter
+    /// wraps around), not a counter reading itself. Unlike `iter`, this method doesn't subtract
+    /// anything from the returned value.
+    ///
+    /// When debug assertions are enabled, this method logs a warning if the returned value differs
+    /// from the cycle count that elapsed during the call to `routine` by more than ten times
+    /// either way, which usually means `routine` returned an absolute counter value or a value in
+    /// a different unit.
     ///
     /// # Example
     /// ```rust
-    /// #[macro_use] extern crate criterion;
-    /// use criterion::*;
-    /// use criterion::black_box;
-    /// use std::time::Instant;
+    /// use farcri::*;
     ///
     /// fn foo() {
     ///     // ...
     /// }
     ///
+    /// fn read_cycle_counter() -> u64 {
+    ///     // e.g., read `DWT.CYCCNT` on Cortex-M
+    /// #   0
+    /// }
+    ///
     /// fn bench(c: &mut Criterion) {
     ///     c.bench_function("iter", move |b| {
     ///         b.iter_custom(|iters| {
-    ///             let start = Instant::now();
+    ///             let start = read_cycle_counter();
     ///             for _i in 0..iters {
     ///                 black_box(foo());
     ///             }
-    ///             start.elapsed()
+    ///             read_cycle_counter().wrapping_sub(start)
     ///         })
     ///     });
     /// }
@@ -117,13 +325,144 @@ impl Bencher<'_> {
     pub fn iter_custom<R>(&mut self, mut routine: R)
     where
         R: FnMut(u64) -> u64,
+    {
+        self.iter_custom_with_progress(|iters, _| routine(iters));
+    }
+
+    /// Like [`Self::iter_custom`], but `routine` also receives a
+    /// [`ProgressReporter`] to report its progress with.
+    ///
+    /// While `routine` runs, the Proxy program hears nothing from the Target
+    /// program and gives up after some time. Use this method if `routine`
+    /// might take more than several seconds, and call
+    /// [`ProgressReporter::report`] periodically outside the measured region.
+    ///
+    /// # Example
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn foo() {
+    ///     // ...
+    /// }
+    ///
+    /// fn read_cycle_counter() -> u64 {
+    ///     // e.g., read `DWT.CYCCNT` on Cortex-M
+    /// #   0
+    /// }
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     c.bench_function("iter", move |b| {
+    ///         b.iter_custom_with_progress(|iters, progress| {
+    ///             let mut elapsed = 0u64;
+    ///             for i in 0..iters {
+    ///                 let start = read_cycle_counter();
+    ///                 black_box(foo());
+    ///                 elapsed += read_cycle_counter().wrapping_sub(start);
+    ///                 if i % 1000 == 999 {
+    ///                     progress.report(i + 1);
+    ///                 }
+    ///             }
+    ///             elapsed
+    ///         })
+    ///     });
+    /// }
+    ///
+    /// criterion_group!(benches, bench);
+    /// criterion_main!(benches);
+    /// ```
+    ///
+    #[inline(never)]
+    pub fn iter_custom_with_progress<R>(&mut self, mut routine: R)
+    where
+        R: FnMut(u64, &mut ProgressReporter<'_, '_>) -> u64,
     {
         self.iterated = true;
         let time_start = self.measurement.now();
-        self.value = routine(self.iters);
+        #[cfg(debug_assertions)]
+        let start = self.measurement.value();
+        self.value = routine(
+            self.iters,
+            &mut ProgressReporter {
+                measurement: &mut self.measurement,
+                iters: self.iters,
+            },
+        );
+        #[cfg(debug_assertions)]
+        check_custom_value(self.value, self.measurement.value().wrapping_sub(start));
         self.elapsed_time = self.measurement.now() - time_start;
     }
 
+    /// Convert this bencher into an [`AsyncBencher`], which times `async` routines by running
+    /// them on `executor`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use core::future::Future;
+    /// use farcri::*;
+    ///
+    /// /// Forwards to the executor of the firmware
+    /// struct Executor;
+    ///
+    /// impl AsyncExecutor for Executor {
+    ///     fn block_on<T>(&self, future: impl Future<Output = T>) -> T {
+    ///         // e.g., `embassy_futures::block_on(future)`
+    /// #       struct NoopWake;
+    /// #       impl std::task::Wake for NoopWake {
+    /// #           fn wake(self: std::sync::Arc<Self>) {}
+    /// #       }
+    /// #       let mut future = Box::pin(future);
+    /// #       let waker = std::task::Waker::from(std::sync::Arc::new(NoopWake));
+    /// #       let mut cx = core::task::Context::from_waker(&waker);
+    /// #       loop {
+    /// #           if let core::task::Poll::Ready(x) = future.as_mut().poll(&mut cx) {
+    /// #               return x;
+    /// #           }
+    /// #       }
+    ///     }
+    /// }
+    ///
+    /// async fn foo() {
+    ///     // ...
+    /// }
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     c.bench_function("iter", move |b| {
+    ///         b.to_async(Executor).iter(|| foo())
+    ///     });
+    /// }
+    ///
+    /// criterion_group!(benches, bench);
+    /// criterion_main!(benches);
+    /// ```
+    pub fn to_async<'a, A: AsyncExecutor>(&'a mut self, executor: A) -> AsyncBencher<'a, 'link, A> {
+        AsyncBencher {
+            bencher: self,
+            executor,
+        }
+    }
+
+    /// Get the [`TestContext`] for recording the outcome of the test beyond
+    /// "didn't panic". Only meaningful in test mode (e.g., `--test`); the
+    /// outcome is ignored in benchmark mode.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     c.bench_function("sum", move |b| {
+    ///         let mut sum = 0u32;
+    ///         b.iter(|| sum = (1..=10).sum());
+    ///         b.test_context().check(sum == 55, format_args!("sum = {}", sum));
+    ///     });
+    /// }
+    /// ```
+    pub fn test_context(&mut self) -> &mut TestContext {
+        &mut self.test_context
+    }
+
     // Benchmarks must actually call one of the iter methods. This causes benchmarks to fail loudly
     // if they don't.
     pub(crate) fn assert_iterated(&mut self) {
@@ -134,6 +473,152 @@ impl Bencher<'_> {
     }
 }
 
+/// Times `async` routines on an [`AsyncExecutor`]. Obtained by [`Bencher::to_async`].
+pub struct AsyncBencher<'a, 'link, A: AsyncExecutor> {
+    bencher: &'a mut Bencher<'link>,
+    executor: A,
+}
+
+impl<A: AsyncExecutor> AsyncBencher<'_, '_, A> {
+    /// Times an `async` `routine` like [`Bencher::iter`] by awaiting the futures it returns one
+    /// after another.
+    ///
+    /// All iterations run in a single call to [`AsyncExecutor::block_on`], so the executor is
+    /// entered only once per sample. However, the measured value includes whatever the executor
+    /// does while the futures run, such as polling them, handling wake-ups, and running other
+    /// tasks when a future is pending. Benchmark a lightweight executor to keep this small, and
+    /// compare with an empty `async` routine to estimate it.
+    ///
+    /// # Timing model
+    ///
+    /// ```text
+    /// elapsed = Instant::now + iters * (routine + Future::poll (until Ready) + executor
+    ///           + mem::drop(O) + Range::next)
+    /// ```
+    #[inline(never)]
+    pub fn iter<O, R, F>(&mut self, mut routine: R)
+    where
+        R: FnMut() -> F,
+        F: Future<Output = O>,
+    {
+        let b = &mut *self.bencher;
+        b.iterated = true;
+        let iters = b.iters;
+        let wants_elapsed_time = b.wants_elapsed_time;
+        let measurement = &mut b.measurement;
+
+        let (value, elapsed_time) = self.executor.block_on(async move {
+            let time_start = wants_elapsed_time.then(|| measurement.now());
+            let start = measurement.value();
+            for _ in 0..iters {
+                black_box(routine().await);
+            }
+            let value = measurement.value().wrapping_sub(start);
+            (
+                value,
+                time_start.map(|time_start| measurement.now() - time_start),
+            )
+        });
+
+        b.value = value;
+        if let Some(elapsed_time) = elapsed_time {
+            b.elapsed_time = elapsed_time;
+        }
+    }
+}
+
+/// Reports the progress of the routine passed to
+/// [`Bencher::iter_custom_with_progress`] to the Proxy program.
+pub struct ProgressReporter<'a, 'link> {
+    measurement: &'a mut measurement::Measurement<'link>,
+    /// The number of iterations the routine was asked to run
+    iters: u64,
+}
+
+impl ProgressReporter<'_, '_> {
+    /// Report that `iters_done` iterations have been completed. This keeps
+    /// the Proxy program from timing out and updates the progress display.
+    ///
+    /// Each call sends a message to the Proxy program, which takes a
+    /// considerable amount of time. Call this outside the measured region,
+    /// and not too often (e.g., no more than a few times per second).
+    pub fn report(&mut self, iters_done: u64) {
+        self.measurement
+            .link()
+            .send(&protocol::UpstreamMessage::Progress {
+                iters_done,
+                iters: self.iters,
+            });
+    }
+}
+
+/// The factor by which the value returned by the routine passed to
+/// [`Bencher::iter_custom`] may differ from the elapsed cycle count before
+/// [`check_custom_value`] warns about it
+#[cfg(debug_assertions)]
+const CUSTOM_VALUE_TOLERANCE: u64 = 10;
+
+/// Check the value returned by the routine passed to [`Bencher::iter_custom`].
+/// `elapsed` is the cycle count elapsed during the call to the routine.
+///
+/// The routine may exclude a part of the call (e.g., setup) from the value,
+/// so they don't have to match. A value off by more than
+/// [`CUSTOM_VALUE_TOLERANCE`] times either way probably isn't a cycle count
+/// (e.g., a counter reading or a value in a different unit), in which case
+/// this logs a warning and returns `false`.
+#[cfg(debug_assertions)]
+fn check_custom_value(value: u64, elapsed: u64) -> bool {
+    let plausible = value <= elapsed.saturating_mul(CUSTOM_VALUE_TOLERANCE)
+        && elapsed <= value.saturating_mul(CUSTOM_VALUE_TOLERANCE);
+    if !plausible {
+        log::warn!(
+            "`iter_custom`'s routine returned {} cycles, but {} cycles elapsed during the \
+            call. It must return the elapsed cycle count, not a counter reading.",
+            value,
+            elapsed
+        );
+    }
+    plausible
+}
+
+/// Records the outcome of a benchmark function run in test mode. Obtained by
+/// [`Bencher::test_context`].
+///
+/// Failures are reported to the Proxy program, which lists them at the end
+/// and exits with a non-zero status.
+#[derive(Default)]
+pub struct TestContext {
+    failure: Option<TestMessageBuf>,
+}
+
+impl TestContext {
+    /// Mark the test as failed. Only the first failure is recorded.
+    /// `message` is truncated if it's too long.
+    pub fn fail(&mut self, message: impl fmt::Display) {
+        if self.failure.is_none() {
+            let mut buf = TestMessageBuf::new();
+            fill_array_string_with_display(&mut buf, Some(&message));
+            self.failure = Some(buf);
+        }
+    }
+
+    /// Mark the test as failed if `condition` is `false`.
+    pub fn check(&mut self, condition: bool, message: impl fmt::Display) {
+        if !condition {
+            self.fail(message);
+        }
+    }
+
+    /// Get a flag indicating whether the test was marked as failed.
+    pub fn is_failed(&self) -> bool {
+        self.failure.is_some()
+    }
+
+    pub(super) fn failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+}
+
 pub fn black_box<T>(dummy: T) -> T {
     unsafe {
         let ret = core::ptr::read_volatile(&dummy);
@@ -141,3 +626,247 @@ pub fn black_box<T>(dummy: T) -> T {
         ret
     }
 }
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_value_delta() {
+        assert!(check_custom_value(0, 0));
+        assert!(check_custom_value(90, 100));
+        assert!(check_custom_value(100, 100));
+        // Within the tolerance
+        assert!(check_custom_value(10, 100));
+        assert!(check_custom_value(1000, 100));
+    }
+
+    #[test]
+    fn custom_value_absolute() {
+        assert!(!check_custom_value(1_000_000_000, 100));
+        assert!(!check_custom_value(u64::MAX, 100));
+        // e.g., nanoseconds on a slow clock, or a value in the wrong unit
+        assert!(!check_custom_value(1, 100));
+        assert!(!check_custom_value(0, 100));
+    }
+}
+
+#[cfg(all(test, feature = "role_proxy"))]
+mod loopback_tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::{
+        bencher::Criterion,
+        proxy::tests::{run_fast_loopback, run_loopback},
+    };
+
+    #[tokio::test]
+    async fn setup_not_timed() {
+        let messages = run_fast_loopback(|c| {
+            c.bench_function("slow_setup", |b| {
+                b.iter_with_setup(
+                    || std::thread::sleep(std::time::Duration::from_millis(2)),
+                    |()| (),
+                )
+            });
+        })
+        .await;
+
+        let values = messages
+            .iter()
+            .find_map(|msg| match msg {
+                protocol::UpstreamMessage::MeasurementComplete { values, .. } => Some(values),
+                _ => None,
+            })
+            .unwrap();
+        assert!(!values.is_empty());
+        // The loopback port measures nanoseconds, and each sample includes
+        // at least one call to `setup`
+        assert!(values.iter().all(|&x| x < 1_000_000), "{:?}", values);
+    }
+
+    #[derive(Debug)]
+    enum DriverError {
+        NoDevice,
+    }
+
+    fn fallible_benchmarks(c: &mut Criterion) {
+        c.bench_function("absent", |b| {
+            b.iter_result(|| Err::<(), _>(DriverError::NoDevice))
+        });
+        c.bench_function("present", |b| b.iter_result(|| Ok::<_, DriverError>(42)));
+    }
+
+    #[tokio::test]
+    async fn failing_benchmark_aborted() {
+        let messages = run_fast_loopback(fallible_benchmarks).await;
+
+        let events: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::BeginningBenchmark { id } => Some(id.to_string()),
+                protocol::UpstreamMessage::MeasurementStart { .. } => Some("start".to_owned()),
+                protocol::UpstreamMessage::BenchmarkFailed { id, message } => {
+                    Some(format!("{} failed: {}", id, message))
+                }
+                protocol::UpstreamMessage::MeasurementComplete { .. } => Some("done".to_owned()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "absent",
+                "absent failed: NoDevice",
+                "present",
+                "start",
+                "done"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_benchmark_fails_test() {
+        let messages = run_loopback(
+            protocol::Mode::Test,
+            Default::default(),
+            0,
+            fallible_benchmarks,
+        )
+        .await;
+
+        let failures: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::TestFailed { id, message } => {
+                    Some(format!("{}: {}", id, message))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(failures, ["absent: NoDevice"]);
+    }
+
+    /// Spins until the future completes, counting the polls
+    #[derive(Default)]
+    struct CountingExecutor {
+        block_ons: std::cell::Cell<u64>,
+        polls: std::cell::Cell<u64>,
+    }
+
+    impl AsyncExecutor for CountingExecutor {
+        fn block_on<T>(&self, future: impl std::future::Future<Output = T>) -> T {
+            struct NoopWake;
+            impl std::task::Wake for NoopWake {
+                fn wake(self: Arc<Self>) {}
+            }
+            let waker = std::task::Waker::from(Arc::new(NoopWake));
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            self.block_ons.set(self.block_ons.get() + 1);
+            let mut future = Box::pin(future);
+            loop {
+                self.polls.set(self.polls.get() + 1);
+                if let std::task::Poll::Ready(x) = future.as_mut().poll(&mut cx) {
+                    return x;
+                }
+            }
+        }
+    }
+
+    /// Returns `Pending` once before completing
+    struct YieldOnce(bool);
+
+    impl std::future::Future for YieldOnce {
+        type Output = ();
+
+        fn poll(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<()> {
+            if self.0 {
+                std::task::Poll::Ready(())
+            } else {
+                self.0 = true;
+                std::task::Poll::Pending
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn async_benchmark() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // (iterations, calls to `block_on`, polls)
+        let counts = Arc::new([AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)]);
+        let messages = run_fast_loopback({
+            let counts = Arc::clone(&counts);
+            move |c| {
+                let executor = CountingExecutor::default();
+                c.bench_function("yield", |b| {
+                    b.to_async(&executor).iter(|| {
+                        counts[0].fetch_add(1, Ordering::Relaxed);
+                        YieldOnce(false)
+                    })
+                });
+                counts[1].store(executor.block_ons.get(), Ordering::Relaxed);
+                counts[2].store(executor.polls.get(), Ordering::Relaxed);
+            }
+        })
+        .await;
+
+        let values = messages
+            .iter()
+            .find_map(|msg| match msg {
+                protocol::UpstreamMessage::MeasurementComplete { values, .. } => Some(values),
+                _ => None,
+            })
+            .expect("no measurement");
+        assert_eq!(values.len(), 10);
+
+        // The outer future is pending whenever `YieldOnce` is, and it
+        // completes on the last poll
+        let [iters, block_ons, polls] = [
+            counts[0].load(Ordering::Relaxed),
+            counts[1].load(Ordering::Relaxed),
+            counts[2].load(Ordering::Relaxed),
+        ];
+        assert!(iters > 0);
+        assert_eq!(polls, iters + block_ons);
+    }
+
+    #[tokio::test]
+    async fn test_failure_reported() {
+        let messages = run_loopback(protocol::Mode::Test, Default::default(), 0, |c| {
+            let mut group = c.benchmark_group("group");
+            group.bench_function("pass", |b| {
+                b.iter(|| ());
+                b.test_context().check(true, "unreachable");
+            });
+            group.bench_function("fail", |b| {
+                b.iter(|| ());
+                b.test_context()
+                    .check(false, format_args!("{} != {}", 1, 2));
+                b.test_context().fail("not the first failure");
+            });
+        })
+        .await;
+
+        let failures: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::TestFailed { id, message } => {
+                    Some((id.to_string(), message.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(failures, [("group/fail".to_owned(), "1 != 2")]);
+
+        // Test mode doesn't take measurements
+        assert!(!messages
+            .iter()
+            .any(|msg| matches!(msg, protocol::UpstreamMessage::MeasurementComplete { .. })));
+    }
+}
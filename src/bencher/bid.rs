@@ -2,7 +2,7 @@ use core::fmt;
 
 #[derive(Clone, Copy)]
 pub struct BenchmarkId<'a> {
-    pub(crate) function_name: Option<&'a dyn fmt::Display>,
+    pub(crate) function_name: Option<IdPart<'a>>,
     pub(crate) parameter: Option<&'a dyn fmt::Display>,
 }
 
@@ -11,11 +11,25 @@ impl<'a> BenchmarkId<'a> {
     #[inline]
     pub fn new(function_name: &'a dyn fmt::Display, parameter: &'a dyn fmt::Display) -> Self {
         BenchmarkId {
-            function_name: Some(function_name),
+            function_name: Some(IdPart::Display(function_name)),
             parameter: Some(parameter),
         }
     }
 
+    /// Construct a new benchmark ID from a string literal function name.
+    /// Unlike `&str`'s [`AsBenchmarkId`] implementation, this copies the name
+    /// verbatim instead of formatting it through [`core::fmt`]. Note that
+    /// `bench_function` still links `core::fmt` for the other kinds of IDs;
+    /// use [`BenchmarkGroup::bench_named`](crate::BenchmarkGroup::bench_named)
+    /// to avoid that.
+    #[inline]
+    pub fn from_static(function_name: &'static str) -> Self {
+        BenchmarkId {
+            function_name: Some(IdPart::Str(function_name)),
+            parameter: None,
+        }
+    }
+
     pub(crate) fn no_function() -> Self {
         Self {
             function_name: None,
@@ -43,7 +57,10 @@ impl fmt::Debug for BenchmarkId<'_> {
             }
         }
         f.debug_struct("BenchmarkId")
-            .field("function_name", &self.function_name.map(DisplayToDebug))
+            .field(
+                "function_name",
+                &self.function_name.as_ref().map(|x| DisplayToDebug(x)),
+            )
             .field("parameter", &self.parameter.map(DisplayToDebug))
             .finish()
     }
@@ -69,8 +86,25 @@ impl AsBenchmarkId for BenchmarkId<'_> {
 impl<S: fmt::Display> AsBenchmarkId for S {
     fn as_benchmark_id(&self) -> BenchmarkId<'_> {
         BenchmarkId {
-            function_name: Some(self),
+            function_name: Some(IdPart::Display(self)),
             parameter: None,
         }
     }
 }
+
+/// A part of [`BenchmarkId`]
+#[derive(Clone, Copy)]
+pub(crate) enum IdPart<'a> {
+    /// Copied verbatim, bypassing `core::fmt`
+    Str(&'a str),
+    Display(&'a dyn fmt::Display),
+}
+
+impl fmt::Display for IdPart<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Str(s) => f.write_str(s),
+            Self::Display(x) => x.fmt(f),
+        }
+    }
+}
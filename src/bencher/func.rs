@@ -1,22 +1,53 @@
-use super::{measurement, protocol, Bencher, ValueBuf};
+use super::{measurement, protocol, Bencher, TestContext, TestMessageBuf, ValueBuf};
+
+/// The default value of `BenchmarkGroup::max_warm_up_doublings`. `iters`
+/// would overflow after 64 doublings.
+pub(super) const DEFAULT_MAX_WARM_UP_DOUBLINGS: u32 = 40;
+
+/// The options of [`Function::sample`] that don't come from
+/// [`protocol::BenchmarkConfig`]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct SamplingOptions {
+    /// See `BenchmarkGroup::max_warm_up_doublings`
+    pub(super) max_warm_up_doublings: u32,
+    /// Send the samples that don't fit in the sample buffer in
+    /// [`protocol::UpstreamMessage::SampleChunk`]s instead of clamping the
+    /// sample size. See [`protocol::FEATURE_STREAMED_SAMPLES`].
+    pub(super) streamed: bool,
+}
 
 pub struct Function<'a> {
     f: &'a mut (dyn FnMut(&mut Bencher<'_>) + 'a),
+    /// The error returned by the routine passed to `Bencher::iter_result`,
+    /// which aborted the warm-up or the measurement
+    error: Option<TestMessageBuf>,
 }
 
 impl<'a> Function<'a> {
     pub fn new(f: &'a mut (dyn FnMut(&mut Bencher<'_>) + 'a)) -> Function {
-        Function { f }
+        Function { f, error: None }
     }
 }
 
 impl Function<'_> {
+    /// Get the error that aborted the last warm-up or measurement, if any.
+    pub(super) fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Collect samples. The returned `bool` indicates whether the time source
+    /// was found reprogrammed (and re-initialized) between samples, in which
+    /// case some of the values are unreliable.
+    ///
+    /// If the routine fails (see [`Self::error`]), the remaining samples are
+    /// left unfilled.
     pub(super) fn bench<'link>(
         &mut self,
         measurement: measurement::Measurement<'link>,
         iters_per_sample: u64,
         out_values: &mut [u64],
-    ) -> measurement::Measurement<'link> {
+        mut out_timestamps: Option<&mut [u64]>,
+    ) -> (bool, measurement::Measurement<'link>) {
         let f = &mut self.f;
 
         let mut b = Bencher {
@@ -26,22 +57,81 @@ impl Function<'_> {
             measurement,
             elapsed_time: Default::default(),
             wants_elapsed_time: false,
+            test_context: Default::default(),
+            black_box_input: true,
+            error: None,
         };
 
-        for out_value in out_values.iter_mut() {
+        let time_origin = b.measurement.value();
+        let mut time_source_tampered = false;
+
+        for (i, out_value) in out_values.iter_mut().enumerate() {
+            if let Some(out_timestamps) = &mut out_timestamps {
+                out_timestamps[i] = b.measurement.value().wrapping_sub(time_origin);
+            }
             (*f)(&mut b);
             b.assert_iterated();
             *out_value = b.value;
+
+            if b.error.is_some() {
+                self.error = b.error;
+                break;
+            }
+
+            // The benchmark code might have reprogrammed the time source
+            // (e.g., a HAL delay implementation taking over SysTick). Check
+            // it outside the timed region.
+            if !b.measurement.check_time_source() {
+                time_source_tampered = true;
+            }
         }
 
-        b.measurement
+        (time_source_tampered, b.measurement)
     }
 
+    /// Run the function once and return its outcome.
+    pub(super) fn test<'link>(
+        &mut self,
+        measurement: measurement::Measurement<'link>,
+    ) -> (TestContext, measurement::Measurement<'link>) {
+        let mut b = Bencher {
+            iterated: false,
+            iters: 1,
+            value: Default::default(),
+            measurement,
+            elapsed_time: Default::default(),
+            wants_elapsed_time: false,
+            test_context: Default::default(),
+            black_box_input: true,
+            error: None,
+        };
+
+        (self.f)(&mut b);
+        b.assert_iterated();
+
+        (b.test_context, b.measurement)
+    }
+
+    /// Run the function with an exponentially increasing number of
+    /// iterations until it runs for `how_long` in total or the iteration
+    /// count has been doubled `max_doublings` times. If `report_progress` is
+    /// `true`, `WarmupProgress` is sent between passes.
+    ///
+    /// The returned `bool` indicates whether the warm-up ended because of
+    /// `max_doublings`, in which case the time source is probably broken.
+    /// The warm-up also ends if the routine fails (see [`Self::error`]).
     pub(super) fn warm_up<'link>(
         &mut self,
         measurement: measurement::Measurement<'link>,
         how_long: measurement::Duration,
-    ) -> (measurement::Duration, u64, measurement::Measurement<'link>) {
+        max_doublings: u32,
+        report_progress: bool,
+    ) -> (
+        measurement::Duration,
+        u64,
+        bool,
+        measurement::Measurement<'link>,
+    ) {
         let f = &mut self.f;
         let mut b = Bencher {
             iterated: false,
@@ -50,10 +140,16 @@ impl Function<'_> {
             measurement,
             elapsed_time: Default::default(),
             wants_elapsed_time: true,
+            test_context: Default::default(),
+            black_box_input: true,
+            error: None,
         };
 
         let mut total_iters = 0;
         let mut elapsed_time = protocol::Duration::default();
+        let mut last_report = protocol::Duration::default();
+        let mut doublings = 0;
+        self.error = None;
         loop {
             (*f)(&mut b);
 
@@ -61,23 +157,85 @@ impl Function<'_> {
 
             total_iters += b.iters;
             elapsed_time += b.elapsed_time;
+            if b.error.is_some() {
+                self.error = b.error;
+                return (elapsed_time, total_iters, false, b.measurement);
+            }
             if elapsed_time > how_long {
-                return (elapsed_time, total_iters, b.measurement);
+                return (elapsed_time, total_iters, false, b.measurement);
+            }
+
+            if doublings >= max_doublings {
+                log::warn!(
+                    "The warm-up ran {} iteration(s) in {}, not reaching {} \
+                    after {} doubling(s). The time source is probably stopped.",
+                    total_iters,
+                    elapsed_time,
+                    how_long,
+                    doublings
+                );
+                return (elapsed_time, total_iters, true, b.measurement);
+            }
+
+            // We are outside the timed region, so this doesn't affect the
+            // measurement
+            if report_progress
+                && elapsed_time.as_nanos() - last_report.as_nanos()
+                    >= protocol::WARMUP_PROGRESS_INTERVAL.as_nanos()
+            {
+                last_report = elapsed_time;
+                b.measurement
+                    .link()
+                    .send(&protocol::UpstreamMessage::WarmupProgress {
+                        iters_so_far: total_iters,
+                        elapsed: elapsed_time,
+                    });
             }
 
             b.iters = b.iters.wrapping_mul(2);
+            doublings += 1;
         }
     }
 
+    /// Warm up and collect samples. Returns early if the routine fails (see
+    /// [`Self::error`]), in which case the returned iteration count is
+    /// meaningless.
+    ///
+    /// If [`SamplingOptions::streamed`] is set, samples that don't fit in
+    /// `out_durations` are sent in chunks, leaving the last ones in
+    /// `out_durations`.
     pub(super) fn sample<'link>(
         &mut self,
         mut measurement: measurement::Measurement<'link>,
         config: &protocol::BenchmarkConfig,
+        options: SamplingOptions,
         out_durations: &mut ValueBuf,
+        out_timestamps: Option<&mut [u64]>,
     ) -> (u64, measurement::Measurement<'link>) {
         let warm_up_time = config.warm_up_time;
         let measurement_time = config.measurement_time;
-        let num_samples = config.sample_size.min(out_durations.capacity()).max(1);
+        // Unless they are streamed, all samples are sent in a single
+        // `MeasurementComplete`, so they must fit in `out_durations`
+        let chunk_len = out_durations.capacity();
+        let num_samples = if options.streamed {
+            config.sample_size.max(1)
+        } else {
+            config.sample_size.min(chunk_len).max(1)
+        };
+
+        if num_samples < config.sample_size {
+            log::warn!(
+                "Clamping the sample size from {} to {}",
+                config.sample_size,
+                num_samples
+            );
+            measurement
+                .link()
+                .send(&protocol::UpstreamMessage::SampleSizeClamped {
+                    requested: config.sample_size,
+                    effective: num_samples,
+                });
+        }
 
         log::debug!("Warm up (warm_up_time = {}) is in progress", warm_up_time);
 
@@ -85,9 +243,20 @@ impl Function<'_> {
             warm_up_goal_duration: warm_up_time,
         });
 
-        let (wu_elapsed, wu_iters, mut measurement) = self.warm_up(measurement, warm_up_time);
+        measurement.take_round_trips();
+        let (wu_elapsed, wu_iters, wu_capped, mut measurement) = self.warm_up(
+            measurement,
+            warm_up_time,
+            options.max_warm_up_doublings,
+            true,
+        );
+        let warm_up_round_trips = measurement.take_round_trips();
         log::debug!("Completed {} iteration(s) in {}", wu_iters, wu_elapsed);
 
+        if self.error.is_some() {
+            return (0, measurement);
+        }
+
         // Calculate the required number of samples for measurement
         //
         // This is akin to the `Flat` sampling mode from Criterion.rs. `Linear`
@@ -95,7 +264,7 @@ impl Function<'_> {
         // constrained systems that FarCri.rs targets.
         let num_iters = wu_iters as u128 * measurement_time.as_nanos() as u128
             / warm_up_time.as_nanos() as u128;
-        let num_iters_per_sample = (num_iters / config.sample_size as u128).max(1) as u64;
+        let num_iters_per_sample = (num_iters / num_samples as u128).max(1) as u64;
         let num_iters = num_iters_per_sample
             .checked_mul(num_samples as _)
             .expect("oops, the iteration count overflowed!");
@@ -114,19 +283,220 @@ impl Function<'_> {
                 warm_up_iter_count: wu_iters,
                 num_samples,
                 num_iters,
+                warm_up_round_trips,
             });
 
-        // `ArrayVec::resize` is missing <https://github.com/bluss/arrayvec/issues/72>
-        while out_durations.len() < num_samples {
-            out_durations.push(Default::default());
+        let out_timestamps = out_timestamps.and_then(|x| {
+            let len = x.len();
+            if num_samples > chunk_len {
+                log::warn!(
+                    "Not recording the sample timestamps because the samples \
+                    are streamed"
+                );
+                return None;
+            }
+            let out = x.get_mut(..num_samples);
+            if out.is_none() {
+                log::warn!(
+                    "Not recording the sample timestamps because the buffer \
+                    ({} elements) is smaller than the sample size ({})",
+                    len,
+                    num_samples
+                );
+            }
+            out
+        });
+
+        // Send the samples that don't fit in `out_durations` in chunks. We
+        // are between samples, so this doesn't affect the measurement.
+        let mut num_remaining = num_samples;
+        let mut time_source_tampered = false;
+        while num_remaining > chunk_len {
+            let chunk = resize_value_buf(out_durations, chunk_len);
+            let (tampered, m) = self.bench(measurement, num_iters_per_sample, chunk, None);
+            measurement = m;
+            time_source_tampered |= tampered;
+
+            if self.error.is_some() {
+                return (0, measurement);
+            }
+
+            measurement
+                .link()
+                .send(&protocol::UpstreamMessage::SampleChunk { values: chunk });
+            num_remaining -= chunk_len;
+        }
+
+        let out_durations = resize_value_buf(out_durations, num_remaining);
+        let (tampered, mut measurement) = self.bench(
+            measurement,
+            num_iters_per_sample,
+            out_durations,
+            out_timestamps,
+        );
+        time_source_tampered |= tampered;
+
+        if self.error.is_some() {
+            return (0, measurement);
         }
-        while out_durations.len() > num_samples {
-            out_durations.pop();
+
+        if wu_capped {
+            measurement
+                .link()
+                .send(&protocol::UpstreamMessage::MeasurementWarning {
+                    kind: protocol::MeasurementWarningKind::WarmUpCapped,
+                });
         }
-        let out_durations = &mut out_durations[..num_samples];
 
-        let measurement = self.bench(measurement, num_iters_per_sample, out_durations);
+        if time_source_tampered {
+            log::warn!("The time source was reprogrammed during measurement");
+            measurement
+                .link()
+                .send(&protocol::UpstreamMessage::MeasurementWarning {
+                    kind: protocol::MeasurementWarningKind::TimerTampered,
+                });
+        }
 
         (num_iters_per_sample, measurement)
     }
 }
+
+fn resize_value_buf(buf: &mut ValueBuf, len: usize) -> &mut [u64] {
+    // `ArrayVec::resize` is missing <https://github.com/bluss/arrayvec/issues/72>
+    while buf.len() < len {
+        buf.push(Default::default());
+    }
+    while buf.len() > len {
+        buf.pop();
+    }
+    &mut buf[..len]
+}
+
+#[cfg(all(test, feature = "role_proxy"))]
+mod loopback_tests {
+    use super::*;
+    use crate::proxy::tests::{fast_overrides, run_fast_loopback, run_loopback};
+
+    #[tokio::test]
+    async fn time_source_tampering_detected() {
+        let messages = run_fast_loopback(|c| {
+            c.bench_function("intact", |b| b.iter(|| ()));
+            c.bench_function("tampering", |b| {
+                b.iter(|| ());
+                crate::target::loopback::tamper_with_time_source();
+            });
+        })
+        .await;
+
+        let warnings: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::BeginningBenchmark { id } => Some(id.to_string()),
+                protocol::UpstreamMessage::MeasurementWarning { kind } => {
+                    Some(format!("{:?}", kind))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(warnings, ["intact", "tampering", "TimerTampered"]);
+    }
+
+    #[tokio::test]
+    async fn warm_up_capped() {
+        let messages = run_fast_loopback(|c| {
+            let mut group = c.benchmark_group("group");
+            group.max_warm_up_doublings(4);
+            // A stopped clock
+            group.bench_function("stopped", |b| b.iter_custom(|_| 0));
+            group.finish();
+        })
+        .await;
+
+        let warm_up_iter_count = messages.iter().find_map(|msg| match *msg {
+            protocol::UpstreamMessage::MeasurementStart {
+                warm_up_iter_count, ..
+            } => Some(warm_up_iter_count),
+            _ => None,
+        });
+        assert_eq!(warm_up_iter_count, Some(0b11111));
+
+        let warnings: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::MeasurementWarning { kind } => Some(*kind),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(warnings, [protocol::MeasurementWarningKind::WarmUpCapped]);
+    }
+
+    #[tokio::test]
+    async fn sample_size_clamped() {
+        let messages = run_loopback(
+            protocol::Mode::Benchmark,
+            protocol::BenchmarkConfigOverrides {
+                sample_size: Some(300),
+                ..fast_overrides()
+            },
+            0,
+            |c| {
+                c.bench_function("noop", |b| b.iter(|| ()));
+            },
+        )
+        .await;
+
+        let clamped = messages.iter().find_map(|msg| match *msg {
+            protocol::UpstreamMessage::SampleSizeClamped {
+                requested,
+                effective,
+            } => Some((requested, effective)),
+            _ => None,
+        });
+        assert_eq!(clamped, Some((300, 128)));
+
+        let num_samples = messages.iter().find_map(|msg| match *msg {
+            protocol::UpstreamMessage::MeasurementStart { num_samples, .. } => Some(num_samples),
+            _ => None,
+        });
+        assert_eq!(num_samples, Some(128));
+
+        let num_values = messages.iter().find_map(|msg| match msg {
+            protocol::UpstreamMessage::MeasurementComplete { values, .. } => Some(values.len()),
+            _ => None,
+        });
+        assert_eq!(num_values, Some(128));
+    }
+
+    #[tokio::test]
+    async fn samples_streamed() {
+        let messages = run_loopback(
+            protocol::Mode::Benchmark,
+            protocol::BenchmarkConfigOverrides {
+                sample_size: Some(300),
+                ..fast_overrides()
+            },
+            protocol::FEATURE_STREAMED_SAMPLES,
+            |c| {
+                c.bench_function("noop", |b| b.iter(|| ()));
+            },
+        )
+        .await;
+
+        assert!(!messages
+            .iter()
+            .any(|msg| matches!(msg, protocol::UpstreamMessage::SampleSizeClamped { .. })));
+
+        let num_samples = messages.iter().find_map(|msg| match *msg {
+            protocol::UpstreamMessage::MeasurementStart { num_samples, .. } => Some(num_samples),
+            _ => None,
+        });
+        assert_eq!(num_samples, Some(300));
+
+        // `TargetLink` merges the chunks into `MeasurementComplete`
+        let num_values = messages.iter().find_map(|msg| match msg {
+            protocol::UpstreamMessage::MeasurementComplete { values, .. } => Some(values.len()),
+            _ => None,
+        });
+        assert_eq!(num_values, Some(300));
+    }
+}
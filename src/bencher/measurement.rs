@@ -6,6 +6,9 @@ pub(super) struct Measurement<'link> {
     // `cryo` is used here to hide `Criterion`'s lifetime. We do this to
     // simplify the interface and to keep it close to that of Criterion.rs.
     link: CryoMutWriteGuard<proxylink::ProxyLink<'link>, LocalLock>,
+    /// The round trips of [`Self::now`] since the last call to
+    /// [`Self::take_round_trips`]
+    round_trips: protocol::RoundTrips,
 }
 
 pub type Instant = protocol::Instant;
@@ -14,7 +17,10 @@ pub type Duration = protocol::Duration;
 impl<'link> Measurement<'link> {
     #[inline]
     pub fn new(link: CryoMutWriteGuard<proxylink::ProxyLink<'link>, LocalLock>) -> Self {
-        Self { link }
+        Self {
+            link,
+            round_trips: Default::default(),
+        }
     }
 
     #[inline]
@@ -27,14 +33,30 @@ impl<'link> Measurement<'link> {
         self.link.io().now()
     }
 
+    /// Check the time source and re-initialize it if it has been
+    /// reprogrammed. Returns `false` in the latter case.
+    #[inline]
+    pub fn check_time_source(&mut self) -> bool {
+        self.link.io().check_time_source()
+    }
+
     pub fn now(&mut self) -> Instant {
+        let start = self.value();
         self.link.send(&protocol::UpstreamMessage::GetInstant);
 
-        match self.link.recv() {
+        let instant = match self.link.recv() {
             protocol::DownstreamMessage::Instant(x) => x,
             other => {
                 panic!("unexpected downstream message: {:?}", other);
             }
-        }
+        };
+        let round_trip = self.value().wrapping_sub(start);
+        self.round_trips.record(round_trip);
+        instant
+    }
+
+    /// Get the round trips of [`Self::now`] recorded so far and start over.
+    pub fn take_round_trips(&mut self) -> protocol::RoundTrips {
+        core::mem::take(&mut self.round_trips)
     }
 }
@@ -4,33 +4,55 @@ use cryo::{cryo, LocalLock};
 use measurement::Measurement;
 use tokenlock::TokenLock;
 
-use crate::utils::utf8_str_prev;
+use crate::utils::{truncate_str, Frequency};
 
 mod analysis;
+mod async_executor;
+#[allow(clippy::module_inception)] // `Bencher` lives in `bencher::bencher`
 mod bencher;
 mod bid;
 mod func;
 pub mod measurement;
 pub(crate) mod protocol;
-mod proxylink;
+pub(crate) mod proxylink;
+mod summary;
 pub mod time;
+mod watchdog;
 
 pub use self::{
-    bencher::{black_box, Bencher},
+    async_executor::AsyncExecutor,
+    bencher::{black_box, AsyncBencher, Bencher, ProgressReporter, TestContext},
     bid::*,
+    watchdog::LinkLostAction,
 };
 
 type GroupNameBuf = ArrayString<128>;
 type FunctionNameBuf = ArrayString<128>;
 type ParameterDescriptionBuf = ArrayString<128>;
-type ValueBuf = ArrayVec<u64, 128>;
+type ValueBuf = ArrayVec<u64, MAX_SAMPLES>;
+type TestMessageBuf = ArrayString<128>;
+type FrequenciesBuf = ArrayVec<u32, { protocol::MAX_FREQUENCIES }>;
+type NameFiltersBuf =
+    ArrayVec<ArrayString<{ protocol::MAX_NAME_FILTER_LEN }>, { protocol::MAX_NAME_FILTERS }>;
+
+/// The maximum number of samples per measurement
+#[cfg(not(feature = "large_samples"))]
+pub(crate) const MAX_SAMPLES: usize = 128;
+#[cfg(feature = "large_samples")]
+pub(crate) const MAX_SAMPLES: usize = 1024;
+
+/// The size of `WorkingArea::link_buffer`. It must accommodate
+/// `MeasurementComplete` with `MAX_SAMPLES` values, each of which takes up
+/// to 5 bytes if it's less than 2³².
+pub(crate) const LINK_BUFFER_LEN: usize = MAX_SAMPLES * 8;
 
 struct WorkingArea {
-    link_buffer: [u8; 1024],
+    link_buffer: [u8; LINK_BUFFER_LEN],
     group_name: GroupNameBuf,
     function_name: FunctionNameBuf,
     parameter_description: ParameterDescriptionBuf,
     value_buf: ValueBuf,
+    name_filters: NameFiltersBuf,
 }
 
 struct WorkingAreaTag;
@@ -40,14 +62,62 @@ type WorkingAreaAccessTokenId = tokenlock::SingletonTokenId<WorkingAreaTag>;
 static WORKING_AREA: TokenLock<WorkingArea, WorkingAreaAccessTokenId> = TokenLock::new(
     WorkingAreaAccessTokenId::new(),
     WorkingArea {
-        link_buffer: [0; 1024],
+        link_buffer: [0; LINK_BUFFER_LEN],
         group_name: ArrayString::new_const(),
         function_name: ArrayString::new_const(),
         parameter_description: ArrayString::new_const(),
         value_buf: ValueBuf::new_const(),
+        name_filters: NameFiltersBuf::new_const(),
     },
 );
 
+/// The image stamp of this build. See [`protocol::IMAGE_STAMP_SYMBOL`].
+#[cfg_attr(feature = "role_target", no_mangle)]
+static FARCRI_IMAGE_STAMP: [u8; protocol::IMAGE_STAMP_LEN] =
+    zero_padded(match option_env!("FARCRI_IMAGE_STAMP") {
+        Some(x) => x.as_bytes(),
+        None => &[],
+    });
+
+/// An ELF note holding the build descriptor of this build. See
+/// [`protocol::BUILD_DESCRIPTOR_ENV`].
+#[repr(C)]
+struct BuildNote {
+    namesz: u32,
+    descsz: u32,
+    ty: u32,
+    /// [`protocol::BUILD_NOTE_NAME`] with a terminator, padded to a multiple
+    /// of four bytes
+    name: [u8; 8],
+    desc: [u8; protocol::BUILD_DESCRIPTOR_LEN],
+}
+
+#[cfg_attr(
+    all(feature = "role_target", any(target_os = "none", target_os = "linux")),
+    link_section = ".note.farcri"
+)]
+static FARCRI_BUILD_NOTE: BuildNote = BuildNote {
+    namesz: protocol::BUILD_NOTE_NAME.len() as u32 + 1,
+    descsz: protocol::BUILD_DESCRIPTOR_LEN as u32,
+    ty: protocol::BUILD_NOTE_TYPE,
+    name: zero_padded(protocol::BUILD_NOTE_NAME.as_bytes()),
+    desc: zero_padded(match option_env!("FARCRI_BUILD_DESCRIPTOR") {
+        Some(x) => x.as_bytes(),
+        None => &[],
+    }),
+};
+
+/// Copy `src` to a zero-filled array, truncating it if it doesn't fit.
+const fn zero_padded<const N: usize>(src: &[u8]) -> [u8; N] {
+    let mut out = [0; N];
+    let mut i = 0;
+    while i < src.len() && i < N {
+        out[i] = src[i];
+        i += 1;
+    }
+    out
+}
+
 /// Target-independent entry point to be called by [`crate::target::main`].
 ///
 /// # Safety
@@ -61,28 +131,167 @@ pub(crate) unsafe fn main(groups: impl FnOnce(&mut Criterion), io: &mut crate::t
 
     let mut link = proxylink::ProxyLink::new(io, &mut work.link_buffer);
 
-    let mode = match link.recv() {
-        protocol::DownstreamMessage::Greeting { mode, _unused } => mode,
-        other => {
-            panic!("unexpected downstream message: {:?}", other);
+    let (mode, bench_config_overrides, frequencies, skip_benchmarks, feature_bits) = {
+        let config = match link.recv() {
+            protocol::DownstreamMessage::Greeting { config } => config,
+            other => {
+                panic!("unexpected downstream message: {:?}", other);
+            }
+        };
+
+        assert_eq!(
+            config.protocol_version,
+            protocol::PROTOCOL_VERSION,
+            "protocol version mismatch"
+        );
+
+        // `config` borrows `link`'s buffer, so copy the filters to our own
+        // storage
+        work.name_filters.clear();
+        for &filter in config.name_filters.iter() {
+            work.name_filters
+                .push(ArrayString::from(filter).expect("name filter is too long"));
         }
+
+        (
+            config.mode,
+            config.bench_config_overrides,
+            config.frequencies,
+            config.skip_benchmarks,
+            config.feature_bits,
+        )
     };
 
+    let loop_overhead = if feature_bits & protocol::FEATURE_LOOP_OVERHEAD != 0 {
+        Some(measure_loop_overhead(&mut link, &mut work.value_buf))
+    } else {
+        None
+    };
+
+    // Report the image stamp. Read it through a volatile load so that the
+    // compiler doesn't constant-fold it, which would allow the linker to
+    // discard `FARCRI_IMAGE_STAMP`. The build note is only read by the Proxy
+    // program from the executable, but it's kept in the same way.
+    let image_stamp = unsafe { core::ptr::read_volatile(&FARCRI_IMAGE_STAMP) };
+    let _ = unsafe { core::ptr::read_volatile(&FARCRI_BUILD_NOTE.ty) };
+    crate::idtable::retain();
+    let image_stamp_len = (image_stamp.iter())
+        .position(|&b| b == 0)
+        .unwrap_or(image_stamp.len());
+    link.send(&protocol::UpstreamMessage::GreetingReply {
+        image_stamp: core::str::from_utf8(&image_stamp[..image_stamp_len]).unwrap_or(""),
+        mode,
+        loop_overhead,
+        capacities: protocol::TargetCapacities {
+            link_buffer_len: LINK_BUFFER_LEN as u32,
+            max_samples: MAX_SAMPLES as u32,
+            max_group_id_len: work.group_name.capacity() as u32,
+            max_function_id_len: work.function_name.capacity() as u32,
+            max_value_str_len: work.parameter_description.capacity() as u32,
+        },
+    });
+
+    if feature_bits & protocol::FEATURE_LINK_CHECK != 0 {
+        link.answer_echo_requests();
+    }
+
     let mut cri = Criterion {
         link,
         mode,
+        bench_config_overrides,
+        name_filters: &work.name_filters,
         group_name: &mut work.group_name,
+        group_name_len: 0,
         function_name: &mut work.function_name,
         parameter_description: &mut work.parameter_description,
         value_buf: &mut work.value_buf,
+        in_flight: false,
+        frequencies,
+        frequency_setter: None,
+        skip_benchmarks,
+        skip_remaining: false,
+        streamed_samples: feature_bits & protocol::FEATURE_STREAMED_SAMPLES != 0,
     };
 
     // `groups` will call `Criterion::benchmark_group`
     groups(&mut cri);
 
+    if !cri.frequencies.is_empty() && cri.frequency_setter.is_none() {
+        log::warn!(
+            "Clock frequencies were requested, but the benchmark code didn't \
+            register a frequency setter by `Criterion::frequency_setter`"
+        );
+    }
+
     cri.link.send(&protocol::UpstreamMessage::End);
 }
 
+/// Measure the overhead of `Bencher::iter`'s loop by running it with a no-op
+/// routine.
+fn measure_loop_overhead(
+    link: &mut proxylink::ProxyLink<'_>,
+    value_buf: &mut ValueBuf,
+) -> protocol::LoopOverhead {
+    const NUM_ITERS: u64 = 1000;
+    const NUM_SAMPLES: usize = 16;
+
+    value_buf.clear();
+    value_buf.extend([0; NUM_SAMPLES].iter().copied());
+
+    {
+        cryo!(let link: CryoMut<_, LocalLock> = link);
+        let mut routine = |b: &mut Bencher<'_>| b.iter(|| ());
+        // The no-op routine doesn't touch the time source
+        let _ = func::Function::new(&mut routine).bench(
+            Measurement::new(link.write()),
+            NUM_ITERS,
+            value_buf,
+            None,
+        );
+    }
+
+    // Take the minimum, which is the least affected by interrupts and cache
+    // misses
+    let value = value_buf.iter().copied().min().unwrap();
+    log::debug!("Loop overhead: {} per {} iterations", value, NUM_ITERS);
+
+    protocol::LoopOverhead {
+        iters: NUM_ITERS,
+        value,
+    }
+}
+
+/// The execution mode of the benchmark harness, obtained by
+/// [`Criterion::mode`]
+///
+/// Benchmark code can use this to skip or reduce expensive setup (e.g.,
+/// generating a large dataset) when the benchmarks won't be measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Run and measure benchmarks normally (`--bench`).
+    Benchmark,
+    /// Run each benchmark once to verify that it works (`--test`). The
+    /// benchmark functions are called.
+    Test,
+    /// List benchmarks without running them (`--list`). The benchmark
+    /// functions are not called.
+    List,
+    /// Run benchmarks for a specified duration without measuring them
+    /// (`--profile-time`).
+    Profile,
+}
+
+impl From<protocol::Mode> for ExecutionMode {
+    fn from(x: protocol::Mode) -> Self {
+        match x {
+            protocol::Mode::Benchmark => Self::Benchmark,
+            protocol::Mode::Test => Self::Test,
+            protocol::Mode::List => Self::List,
+            protocol::Mode::Profile => Self::Profile,
+        }
+    }
+}
+
 /// The benchmark manager
 ///
 /// In FarCri.rs, `Criterion` is always provided by the benchmark harness and
@@ -92,26 +301,204 @@ pub(crate) unsafe fn main(groups: impl FnOnce(&mut Criterion), io: &mut crate::t
 pub struct Criterion<'link> {
     link: proxylink::ProxyLink<'link>,
     mode: protocol::Mode,
+    /// The configuration specified by the Proxy program. Takes precedence
+    /// over [`BenchmarkGroup`]'s settings.
+    bench_config_overrides: protocol::BenchmarkConfigOverrides,
+    name_filters: &'link NameFiltersBuf,
     group_name: &'link mut GroupNameBuf,
+    /// The length of the current group's name before truncation
+    group_name_len: usize,
     function_name: &'link mut FunctionNameBuf,
     parameter_description: &'link mut ParameterDescriptionBuf,
     value_buf: &'link mut ValueBuf,
+    /// Set while a benchmark function is running. If it's still set when
+    /// `BenchmarkGroup` is dropped, the benchmark function has been unwound
+    /// by a panic, and the link may be in the middle of an exchange.
+    in_flight: bool,
+    frequencies: FrequenciesBuf,
+    frequency_setter: Option<fn(u32) -> u32>,
+    /// The number of matching benchmarks yet to be skipped. See
+    /// [`protocol::TargetConfig::skip_benchmarks`].
+    skip_benchmarks: u32,
+    /// Set by [`protocol::DownstreamMessage::SkipRemaining`]
+    skip_remaining: bool,
+    /// See [`protocol::FEATURE_STREAMED_SAMPLES`]
+    streamed_samples: bool,
 }
 
 impl<'link> Criterion<'link> {
-    pub fn benchmark_group(&mut self, group_name: &str) -> BenchmarkGroup<'link, '_> {
-        // Copy `group_name` to `self.group_name`. If it doesn't fit, copy
+    /// Get the execution mode.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     // There's no point in generating the input in List mode, and a
+    ///     // small input suffices in Test mode
+    ///     let len = match c.mode() {
+    ///         ExecutionMode::List => 0,
+    ///         ExecutionMode::Test => 16,
+    ///         _ => 4096,
+    ///     };
+    ///     let table: Vec<u32> = (0..len).map(|i| i * i).collect();
+    ///
+    ///     c.bench_function("sum", |b| b.iter(|| table.iter().sum::<u32>()));
+    /// }
+    /// ```
+    pub fn mode(&self) -> ExecutionMode {
+        self.mode.into()
+    }
+
+    /// Register a function to switch the core clock frequency, enabling the
+    /// Proxy program's `--farcri-frequencies` option. This is opt-in because
+    /// reconfiguring clocks is inherently board-specific.
+    ///
+    /// When the Proxy program requests a list of frequencies, each benchmark
+    /// is measured once for each frequency. Before each measurement,
+    /// `setter` is called with the requested frequency in hertz. It should
+    /// reconfigure the clock tree (and anything that depends on it, such as
+    /// flash wait states and the communication peripheral) and return the
+    /// frequency actually achieved. The results are labeled with the
+    /// returned frequency. Benchmarks are run only once in Test mode.
+    ///
+    /// The clock is left at the last requested frequency.
+    ///
+    /// Note that the measured values are in the units of the Target
+    /// system's time source. If it counts core clock cycles (which is the
+    /// case on Cortex-M), the values won't reflect the change in the
+    /// wall-clock time.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn set_sysclk(hz: u32) -> u32 {
+    ///     // Reconfigure the PLL here...
+    ///     hz
+    /// }
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     c.frequency_setter(set_sysclk);
+    ///     c.bench_function("noop", |b| b.iter(|| ()));
+    /// }
+    /// ```
+    pub fn frequency_setter(&mut self, setter: fn(u32) -> u32) -> &mut Self {
+        self.frequency_setter = Some(setter);
+        self
+    }
+
+    /// Register a function to put the hardware in a safe state if the Proxy
+    /// program goes silent. This is for rigs where a benchmark drives
+    /// something that shouldn't be left running unattended (e.g., a motor
+    /// or a heater).
+    ///
+    /// Once the Target program has waited for a message from the Proxy
+    /// program for `timeout`, `hook` is called, and then `action` is taken.
+    /// `timeout` is in the units of the Target system's time source (core
+    /// clock cycles on Cortex-M) because the Target program has no other
+    /// clock. It should be generous: the Proxy program can take a few
+    /// seconds to analyze a measurement before letting the Target program
+    /// continue. The watchdog only runs while the Target program is waiting
+    /// for a message, not while a benchmark is running.
+    ///
+    /// This is only supported by the targets communicating through RTT (the
+    /// Cortex-M targets). Without a registered hook, the Target program
+    /// waits forever.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `timeout` is zero.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn stop_motor() {
+    ///     // Drive the motor's enable pin low here...
+    /// }
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     // 60 seconds at 84 MHz
+    ///     c.on_link_lost(60 * 84_000_000, LinkLostAction::Reset, stop_motor);
+    ///     c.bench_function("noop", |b| b.iter(|| ()));
+    /// }
+    /// ```
+    pub fn on_link_lost(&mut self, timeout: u64, action: LinkLostAction, hook: fn()) -> &mut Self {
+        assert_ne!(timeout, 0, "link watchdog timeout must be non-zero");
+        if !crate::target::BencherIo::CAN_POLL_READ {
+            log::warn!("The link watchdog isn't supported by this target");
+        }
+        self.link
+            .set_watchdog(watchdog::LinkWatchdog::new(timeout, action, hook));
+        self
+    }
+
+    /// Choose how the Proxy program presents the values measured by the
+    /// following benchmarks. This remains in effect until changed again.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn checksum(data: &[u8]) -> u8 {
+    ///     data.iter().fold(0, |x, &y| x ^ y)
+    /// }
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     let data = [0u8; 4096];
+    ///     // Report cycles per KiB instead of cycles per iteration
+    ///     c.value_format(ValueFormat::per_unit(4, "KiB"));
+    ///     c.bench_function("checksum", |b| b.iter(|| checksum(&data)));
+    ///     c.value_format(ValueFormat::Native);
+    /// }
+    /// ```
+    pub fn value_format(&mut self, format: ValueFormat) -> &mut Self {
+        if let ValueFormat::PerUnit { amount, .. } = format {
+            assert_ne!(amount, 0, "value format amount must be non-zero");
+        }
+        self.link
+            .send(&protocol::UpstreamMessage::ValueFormatChanged {
+                format: format.into(),
+            });
+        self
+    }
+
+    /// Start a benchmark group named by `group_name`, which is formatted
+    /// directly into the group name buffer (see
+    /// [`Self::benchmark_group_fmt`]).
+    pub fn benchmark_group(
+        &mut self,
+        group_name: impl core::fmt::Display,
+    ) -> BenchmarkGroup<'link, '_> {
+        self.benchmark_group_dyn(&group_name)
+    }
+
+    /// Start a benchmark group with a name built at runtime, e.g.,
+    /// `c.benchmark_group_fmt(format_args!("crypto/{}/{}", algo, key_len))`.
+    /// This doesn't need a temporary buffer for the name.
+    pub fn benchmark_group_fmt(
+        &mut self,
+        group_name: core::fmt::Arguments<'_>,
+    ) -> BenchmarkGroup<'link, '_> {
+        self.benchmark_group_dyn(&group_name)
+    }
+
+    // Not generic to avoid monomorphizing the whole thing for every type of
+    // group name
+    fn benchmark_group_dyn(
+        &mut self,
+        group_name: &dyn core::fmt::Display,
+    ) -> BenchmarkGroup<'link, '_> {
+        // Format `group_name` into `self.group_name`. If it doesn't fit, keep
         // as many Unicode scalars as possible. (Ideally grapheme boundaries
         // should be used, but that's probably too much to handle for MCUs)
-        // TODO: the broad objective overlaps with `fill_array_string_with_display`
-        self.group_name.clear();
-        let group_name = if group_name.len() > self.group_name.capacity() {
-            let new_len = utf8_str_prev(group_name.as_bytes(), self.group_name.capacity());
-            &group_name[..new_len]
-        } else {
-            group_name
-        };
-        self.group_name.push_str(group_name);
+        self.group_name_len =
+            fill_array_string_with_display(&mut *self.group_name, Some(group_name));
 
         self.link
             .send(&protocol::UpstreamMessage::BeginningBenchmarkGroup {
@@ -121,6 +508,11 @@ impl<'link> Criterion<'link> {
         BenchmarkGroup {
             cri: self,
             throughput: None,
+            tags: &[],
+            timestamp_buf: None,
+            compare_black_box: false,
+            max_warm_up_doublings: func::DEFAULT_MAX_WARM_UP_DOUBLINGS,
+            config: protocol::BenchmarkConfigOverrides::default(),
         }
     }
 
@@ -146,14 +538,77 @@ pub enum Throughput {
     /// collection, but could also be the number of lines of input text or the number of values to
     /// parse.
     Elements(u64),
+
+    /// Measure throughput in terms of a user-defined unit per second (e.g.,
+    /// bits, packets, or operations). `amount` is the number of units
+    /// processed by one iteration of the benchmarked code, and `unit` is the
+    /// unit's name used in reports. See also [`Throughput::custom`].
+    Custom { amount: u64, unit: ArrayString<16> },
+}
+
+impl Throughput {
+    /// Construct a [`Throughput::Custom`]. If `unit` is longer than 16 bytes,
+    /// it's truncated at a `char` boundary.
+    pub fn custom(amount: u64, unit: &str) -> Self {
+        Self::Custom {
+            amount,
+            unit: unit_name(unit),
+        }
+    }
+}
+
+/// How the Proxy program presents the measured values. See
+/// [`Criterion::value_format`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueFormat {
+    /// Present the values in the unit of the Target system's time source
+    /// (e.g., clock cycles). This is the default.
+    Native,
+
+    /// Present the values divided by `amount`, in the time source's unit per
+    /// `unit` (e.g., `cycles/block`). Use this when each iteration of the
+    /// benchmarked code processes a fixed amount of a domain-specific unit
+    /// and the cost per unit is more meaningful than the cost per iteration.
+    /// See also [`ValueFormat::per_unit`].
+    PerUnit { amount: u64, unit: ArrayString<16> },
+}
+
+impl ValueFormat {
+    /// Construct a [`ValueFormat::PerUnit`]. If `unit` is longer than 16
+    /// bytes, it's truncated at a `char` boundary.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `amount` is zero.
+    pub fn per_unit(amount: u64, unit: &str) -> Self {
+        assert_ne!(amount, 0, "value format amount must be non-zero");
+        Self::PerUnit {
+            amount,
+            unit: unit_name(unit),
+        }
+    }
+}
+
+/// Copy a unit name for [`Throughput::custom`] or [`ValueFormat::per_unit`],
+/// truncating it if necessary.
+fn unit_name(unit: &str) -> ArrayString<16> {
+    let mut buf = ArrayString::new();
+    fill_array_string_with_str(&mut buf, unit);
+    buf
 }
 
 pub struct BenchmarkGroup<'link, 'cri> {
     cri: &'cri mut Criterion<'link>,
     throughput: Option<Throughput>,
+    tags: &'static [(&'static str, &'static str)],
+    /// Set by [`Self::record_sample_timestamps`]
+    timestamp_buf: Option<&'cri mut [u64]>,
+    compare_black_box: bool,
+    max_warm_up_doublings: u32,
+    config: protocol::BenchmarkConfigOverrides,
 }
 
-impl BenchmarkGroup<'_, '_> {
+impl<'cri> BenchmarkGroup<'_, 'cri> {
     /// Set the input size for this benchmark group. Used for reporting the
     /// throughput.
     pub fn throughput(&mut self, throughput: Throughput) -> &mut Self {
@@ -161,13 +616,208 @@ impl BenchmarkGroup<'_, '_> {
         self
     }
 
+    /// Attach key-value metadata to the following benchmarks in this group,
+    /// replacing the previously set ones. The Proxy program includes them in
+    /// the JSON output (`--farcri-save-json` and `--farcri-results`) so that
+    /// downstream tools don't have to parse them out of benchmark IDs. Pass
+    /// `&[]` to remove them.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     let mut group = c.benchmark_group("sort");
+    ///     group.tags(&[("algorithm", "insertion"), ("stable", "yes")]);
+    ///     group.bench_named("insertion", |b| b.iter(|| ()));
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there are more than 4 tags, if a key or a value
+    /// is longer than 32 bytes, or if a key appears more than once.
+    pub fn tags(&mut self, tags: &'static [(&'static str, &'static str)]) -> &mut Self {
+        assert!(tags.len() <= protocol::MAX_TAGS, "too many tags");
+        for (i, &(key, value)) in tags.iter().enumerate() {
+            assert!(
+                key.len() <= protocol::MAX_TAG_LEN && value.len() <= protocol::MAX_TAG_LEN,
+                "tag key or value is too long"
+            );
+            assert!(
+                tags[..i].iter().all(|&(other, _)| other != key),
+                "duplicate tag key"
+            );
+        }
+        self.tags = tags;
+        self
+    }
+
+    /// Record the counter value at the start of each sample in `buf`. The
+    /// Proxy program uses them to detect a drift of measured values within
+    /// a benchmark (e.g., due to thermal throttling).
+    ///
+    /// `buf` must have at least as many elements as the sample size of the
+    /// benchmarks in this group. Otherwise, the timestamps aren't recorded.
+    /// The buffer is borrowed from the caller so that benchmarks not using
+    /// this feature don't pay for the memory.
+    ///
+    /// This is disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     let mut timestamps = [0; 32];
+    ///     let mut group = c.benchmark_group("sort");
+    ///     group.sample_size(32);
+    ///     group.record_sample_timestamps(&mut timestamps);
+    ///     group.bench_function("quicksort", |b| b.iter(|| ()));
+    /// }
+    /// ```
+    pub fn record_sample_timestamps(&mut self, buf: &'cri mut [u64]) -> &mut Self {
+        self.timestamp_buf = Some(buf);
+        self
+    }
+
+    /// Specify whether to run each benchmark in this group twice, with and
+    /// without passing the input of [`Bencher::iter_with_input`] through
+    /// [`black_box`]. The runs are reported as separate benchmarks, labeled
+    /// `(black_box input)` and `(plain input)` respectively. A large
+    /// difference means the optimizer specializes the benchmarked code for
+    /// the input, which the benchmark probably doesn't intend to measure.
+    ///
+    /// Only the input of `iter_with_input` is affected; the two runs of a
+    /// benchmark using other timing loops measure the same code.
+    ///
+    /// This is disabled by default.
+    pub fn compare_black_box(&mut self, enable: bool) -> &mut Self {
+        self.compare_black_box = enable;
+        self
+    }
+
+    /// Change the number of samples collected for each benchmark in this
+    /// group. The Proxy program's `--sample-size` option takes precedence.
+    ///
+    /// The Target program holds at most 128 samples (1024 with the
+    /// `large_samples` feature). A larger sample size is clamped to that, and
+    /// the Proxy program warns about it, unless the Proxy program is run with
+    /// `--farcri-stream-samples`, in which case the samples are sent in
+    /// chunks as the buffer fills up.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `n` is zero.
+    pub fn sample_size(&mut self, n: usize) -> &mut Self {
+        assert_ne!(n, 0, "sample size must be non-zero");
+        self.config.sample_size = Some(n);
+        self
+    }
+
+    /// Change the number of bootstrap resamples used by the Proxy program's
+    /// analysis of the benchmarks in this group.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `n` is zero.
+    pub fn nresamples(&mut self, n: usize) -> &mut Self {
+        assert_ne!(n, 0, "number of resamples must be non-zero");
+        self.config.nresamples = Some(n);
+        self
+    }
+
+    /// Change the target measurement time for each benchmark in this group.
+    /// The Proxy program's `--profile-time` option takes precedence.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `dur` is zero.
+    pub fn measurement_time(&mut self, dur: core::time::Duration) -> &mut Self {
+        assert_ne!(dur.as_nanos(), 0, "measurement time must be non-zero");
+        self.config.measurement_time = Some(dur.into());
+        self
+    }
+
+    /// Change the warm-up time for each benchmark in this group.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `dur` is zero.
+    pub fn warm_up_time(&mut self, dur: core::time::Duration) -> &mut Self {
+        assert_ne!(dur.as_nanos(), 0, "warm-up time must be non-zero");
+        self.config.warm_up_time = Some(dur.into());
+        self
+    }
+
+    /// Change the maximum number of times a warm-up doubles the iteration
+    /// count. A warm-up normally ends when it has run for the warm-up time,
+    /// but if the time source is stopped or broken, it ends here instead,
+    /// and the Proxy program is warned that the values are unreliable.
+    ///
+    /// The default value is 40.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `n` is greater than 63, which would overflow
+    /// the iteration count.
+    pub fn max_warm_up_doublings(&mut self, n: u32) -> &mut Self {
+        assert!(n < 64, "maximum warm-up doublings must be less than 64");
+        self.max_warm_up_doublings = n;
+        self
+    }
+
     /// Benchmark the given parameterless function inside this benchmark group.
     pub fn bench_function(
         &mut self,
         id: impl AsBenchmarkId,
         mut f: impl FnMut(&mut Bencher<'_>),
     ) -> &mut Self {
-        self.bench_function_inner(id.as_benchmark_id(), &mut f)
+        let id = id.as_benchmark_id();
+        if !self.compare_black_box {
+            return self.bench_function_inner(id, &mut f);
+        }
+
+        for &(black_box_input, label) in BLACK_BOX_LABELS {
+            let function_name = BlackBoxLabel(id.function_name, label);
+            let id = BenchmarkId {
+                function_name: Some(IdPart::Display(&function_name)),
+                parameter: id.parameter,
+            };
+            self.bench_function_inner(id, &mut |b: &mut Bencher<'_>| {
+                b.black_box_input = black_box_input;
+                f(b)
+            });
+        }
+        self
+    }
+
+    /// Benchmark the given parameterless function inside this benchmark
+    /// group, naming it with a string literal. This is equivalent to
+    /// `bench_function(BenchmarkId::from_static(name), f)`, but doesn't link
+    /// [`core::fmt`] for the benchmark ID.
+    pub fn bench_named(
+        &mut self,
+        name: &'static str,
+        mut f: impl FnMut(&mut Bencher<'_>),
+    ) -> &mut Self {
+        if !self.compare_black_box {
+            let function_id_len = fill_array_string_with_str(self.cri.function_name, name);
+            return self.run_benchmark(Some(function_id_len), None, &mut f);
+        }
+
+        for &(black_box_input, label) in BLACK_BOX_LABELS {
+            // Equivalent to `BlackBoxLabel`
+            let function_id_len =
+                fill_array_string_with_strs(self.cri.function_name, &[name, " (", label, ")"]);
+            self.run_benchmark(Some(function_id_len), None, &mut |b: &mut Bencher<'_>| {
+                b.black_box_input = black_box_input;
+                f(b)
+            });
+        }
+        self
     }
 
     /// Benchmark the given parameterized function inside this benchmark group.
@@ -180,63 +830,250 @@ impl BenchmarkGroup<'_, '_> {
         self.bench_function(id, move |b| f(b, input))
     }
 
+    /// Benchmark the given function inside this benchmark group, passing
+    /// `state` to it. Unlike a closure capturing `state`, this makes it
+    /// obvious that the state is only borrowed for the duration of the
+    /// call, so it can be reused by the following benchmarks (e.g., a large
+    /// scratch buffer) without `RefCell`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use farcri::*;
+    ///
+    /// fn bench(c: &mut Criterion) {
+    ///     // Reused by all benchmarks in the group
+    ///     let mut scratch = [0u32; 256];
+    ///
+    ///     let mut group = c.benchmark_group("fill");
+    ///     for &len in &[16, 256] {
+    ///         let id = BenchmarkId::from_parameter(&len);
+    ///         group.bench_function_with(id, &mut scratch, |b, scratch| {
+    ///             b.iter(|| scratch[..len].iter_mut().for_each(|x| *x += 1))
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// criterion_group!(benches, bench);
+    /// criterion_main!(benches);
+    /// ```
+    pub fn bench_function_with<S: ?Sized>(
+        &mut self,
+        id: impl AsBenchmarkId,
+        state: &mut S,
+        mut f: impl FnMut(&mut Bencher<'_>, &mut S),
+    ) -> &mut Self {
+        self.bench_function(id, move |b| f(b, state))
+    }
+
+    /// Benchmark the given parameterized function inside this benchmark
+    /// group, passing a mutable reference to `input` to it. See
+    /// [`Self::bench_function_with`].
+    pub fn bench_with_input_mut<I: ?Sized>(
+        &mut self,
+        id: impl AsBenchmarkId,
+        input: &mut I,
+        f: impl FnMut(&mut Bencher<'_>, &mut I),
+    ) -> &mut Self {
+        self.bench_function_with(id, input, f)
+    }
+
     fn bench_function_inner(
         &mut self,
         id: BenchmarkId<'_>,
         f: &mut dyn FnMut(&mut Bencher<'_>),
+    ) -> &mut Self {
+        let function_id_len = id.function_name.map(|x| match x {
+            IdPart::Str(s) => fill_array_string_with_str(self.cri.function_name, s),
+            IdPart::Display(x) => fill_array_string_with_display(self.cri.function_name, Some(x)),
+        });
+        let value_str_len = id
+            .parameter
+            .map(|x| fill_array_string_with_display(self.cri.parameter_description, Some(x)));
+        self.run_benchmark(function_id_len, value_str_len, f)
+    }
+
+    /// Run the benchmark whose ID components are in `self.cri.function_name`
+    /// and `self.cri.parameter_description`. `function_id_len` and
+    /// `value_str_len` are the lengths of the components before truncation,
+    /// or `None` if they are absent.
+    fn run_benchmark(
+        &mut self,
+        function_id_len: Option<usize>,
+        value_str_len: Option<usize>,
+        f: &mut dyn FnMut(&mut Bencher<'_>),
     ) -> &mut Self {
         let id = protocol::RawBenchmarkId {
             group_id: self.cri.group_name.as_str(),
-            function_id: if let Some(x) = &id.function_name {
-                fill_array_string_with_display(&mut self.cri.function_name, Some(x));
-                Some(self.cri.function_name.as_str())
-            } else {
-                None
-            },
-            value_str: if let Some(x) = &id.parameter {
-                fill_array_string_with_display(&mut self.cri.parameter_description, Some(x));
-                Some(self.cri.parameter_description.as_str())
-            } else {
-                None
-            },
+            function_id: function_id_len.and(Some(self.cri.function_name.as_str())),
+            value_str: value_str_len.and(Some(self.cri.parameter_description.as_str())),
             throughput: self.throughput.map(Into::into),
         };
+        let function_id_len = function_id_len.unwrap_or(0);
+        let value_str_len = value_str_len.unwrap_or(0);
+
+        if !self.cri.name_filters.is_empty()
+            && !self.cri.name_filters.iter().any(|x| id.contains(x))
+        {
+            log::debug!("Skipping {} because it doesn't match the filters", id);
+            self.cri
+                .link
+                .send(&protocol::UpstreamMessage::SkippingBenchmark {
+                    id,
+                    reason: protocol::SkipReason::Filter,
+                });
+            return self;
+        }
+
+        if self.cri.skip_benchmarks > 0 {
+            log::debug!("Skipping {} to resume the previous run", id);
+            self.cri.skip_benchmarks -= 1;
+            self.cri
+                .link
+                .send(&protocol::UpstreamMessage::SkippingBenchmark {
+                    id,
+                    reason: protocol::SkipReason::Resume,
+                });
+            return self;
+        }
+
+        if self.cri.skip_remaining {
+            log::debug!("Skipping {} because the time budget is exhausted", id);
+            self.cri
+                .link
+                .send(&protocol::UpstreamMessage::SkippingBenchmark {
+                    id,
+                    reason: protocol::SkipReason::Budget,
+                });
+            return self;
+        }
+
+        if self.cri.group_name_len > id.group_id.len()
+            || function_id_len > id.function_id.map_or(0, str::len)
+            || value_str_len > id.value_str.map_or(0, str::len)
+        {
+            self.cri.link.send(&protocol::UpstreamMessage::IdTruncated {
+                id,
+                group_id_len: self.cri.group_name_len as u32,
+                function_id_len: function_id_len as u32,
+                value_str_len: value_str_len as u32,
+            });
+        }
+
+        if !matches!(self.cri.mode, protocol::Mode::List) {
+            self.cri
+                .link
+                .send(&protocol::UpstreamMessage::RunningBenchmark { id });
+        }
+
+        let mut bench_config = protocol::BenchmarkConfig::default();
+        self.config.apply(&mut bench_config);
+        self.cri.bench_config_overrides.apply(&mut bench_config);
 
         let mut func = func::Function::new(f);
 
+        self.cri.in_flight = true;
+
         match self.cri.mode {
             protocol::Mode::Benchmark => {
-                // TODO: send `SkippingBenchmark` if skipped
+                // Measure once for each requested frequency, or just once if
+                // frequency switching isn't enabled
+                let setter = self.cri.frequency_setter;
+                let frequencies = self.cri.frequencies.clone();
+                let frequencies: &[u32] = if setter.is_some() { &frequencies } else { &[] };
+
+                for i in 0..frequencies.len().max(1) {
+                    if let (Some(setter), Some(&requested)) = (setter, frequencies.get(i)) {
+                        let effective = setter(requested);
+                        log::info!(
+                            "Switched to {} (requested {})",
+                            Frequency(effective),
+                            Frequency(requested)
+                        );
+                        self.cri
+                            .link
+                            .send(&protocol::UpstreamMessage::FrequencyChanged {
+                                requested,
+                                effective,
+                            });
+                    }
+
+                    if !self.tags.is_empty() {
+                        self.cri
+                            .link
+                            .send(&protocol::UpstreamMessage::BenchmarkTags {
+                                tags: self.tags.iter().copied().collect(),
+                            });
+                    }
+
+                    self.cri
+                        .link
+                        .send(&protocol::UpstreamMessage::BeginningBenchmark { id });
+
+                    {
+                        cryo!(let link: CryoMut<_, LocalLock> = &mut self.cri.link);
+                        analysis::common(
+                            &id,
+                            &mut func,
+                            &bench_config,
+                            func::SamplingOptions {
+                                max_warm_up_doublings: self.max_warm_up_doublings,
+                                streamed: self.cri.streamed_samples,
+                            },
+                            &mut self.cri.value_buf,
+                            self.timestamp_buf.as_deref_mut(),
+                            Measurement::new(link.write()),
+                        );
+                    }
+
+                    // Wait for a `Continue` message
+                    log::debug!("Waiting for `Continue`...");
+                    if self.cri.link.recv_continue() {
+                        self.cri.skip_remaining = true;
+                    }
+                }
+            } // protocol::Mode::Benchmark
+
+            protocol::Mode::List => {
                 self.cri
                     .link
-                    .send(&protocol::UpstreamMessage::BeginningBenchmark { id });
-
-                cryo!(let link: CryoMut<_, LocalLock> = &mut self.cri.link);
-                analysis::common(
-                    &id,
-                    &mut func,
-                    &protocol::BenchmarkConfig::default(),
-                    &mut self.cri.value_buf,
-                    Measurement::new(link.write()),
-                );
-            } // protocol::Mode::Benchmark
+                    .send(&protocol::UpstreamMessage::ListedBenchmark { id });
+            } // protocol::Mode::List
+
+            protocol::Mode::Profile => {
+                let profile_time = bench_config.measurement_time;
+                log::info!("Profiling {} for {}", id, profile_time);
+                let (_, total_iters, _, _) = {
+                    cryo!(let link: CryoMut<_, LocalLock> = &mut self.cri.link);
+                    func.warm_up(
+                        Measurement::new(link.write()),
+                        profile_time,
+                        self.max_warm_up_doublings,
+                        false,
+                    )
+                };
+                log::info!("... Completed {} iteration(s)", total_iters);
+            } // protocol::Mode::Profile
 
             protocol::Mode::Test => {
-                cryo!(let link: CryoMut<_, LocalLock> = &mut self.cri.link);
                 log::info!("Testing {}", id);
-                func.bench(Measurement::new(link.write()), 1, &mut [Default::default()]);
-                log::info!("... Success");
+                let test_context = {
+                    cryo!(let link: CryoMut<_, LocalLock> = &mut self.cri.link);
+                    catch_test_panic(|| func.test(Measurement::new(link.write())).0)
+                };
+
+                if let Some(message) = test_context.failure() {
+                    log::error!("... Failed: {}", message);
+                    self.cri
+                        .link
+                        .send(&protocol::UpstreamMessage::TestFailed { id, message });
+                } else {
+                    log::info!("... Success");
+                }
             } // protocol::Mode::Test
         } // match self.cri.mode
 
-        // Wait for a `Continue` message
-        log::debug!("Waiting for `Continue`...");
-        match self.cri.link.recv() {
-            protocol::DownstreamMessage::Continue => {}
-            other => {
-                panic!("unexpected downstream message: {:?}", other);
-            }
-        }
+        self.cri.in_flight = false;
 
         self
     }
@@ -248,32 +1085,663 @@ impl BenchmarkGroup<'_, '_> {
     pub fn finish(self) {}
 }
 
+/// The variants of a benchmark run by [`BenchmarkGroup::compare_black_box`]:
+/// whether the input is passed through `black_box` and the label appended
+/// to the function name
+const BLACK_BOX_LABELS: &[(bool, &str)] = &[(true, "black_box input"), (false, "plain input")];
+
+/// The function name of a benchmark run by
+/// [`BenchmarkGroup::compare_black_box`]
+struct BlackBoxLabel<'a>(Option<IdPart<'a>>, &'static str);
+
+impl core::fmt::Display for BlackBoxLabel<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(function_name) => write!(f, "{} ({})", function_name, self.1),
+            None => f.write_str(self.1),
+        }
+    }
+}
+
 impl Drop for BenchmarkGroup<'_, '_> {
     fn drop(&mut self) {
         let cri = &mut *self.cri;
+
+        // Don't touch the link if we are unwinding. It might be in an
+        // inconsistent state, and a panic here would hide the original one.
+        if cri.in_flight || is_panicking() {
+            log::warn!("Abandoning the benchmark group because of a panic");
+            return;
+        }
+
         cri.link
             .send(&protocol::UpstreamMessage::FinishedBenchmarkGroup);
 
         // Wait for a `Continue` message
         log::debug!("Waiting for `Continue`...");
-        match cri.link.recv() {
-            protocol::DownstreamMessage::Continue => {}
-            other => {
-                panic!("unexpected downstream message: {:?}", other);
-            }
+        if cri.link.recv_continue() {
+            cri.skip_remaining = true;
         }
     }
 }
 
+/// Check if the current thread is unwinding. Panics always abort on the
+/// Target system, so this is only meaningful on the host.
+fn is_panicking() -> bool {
+    match () {
+        #[cfg(not(feature = "role_target"))]
+        () => std::thread::panicking(),
+        #[cfg(feature = "role_target")]
+        () => false,
+    }
+}
+
+/// Call `f`, converting a panic into a test failure on the host, where
+/// panics can be caught. This lets the remaining tests run.
+fn catch_test_panic(f: impl FnOnce() -> TestContext) -> TestContext {
+    match () {
+        #[cfg(not(feature = "role_target"))]
+        () => std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|e| {
+            let message = if let Some(x) = e.downcast_ref::<&str>() {
+                x
+            } else if let Some(x) = e.downcast_ref::<String>() {
+                x.as_str()
+            } else {
+                "Box<dyn Any>"
+            };
+            let mut test_context = TestContext::default();
+            test_context.fail(format_args!("panicked at '{}'", message));
+            test_context
+        }),
+        #[cfg(feature = "role_target")]
+        () => f(),
+    }
+}
+
+/// Copy `s` into `buf`. If it doesn't fit, keep as many Unicode scalars as
+/// possible, like [`fill_array_string_with_display`] does. Returns the length
+/// of `s`.
+pub(crate) fn fill_array_string_with_str<const N: usize>(
+    buf: &mut ArrayString<N>,
+    s: &str,
+) -> usize {
+    fill_array_string_with_strs(buf, &[s])
+}
+
+/// Copy the concatenation of `pieces` into `buf` without going through
+/// `core::fmt`. Truncates and returns the length like
+/// [`fill_array_string_with_display`] does.
+pub(crate) fn fill_array_string_with_strs<const N: usize>(
+    buf: &mut ArrayString<N>,
+    pieces: &[&str],
+) -> usize {
+    let mut writer = TruncatingWriter::new(buf);
+    for piece in pieces {
+        writer.push(piece);
+    }
+    writer.len
+}
+
 // TODO: Implement a better way to be dynamic over `N`. Const generics is nice
 //       but doesn't support unsizing (yet?).
-fn fill_array_string_with_display<const N: usize>(
+/// Format `display` into `buf`. If it doesn't fit, keep as many Unicode
+/// scalars as possible, like `Criterion::benchmark_group` does. Returns the
+/// length of the whole formatted string.
+pub(crate) fn fill_array_string_with_display<const N: usize>(
     buf: &mut ArrayString<N>,
     display: Option<&dyn core::fmt::Display>,
-) {
-    buf.clear();
+) -> usize {
+    use core::fmt::Write;
+
+    let mut writer = TruncatingWriter::new(buf);
     if let Some(display) = display {
-        // Should there be an error, it's probably a capacity error
-        let _ = write!(buf as &mut dyn core::fmt::Write, "{}", display);
+        // `TruncatingWriter` doesn't fail, but `display` might
+        let _ = write!(writer, "{}", display);
+    }
+    writer.len
+}
+
+/// Appends strings to an `ArrayString` until one doesn't fit
+struct TruncatingWriter<'a, const N: usize> {
+    buf: &'a mut ArrayString<N>,
+    /// The length of the whole string, including the truncated part
+    len: usize,
+    truncated: bool,
+}
+
+impl<'a, const N: usize> TruncatingWriter<'a, N> {
+    fn new(buf: &'a mut ArrayString<N>) -> Self {
+        buf.clear();
+        Self {
+            buf,
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        self.len += s.len();
+        if !self.truncated {
+            let fitting = truncate_str(s, self.buf.remaining_capacity());
+            self.buf.push_str(fitting);
+            // Stop appending. Later pieces might fit but would
+            // misrepresent the string. Keep counting the length, though.
+            self.truncated = fitting.len() < s.len();
+        }
+    }
+}
+
+impl<const N: usize> core::fmt::Write for TruncatingWriter<'_, N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push(s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execution_mode_mirrors_protocol_mode() {
+        let modes = [
+            protocol::Mode::Benchmark,
+            protocol::Mode::Test,
+            protocol::Mode::List,
+            protocol::Mode::Profile,
+        ];
+        for &mode in modes.iter() {
+            // Make sure `modes` covers all variants
+            match mode {
+                protocol::Mode::Benchmark
+                | protocol::Mode::Test
+                | protocol::Mode::List
+                | protocol::Mode::Profile => {}
+            }
+
+            assert_eq!(
+                format!("{:?}", ExecutionMode::from(mode)),
+                format!("{:?}", mode)
+            );
+        }
+    }
+
+    #[test]
+    fn display_truncated() {
+        let mut buf = ArrayString::<8>::new();
+        let mut fill = |display: &dyn core::fmt::Display| {
+            let len = fill_array_string_with_display(&mut buf, Some(display));
+            assert_eq!(len, display.to_string().len());
+            buf.to_string()
+        };
+
+        assert_eq!(fill(&"0123"), "0123");
+        assert_eq!(fill(&"0123456789"), "01234567");
+        // Don't split a scalar value
+        assert_eq!(fill(&"0123456α"), "0123456");
+        // Don't resume after truncating a piece
+        assert_eq!(fill(&format_args!("{}{}", "0123456α", "x")), "0123456");
+    }
+
+    #[test]
+    fn unit_name_truncated() {
+        assert_eq!(
+            Throughput::custom(1, "0123456789abcdefg"),
+            Throughput::Custom {
+                amount: 1,
+                unit: ArrayString::from("0123456789abcdef").unwrap()
+            }
+        );
+        // "パケット通信" is 18 bytes long; don't split "信"
+        assert_eq!(
+            ValueFormat::per_unit(1, "パケット通信"),
+            ValueFormat::PerUnit {
+                amount: 1,
+                unit: ArrayString::from("パケット通").unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn str_truncated_like_display() {
+        let mut by_str = ArrayString::<8>::new();
+        let mut by_display = ArrayString::<8>::new();
+        for &s in &["", "0123", "01234567", "0123456789", "0123456α"] {
+            let len_by_str = fill_array_string_with_str(&mut by_str, s);
+            let len_by_display = fill_array_string_with_display(&mut by_display, Some(&s));
+            assert_eq!(by_str, by_display);
+            assert_eq!((len_by_str, len_by_display), (s.len(), s.len()));
+        }
+    }
+
+    #[test]
+    fn strs_truncated_like_display() {
+        let mut by_strs = ArrayString::<8>::new();
+        let mut by_display = ArrayString::<8>::new();
+        for pieces in &[
+            &["", ""][..],
+            &["0123", "4567"],
+            &["0123456α", "x"],
+            &["01", "23456789"],
+        ] {
+            let len_by_strs = fill_array_string_with_strs(&mut by_strs, pieces);
+            let len_by_display = fill_array_string_with_display(
+                &mut by_display,
+                Some(&format_args!("{}{}", pieces[0], pieces[1])),
+            );
+            assert_eq!(by_strs, by_display);
+            assert_eq!(len_by_strs, len_by_display);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "role_proxy"))]
+mod loopback_tests {
+    use super::*;
+    use crate::proxy::tests::{
+        fast_overrides, loopback_config, run_fast_loopback, run_loopback,
+        run_loopback_until_silent, run_loopback_with_config,
+    };
+
+    #[tokio::test]
+    async fn tags_sent() {
+        let messages = run_fast_loopback(|c| {
+            let mut group = c.benchmark_group("sort");
+            group.tags(&[("algorithm", "insertion"), ("opt", "O2")]);
+            group.bench_function("a", |b| b.iter(|| ()));
+            group.tags(&[]);
+            group.bench_function("b", |b| b.iter(|| ()));
+            group.finish();
+        })
+        .await;
+
+        let events: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::BeginningBenchmark { id } => Some(id.to_string()),
+                protocol::UpstreamMessage::BenchmarkTags { tags } => Some(format!("{:?}", tags)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                r#"[("algorithm", "insertion"), ("opt", "O2")]"#,
+                "sort/a",
+                "sort/b"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn named_ids_match_display() {
+        // The second one only overflows `FunctionNameBuf` with a
+        // `compare_black_box` label. The third one always does.
+        let leak = |s: String| -> &'static str { Box::leak(s.into_boxed_str()) };
+        let names = ["a", leak("α".repeat(60)), leak("α".repeat(70))];
+
+        let benchmarked_ids =
+            |compare_black_box, f: fn(&mut BenchmarkGroup<'_, '_>, &'static str)| async move {
+                let messages = run_fast_loopback(move |c| {
+                    let mut group = c.benchmark_group("g");
+                    group.compare_black_box(compare_black_box);
+                    for &name in names.iter() {
+                        f(&mut group, name);
+                    }
+                })
+                .await;
+                messages
+                    .iter()
+                    .filter_map(|msg| match msg {
+                        protocol::UpstreamMessage::BeginningBenchmark { id } => {
+                            Some(id.to_string())
+                        }
+                        protocol::UpstreamMessage::IdTruncated {
+                            function_id_len, ..
+                        } => Some(format!("truncated {}", function_id_len)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+        for &compare_black_box in &[false, true] {
+            let by_display = benchmarked_ids(compare_black_box, |group, name| {
+                group.bench_function(name, |b| b.iter(|| ()));
+            })
+            .await;
+            let by_static = benchmarked_ids(compare_black_box, |group, name| {
+                group.bench_function(BenchmarkId::from_static(name), |b| b.iter(|| ()));
+            })
+            .await;
+            let by_named = benchmarked_ids(compare_black_box, |group, name| {
+                group.bench_named(name, |b| b.iter(|| ()));
+            })
+            .await;
+            assert!(by_display.iter().any(|x| x.starts_with("truncated")));
+            assert_eq!(by_static, by_display);
+            assert_eq!(by_named, by_display);
+        }
+    }
+
+    #[tokio::test]
+    async fn black_box_compared() {
+        let messages = run_loopback(protocol::Mode::Test, Default::default(), 0, |c| {
+            let mut group = c.benchmark_group("double");
+            group.compare_black_box(true);
+            group.bench_with_input(crate::BenchmarkId::from_parameter(&3), &3u32, |b, &n| {
+                b.iter_with_input(n, |n| n * 2)
+            });
+            group.bench_function("f", |b| b.iter_with_input(4u32, |n| n * 2));
+            group.finish();
+        })
+        .await;
+
+        let ids: Vec<String> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::RunningBenchmark { id } => Some(id.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "double/black_box input/3",
+                "double/plain input/3",
+                "double/f (black_box input)",
+                "double/f (plain input)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn value_format_reported() {
+        let messages = run_loopback(protocol::Mode::List, Default::default(), 0, |c| {
+            c.value_format(crate::ValueFormat::per_unit(4, "KiB"));
+            c.bench_function("a", |b| b.iter(|| ()));
+        })
+        .await;
+
+        let formats: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match *msg {
+                protocol::UpstreamMessage::ValueFormatChanged { format } => Some(format),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            formats,
+            [protocol::ValueFormat::PerUnit {
+                amount: 4,
+                unit: arrayvec::ArrayString::from("KiB").unwrap(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn state_shared_across_benchmarks() {
+        let messages = run_loopback(protocol::Mode::Test, Default::default(), 0, |c| {
+            let mut calls = 0u32;
+            let mut scratch = [0u8; 64];
+
+            let mut group = c.benchmark_group("group");
+            group.bench_function_with("a", &mut calls, |b, calls| {
+                *calls += 1;
+                b.iter(|| ())
+            });
+            for &len in &[16usize, 64] {
+                group.bench_with_input_mut(
+                    crate::BenchmarkId::from_parameter(&len),
+                    &mut scratch,
+                    |b, scratch| b.iter(|| scratch[..len].iter_mut().for_each(|x| *x += 1)),
+                );
+                // `scratch` is no longer borrowed here
+                assert_eq!(scratch[15], 1 + (len == 64) as u8);
+            }
+            group.bench_function_with("b", &mut calls, |b, calls| {
+                *calls += 1;
+                b.iter(|| ())
+            });
+            group.finish();
+
+            assert_eq!(calls, 2);
+        })
+        .await;
+
+        let num_ran = messages
+            .iter()
+            .filter(|msg| matches!(msg, protocol::UpstreamMessage::RunningBenchmark { .. }))
+            .count();
+        assert_eq!(num_ran, 4);
+    }
+
+    #[tokio::test]
+    async fn truncated_ids_reported() {
+        let messages = run_loopback(protocol::Mode::List, Default::default(), 0, |c| {
+            let mut group = c.benchmark_group("a".repeat(130));
+            group.bench_function("fits", |b| b.iter(|| ()));
+            group.finish();
+
+            let mut group = c.benchmark_group("group");
+            group.bench_function("fits", |b| b.iter(|| ()));
+            let long = "b".repeat(200);
+            group.bench_with_input(crate::BenchmarkId::new(&"f", &long), &(), |b, _| {
+                b.iter(|| ())
+            });
+            group.finish();
+        })
+        .await;
+
+        let reports: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::IdTruncated {
+                    id,
+                    group_id_len,
+                    function_id_len,
+                    value_str_len,
+                } => Some((
+                    id.to_string().len(),
+                    *group_id_len,
+                    *function_id_len,
+                    *value_str_len,
+                )),
+                _ => None,
+            })
+            .collect();
+        // "aaa...(128 bytes)/fits" and "group/f/bbb...(128 bytes)"
+        assert_eq!(reports, [(133, 130, 4, 0), (136, 5, 1, 200)]);
+
+        // Reported before listing the truncated ID
+        let order: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::IdTruncated { .. } => Some("truncated"),
+                protocol::UpstreamMessage::ListedBenchmark { .. } => Some("listed"),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            order,
+            ["truncated", "listed", "listed", "truncated", "listed"]
+        );
+    }
+
+    #[tokio::test]
+    async fn group_names_formatted() {
+        let messages = run_loopback(protocol::Mode::List, Default::default(), 0, |c| {
+            c.benchmark_group_fmt(format_args!("crypto/{}/{}", "aes", 128));
+            c.benchmark_group(256u32);
+            // Truncated at a scalar boundary
+            c.benchmark_group_fmt(format_args!("{:a<127}{}", "", "α"));
+        })
+        .await;
+
+        let groups: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::BeginningBenchmarkGroup { group } => {
+                    Some(group.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(groups, ["crypto/aes/128", "256", &"a".repeat(127)]);
+    }
+
+    #[tokio::test]
+    async fn test_panic_reported() {
+        let messages = run_loopback(protocol::Mode::Test, Default::default(), 0, |c| {
+            let mut group = c.benchmark_group("group");
+            group.bench_function("panic", |b| b.iter(|| panic!("boom {}", 42)));
+            group.bench_function("pass", |b| b.iter(|| ()));
+        })
+        .await;
+
+        let failures: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::TestFailed { id, message } => {
+                    Some((id.to_string(), message.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            failures,
+            [("group/panic".to_owned(), "panicked at 'boom 42'")]
+        );
+
+        // The group is finished normally
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, protocol::UpstreamMessage::FinishedBenchmarkGroup)));
+    }
+
+    #[tokio::test]
+    async fn benchmark_panic_propagated() {
+        let (messages, result) = run_loopback_until_silent(protocol::Mode::Benchmark, |c| {
+            c.bench_function("panic", |b| b.iter(|| panic!("boom")));
+        })
+        .await;
+
+        // The Target program should just stop talking without finishing the
+        // group
+        assert!(!messages.iter().any(|msg| matches!(
+            msg,
+            protocol::UpstreamMessage::FinishedBenchmarkGroup | protocol::UpstreamMessage::End
+        )));
+
+        // The original panic should be propagated
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    }
+
+    #[tokio::test]
+    async fn frequencies_switched() {
+        let mut config = loopback_config(protocol::Mode::Benchmark, fast_overrides(), 0);
+        config.frequencies = [48_000_000, 84_000_000].iter().cloned().collect();
+
+        let messages = run_loopback_with_config(config, |c| {
+            c.frequency_setter(|hz| hz / 2);
+            let mut group = c.benchmark_group("group");
+            group.bench_function("a", |b| b.iter(|| ()));
+            group.bench_function("b", |b| b.iter(|| ()));
+        })
+        .await;
+
+        // Each benchmark is measured at each frequency
+        let runs: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::FrequencyChanged {
+                    requested,
+                    effective,
+                } => Some(format!("{} {}", requested, effective)),
+                protocol::UpstreamMessage::BeginningBenchmark { id } => Some(id.to_string()),
+                protocol::UpstreamMessage::MeasurementComplete { .. } => Some("done".to_owned()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                "48000000 24000000",
+                "group/a",
+                "done",
+                "84000000 42000000",
+                "group/a",
+                "done",
+                "48000000 24000000",
+                "group/b",
+                "done",
+                "84000000 42000000",
+                "group/b",
+                "done",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn frequencies_ignored_without_setter() {
+        let mut config = loopback_config(protocol::Mode::Benchmark, Default::default(), 0);
+        config.frequencies = [48_000_000, 84_000_000].iter().cloned().collect();
+        config.bench_config_overrides = fast_overrides();
+
+        let messages = run_loopback_with_config(config, |c| {
+            c.bench_function("a", |b| b.iter(|| ()));
+        })
+        .await;
+
+        assert!(!messages
+            .iter()
+            .any(|msg| matches!(msg, protocol::UpstreamMessage::FrequencyChanged { .. })));
+        let num_measurements = messages
+            .iter()
+            .filter(|msg| matches!(msg, protocol::UpstreamMessage::MeasurementComplete { .. }))
+            .count();
+        assert_eq!(num_measurements, 1);
+    }
+
+    #[tokio::test]
+    async fn capacities_reported() {
+        let messages = run_loopback(protocol::Mode::Test, Default::default(), 0, |_| {}).await;
+        match messages[0] {
+            protocol::UpstreamMessage::GreetingReply { capacities, .. } => assert_eq!(
+                capacities,
+                protocol::TargetCapacities {
+                    link_buffer_len: crate::bencher::LINK_BUFFER_LEN as u32,
+                    max_samples: crate::bencher::MAX_SAMPLES as u32,
+                    max_group_id_len: 128,
+                    max_function_id_len: 128,
+                    max_value_str_len: 128,
+                }
+            ),
+            ref other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn loop_overhead_reported() {
+        let loop_overhead = |feature_bits| async move {
+            let messages = run_loopback(
+                protocol::Mode::Test,
+                Default::default(),
+                feature_bits,
+                |_| {},
+            )
+            .await;
+            match messages[0] {
+                protocol::UpstreamMessage::GreetingReply { loop_overhead, .. } => loop_overhead,
+                ref other => panic!("unexpected message: {:?}", other),
+            }
+        };
+
+        assert!(loop_overhead(0).await.is_none());
+
+        let loop_overhead = loop_overhead(protocol::FEATURE_LOOP_OVERHEAD)
+            .await
+            .unwrap();
+        assert_eq!(loop_overhead.iters, 1000);
     }
 }
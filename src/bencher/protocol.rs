@@ -1,38 +1,295 @@
+//! The protocol between the Proxy program and the Target program
+//!
+//! # Turn-taking
+//!
+//! The link might be half-duplex (e.g., a half-duplex UART adapter), so the
+//! peers must never send data at the same time. Except during the handshake,
+//! which tolerates lost packets by retrying, the protocol is strictly
+//! request/response from the Target program's perspective:
+//!
+//!  - After the handshake, the Target program waits for
+//!    [`DownstreamMessage::Greeting`].
+//!  - If requested by [`FEATURE_LINK_CHECK`], the Target program waits for
+//!    [`DownstreamMessage::EchoRequest`] (answered by
+//!    [`UpstreamMessage::EchoReply`]) or [`DownstreamMessage::Continue`] after
+//!    sending [`UpstreamMessage::GreetingReply`] and each
+//!    [`UpstreamMessage::EchoReply`].
+//!  - Otherwise, the Proxy program only sends a message in response to an
+//!    [`UpstreamMessage`] after which the Target program waits for a reply:
+//!    [`UpstreamMessage::GetInstant`] (answered by
+//!    [`DownstreamMessage::Instant`]), [`UpstreamMessage::MeasurementComplete`],
+//!    [`UpstreamMessage::BenchmarkFailed`], and
+//!    [`UpstreamMessage::FinishedBenchmarkGroup`] (answered by
+//!    [`DownstreamMessage::Continue`]). Exactly one reply is sent for each of
+//!    them.
+//!  - The Target program doesn't send anything while it's waiting for a reply.
+//!
+//! Both peers tolerate some deviations from these rules. The Target program
+//! counts the [`DownstreamMessage::Continue`]s (and
+//! [`DownstreamMessage::SkipRemaining`]s) it has received against the
+//! messages it has sent that expect one, so a reply that arrives early (e.g.,
+//! coalesced with the previous reply) is kept for the next wait instead of
+//! being discarded. The Proxy program ignores an
+//! [`UpstreamMessage::FinishedBenchmarkGroup`] that immediately follows
+//! another one, which can only be a duplicate.
+//!
+//! The Proxy program's `TargetLink` enforces this by deferring outgoing
+//! messages until the Target program asks for them.
+use arrayvec::{ArrayString, ArrayVec};
 use core::borrow::Borrow;
 use core::fmt;
 use serde::{Deserialize, Serialize};
 
 pub use super::time::{Duration, Instant};
 
-pub(crate) const HANDSHAKE_MAGIC: &[u8] = b"\x01fluttershyyay";
-pub(crate) const HANDSHAKE_NONCE_LEN: usize = 16;
+// The items documented in `doc/protocol_vectors.cbor.txt` are `pub` so that
+// `crate::protocol_doc` can re-export them.
 
-pub(crate) const HANDSHAKE_END_MAGIC: &[u8] = b"\x02applejack";
+/// Sent by the Proxy program to start a handshake, followed by a nonce of
+/// [`HANDSHAKE_NONCE_LEN`] bytes. The Target program echoes both back.
+pub const HANDSHAKE_MAGIC: &[u8] = b"\x01fluttershyyay";
+pub const HANDSHAKE_NONCE_LEN: usize = 16;
+
+/// Sent by the Proxy program to end a handshake after receiving the echo.
+/// The Target program echoes it back, and then the peers exchange SLIP
+/// frames.
+pub const HANDSHAKE_END_MAGIC: &[u8] = b"\x02applejack";
+
+/// Terminates a SLIP frame. Each frame carries one CBOR-encoded message.
+/// Empty frames are ignored.
+pub const SLIP_FRAME_END: u8 = 0xc0;
+/// Starts a SLIP escape sequence
+pub const SLIP_FRAME_ESC: u8 = 0xdb;
+/// Follows [`SLIP_FRAME_ESC`] to represent [`SLIP_FRAME_END`] in a frame
+pub const SLIP_FRAME_ESC_END: u8 = 0xdc;
+/// Follows [`SLIP_FRAME_ESC`] to represent [`SLIP_FRAME_ESC`] in a frame
+pub const SLIP_FRAME_ESC_ESC: u8 = 0xdd;
+
+/// The ITM stimulus port through which the Target program sends upstream
+/// bytes when the `comm_swo` feature is enabled.
+#[cfg_attr(
+    not(any(feature = "role_proxy", feature = "comm_swo")),
+    allow(dead_code)
+)]
+pub(crate) const SWO_STIMULUS_PORT: u8 = 1;
+
+/// The name of the RTT up channel through which the Target program sends the
+/// frame trace when the `frame_trace` feature is enabled.
+///
+/// The frame trace is a sequence of records, each of which consists of a
+/// direction byte ([`FRAME_TRACE_DOWNSTREAM`] or [`FRAME_TRACE_UPSTREAM`]),
+/// the length of the frame (`u16`, little endian), and the CBOR-encoded
+/// message (without SLIP framing).
+#[cfg_attr(not(feature = "role_proxy"), allow(dead_code))]
+pub(crate) const FRAME_TRACE_CHANNEL_NAME: &str = "FrameTrace";
+/// Marks a frame received by the Target program in the frame trace.
+#[cfg_attr(
+    not(any(feature = "role_proxy", feature = "frame_trace")),
+    allow(dead_code)
+)]
+pub(crate) const FRAME_TRACE_DOWNSTREAM: u8 = 0;
+/// Marks a frame sent by the Target program in the frame trace.
+#[cfg_attr(
+    not(any(feature = "role_proxy", feature = "frame_trace")),
+    allow(dead_code)
+)]
+pub(crate) const FRAME_TRACE_UPSTREAM: u8 = 1;
+
+/// The name of the RTT up channel through which the Target program sends
+/// bulk data when the `rtt_bulk_channel` feature is enabled.
+///
+/// After sending [`UpstreamMessage::SampleChunk`],
+/// [`UpstreamMessage::SampleTimestamps`], or
+/// [`UpstreamMessage::MeasurementComplete`], the Target program sends all
+/// messages through this channel instead of the terminal channel until it
+/// receives a message. The Proxy program restores the order of the messages
+/// by draining the terminal channel before passing on the data found in this
+/// channel. This works because the terminal channel stays silent until the
+/// Proxy program replies to `MeasurementComplete`.
+#[cfg_attr(not(feature = "role_proxy"), allow(dead_code))]
+pub(crate) const BULK_CHANNEL_NAME: &str = "Bulk";
+
+/// The version of the protocol. Both sides should use exactly the same version
+/// of `farcri`, so this is only used to detect a skew between the Proxy program
+/// and a prebuilt Target program.
+///
+/// Bump this in every change to the encoding of the messages (e.g., a new
+/// field or variant) so that a mismatched pair is rejected at the greeting
+/// instead of failing to decode a message in the middle of a run.
+pub const PROTOCOL_VERSION: u32 = 19;
+
+/// The maximum number of [`TargetConfig::name_filters`].
+pub(crate) const MAX_NAME_FILTERS: usize = 4;
+/// The maximum length of each element of [`TargetConfig::name_filters`] in
+/// bytes.
+pub(crate) const MAX_NAME_FILTER_LEN: usize = 32;
+/// The maximum number of [`TargetConfig::frequencies`].
+pub(crate) const MAX_FREQUENCIES: usize = 8;
+/// The maximum number of [`UpstreamMessage::BenchmarkTags::tags`].
+pub(crate) const MAX_TAGS: usize = 4;
+/// The maximum length of the key and the value of each element of
+/// [`UpstreamMessage::BenchmarkTags::tags`] in bytes.
+pub(crate) const MAX_TAG_LEN: usize = 32;
+
+/// The name of the symbol holding the image stamp in the Target program. The
+/// image stamp identifies the build of the Target program and is embedded at
+/// build time through the environment variable of the same name.
+#[cfg_attr(not(feature = "role_proxy"), allow(dead_code))]
+pub(crate) const IMAGE_STAMP_SYMBOL: &str = "FARCRI_IMAGE_STAMP";
+/// The size of the image stamp. Shorter stamps are padded with zeros.
+pub(crate) const IMAGE_STAMP_LEN: usize = 32;
+
+/// The environment variable through which the Proxy program passes the
+/// build descriptor to the Target program. The build descriptor describes the
+/// build configuration of the Target program (e.g., the Cargo features) and
+/// is embedded in the ELF note [`BUILD_NOTE_SECTION`].
+#[cfg_attr(not(feature = "role_proxy"), allow(dead_code))]
+pub(crate) const BUILD_DESCRIPTOR_ENV: &str = "FARCRI_BUILD_DESCRIPTOR";
+/// The size of the build descriptor. Shorter descriptors are padded with
+/// zeros.
+pub(crate) const BUILD_DESCRIPTOR_LEN: usize = 512;
+/// The name of the section containing the note that holds the build
+/// descriptor
+#[cfg_attr(not(feature = "role_proxy"), allow(dead_code))]
+pub(crate) const BUILD_NOTE_SECTION: &str = ".note.farcri";
+/// The owner name of the note that holds the build descriptor
+pub(crate) const BUILD_NOTE_NAME: &str = "FarCri";
+/// The type of the note that holds the build descriptor
+pub(crate) const BUILD_NOTE_TYPE: u32 = 1;
+
+/// The name of the link section containing the benchmark ID candidates
+/// (`crate::idtable::StaticId`) for `--farcri-list-static`
+#[cfg_attr(not(feature = "role_proxy"), allow(dead_code))]
+pub(crate) const STATIC_IDS_SECTION: &str = "farcri_ids";
+/// A [`STATIC_IDS_SECTION`] entry that doesn't represent anything
+pub(crate) const STATIC_ID_KIND_SENTINEL: usize = 0;
+/// A [`STATIC_IDS_SECTION`] entry containing an exact benchmark ID
+pub(crate) const STATIC_ID_KIND_BENCHMARK: usize = 1;
+/// A [`STATIC_IDS_SECTION`] entry containing the path of a function passed
+/// to `criterion_group!`
+pub(crate) const STATIC_ID_KIND_FUNCTION: usize = 2;
+
+/// The environment variable through which the Proxy program passes the
+/// address to connect to (e.g., `127.0.0.1:41234`) to a Target program built
+/// with `target_std`.
+#[cfg_attr(
+    not(any(feature = "role_proxy", feature = "target_std")),
+    allow(dead_code)
+)]
+pub(crate) const STD_LINK_ADDR_ENV: &str = "FARCRI_LINK_ADDR";
 
 /// A message sent from the Proxy program to the Target program.
 #[derive(Debug, Deserialize)]
-#[cfg_attr(feature = "role_proxy", derive(Serialize))]
+#[cfg_attr(feature = "role_proxy", derive(Serialize, Clone))]
 pub(crate) enum DownstreamMessage<Str> {
     Greeting {
-        /// A dummy message to use the `Str` generic parameter
-        _unused: Str,
-        mode: Mode,
+        config: TargetConfig<Str>,
     },
     /// Terminate the Target program's listening loop and causes it to proceed
     /// to the next task.
     Continue,
     /// Response to [`UpstreamMessage::GetInstant`].
     Instant(Instant),
+    /// Requests the Target program to send `payload` back by
+    /// [`UpstreamMessage::EchoReply`]. Only sent when requested by
+    /// [`FEATURE_LINK_CHECK`], until [`Self::Continue`] is sent.
+    EchoRequest {
+        /// ASCII characters (which don't need SLIP escape sequences), at
+        /// most [`max_echo_payload_len`] bytes long
+        payload: Str,
+    },
+    /// Sent instead of [`Self::Continue`] in reply to
+    /// [`UpstreamMessage::MeasurementComplete`] or
+    /// [`UpstreamMessage::FinishedBenchmarkGroup`] to make the Target program
+    /// skip all remaining benchmarks (with [`SkipReason::Budget`]). Used by
+    /// `--farcri-suite-time-budget`.
+    SkipRemaining,
 }
 
-#[derive(Debug, Deserialize, Copy, Clone)]
-#[cfg_attr(feature = "role_proxy", derive(Serialize))]
+/// The configuration of the Target program, sent by
+/// [`DownstreamMessage::Greeting`].
+///
+/// `Str` can be `String` or `&str`.
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "role_proxy", derive(Serialize, Clone))]
+pub(crate) struct TargetConfig<Str> {
+    /// Must be equal to [`PROTOCOL_VERSION`].
+    pub(crate) protocol_version: u32,
+    pub(crate) mode: Mode,
+    pub(crate) bench_config_overrides: BenchmarkConfigOverrides,
+    /// Only run the benchmarks whose IDs contain any of these strings. Run
+    /// all benchmarks if empty.
+    pub(crate) name_filters: ArrayVec<Str, MAX_NAME_FILTERS>,
+    /// Measure each benchmark at each of these clock frequencies (in hertz)
+    /// by calling the frequency setter registered by the benchmark code.
+    /// Measure only once at the current frequency if empty or if no setter
+    /// is registered.
+    pub(crate) frequencies: ArrayVec<u32, MAX_FREQUENCIES>,
+    /// Skip this many benchmarks (among those matching `name_filters`)
+    /// before running any. Used to resume a run after the Target program
+    /// faulted.
+    pub(crate) skip_benchmarks: u32,
+    /// Optional features, a combination of `FEATURE_*` bits. Unknown bits are
+    /// ignored.
+    pub(crate) feature_bits: u32,
+}
+
+/// A bit in [`TargetConfig::feature_bits`]. Requests the Target program to
+/// measure the overhead of `Bencher::iter`'s loop and report it in
+/// [`UpstreamMessage::GreetingReply`].
+pub(crate) const FEATURE_LOOP_OVERHEAD: u32 = 1 << 0;
+
+/// A bit in [`TargetConfig::feature_bits`]. Requests the Target program to
+/// answer [`DownstreamMessage::EchoRequest`]s after sending
+/// [`UpstreamMessage::GreetingReply`], until it receives
+/// [`DownstreamMessage::Continue`].
+pub(crate) const FEATURE_LINK_CHECK: u32 = 1 << 1;
+
+/// A bit in [`TargetConfig::feature_bits`]. Requests the Target program to
+/// collect as many samples as requested even if they don't fit in its sample
+/// buffer, sending them in [`UpstreamMessage::SampleChunk`]s instead of
+/// clamping the sample size.
+pub(crate) const FEATURE_STREAMED_SAMPLES: u32 = 1 << 2;
+
+/// The maximum number of bytes taken by [`DownstreamMessage::EchoRequest`]
+/// and [`UpstreamMessage::EchoReply`] in addition to the payload, including
+/// SLIP escape sequences for a payload consisting of ASCII characters
+#[cfg_attr(not(feature = "role_proxy"), allow(dead_code))]
+const ECHO_OVERHEAD: usize = 32;
+
+/// Get the maximum length of the payload of
+/// [`DownstreamMessage::EchoRequest`] for a Target program reporting
+/// `link_buffer_len` in [`TargetCapacities`]. The Target program needs to
+/// hold the payload and the encoded [`UpstreamMessage::EchoReply`] at the same
+/// time.
+#[cfg_attr(not(feature = "role_proxy"), allow(dead_code))]
+pub(crate) fn max_echo_payload_len(link_buffer_len: usize) -> usize {
+    link_buffer_len.saturating_sub(ECHO_OVERHEAD) / 2
+}
+
+/// The maximum length of [`UpstreamMessage::Panicked::message`] in bytes.
+/// Longer messages are truncated.
+#[cfg(any(feature = "panic_reset", all(test, feature = "role_proxy")))]
+pub(crate) const MAX_PANIC_MESSAGE_LEN: usize = 128;
+
+/// The size of the buffer the panic handler needs to encode
+/// [`UpstreamMessage::Panicked`], including SLIP escape sequences for any
+/// message
+#[cfg(any(feature = "panic_reset", all(test, feature = "role_proxy")))]
+pub(crate) const PANIC_REPORT_BUF_LEN: usize = (MAX_PANIC_MESSAGE_LEN + 32) * 2 + 1;
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
 /// Enum representing the execution mode.
 pub(crate) enum Mode {
     /// Run benchmarks normally.
     Benchmark,
     /// Run benchmarks once to verify that they work, but otherwise do not measure them.
     Test,
+    /// List benchmarks without running them.
+    List,
+    /// Run benchmarks for [`BenchmarkConfig::measurement_time`] without
+    /// measuring them, so that an external profiler can observe them.
+    Profile,
 }
 
 /// A message sent from the Target program to the Proxy program. This is sort
@@ -46,6 +303,16 @@ pub(crate) enum Mode {
 #[derive(Debug, Serialize)]
 #[cfg_attr(feature = "role_proxy", derive(Deserialize))]
 pub(crate) enum UpstreamMessage<Str, Values> {
+    /// Response to [`DownstreamMessage::Greeting`]. Not in `IncomingMessage`.
+    GreetingReply {
+        /// The image stamp of the Target program (see [`IMAGE_STAMP_SYMBOL`])
+        image_stamp: Str,
+        /// [`TargetConfig::mode`] as understood by the Target program
+        mode: Mode,
+        /// Only present if requested by [`FEATURE_LOOP_OVERHEAD`].
+        loop_overhead: Option<LoopOverhead>,
+        capacities: TargetCapacities,
+    },
     BeginningBenchmarkGroup {
         group: Str,
     },
@@ -55,15 +322,112 @@ pub(crate) enum UpstreamMessage<Str, Values> {
     },
     SkippingBenchmark {
         id: RawBenchmarkId<Str>,
+        reason: SkipReason,
+    },
+    /// Sent before [`Self::RunningBenchmark`] (or [`Self::ListedBenchmark`]
+    /// in [`Mode::List`]) if components of the benchmark's ID didn't fit in
+    /// the Target program's buffers (see [`TargetCapacities`]) and were
+    /// truncated. `id` is the truncated ID. The lengths are those of the
+    /// components before truncation in bytes (zero if absent). Not in
+    /// `IncomingMessage`.
+    IdTruncated {
+        id: RawBenchmarkId<Str>,
+        group_id_len: u32,
+        function_id_len: u32,
+        value_str_len: u32,
+    },
+    /// Sent before running each benchmark in any mode other than
+    /// [`Mode::List`]. Lets the Proxy program know which benchmark was
+    /// running if the Target program faults. Not in `IncomingMessage`.
+    RunningBenchmark {
+        id: RawBenchmarkId<Str>,
+    },
+    /// Sent before [`Self::BeginningBenchmark`] when the Target program has
+    /// switched to one of [`TargetConfig::frequencies`]. Applies to the
+    /// following benchmark. Not in `IncomingMessage`.
+    FrequencyChanged {
+        /// The requested frequency in hertz
+        requested: u32,
+        /// The frequency reported by the frequency setter in hertz
+        effective: u32,
+    },
+    /// Sent before [`Self::BeginningBenchmark`] if tags are set by
+    /// `BenchmarkGroup::tags`. Applies to the following benchmark. Not in
+    /// `IncomingMessage`.
+    BenchmarkTags {
+        /// Key-value pairs with unique keys
+        tags: ArrayVec<(Str, Str), MAX_TAGS>,
+    },
+    /// Sent by `Criterion::value_format`. Applies to the following
+    /// benchmarks. Not in `IncomingMessage`.
+    ValueFormatChanged {
+        format: ValueFormat,
+    },
+    /// Sent in [`Mode::List`] for each benchmark instead of running it. Not
+    /// in `IncomingMessage`.
+    ListedBenchmark {
+        id: RawBenchmarkId<Str>,
     },
     Warmup {
         warm_up_goal_duration: Duration,
     },
+    /// Sent between the passes of a warm-up at most once per
+    /// [`WARMUP_PROGRESS_INTERVAL`] of warm-up time, outside the timed
+    /// regions. Not in `IncomingMessage`.
+    WarmupProgress {
+        /// The number of iterations run so far
+        iters_so_far: u64,
+        /// The measured time of the iterations run so far
+        elapsed: Duration,
+    },
+    /// Sent by the routine passed to `Bencher::iter_custom_with_progress`
+    /// while it runs. Not in `IncomingMessage`.
+    Progress {
+        /// The number of iterations completed so far
+        iters_done: u64,
+        /// The number of iterations the routine was asked to run
+        iters: u64,
+    },
+    /// The requested sample size exceeds the capacity of the Target program's
+    /// sample buffer and was clamped. Sent before `MeasurementStart`. Not sent
+    /// if requested by [`FEATURE_STREAMED_SAMPLES`]. Not in
+    /// `IncomingMessage`.
+    SampleSizeClamped {
+        requested: usize,
+        effective: usize,
+    },
     MeasurementStart {
         warm_up_iter_count: u64,
         warm_up_duration: Duration,
         num_samples: usize,
         num_iters: u64,
+        /// The round trips of the `GetInstant`s sent during the warm-up
+        warm_up_round_trips: RoundTrips,
+    },
+    /// The counter values at the start of each sample, relative to the start
+    /// of the first sample. Parallel to the `values` of the following
+    /// `MeasurementComplete`. Only sent if requested by
+    /// `BenchmarkGroup::record_sample_timestamps`. Not in `IncomingMessage`.
+    ///
+    /// This is a separate message because both arrays might not fit in the
+    /// Target program's link buffer at once.
+    SampleTimestamps {
+        timestamps: Values,
+    },
+    /// The samples collected so far, preceding those in the `values` of the
+    /// following `MeasurementComplete`. Sent between samples when the sample
+    /// buffer fills up, only if requested by [`FEATURE_STREAMED_SAMPLES`]. Not
+    /// in `IncomingMessage`.
+    SampleChunk {
+        values: Values,
+    },
+    /// Sent instead of [`Self::MeasurementComplete`] if the routine passed
+    /// to `Bencher::iter_result` returned `Err` during the warm-up or the
+    /// measurement, which was then aborted. `message` is the error formatted
+    /// with `Debug`, truncated to 128 bytes. Not in `IncomingMessage`.
+    BenchmarkFailed {
+        id: RawBenchmarkId<Str>,
+        message: Str,
     },
     MeasurementComplete {
         num_iters_per_sample: u64,
@@ -71,6 +435,18 @@ pub(crate) enum UpstreamMessage<Str, Values> {
         benchmark_config: BenchmarkConfig,
         // sampling_method: always `Flat`
     },
+    /// Something compromised the values of the following
+    /// `MeasurementComplete`. Not in `IncomingMessage`.
+    MeasurementWarning {
+        kind: MeasurementWarningKind,
+    },
+
+    /// A benchmark function run in test mode reported a failure through
+    /// `TestContext`. Not in `IncomingMessage`.
+    TestFailed {
+        id: RawBenchmarkId<Str>,
+        message: Str,
+    },
 
     /// Indicates there are no more benchmark tests remaining. Not in
     /// `IncomingMessage`.
@@ -78,6 +454,104 @@ pub(crate) enum UpstreamMessage<Str, Values> {
 
     /// Queries the current time Not in `IncomingMessage`.
     GetInstant,
+
+    /// Response to [`DownstreamMessage::EchoRequest`]. Not in
+    /// `IncomingMessage`.
+    EchoReply {
+        payload: Str,
+    },
+
+    /// The Target program panicked and is about to reset itself. Only sent
+    /// by a Target program built with the `panic_reset` feature. May be sent
+    /// at any time regardless of the turn-taking rules. Not in
+    /// `IncomingMessage`.
+    #[cfg_attr(
+        not(any(feature = "panic_reset", feature = "role_proxy")),
+        allow(dead_code)
+    )]
+    Panicked {
+        message: Str,
+    },
+}
+
+/// The minimum interval between [`UpstreamMessage::WarmupProgress`]s,
+/// measured in warm-up time
+pub(crate) const WARMUP_PROGRESS_INTERVAL: Duration = Duration::from_nanos(250_000_000);
+
+/// The reason of [`UpstreamMessage::SkippingBenchmark`]
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "role_proxy", derive(Deserialize))]
+pub(crate) enum SkipReason {
+    /// The benchmark doesn't match [`TargetConfig::name_filters`].
+    Filter,
+    /// The benchmark is skipped by [`TargetConfig::skip_benchmarks`].
+    Resume,
+    /// The Proxy program sent [`DownstreamMessage::SkipRemaining`].
+    Budget,
+}
+
+/// The cause of [`UpstreamMessage::MeasurementWarning`]
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "role_proxy", derive(Deserialize))]
+pub(crate) enum MeasurementWarningKind {
+    /// The time source was found reprogrammed between samples (and was
+    /// re-initialized). On Cortex-M, this means something other than the
+    /// Target program wrote to SysTick's registers.
+    TimerTampered,
+    /// The warm-up reached `BenchmarkGroup::max_warm_up_doublings` before
+    /// running for the warm-up time. The time source is probably stopped.
+    WarmUpCapped,
+}
+
+/// Round trips of [`UpstreamMessage::GetInstant`] (from sending it to
+/// receiving [`DownstreamMessage::Instant`]) measured by the Target
+/// program's time source
+#[derive(Debug, Default, Serialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "role_proxy", derive(Deserialize))]
+pub(crate) struct RoundTrips {
+    pub(crate) count: u32,
+    /// The shortest round trip
+    pub(crate) min: u64,
+    /// The sum of the round trips
+    pub(crate) total: u64,
+}
+
+impl RoundTrips {
+    pub(crate) fn record(&mut self, value: u64) {
+        self.min = if self.count == 0 {
+            value
+        } else {
+            self.min.min(value)
+        };
+        self.count = self.count.saturating_add(1);
+        self.total = self.total.saturating_add(value);
+    }
+}
+
+/// The measured value of `Bencher::iter` with a no-op routine, reported by
+/// [`UpstreamMessage::GreetingReply`].
+#[derive(Debug, Serialize, Clone, Copy)]
+#[cfg_attr(feature = "role_proxy", derive(Deserialize))]
+pub(crate) struct LoopOverhead {
+    pub(crate) iters: u64,
+    pub(crate) value: u64,
+}
+
+/// The sizes of the Target program's fixed-size buffers, reported by
+/// [`UpstreamMessage::GreetingReply`]. They depend on the build
+/// configuration (e.g., the `large_samples` feature).
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "role_proxy", derive(Deserialize))]
+pub(crate) struct TargetCapacities {
+    /// The size of the buffer holding an encoded message in bytes
+    pub(crate) link_buffer_len: u32,
+    /// The maximum number of samples per measurement
+    pub(crate) max_samples: u32,
+    /// The maximum lengths of [`RawBenchmarkId`]'s components in bytes.
+    /// Longer ones are truncated.
+    pub(crate) max_group_id_len: u32,
+    pub(crate) max_function_id_len: u32,
+    pub(crate) max_value_str_len: u32,
 }
 
 #[derive(Debug, Serialize, Copy, Clone)]
@@ -116,11 +590,43 @@ impl<Str: Borrow<str>> fmt::Display for RawBenchmarkId<Str> {
     }
 }
 
+impl<Str: Borrow<str>> RawBenchmarkId<Str> {
+    /// Check if the string representation of `self` (as produced by
+    /// `Display`) contains `needle`.
+    pub(crate) fn contains(&self, needle: &str) -> bool {
+        let parts = [
+            Some(self.group_id.borrow()),
+            self.function_id.as_ref().map(Str::borrow),
+            self.value_str.as_ref().map(Str::borrow),
+        ];
+
+        // Avoid formatting the whole string, which requires a buffer
+        let mut haystack = (parts.iter())
+            .filter_map(|&x| x)
+            .filter(|x| !x.is_empty())
+            .enumerate()
+            .flat_map(|(i, part)| {
+                let separator = if i == 0 { "" } else { "/" };
+                separator.bytes().chain(part.bytes())
+            });
+
+        loop {
+            if haystack.clone().take(needle.len()).eq(needle.bytes()) {
+                return true;
+            }
+            if haystack.next().is_none() {
+                return false;
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
 #[cfg_attr(feature = "role_proxy", derive(Deserialize))]
 pub(crate) enum Throughput {
     Bytes(u64),
     Elements(u64),
+    Custom { amount: u64, unit: ArrayString<16> },
 }
 
 impl From<super::Throughput> for Throughput {
@@ -129,6 +635,25 @@ impl From<super::Throughput> for Throughput {
         match x {
             crate::Throughput::Bytes(x) => Self::Bytes(x),
             crate::Throughput::Elements(x) => Self::Elements(x),
+            crate::Throughput::Custom { amount, unit } => Self::Custom { amount, unit },
+        }
+    }
+}
+
+/// The wire form of `crate::ValueFormat`
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "role_proxy", derive(Deserialize))]
+pub(crate) enum ValueFormat {
+    Native,
+    PerUnit { amount: u64, unit: ArrayString<16> },
+}
+
+impl From<super::ValueFormat> for ValueFormat {
+    #[inline]
+    fn from(x: super::ValueFormat) -> Self {
+        match x {
+            crate::ValueFormat::Native => Self::Native,
+            crate::ValueFormat::PerUnit { amount, unit } => Self::PerUnit { amount, unit },
         }
     }
 }
@@ -145,6 +670,33 @@ pub struct BenchmarkConfig {
     pub warm_up_time: Duration,
 }
 
+/// Overrides the fields of [`BenchmarkConfig`] that are `Some(_)`.
+#[derive(Debug, Default, Deserialize, Copy, Clone)]
+#[cfg_attr(feature = "role_proxy", derive(Serialize))]
+pub(crate) struct BenchmarkConfigOverrides {
+    pub(crate) measurement_time: Option<Duration>,
+    pub(crate) nresamples: Option<usize>,
+    pub(crate) sample_size: Option<usize>,
+    pub(crate) warm_up_time: Option<Duration>,
+}
+
+impl BenchmarkConfigOverrides {
+    pub(crate) fn apply(&self, config: &mut BenchmarkConfig) {
+        if let Some(x) = self.measurement_time {
+            config.measurement_time = x;
+        }
+        if let Some(x) = self.nresamples {
+            config.nresamples = x;
+        }
+        if let Some(x) = self.sample_size {
+            config.sample_size = x;
+        }
+        if let Some(x) = self.warm_up_time {
+            config.warm_up_time = x;
+        }
+    }
+}
+
 impl Default for BenchmarkConfig {
     #[inline]
     fn default() -> Self {
@@ -156,3 +708,248 @@ impl Default for BenchmarkConfig {
         }
     }
 }
+
+#[cfg(all(test, feature = "role_proxy"))]
+mod vectors;
+
+#[cfg(all(test, feature = "role_proxy"))]
+mod tests {
+    use super::*;
+
+    /// Encode `msg` in the same way as `ProxyLink::send` and decode it in the
+    /// same way as `TargetLink::recv`.
+    fn upstream_round_trip(
+        msg: &UpstreamMessage<&str, &[u64]>,
+    ) -> UpstreamMessage<String, Vec<u64>> {
+        let mut buf = [0u8; crate::bencher::LINK_BUFFER_LEN];
+        let writer = serde_cbor::ser::SliceWrite::new(&mut buf[..]);
+        let mut ser = serde_cbor::ser::Serializer::new(writer);
+        msg.serialize(&mut ser).unwrap();
+        let num_bytes = ser.into_inner().bytes_written();
+
+        serde_cbor::from_slice(&buf[..num_bytes]).unwrap()
+    }
+
+    fn throughput_round_trip(throughput: Throughput) -> Throughput {
+        let msg = UpstreamMessage::BeginningBenchmark {
+            id: RawBenchmarkId {
+                group_id: "group",
+                function_id: Some("function"),
+                value_str: None,
+                throughput: Some(throughput),
+            },
+        };
+        match upstream_round_trip(&msg) {
+            UpstreamMessage::BeginningBenchmark { id } => {
+                assert_eq!(id.group_id, "group");
+                assert_eq!(id.function_id.as_deref(), Some("function"));
+                assert_eq!(id.value_str, None);
+                id.throughput.unwrap()
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    fn test_config<Str>(name_filters: &[Str]) -> TargetConfig<Str>
+    where
+        Str: Clone,
+    {
+        TargetConfig {
+            protocol_version: PROTOCOL_VERSION,
+            mode: Mode::Benchmark,
+            bench_config_overrides: BenchmarkConfigOverrides {
+                sample_size: Some(20),
+                warm_up_time: Some(Duration::from_nanos(1_000_000_000)),
+                ..Default::default()
+            },
+            name_filters: name_filters.iter().cloned().collect(),
+            frequencies: (0..MAX_FREQUENCIES as u32).map(|i| u32::MAX - i).collect(),
+            skip_benchmarks: 42,
+            feature_bits: 0x8000_0001,
+        }
+    }
+
+    fn check_config<Str: Borrow<str>>(config: &TargetConfig<Str>, name_filters: &[&str]) {
+        assert_eq!(config.protocol_version, PROTOCOL_VERSION);
+        assert!(matches!(config.mode, Mode::Benchmark));
+        assert_eq!(config.bench_config_overrides.sample_size, Some(20));
+        assert_eq!(config.bench_config_overrides.nresamples, None);
+        assert_eq!(config.bench_config_overrides.measurement_time, None);
+        assert_eq!(
+            config.bench_config_overrides.warm_up_time,
+            Some(Duration::from_nanos(1_000_000_000))
+        );
+        assert_eq!(
+            config
+                .name_filters
+                .iter()
+                .map(|x| x.borrow())
+                .collect::<Vec<&str>>(),
+            name_filters
+        );
+        assert_eq!(config.frequencies.len(), MAX_FREQUENCIES);
+        assert_eq!(config.frequencies[1], u32::MAX - 1);
+        assert_eq!(config.skip_benchmarks, 42);
+        assert_eq!(config.feature_bits, 0x8000_0001);
+    }
+
+    const NAME_FILTERS: &[&str] = &["sort", "", "0123456789abcdef0123456789abcdef"];
+
+    /// Encode `DownstreamMessage<&str>` and decode it in the same way as
+    /// `ProxyLink::recv`
+    #[test]
+    fn greeting_round_trip_str() {
+        let msg = DownstreamMessage::Greeting {
+            config: test_config(NAME_FILTERS),
+        };
+        let mut bytes = serde_cbor::to_vec(&msg).unwrap();
+
+        let decoded: DownstreamMessage<&str> = serde_cbor::de::from_mut_slice(&mut bytes).unwrap();
+        match decoded {
+            DownstreamMessage::Greeting { config } => check_config(&config, NAME_FILTERS),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    /// Encode `DownstreamMessage<String>` in the same way as `TargetLink::send`
+    /// and decode it into `DownstreamMessage<String>`
+    #[test]
+    fn greeting_round_trip_string() {
+        let name_filters: Vec<String> = NAME_FILTERS.iter().map(|&x| x.to_owned()).collect();
+        let msg = DownstreamMessage::Greeting {
+            config: test_config(&name_filters),
+        };
+        let bytes = serde_cbor::to_vec(&msg).unwrap();
+
+        match serde_cbor::from_slice::<DownstreamMessage<String>>(&bytes).unwrap() {
+            DownstreamMessage::Greeting { config } => check_config(&config, NAME_FILTERS),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn greeting_fits_in_link_buffer() {
+        let name_filters = vec!["x".repeat(MAX_NAME_FILTER_LEN); MAX_NAME_FILTERS];
+        let msg = DownstreamMessage::Greeting {
+            config: test_config(&name_filters[..]),
+        };
+        let bytes = serde_cbor::to_vec(&msg).unwrap();
+
+        // `WorkingArea::link_buffer` is at least 1024 bytes long. Leave some room
+        // for SLIP escape sequences.
+        assert!(bytes.len() < 512, "{}", bytes.len());
+    }
+
+    #[test]
+    fn sample_timestamps_fit_in_link_buffer() {
+        // `upstream_round_trip` uses a buffer as large as
+        // `WorkingArea::link_buffer` and panics if the message doesn't fit.
+        // Assume the measurement doesn't take longer than 2³² cycles.
+        let timestamps: Vec<u64> = (0..crate::bencher::MAX_SAMPLES as u64)
+            .map(|i| u64::from(u32::MAX) - i)
+            .collect();
+        let msg = UpstreamMessage::SampleTimestamps {
+            timestamps: &timestamps[..],
+        };
+        match upstream_round_trip(&msg) {
+            UpstreamMessage::SampleTimestamps {
+                timestamps: decoded,
+            } => assert_eq!(decoded, timestamps),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tags_fit_in_link_buffer() {
+        let key = "k".repeat(MAX_TAG_LEN);
+        let value = "v".repeat(MAX_TAG_LEN);
+        let msg = UpstreamMessage::<_, &[u64]>::BenchmarkTags {
+            tags: (0..MAX_TAGS).map(|_| (&key[..], &value[..])).collect(),
+        };
+        let bytes = serde_cbor::to_vec(&msg).unwrap();
+
+        // Leave room for SLIP escape sequences
+        assert!(
+            bytes.len() < crate::bencher::LINK_BUFFER_LEN / 2,
+            "{}",
+            bytes.len()
+        );
+
+        match upstream_round_trip(&msg) {
+            UpstreamMessage::BenchmarkTags { tags } => {
+                assert_eq!(tags.len(), MAX_TAGS);
+                assert_eq!(tags[0], (key.clone(), value.clone()));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn echo_fits_in_link_buffer() {
+        let len = max_echo_payload_len(crate::bencher::LINK_BUFFER_LEN);
+        let payload = "x".repeat(len);
+
+        // Both messages are followed by `SLIP_FRAME_END`
+        let request = serde_cbor::to_vec(&DownstreamMessage::EchoRequest {
+            payload: &payload[..],
+        })
+        .unwrap();
+        assert!(
+            request.len() < crate::bencher::LINK_BUFFER_LEN,
+            "{}",
+            request.len()
+        );
+
+        // `ProxyLink::answer_echo_requests` encodes the reply in the part of
+        // the link buffer not occupied by the payload
+        let reply = serde_cbor::to_vec(&UpstreamMessage::<_, &[u64]>::EchoReply {
+            payload: &payload[..],
+        })
+        .unwrap();
+        assert!(
+            reply.len() < crate::bencher::LINK_BUFFER_LEN - len,
+            "{}",
+            reply.len()
+        );
+    }
+
+    #[test]
+    fn benchmark_id_contains() {
+        let id = RawBenchmarkId {
+            group_id: "sort",
+            function_id: Some("quick"),
+            value_str: Some("100"),
+            throughput: None,
+        };
+        for &needle in &["", "sort", "sort/quick/100", "t/q", "ck/1", "100"] {
+            assert!(id.contains(needle), "{:?}", needle);
+        }
+        for &needle in &["sort/quick/1000", "sortquick", "//", "merge"] {
+            assert!(!id.contains(needle), "{:?}", needle);
+        }
+
+        let id = RawBenchmarkId {
+            group_id: "",
+            function_id: Some("noop"),
+            value_str: None,
+            throughput: None,
+        };
+        assert!(id.contains("noop"));
+        assert!(!id.contains("/noop"));
+    }
+
+    #[test]
+    fn throughput_bytes_elements_round_trip() {
+        for &throughput in &[Throughput::Bytes(42), Throughput::Elements(u64::MAX)] {
+            assert_eq!(throughput_round_trip(throughput), throughput);
+        }
+    }
+
+    #[test]
+    fn throughput_custom_round_trip() {
+        for &unit in &["bits", "", "0123456789abcdef", "パケット"] {
+            let throughput = Throughput::from(crate::Throughput::custom(1500, unit));
+            assert_eq!(throughput_round_trip(throughput), throughput);
+        }
+    }
+}
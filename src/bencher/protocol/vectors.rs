@@ -0,0 +1,426 @@
+//! Golden test of the wire encoding (`doc/protocol_vectors.cbor.txt`)
+//!
+//! The file lists the handshake byte sequences, the SLIP framing, and the
+//! CBOR encoding of every message variant with representative field values,
+//! for those implementing the Target program in another language. The test
+//! fails if the encodings drift from the committed file. After an
+//! intentional protocol change, bump `PROTOCOL_VERSION` and regenerate the
+//! file by running the test with `FARCRI_BLESS_PROTOCOL_VECTORS=1`.
+use arrayvec::ArrayString;
+use serde::Serialize;
+use std::{fmt::Write, path::Path};
+
+use super::*;
+
+const PATH: &str = "doc/protocol_vectors.cbor.txt";
+
+type Upstream = UpstreamMessage<&'static str, &'static [u64]>;
+type Downstream = DownstreamMessage<&'static str>;
+
+fn downstream_name(msg: &Downstream) -> &'static str {
+    // Exhaustive so that a new variant can't be left out of the file
+    match msg {
+        DownstreamMessage::Greeting { .. } => "Greeting",
+        DownstreamMessage::Continue => "Continue",
+        DownstreamMessage::Instant(_) => "Instant",
+        DownstreamMessage::EchoRequest { .. } => "EchoRequest",
+        DownstreamMessage::SkipRemaining => "SkipRemaining",
+    }
+}
+
+fn upstream_name(msg: &Upstream) -> &'static str {
+    match msg {
+        UpstreamMessage::GreetingReply { .. } => "GreetingReply",
+        UpstreamMessage::BeginningBenchmarkGroup { .. } => "BeginningBenchmarkGroup",
+        UpstreamMessage::FinishedBenchmarkGroup => "FinishedBenchmarkGroup",
+        UpstreamMessage::BeginningBenchmark { .. } => "BeginningBenchmark",
+        UpstreamMessage::SkippingBenchmark { .. } => "SkippingBenchmark",
+        UpstreamMessage::IdTruncated { .. } => "IdTruncated",
+        UpstreamMessage::RunningBenchmark { .. } => "RunningBenchmark",
+        UpstreamMessage::FrequencyChanged { .. } => "FrequencyChanged",
+        UpstreamMessage::BenchmarkTags { .. } => "BenchmarkTags",
+        UpstreamMessage::ValueFormatChanged { .. } => "ValueFormatChanged",
+        UpstreamMessage::ListedBenchmark { .. } => "ListedBenchmark",
+        UpstreamMessage::Warmup { .. } => "Warmup",
+        UpstreamMessage::WarmupProgress { .. } => "WarmupProgress",
+        UpstreamMessage::Progress { .. } => "Progress",
+        UpstreamMessage::SampleSizeClamped { .. } => "SampleSizeClamped",
+        UpstreamMessage::MeasurementStart { .. } => "MeasurementStart",
+        UpstreamMessage::SampleTimestamps { .. } => "SampleTimestamps",
+        UpstreamMessage::SampleChunk { .. } => "SampleChunk",
+        UpstreamMessage::BenchmarkFailed { .. } => "BenchmarkFailed",
+        UpstreamMessage::MeasurementComplete { .. } => "MeasurementComplete",
+        UpstreamMessage::MeasurementWarning { .. } => "MeasurementWarning",
+        UpstreamMessage::TestFailed { .. } => "TestFailed",
+        UpstreamMessage::End => "End",
+        UpstreamMessage::GetInstant => "GetInstant",
+        UpstreamMessage::EchoReply { .. } => "EchoReply",
+        UpstreamMessage::Panicked { .. } => "Panicked",
+    }
+}
+
+/// `(label, message)`. The label distinguishes multiple examples of a
+/// variant.
+fn downstream_examples() -> Vec<(&'static str, Downstream)> {
+    vec![
+        (
+            "",
+            DownstreamMessage::Greeting {
+                config: TargetConfig {
+                    protocol_version: PROTOCOL_VERSION,
+                    mode: Mode::Benchmark,
+                    bench_config_overrides: BenchmarkConfigOverrides {
+                        sample_size: Some(20),
+                        warm_up_time: Some(Duration::from_nanos(1_000_000_000)),
+                        ..Default::default()
+                    },
+                    name_filters: ["sort"].iter().cloned().collect(),
+                    frequencies: [84_000_000].iter().cloned().collect(),
+                    skip_benchmarks: 0,
+                    feature_bits: FEATURE_LOOP_OVERHEAD | FEATURE_LINK_CHECK,
+                },
+            },
+        ),
+        (
+            "Mode::Test",
+            DownstreamMessage::Greeting {
+                config: TargetConfig {
+                    protocol_version: PROTOCOL_VERSION,
+                    mode: Mode::Test,
+                    bench_config_overrides: Default::default(),
+                    name_filters: Default::default(),
+                    frequencies: Default::default(),
+                    skip_benchmarks: 3,
+                    feature_bits: 0,
+                },
+            },
+        ),
+        ("", DownstreamMessage::Continue),
+        (
+            "",
+            DownstreamMessage::Instant(Instant::from_nanos(1_234_567_890)),
+        ),
+        ("", DownstreamMessage::EchoRequest { payload: "0123" }),
+        ("", DownstreamMessage::SkipRemaining),
+    ]
+}
+
+fn upstream_examples() -> Vec<(&'static str, Upstream)> {
+    let id = |throughput| RawBenchmarkId {
+        group_id: "sort",
+        function_id: Some("quick"),
+        value_str: Some("16"),
+        throughput,
+    };
+    vec![
+        (
+            "",
+            UpstreamMessage::GreetingReply {
+                image_stamp: "0123456789abcdef",
+                mode: Mode::Benchmark,
+                loop_overhead: Some(LoopOverhead {
+                    iters: 1000,
+                    value: 4001,
+                }),
+                capacities: TargetCapacities {
+                    link_buffer_len: 2048,
+                    max_samples: 128,
+                    max_group_id_len: 128,
+                    max_function_id_len: 128,
+                    max_value_str_len: 128,
+                },
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::BeginningBenchmarkGroup { group: "sort" },
+        ),
+        ("", UpstreamMessage::FinishedBenchmarkGroup),
+        ("", UpstreamMessage::BeginningBenchmark { id: id(None) }),
+        (
+            "Throughput::Bytes",
+            UpstreamMessage::BeginningBenchmark {
+                id: id(Some(Throughput::Bytes(64))),
+            },
+        ),
+        (
+            "Throughput::Elements",
+            UpstreamMessage::BeginningBenchmark {
+                id: id(Some(Throughput::Elements(16))),
+            },
+        ),
+        (
+            "Throughput::Custom",
+            UpstreamMessage::BeginningBenchmark {
+                id: id(Some(Throughput::Custom {
+                    amount: 2,
+                    unit: ArrayString::from("block").unwrap(),
+                })),
+            },
+        ),
+        (
+            "no function or value",
+            UpstreamMessage::BeginningBenchmark {
+                id: RawBenchmarkId {
+                    group_id: "noop",
+                    function_id: None,
+                    value_str: None,
+                    throughput: None,
+                },
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::SkippingBenchmark {
+                id: id(None),
+                reason: SkipReason::Filter,
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::IdTruncated {
+                id: id(None),
+                group_id_len: 4,
+                function_id_len: 200,
+                value_str_len: 2,
+            },
+        ),
+        ("", UpstreamMessage::RunningBenchmark { id: id(None) }),
+        (
+            "",
+            UpstreamMessage::FrequencyChanged {
+                requested: 84_000_000,
+                effective: 84_000_000,
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::BenchmarkTags {
+                tags: [("algorithm", "quicksort"), ("opt", "O2")]
+                    .iter()
+                    .copied()
+                    .collect(),
+            },
+        ),
+        (
+            "ValueFormat::Native",
+            UpstreamMessage::ValueFormatChanged {
+                format: ValueFormat::Native,
+            },
+        ),
+        (
+            "ValueFormat::PerUnit",
+            UpstreamMessage::ValueFormatChanged {
+                format: ValueFormat::PerUnit {
+                    amount: 4,
+                    unit: ArrayString::from("KiB").unwrap(),
+                },
+            },
+        ),
+        ("", UpstreamMessage::ListedBenchmark { id: id(None) }),
+        (
+            "",
+            UpstreamMessage::Warmup {
+                warm_up_goal_duration: Duration::from_nanos(3_000_000_000),
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::WarmupProgress {
+                iters_so_far: 4096,
+                elapsed: Duration::from_nanos(250_000_000),
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::Progress {
+                iters_done: 10,
+                iters: 100,
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::SampleSizeClamped {
+                requested: 1000,
+                effective: 128,
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::MeasurementStart {
+                warm_up_iter_count: 65535,
+                warm_up_duration: Duration::from_nanos(3_000_000_000),
+                num_samples: 50,
+                num_iters: 1275,
+                warm_up_round_trips: RoundTrips {
+                    count: 34,
+                    min: 5000,
+                    total: 200_000,
+                },
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::SampleTimestamps {
+                timestamps: &[0, 24, 4_294_967_296],
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::SampleChunk {
+                values: &[1_000, 998, 1_003],
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::BenchmarkFailed {
+                id: id(None),
+                message: "NoDevice",
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::MeasurementComplete {
+                num_iters_per_sample: 25,
+                values: &[1000, 1001, 998],
+                benchmark_config: BenchmarkConfig::default(),
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::MeasurementWarning {
+                kind: MeasurementWarningKind::TimerTampered,
+            },
+        ),
+        (
+            "",
+            UpstreamMessage::TestFailed {
+                id: id(None),
+                message: "assertion failed",
+            },
+        ),
+        ("", UpstreamMessage::End),
+        ("", UpstreamMessage::GetInstant),
+        ("", UpstreamMessage::EchoReply { payload: "0123" }),
+        (
+            "",
+            UpstreamMessage::Panicked {
+                message: "panicked at 'oops'",
+            },
+        ),
+    ]
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Write `Name (label): hex` for each example in `examples`.
+fn write_examples<T: Serialize>(
+    out: &mut String,
+    examples: &[(&'static str, T)],
+    name: impl Fn(&T) -> &'static str,
+) {
+    for (label, msg) in examples {
+        let bytes = serde_cbor::to_vec(msg).unwrap();
+        match *label {
+            "" => write!(out, "{}", name(msg)).unwrap(),
+            label => write!(out, "{} ({})", name(msg), label).unwrap(),
+        }
+        writeln!(out, ": {}", hex(&bytes)).unwrap();
+    }
+}
+
+fn generate() -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# FarCri.rs wire protocol test vectors (protocol version {})\n\
+        #\n\
+        # Generated by a test in `src/bencher/protocol/vectors.rs`. Do not edit.\n\
+        # Byte sequences are in hexadecimal. The constants are provided by\n\
+        # `farcri::protocol_doc` (the `protocol_doc` feature).\n\
+        #\n\
+        # Handshake: the Proxy program sends HANDSHAKE_MAGIC followed by a\n\
+        # random nonce of HANDSHAKE_NONCE_LEN bytes, repeating it until the\n\
+        # Target program echoes both back. Then it sends HANDSHAKE_END_MAGIC,\n\
+        # which the Target program also echoes back.\n\
+        #\n\
+        # Framing: after the handshake, each message is a CBOR item (serde's\n\
+        # externally tagged representation, with field names as map keys) in\n\
+        # a SLIP frame terminated by SLIP_FRAME_END. Empty frames are ignored.\n\
+        # The messages below are listed without framing.\n",
+        PROTOCOL_VERSION
+    )
+    .unwrap();
+
+    writeln!(out, "[handshake]").unwrap();
+    writeln!(out, "HANDSHAKE_MAGIC: {}", hex(HANDSHAKE_MAGIC)).unwrap();
+    writeln!(out, "HANDSHAKE_NONCE_LEN: {}", HANDSHAKE_NONCE_LEN).unwrap();
+    writeln!(out, "HANDSHAKE_END_MAGIC: {}", hex(HANDSHAKE_END_MAGIC)).unwrap();
+    let mut request = HANDSHAKE_MAGIC.to_vec();
+    request.extend((0..HANDSHAKE_NONCE_LEN as u8).map(|i| 0x30 + i));
+    writeln!(out, "request (example nonce): {}", hex(&request)).unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "[framing]").unwrap();
+    for &(name, value) in &[
+        ("SLIP_FRAME_END", SLIP_FRAME_END),
+        ("SLIP_FRAME_ESC", SLIP_FRAME_ESC),
+        ("SLIP_FRAME_ESC_END", SLIP_FRAME_ESC_END),
+        ("SLIP_FRAME_ESC_ESC", SLIP_FRAME_ESC_ESC),
+    ] {
+        writeln!(out, "{}: {:02x}", name, value).unwrap();
+    }
+    let payload = [0x01, SLIP_FRAME_END, 0x02, SLIP_FRAME_ESC, 0x03];
+    let mut frame = [0u8; 16];
+    frame[..payload.len()].copy_from_slice(&payload);
+    let frame_len = crate::bencher::proxylink::frame_in_place(&mut frame, payload.len());
+    writeln!(
+        out,
+        "example: {} -> {}",
+        hex(&payload),
+        hex(&frame[..frame_len])
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "[downstream] (Proxy program -> Target program)").unwrap();
+    write_examples(&mut out, &downstream_examples(), downstream_name);
+    writeln!(out).unwrap();
+
+    writeln!(out, "[upstream] (Target program -> Proxy program)").unwrap();
+    write_examples(&mut out, &upstream_examples(), upstream_name);
+
+    out
+}
+
+#[test]
+fn protocol_vectors() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(PATH);
+    let generated = generate();
+
+    if std::env::var_os("FARCRI_BLESS_PROTOCOL_VECTORS").is_some() {
+        std::fs::write(&path, &generated).unwrap();
+        return;
+    }
+
+    let committed = std::fs::read_to_string(&path).unwrap_or_default();
+    let num_lines = committed.lines().count().max(generated.lines().count());
+    let mismatch = (committed.lines().chain(std::iter::repeat("")))
+        .zip(generated.lines().chain(std::iter::repeat("")))
+        .take(num_lines)
+        .enumerate()
+        .find(|(_, (x, y))| x != y);
+    if let Some((i, (committed_line, generated_line))) = mismatch {
+        panic!(
+            "The protocol encodings differ from `{}` at line {}:\n  committed: {}\n  generated: {}\n\
+            If the change is intentional, bump `PROTOCOL_VERSION` and regenerate the file by \
+            running this test with `FARCRI_BLESS_PROTOCOL_VECTORS=1`.",
+            PATH,
+            i + 1,
+            committed_line,
+            generated_line
+        );
+    }
+}
@@ -1,7 +1,10 @@
 //! Connection to the Proxy program
 use serde::Serialize;
 
-use super::protocol;
+use super::{
+    protocol::{self, SLIP_FRAME_END, SLIP_FRAME_ESC, SLIP_FRAME_ESC_END, SLIP_FRAME_ESC_ESC},
+    watchdog::LinkWatchdog,
+};
 use crate::target::BencherIo;
 
 pub(crate) struct ProxyLink<'a> {
@@ -14,13 +17,22 @@ pub(crate) struct ProxyLink<'a> {
     buf_len: usize,
     /// `buf[buf_pos..buf_scan]` does not contain `SLIP_FRAME_END`.
     buf_scan: usize,
+    /// Messages are being sent through the bulk channel. See
+    /// [`protocol::BULK_CHANNEL_NAME`].
+    #[cfg(feature = "rtt_bulk_channel")]
+    bulk: bool,
+    /// See [`Self::set_watchdog`]
+    watchdog: Option<LinkWatchdog>,
+    /// The number of messages sent so far that the Proxy program answers with
+    /// `Continue` or `SkipRemaining`. See [`Self::recv_continue`].
+    continues_expected: u32,
+    /// The number of `Continue`s and `SkipRemaining`s received so far
+    continues_received: u32,
+    /// A `SkipRemaining` was received and hasn't been reported by
+    /// [`Self::recv_continue`] yet.
+    skip_received: bool,
 }
 
-const SLIP_FRAME_END: u8 = 0xc0;
-const SLIP_FRAME_ESC: u8 = 0xdb;
-const SLIP_FRAME_ESC_END: u8 = 0xdc;
-const SLIP_FRAME_ESC_ESC: u8 = 0xdd;
-
 impl<'a> ProxyLink<'a> {
     #[inline]
     pub fn new(io: &'a mut BencherIo, buf: &'a mut [u8]) -> Self {
@@ -44,9 +56,9 @@ impl<'a> ProxyLink<'a> {
         packet[..protocol::HANDSHAKE_MAGIC.len()].copy_from_slice(protocol::HANDSHAKE_MAGIC);
 
         log::debug!("Performing handshake");
+        let mut b = next(io);
         'outer: loop {
             loop {
-                let b = next(io);
                 if b == protocol::HANDSHAKE_MAGIC[0] {
                     break;
                 } else if got_handshape {
@@ -55,13 +67,15 @@ impl<'a> ProxyLink<'a> {
                     }
                     break 'outer;
                 }
+                b = next(io);
             }
 
             // Read `HANDSHAKE_MAGIC[1..]` and nonce
             for &b_ref in protocol::HANDSHAKE_MAGIC[1..].iter() {
-                let b = next(io);
+                b = next(io);
                 if b != b_ref {
-                    // invalid packet
+                    // Invalid packet. Don't discard `b` - it might be the
+                    // start of the next packet.
                     continue 'outer;
                 }
             }
@@ -80,6 +94,8 @@ impl<'a> ProxyLink<'a> {
 
             // Now we can accept `HANDSHAKE_END_MAGIC`
             got_handshape = true;
+
+            b = next(io);
         }
 
         for &b_ref in protocol::HANDSHAKE_END_MAGIC[1..].iter() {
@@ -102,9 +118,21 @@ impl<'a> ProxyLink<'a> {
             buf_pos: 0,
             buf_len: 0,
             buf_scan: 0,
+            #[cfg(feature = "rtt_bulk_channel")]
+            bulk: false,
+            watchdog: None,
+            continues_expected: 0,
+            continues_received: 0,
+            skip_received: false,
         }
     }
 
+    /// Make the following calls to [`Self::recv`] fire `watchdog` if no
+    /// message arrives before it times out.
+    pub fn set_watchdog(&mut self, watchdog: LinkWatchdog) {
+        self.watchdog = Some(watchdog);
+    }
+
     #[inline]
     pub fn io(&mut self) -> &mut BencherIo {
         self.io
@@ -112,149 +140,345 @@ impl<'a> ProxyLink<'a> {
 
     /// Receive one `DownstreamMessage`.
     pub fn recv(&mut self) -> protocol::DownstreamMessage<&str> {
+        #[cfg(feature = "rtt_bulk_channel")]
+        {
+            self.bulk = false;
+        }
+
         loop {
-            let packet_start = self.buf_pos;
-            if let Some(end) = self.buf[self.buf_scan..self.buf_len]
-                .iter()
-                .position(|&b| b == SLIP_FRAME_END)
-            {
-                // Found the terminator of the current packet
-                self.buf_scan += end + 1;
-                self.buf_pos = self.buf_scan;
-                if end > 0 {
-                    // Non-empty message.
-                    let mut packet_end = self.buf_pos - 1;
-
-                    // Expand SLIP escape sequences
-                    {
-                        let mut window = &mut self.buf[packet_start..packet_end];
-                        let mut read_ptr = 0;
-                        while read_ptr < window.len() {
-                            let b1 = window[read_ptr];
-                            if b1 == SLIP_FRAME_ESC && read_ptr + 1 < window.len() {
-                                let b2 = window[read_ptr + 1];
-                                window[0] = match b2 {
-                                    SLIP_FRAME_ESC_END => SLIP_FRAME_END,
-                                    SLIP_FRAME_ESC_ESC => SLIP_FRAME_ESC,
-                                    _ => panic!("invalid SLIP escape"),
-                                };
-                                read_ptr += 1;
-                            } else {
-                                window[0] = b1;
-                            }
-                            window = &mut window[1..];
-                        }
-                        packet_end -= window.len();
-                    }
+            if let Some(packet) = self.take_buffered_packet() {
+                return decode(self.io, &mut self.buf[packet]);
+            }
 
-                    // Decode it
-                    let packet = &mut self.buf[packet_start..packet_end];
-                    log::trace!("recv (raw): {:?}", packet);
-                    let msg = serde_cbor::de::from_mut_slice(packet).unwrap();
-                    log::debug!("recv: {:?}", msg);
-                    return msg;
+            // Looks like we need to read some more to find the terminator
+            if self.buf.len() - self.buf_len <= 1 {
+                // The buffer is full.
+                if self.buf_pos == 0 {
+                    panic!("too large received packet");
+                } else {
+                    // We can make some room by discarding the already-read
+                    // portion `buf[0..buf_pos]`.
+                    self.buf.copy_within(self.buf_pos..self.buf_len, 0);
+                    self.buf_len -= self.buf_pos;
+                    self.buf_pos = 0;
+                    self.buf_scan = self.buf_len;
                 }
             } else {
-                // Looks like we need to read some more to find the terminator
-                if self.buf.len() - self.buf_len <= 1 {
-                    // The buffer is full.
-                    if self.buf_pos == 0 {
-                        panic!("too large received packet");
-                    } else {
-                        // We can make some room by discarding the already-read
-                        // portion `buf[0..buf_pos]`.
-                        self.buf.copy_within(self.buf_pos..self.buf_len, 0);
-                        self.buf_len -= self.buf_pos;
-                        self.buf_pos = 0;
-                        self.buf_scan = self.buf_len;
+                let buf_outer = &mut self.buf[self.buf_len..];
+                let num_read_bytes = match &mut self.watchdog {
+                    Some(watchdog) => {
+                        watchdog.start(self.io.now());
+                        match self.io.poll_read(buf_outer, |now| watchdog.poll(now)) {
+                            0 => watchdog.fire(),
+                            n => n,
+                        }
                     }
+                    None => self.io.read(buf_outer),
+                };
+                assert!(num_read_bytes <= buf_outer.len());
+                assert_ne!(num_read_bytes, 0);
+
+                self.buf_scan = self.buf_len;
+                self.buf_len += num_read_bytes;
+            }
+        }
+    }
+
+    /// Consume the next complete, non-empty packet in the receiving buffer
+    /// and expand its SLIP escape sequences in-place. Returns the range of the
+    /// expanded packet in `self.buf`, or `None` if the buffer doesn't contain
+    /// a complete packet.
+    fn take_buffered_packet(&mut self) -> Option<core::ops::Range<usize>> {
+        loop {
+            let packet_start = self.buf_pos;
+            let end = self.buf[self.buf_scan..self.buf_len]
+                .iter()
+                .position(|&b| b == SLIP_FRAME_END)?;
+
+            // Found the terminator of the current packet
+            self.buf_scan += end + 1;
+            self.buf_pos = self.buf_scan;
+            if end == 0 {
+                // Empty packet
+                continue;
+            }
+            let mut packet_end = self.buf_pos - 1;
+
+            // Expand SLIP escape sequences
+            let mut window = &mut self.buf[packet_start..packet_end];
+            let mut read_ptr = 0;
+            while read_ptr < window.len() {
+                let b1 = window[read_ptr];
+                if b1 == SLIP_FRAME_ESC && read_ptr + 1 < window.len() {
+                    let b2 = window[read_ptr + 1];
+                    window[0] = match b2 {
+                        SLIP_FRAME_ESC_END => SLIP_FRAME_END,
+                        SLIP_FRAME_ESC_ESC => SLIP_FRAME_ESC,
+                        _ => panic!("invalid SLIP escape"),
+                    };
+                    read_ptr += 1;
                 } else {
-                    let buf_outer = &mut self.buf[self.buf_len..];
-                    let num_read_bytes = self.io.read(buf_outer);
-                    assert!(num_read_bytes <= buf_outer.len());
-                    assert_ne!(num_read_bytes, 0);
+                    window[0] = b1;
+                }
+                window = &mut window[1..];
+            }
+            packet_end -= window.len();
 
-                    self.buf_scan = self.buf_len;
-                    self.buf_len += num_read_bytes;
+            return Some(packet_start..packet_end);
+        }
+    }
+
+    /// Receive `Continue` or `SkipRemaining` in reply to
+    /// `MeasurementComplete`, `BenchmarkFailed`, or `FinishedBenchmarkGroup`.
+    /// Returns `true` if `SkipRemaining` was received.
+    ///
+    /// The replies are counted rather than matched one by one. A reply that
+    /// arrived before the Target program started waiting for it (e.g., one
+    /// coalesced with the reply to the previous message) has been saved by
+    /// [`Self::send`] and is consumed without waiting.
+    pub fn recv_continue(&mut self) -> bool {
+        while self.continues_received < self.continues_expected {
+            let skip = match self.recv() {
+                protocol::DownstreamMessage::Continue => false,
+                protocol::DownstreamMessage::SkipRemaining => true,
+                other => {
+                    panic!("unexpected downstream message: {:?}", other);
+                }
+            };
+            self.skip_received |= skip;
+            self.continues_received += 1;
+        }
+        core::mem::take(&mut self.skip_received)
+    }
+
+    /// Count the `Continue`s and `SkipRemaining`s remaining in the receiving
+    /// buffer before [`Self::send`] discards it, so that
+    /// [`Self::recv_continue`] doesn't wait for them again.
+    fn save_early_replies(&mut self) {
+        while let Some(packet) = self.take_buffered_packet() {
+            match decode(self.io, &mut self.buf[packet]) {
+                protocol::DownstreamMessage::Continue => {}
+                protocol::DownstreamMessage::SkipRemaining => self.skip_received = true,
+                other => {
+                    log::warn!("discarding an unexpected downstream message: {:?}", other);
+                    continue;
                 }
             }
+            log::debug!("saving a reply that arrived early");
+            self.continues_received += 1;
         }
     }
 
     /// Send one `UpstreamMessage`. Destroys any remaining messages in the
-    /// receiving buffer.
+    /// receiving buffer except for the replies saved for
+    /// [`Self::recv_continue`].
     pub fn send(&mut self, msg: &protocol::UpstreamMessage<&str, &[u64]>) {
+        self.save_early_replies();
+        if matches!(
+            msg,
+            protocol::UpstreamMessage::MeasurementComplete { .. }
+                | protocol::UpstreamMessage::BenchmarkFailed { .. }
+                | protocol::UpstreamMessage::FinishedBenchmarkGroup
+        ) {
+            self.continues_expected += 1;
+        }
+
         self.buf_pos = 0;
         self.buf_len = 0;
         self.buf_scan = 0;
 
-        // Encode
-        let writer = serde_cbor::ser::SliceWrite::new(self.buf);
-        let mut ser = serde_cbor::ser::Serializer::new(writer);
-        msg.serialize(&mut ser).unwrap();
-        let num_bytes = ser.into_inner().bytes_written();
-
-        log::debug!("send: {:?}", msg);
-        log::trace!("  encoded as: {:?}", &self.buf[..num_bytes]);
-
-        // Create a SLIP frame
-        let num_extra_bytes = self.buf[..num_bytes]
-            .iter()
-            .filter(|&&b| matches!(b, SLIP_FRAME_END | SLIP_FRAME_ESC))
-            .count();
-        let num_frame_bytes = num_bytes
-            .checked_add(num_extra_bytes)
-            .and_then(|x| x.checked_add(1))
-            .expect("packet being sent is too large");
+        #[cfg(feature = "rtt_bulk_channel")]
         {
-            let mut window = self
-                .buf
-                .get_mut(..num_frame_bytes)
-                .expect("packet being sent is too large");
-            let mut read_ptr = num_bytes.wrapping_sub(1);
-
-            // Append `SLIP_FRAME_END`
-            if let [tail @ .., head] = window {
-                *head = SLIP_FRAME_END;
-                window = tail;
-            } else {
-                unreachable!();
-            }
+            self.bulk |= matches!(
+                msg,
+                protocol::UpstreamMessage::SampleChunk { .. }
+                    | protocol::UpstreamMessage::SampleTimestamps { .. }
+                    | protocol::UpstreamMessage::MeasurementComplete { .. }
+            );
+        }
 
-            // Escape in-place
-            while read_ptr < window.len() {
-                let b = window[read_ptr];
-                let escape_code = match b {
-                    SLIP_FRAME_END => SLIP_FRAME_ESC_END,
-                    SLIP_FRAME_ESC => SLIP_FRAME_ESC_ESC,
-                    _ => b,
-                };
+        send_in(self.io, self.buf, msg, self.is_bulk());
+    }
 
-                if escape_code == b || window.len() < 2 {
-                    // output as-is
-                    if let [tail @ .., head] = window {
-                        *head = b;
-                        window = tail;
-                    } else {
-                        unreachable!();
-                    }
-                } else {
-                    if let [tail @ .., head1, head2] = window {
-                        *head1 = SLIP_FRAME_ESC;
-                        *head2 = escape_code;
-                        window = tail;
-                    } else {
-                        unreachable!();
-                    }
+    /// Check if messages are being sent through the bulk channel.
+    fn is_bulk(&self) -> bool {
+        match () {
+            #[cfg(feature = "rtt_bulk_channel")]
+            () => self.bulk,
+            #[cfg(not(feature = "rtt_bulk_channel"))]
+            () => false,
+        }
+    }
+
+    /// Answer `DownstreamMessage::EchoRequest`s with
+    /// `UpstreamMessage::EchoReply`s until a `DownstreamMessage::Continue`
+    /// message arrives.
+    ///
+    /// The payload is moved to the end of the buffer while the reply is
+    /// encoded in the rest, hence [`protocol::max_echo_payload_len`].
+    pub fn answer_echo_requests(&mut self) {
+        let base = self.buf.as_ptr() as usize;
+        loop {
+            let payload = match self.recv() {
+                protocol::DownstreamMessage::EchoRequest { payload } => {
+                    let start = payload.as_ptr() as usize - base;
+                    start..start + payload.len()
+                }
+                protocol::DownstreamMessage::Continue => return,
+                other => {
+                    panic!("unexpected downstream message: {:?}", other);
                 }
+            };
+
+            self.buf_pos = 0;
+            self.buf_len = 0;
+            self.buf_scan = 0;
+
+            let split = self.buf.len() - payload.len();
+            self.buf.copy_within(payload, split);
+            let (buf, payload) = self.buf.split_at_mut(split);
+            let payload = core::str::from_utf8(payload).unwrap();
+            send_in(
+                self.io,
+                buf,
+                &protocol::UpstreamMessage::EchoReply { payload },
+                false,
+            );
+        }
+    }
+}
 
-                read_ptr = read_ptr.wrapping_sub(1);
-            }
+/// Encode `msg` in `buf` and send it as a SLIP frame, through the bulk
+/// channel if `bulk` is `true`.
+fn send_in(
+    io: &mut BencherIo,
+    buf: &mut [u8],
+    msg: &protocol::UpstreamMessage<&str, &[u64]>,
+    bulk: bool,
+) {
+    let num_bytes = encode(buf, msg);
+
+    #[cfg(feature = "frame_trace")]
+    io.trace_frame(protocol::FRAME_TRACE_UPSTREAM, &buf[..num_bytes]);
+    #[cfg(not(feature = "frame_trace"))]
+    {
+        log::debug!("send: {:?}", msg);
+        log::trace!("  encoded as: {:?}", &buf[..num_bytes]);
+    }
+
+    let num_frame_bytes = frame_in_place(buf, num_bytes);
+
+    // Send it
+    #[cfg(not(feature = "frame_trace"))]
+    log::trace!("  SLIP frame: {:?}", &buf[..num_frame_bytes]);
+
+    match () {
+        #[cfg(feature = "rtt_bulk_channel")]
+        () if bulk => io.write_bulk(&buf[..num_frame_bytes]),
+        _ => {
+            let _ = bulk;
+            io.write(&buf[..num_frame_bytes]);
         }
+    }
+}
 
-        // Send it
-        log::trace!("  SLIP frame: {:?}", &self.buf[..num_frame_bytes]);
+/// Encode [`protocol::UpstreamMessage::Panicked`] reporting `info` as a SLIP
+/// frame in `buf`, truncating the message to
+/// [`protocol::MAX_PANIC_MESSAGE_LEN`] bytes. Doesn't log anything, so this
+/// can be called by a panic handler.
+#[cfg(any(feature = "panic_reset", all(test, feature = "role_proxy")))]
+pub(crate) fn encode_panic_report<'a>(
+    buf: &'a mut [u8; protocol::PANIC_REPORT_BUF_LEN],
+    info: &dyn core::fmt::Display,
+) -> &'a [u8] {
+    let mut message = arrayvec::ArrayString::<{ protocol::MAX_PANIC_MESSAGE_LEN }>::new();
+    super::fill_array_string_with_display(&mut message, Some(info));
+    let num_bytes = encode(
+        buf,
+        &protocol::UpstreamMessage::Panicked { message: &message },
+    );
+    let num_frame_bytes = frame_in_place(buf, num_bytes);
+    &buf[..num_frame_bytes]
+}
 
-        self.io.write(&self.buf[..num_frame_bytes]);
+/// Decode a packet received from the Proxy program.
+fn decode<'a>(io: &mut BencherIo, packet: &'a mut [u8]) -> protocol::DownstreamMessage<&'a str> {
+    #[cfg(feature = "frame_trace")]
+    io.trace_frame(protocol::FRAME_TRACE_DOWNSTREAM, packet);
+    #[cfg(not(feature = "frame_trace"))]
+    {
+        let _ = io;
+        log::trace!("recv (raw): {:?}", packet);
     }
+    let msg = serde_cbor::de::from_mut_slice(packet).unwrap();
+    #[cfg(not(feature = "frame_trace"))]
+    log::debug!("recv: {:?}", msg);
+    msg
+}
+
+/// Encode `msg` in `buf`. Returns the number of bytes written.
+fn encode(buf: &mut [u8], msg: &protocol::UpstreamMessage<&str, &[u64]>) -> usize {
+    let writer = serde_cbor::ser::SliceWrite::new(buf);
+    let mut ser = serde_cbor::ser::Serializer::new(writer);
+    msg.serialize(&mut ser).unwrap();
+    ser.into_inner().bytes_written()
+}
+
+/// Turn the packet in `buf[..num_bytes]` into a SLIP frame in-place. Returns
+/// the size of the frame.
+pub(super) fn frame_in_place(buf: &mut [u8], num_bytes: usize) -> usize {
+    let num_extra_bytes = buf[..num_bytes]
+        .iter()
+        .filter(|&&b| matches!(b, SLIP_FRAME_END | SLIP_FRAME_ESC))
+        .count();
+    let num_frame_bytes = num_bytes
+        .checked_add(num_extra_bytes)
+        .and_then(|x| x.checked_add(1))
+        .expect("packet being sent is too large");
+    {
+        let mut window = buf
+            .get_mut(..num_frame_bytes)
+            .expect("packet being sent is too large");
+        let mut read_ptr = num_bytes.wrapping_sub(1);
+
+        // Append `SLIP_FRAME_END`
+        if let [tail @ .., head] = window {
+            *head = SLIP_FRAME_END;
+            window = tail;
+        } else {
+            unreachable!();
+        }
+
+        // Escape in-place
+        while read_ptr < window.len() {
+            let b = window[read_ptr];
+            let escape_code = match b {
+                SLIP_FRAME_END => SLIP_FRAME_ESC_END,
+                SLIP_FRAME_ESC => SLIP_FRAME_ESC_ESC,
+                _ => b,
+            };
+
+            if escape_code == b || window.len() < 2 {
+                // output as-is
+                if let [tail @ .., head] = window {
+                    *head = b;
+                    window = tail;
+                } else {
+                    unreachable!();
+                }
+            } else {
+                if let [tail @ .., head1, head2] = window {
+                    *head1 = SLIP_FRAME_ESC;
+                    *head2 = escape_code;
+                    window = tail;
+                } else {
+                    unreachable!();
+                }
+            }
+
+            read_ptr = read_ptr.wrapping_sub(1);
+        }
+    }
+
+    num_frame_bytes
 }
@@ -0,0 +1,237 @@
+//! Summary statistics computed by the Target program
+//!
+//! The Proxy program does the actual analysis, but the Target program logs a
+//! summary of each measurement for a quick look at the target's log. `f64`
+//! arithmetic is slow and bloats the code on targets without a hardware FPU
+//! (e.g., Cortex-M0), where it's implemented in software, so the summary is
+//! computed in fixed-point arithmetic unless the `fpu` feature is enabled.
+//! The Proxy program enables it based on the target architecture.
+use core::fmt;
+
+/// The number of fractional bits of [`Fixed`]
+const FRAC_BITS: u32 = 16;
+
+/// An unsigned fixed-point number with [`FRAC_BITS`] fractional bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Fixed(u64);
+
+impl Fixed {
+    const ONE: u64 = 1 << FRAC_BITS;
+
+    /// Saturates on overflow.
+    fn from_u128_frac(x: u128) -> Self {
+        Self(x.min(u64::MAX as u128) as u64)
+    }
+
+    fn from_f64(x: f64) -> Self {
+        // `as` saturates, and maps NaN to zero
+        Self((x * Self::ONE as f64) as u64)
+    }
+
+    #[cfg(test)]
+    fn to_f64(self) -> f64 {
+        self.0 as f64 / Self::ONE as f64
+    }
+}
+
+impl fmt::Display for Fixed {
+    /// Display `self` with three fractional digits, rounding it down.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let int = self.0 >> FRAC_BITS;
+        let frac = ((self.0 & (Self::ONE - 1)) * 1000) >> FRAC_BITS;
+        write!(f, "{}.{:03}", int, frac)
+    }
+}
+
+/// The summary statistics of a measurement, in the measurement's unit per
+/// iteration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Summary {
+    pub(super) mean: Fixed,
+    /// The sample standard deviation
+    pub(super) std_dev: Fixed,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mean = {}, std. dev. = {}", self.mean, self.std_dev)
+    }
+}
+
+/// Summarize the samples of a measurement. Returns `None` if there are
+/// fewer than two samples.
+pub(super) fn summarize(values: &[u64], num_iters_per_sample: u64) -> Option<Summary> {
+    if values.len() < 2 || num_iters_per_sample == 0 {
+        None
+    } else if cfg!(feature = "fpu") {
+        Some(summarize_float(values, num_iters_per_sample))
+    } else {
+        Some(summarize_fixed(values, num_iters_per_sample))
+    }
+}
+
+/// The fixed-point implementation of [`summarize`]. The intermediate values
+/// saturate if the samples exceed 2⁴⁰ or so.
+fn summarize_fixed(values: &[u64], num_iters_per_sample: u64) -> Summary {
+    let n = values.len() as u128;
+    let iters = num_iters_per_sample as u128;
+
+    let sum: u128 = values.iter().map(|&x| x as u128).sum();
+    // The mean of the samples, with `FRAC_BITS` fractional bits
+    let mean = (sum << FRAC_BITS) / n;
+
+    // The sum of squared deviations, with `FRAC_BITS * 2` fractional bits
+    let sum_sq = values.iter().fold(0u128, |acc, &x| {
+        let x = (x as u128) << FRAC_BITS;
+        let dev = x.max(mean) - x.min(mean);
+        acc.saturating_add(dev.saturating_mul(dev))
+    });
+    let variance = sum_sq / (n - 1);
+
+    Summary {
+        mean: Fixed::from_u128_frac(mean / iters),
+        std_dev: Fixed::from_u128_frac(isqrt(variance) / iters),
+    }
+}
+
+/// The `f64` implementation of [`summarize`]
+fn summarize_float(values: &[u64], num_iters_per_sample: u64) -> Summary {
+    let n = values.len() as f64;
+    let iters = num_iters_per_sample as f64;
+
+    let mean = values.iter().map(|&x| x as f64).sum::<f64>() / n;
+    let sum_sq: f64 = values
+        .iter()
+        .map(|&x| (x as f64 - mean) * (x as f64 - mean))
+        .sum();
+    let variance = sum_sq / (n - 1.0);
+
+    Summary {
+        mean: Fixed::from_f64(mean / iters),
+        std_dev: Fixed::from_f64(sqrt(variance) / iters),
+    }
+}
+
+/// The integer square root, rounded down
+fn isqrt(x: u128) -> u128 {
+    if x < 2 {
+        return x;
+    }
+    // Newton's method, starting from a power of two not less than the result
+    let bits = 128 - x.leading_zeros();
+    let mut y = 1u128 << (bits - bits / 2);
+    loop {
+        let next = (y + x / y) / 2;
+        if next >= y {
+            return y;
+        }
+        y = next;
+    }
+}
+
+/// The square root. `core` doesn't provide `f64::sqrt`.
+fn sqrt(x: f64) -> f64 {
+    if x.is_nan() || x <= 0.0 {
+        return 0.0;
+    }
+    // Newton's method, starting from a value not less than the result. The
+    // sequence decreases monotonically until it converges.
+    let mut y = isqrt(x as u128) as f64 + 1.0;
+    for _ in 0..64 {
+        let next = (y + x / y) / 2.0;
+        if next >= y {
+            break;
+        }
+        y = next;
+    }
+    y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that both implementations match the straightforward `f64`
+    /// calculation within `tolerance` (relative to the mean)
+    fn check(values: &[u64], num_iters_per_sample: u64, tolerance: f64) {
+        let n = values.len() as f64;
+        let iters = num_iters_per_sample as f64;
+        let mean = values.iter().map(|&x| x as f64).sum::<f64>() / n;
+        let variance = values
+            .iter()
+            .map(|&x| (x as f64 - mean).powi(2))
+            .sum::<f64>()
+            / (n - 1.0);
+        let (mean, std_dev) = (mean / iters, variance.sqrt() / iters);
+
+        for &(name, summary) in &[
+            ("fixed", summarize_fixed(values, num_iters_per_sample)),
+            ("float", summarize_float(values, num_iters_per_sample)),
+        ] {
+            let abs_tolerance = (mean * tolerance).max(1.0 / Fixed::ONE as f64);
+            assert!(
+                (summary.mean.to_f64() - mean).abs() <= abs_tolerance,
+                "{}: mean = {}, expected {}",
+                name,
+                summary.mean.to_f64(),
+                mean
+            );
+            assert!(
+                (summary.std_dev.to_f64() - std_dev).abs() <= abs_tolerance,
+                "{}: std_dev = {}, expected {}",
+                name,
+                summary.std_dev.to_f64(),
+                std_dev
+            );
+        }
+    }
+
+    #[test]
+    fn matches_f64_reference() {
+        check(&[1000, 1000], 1, 1e-9);
+        check(&[1000, 1001, 999, 1003, 998, 1000, 1002], 1, 1e-6);
+        check(&[10, 20, 30, 40, 50], 3, 1e-6);
+        check(&[1, 2], 7, 1e-6);
+
+        // Large values with a small spread
+        let values: Vec<u64> = (0..128).map(|i| 4_000_000_000 + i * 37 % 101).collect();
+        check(&values, 1000, 1e-6);
+
+        // Pseudo-random values
+        let mut state = 0x1234_5678_u64;
+        let values: Vec<u64> = (0..1024)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                50_000 + (state >> 48)
+            })
+            .collect();
+        check(&values, 16, 1e-6);
+    }
+
+    #[test]
+    fn too_few_samples() {
+        assert_eq!(summarize(&[], 1), None);
+        assert_eq!(summarize(&[42], 1), None);
+        assert_eq!(summarize(&[42, 43], 0), None);
+    }
+
+    #[test]
+    fn integer_sqrt() {
+        for x in (0..10_000u128).chain([u64::MAX as u128, u128::MAX].iter().cloned()) {
+            let y = isqrt(x);
+            assert!(y * y <= x, "{}", x);
+            assert!(
+                !matches!((y + 1).checked_mul(y + 1), Some(z) if z <= x),
+                "{}",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn display_fixed() {
+        assert_eq!(Fixed::from_f64(12.5).to_string(), "12.500");
+        assert_eq!(Fixed::from_f64(0.0015).to_string(), "0.001");
+        assert_eq!(Fixed(u64::MAX).to_string(), "281474976710655.999");
+    }
+}
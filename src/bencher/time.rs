@@ -17,18 +17,50 @@ impl fmt::Debug for Instant {
 #[serde(transparent)]
 pub struct Duration(u64);
 
+/// The units used by `<Duration as Display>::fmt`: `(nanoseconds per unit,
+/// upper limit (exclusive), suffix)`.
+const DISPLAY_UNITS: &[(u64, u64, &str)] = &[
+    (1, 1_000, "ns"),
+    (1_000, 1_000, "μs"),
+    (1_000_000, 1_000, "ms"),
+    (1_000_000_000, 60, "s"),
+    (60_000_000_000, 60, "min"),
+    (3_600_000_000_000, u64::MAX, "h"),
+];
+
 impl fmt::Display for Duration {
+    /// Format the duration with three significant digits (e.g., `12.3ms`),
+    /// choosing the smallest unit in which the value stays below the next
+    /// unit.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let (value, suffix) = if self.0 < 5_000 {
-            (self.0, "ns")
-        } else if self.0 < 5_000_000 {
-            (self.0 / 1_000, "μs")
-        } else if self.0 < 5_000_000_000 {
-            (self.0 / 1_000_000, "ms")
-        } else {
-            (self.0 / 1_000_000_000, "s")
-        };
-        write!(f, "{}{}", value, suffix)
+        // Integer arithmetic only - this runs on the target, too
+        let rounded = |unit: u64| self.0 / unit + (self.0 % unit >= unit - unit / 2) as u64;
+
+        let &(unit, _, suffix) = DISPLAY_UNITS
+            .iter()
+            .find(|&&(unit, limit, _)| rounded(unit) < limit)
+            .unwrap_or(&DISPLAY_UNITS[DISPLAY_UNITS.len() - 1]);
+
+        if unit == 1 {
+            return write!(f, "{}{}", self.0, suffix);
+        }
+
+        for &(digits, scale) in &[(2, 100), (1, 10), (0, 1)] {
+            let mut int = self.0 / unit;
+            let mut frac = ((self.0 % unit) * scale + unit / 2) / unit;
+            if frac == scale {
+                int += 1;
+                frac = 0;
+            }
+
+            if digits == 0 {
+                return write!(f, "{}{}", int, suffix);
+            } else if int < 1000 / scale {
+                return write!(f, "{}.{:0digits$}{}", int, frac, suffix, digits = digits);
+            }
+        }
+
+        unreachable!()
     }
 }
 
@@ -38,6 +70,19 @@ impl fmt::Debug for Duration {
     }
 }
 
+impl From<core::time::Duration> for Duration {
+    /// Convert a `core::time::Duration`, saturating at `u64::MAX`
+    /// nanoseconds.
+    #[inline]
+    fn from(x: core::time::Duration) -> Self {
+        Self(
+            x.as_secs()
+                .saturating_mul(1_000_000_000)
+                .saturating_add(x.subsec_nanos() as u64),
+        )
+    }
+}
+
 impl ops::Add for Duration {
     type Output = Self;
 
@@ -86,3 +131,37 @@ impl Duration {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_display() {
+        let cases: &[(u64, &str)] = &[
+            (0, "0ns"),
+            (999, "999ns"),
+            (1_000, "1.00μs"),
+            (4_999, "5.00μs"),
+            (5_001, "5.00μs"),
+            (12_345, "12.3μs"),
+            (123_456, "123μs"),
+            (999_499, "999μs"),
+            (999_500, "1.00ms"),
+            (3_000_000_000, "3.00s"),
+            (59_499_000_000, "59.5s"),
+            (59_999_999_999, "1.00min"),
+            (90_000_000_000, "1.50min"),
+            (7_200_000_000_000, "2.00h"),
+            (u64::MAX, "5124096h"),
+        ];
+        for &(nanos, expected) in cases {
+            assert_eq!(
+                format!("{}", Duration::from_nanos(nanos)),
+                expected,
+                "{}ns",
+                nanos
+            );
+        }
+    }
+}
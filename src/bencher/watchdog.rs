@@ -0,0 +1,115 @@
+//! Silence watchdog on the link to the Proxy program
+//! (`Criterion::on_link_lost`)
+//!
+//! If the Proxy program goes away without closing the link (e.g., it's
+//! killed or the host goes to sleep), the Target program waits for the next
+//! message forever, leaving whatever the last benchmark started (motors,
+//! heaters, ...) running. The watchdog measures how long the Target program
+//! has been waiting for a message and, once the silence reaches the timeout,
+//! calls the safe-state hook registered by the benchmark code and then resets
+//! or parks the target.
+use crate::target;
+
+/// What to do after calling the safe-state hook registered by
+/// [`Criterion::on_link_lost`](crate::Criterion::on_link_lost)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkLostAction {
+    /// Reset the target (`SCB::sys_reset` on Cortex-M). The firmware then
+    /// starts over and waits for a new Proxy program.
+    Reset,
+    /// Stop in a busy loop, keeping the target as the hook left it
+    Park,
+}
+
+pub(crate) struct LinkWatchdog {
+    /// In the units of the time source
+    timeout: u64,
+    action: LinkLostAction,
+    hook: fn(),
+    /// How long the current wait has lasted
+    silent_for: u64,
+    /// The time of the last call to `start` or `poll`
+    last_poll: u64,
+}
+
+impl LinkWatchdog {
+    pub(crate) fn new(timeout: u64, action: LinkLostAction, hook: fn()) -> Self {
+        Self {
+            timeout,
+            action,
+            hook,
+            silent_for: 0,
+            last_poll: 0,
+        }
+    }
+
+    /// Start waiting for a message at `now`.
+    pub(crate) fn start(&mut self, now: u64) {
+        self.silent_for = 0;
+        self.last_poll = now;
+    }
+
+    /// Record that no message has arrived by `now`. Returns `true` if the
+    /// silence has reached the timeout.
+    ///
+    /// The silence is accumulated from poll to poll rather than measured
+    /// from `start`. If the time source goes backward (e.g., because the
+    /// benchmark code reprogrammed it), the interval is counted as zero
+    /// instead of being mistaken for a very long one.
+    pub(crate) fn poll(&mut self, now: u64) -> bool {
+        self.silent_for = self
+            .silent_for
+            .saturating_add(now.saturating_sub(self.last_poll));
+        self.last_poll = now;
+        self.silent_for >= self.timeout
+    }
+
+    /// Call the safe-state hook and take the configured action.
+    pub(crate) fn fire(&self) -> ! {
+        log::error!(
+            "No message from the Proxy program for {} time source units; \
+            assuming the link is lost",
+            self.silent_for
+        );
+        (self.hook)();
+        match self.action {
+            LinkLostAction::Reset => target::reset(),
+            LinkLostAction::Park => loop {
+                core::hint::spin_loop();
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silence_accumulated() {
+        let mut watchdog = LinkWatchdog::new(100, LinkLostAction::Park, || {});
+
+        watchdog.start(1000);
+        assert!(!watchdog.poll(1040));
+        assert!(!watchdog.poll(1099));
+        assert!(watchdog.poll(1100));
+
+        // A message arrived; the next wait starts over
+        watchdog.start(5000);
+        assert!(!watchdog.poll(5060));
+        // The time source went backward
+        assert!(!watchdog.poll(10));
+        assert!(!watchdog.poll(49));
+        assert!(watchdog.poll(50));
+    }
+
+    #[test]
+    fn no_overflow() {
+        let mut watchdog = LinkWatchdog::new(u64::MAX, LinkLostAction::Reset, || {});
+        watchdog.start(0);
+        assert!(!watchdog.poll(u64::MAX - 1));
+        assert!(watchdog.poll(u64::MAX));
+        watchdog.start(u64::MAX);
+        assert!(!watchdog.poll(u64::MAX));
+    }
+}
@@ -1,12 +1,15 @@
 //! Utility functions for finding the `cargo bench` command that was used to
 //! build the currently running executable and running the same command with
 //! additional parameters.
+#[cfg(feature = "role_proxy")]
+use serde::Serialize;
 use serde::{de, Deserialize};
 use std::{
     env,
-    ffi::OsString,
+    ffi::{OsStr, OsString},
+    fmt,
     io::{BufRead, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
@@ -15,44 +18,216 @@ use crate::utils::Serde;
 #[derive(Debug)]
 pub struct CompiledExecutable {
     pub path: PathBuf,
+    /// The name of the bench target.
+    pub name: String,
     pub library_paths: Vec<PathBuf>,
+    /// The compilation profile of the bench artifact, if reported by Cargo.
+    pub profile: Option<ArtifactProfile>,
+    /// The library crates built along with the bench target. Empty if
+    /// unknown.
+    pub crates: Vec<ArtifactCrate>,
+    /// The image to program into the target. `path` still refers to the
+    /// executable, which is used for reading symbols.
+    pub format: ImageFormat,
 }
 
-pub fn compile_self(modify_cmd: impl FnOnce(&mut Command) -> &mut Command) -> CompiledExecutable {
-    let (cargo_path, package_path, cargo_args) = super::cargo::cargo_bench_path_args()
-        .expect("could not determine the cargo command used to build this target");
+/// The format of the image programmed into the target, see
+/// [`CompiledExecutable::format`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageFormat {
+    /// The executable itself (ELF)
+    Elf,
+    /// A raw binary image at `path` generated from the executable, to be
+    /// loaded at `base_address`
+    Bin { path: PathBuf, base_address: u64 },
+    /// An Intel HEX image at `path` generated from the executable
+    // Not produced by the built-in targets
+    #[allow(dead_code)]
+    Hex { path: PathBuf },
+}
+
+/// A library crate reported by a `compiler-artifact` message
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "role_proxy", derive(Serialize, Deserialize))]
+pub struct ArtifactCrate {
+    pub name: String,
+    /// The enabled Cargo features
+    pub features: Vec<String>,
+}
+
+impl CompiledExecutable {
+    /// Construct a `CompiledExecutable` describing an executable that was
+    /// built without going through [`compile_self`].
+    #[cfg(feature = "role_proxy")]
+    pub fn prebuilt(path: PathBuf) -> Self {
+        let file_stem = path.file_stem().and_then(|x| x.to_str()).unwrap_or("");
+
+        // Remove the crate disambiguator (`-` followed by a hex hash) if
+        // there's one. A target name may contain `-` by itself.
+        let name = match file_stem.rfind('-') {
+            Some(i)
+                if i > 0
+                    && i + 1 < file_stem.len()
+                    && file_stem[i + 1..].bytes().all(|b| b.is_ascii_hexdigit()) =>
+            {
+                &file_stem[..i]
+            }
+            _ => file_stem,
+        }
+        .to_owned();
+
+        Self {
+            path,
+            name,
+            library_paths: Vec::new(),
+            profile: None,
+            crates: Vec::new(),
+            format: ImageFormat::Elf,
+        }
+    }
+}
+
+/// The error type of [`compile_self`].
+#[derive(Debug)]
+pub enum CompileError {
+    /// The `cargo bench` command used to build the running executable
+    /// couldn't be determined.
+    UnknownCommand(&'static str),
+    /// The build command specified in place of Cargo wasn't found.
+    CommandNotFound(OsString),
+    /// Cargo couldn't be launched.
+    Launch(std::io::Error),
+    /// Cargo's output couldn't be read, or waiting for it to exit failed.
+    Io(std::io::Error),
+    /// Cargo emitted a JSON message that couldn't be parsed.
+    MalformedMessage(String),
+    /// Cargo reported that the build failed (`build-finished` with
+    /// `success: false`), e.g., because of a compile error.
+    BuildFailed {
+        /// The rendered last error diagnostic emitted by the compiler
+        last_error: Option<String>,
+    },
+    /// Cargo exited with a non-zero status without reporting a failed build,
+    /// e.g., because of an invalid argument.
+    CargoFailed(std::process::ExitStatus),
+    /// Cargo didn't report the executable of the bench target.
+    NoArtifact,
+}
 
-    std::env::set_current_dir(package_path).expect("could not cd to the package directory");
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownCommand(reason) => write!(
+                f,
+                "Could not determine the Cargo command used to build this \
+                executable: {}.",
+                reason
+            ),
+            Self::CommandNotFound(command) => write!(
+                f,
+                "The build command {:?} was not found. It must be a path to an \
+                executable or the name of one in `$PATH`.",
+                command
+            ),
+            Self::Launch(_) => write!(f, "Could not launch Cargo."),
+            Self::Io(_) => write!(f, "Could not communicate with Cargo."),
+            Self::MalformedMessage(line) => {
+                write!(f, "Cargo emitted a malformed message: {}", line)
+            }
+            Self::BuildFailed { last_error: None } => write!(f, "The build failed."),
+            Self::BuildFailed {
+                last_error: Some(last_error),
+            } => write!(
+                f,
+                "The build failed. The last error was:\n\n{}",
+                last_error.trim_end()
+            ),
+            Self::CargoFailed(status) => write!(f, "Cargo failed ({}).", status),
+            Self::NoArtifact => write!(f, "Cargo did not report the path of the bench target."),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Launch(e) | Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-    let mut cargo = modify_cmd(&mut Command::new(cargo_path).args(cargo_args).args(&[
-        "--no-run",
-        "--message-format",
-        "json-render-diagnostics",
-    ]))
+/// Run the `cargo bench` command used to build the running executable with
+/// `--no-run`, modified by `modify_cmd`, and get the built bench target.
+///
+/// If `cargo` is given, it's used in place of `$CARGO` (e.g., `cross`, which
+/// accepts the same arguments). It's looked up in `$PATH` unless it contains
+/// a path separator.
+pub fn compile_self(
+    cargo: Option<&OsStr>,
+    modify_cmd: impl FnOnce(&mut Command) -> &mut Command,
+) -> Result<CompiledExecutable, CompileError> {
+    let (mut cargo_path, package_path, cargo_args) =
+        cargo_bench_path_args().map_err(CompileError::UnknownCommand)?;
+
+    if let Some(cargo) = cargo {
+        cargo_path = find_command(cargo, env::var_os("PATH").as_deref())
+            .ok_or_else(|| CompileError::CommandNotFound(cargo.to_owned()))?;
+    }
+
+    // Don't change the current directory of this process - that would race
+    // with anything else running concurrently
+    let mut cargo = modify_cmd(
+        Command::new(cargo_path)
+            .current_dir(package_path)
+            .args(cargo_args)
+            // Cargo doesn't include the diagnostics in the messages with
+            // `json-render-diagnostics`, so we print them ourselves
+            .args(&["--no-run", "--message-format", "json"]),
+    )
     .stdin(Stdio::null())
     .stderr(Stdio::inherit()) // Cargo writes its normal compile output to stderr
     .stdout(Stdio::piped()) // Capture the JSON messages on stdout
     .spawn()
-    .expect("could not launch cargo");
+    .map_err(CompileError::Launch)?;
 
     let cargo_stdout = BufReader::new(cargo.stdout.take().unwrap());
 
     let mut path = None;
+    let mut name = None;
+    let mut profile = None;
     let mut library_paths = Vec::new();
+    let mut crates = Vec::new();
+    let mut last_error = None;
+    let mut build_success = None;
 
     for line in cargo_stdout.lines() {
-        let msg: Message = serde_json_core::from_str(&line.unwrap()).unwrap().0;
-        match msg {
-            Message::CompilerArtifact { target, executable } => {
+        let line = line.map_err(CompileError::Io)?;
+        match parse_message(&line)? {
+            Message::CompilerArtifact {
+                target,
+                executable,
+                profile: artifact_profile,
+                features,
+            } => {
                 if target.kind.0.iter().any(|kind| kind.0 == "bench") {
                     if let Some(executable) = executable {
-                        path = Some(json_unescape(&executable.0).into());
+                        path = Some(json_unescape(&executable.0, &line)?.into());
+                        name = Some(target.name.0);
+                        profile = artifact_profile;
                     }
+                } else if (target.kind.0.iter()).any(|kind| matches!(&*kind.0, "lib" | "rlib")) {
+                    crates.push(ArtifactCrate {
+                        name: target.name.0,
+                        features: (features.map_or_else(Vec::new, |x| x.0).into_iter())
+                            .map(|x| x.0)
+                            .collect(),
+                    });
                 }
             }
             Message::BuildScriptExecuted { linked_paths } => {
                 for path in linked_paths.0 {
-                    let path = json_unescape(&path.0)
+                    let path = json_unescape(&path.0, &line)?
                         .replace("dependency=", "")
                         .replace("crate=", "")
                         .replace("native=", "")
@@ -62,16 +237,82 @@ pub fn compile_self(modify_cmd: impl FnOnce(&mut Command) -> &mut Command) -> Co
                     library_paths.push(path);
                 }
             }
-            _ => (),
+            Message::CompilerMessage { message } => {
+                let rendered = match message.rendered {
+                    // Fall back to the escaped form if it contains an escape
+                    // sequence we can't handle
+                    Some(x) => match json_unescape(&x.0, &line) {
+                        Ok(rendered) => rendered,
+                        Err(_) => x.0,
+                    },
+                    None => continue,
+                };
+                eprint!("{}", rendered);
+
+                // Skip the summary (`aborting due to N previous errors`),
+                // which doesn't tell anything useful by itself
+                if message.level.0.starts_with("error") && !rendered.starts_with("error: aborting")
+                {
+                    last_error = Some(rendered);
+                }
+            }
+            Message::BuildFinished { success } => {
+                build_success = Some(success);
+            }
         }
     }
 
-    cargo.wait().expect("cargo failed");
+    let status = cargo.wait().map_err(CompileError::Io)?;
+    check_build_result(build_success, status, last_error)?;
 
-    CompiledExecutable {
-        path: path.expect("cargo did not return artifact path"),
+    Ok(CompiledExecutable {
+        path: path.ok_or(CompileError::NoArtifact)?,
+        name: name.unwrap(),
         library_paths,
+        profile,
+        crates,
+        format: ImageFormat::Elf,
+    })
+}
+
+/// Check the result of a Cargo invocation. `build_success` is the `success`
+/// field of `build-finished`, if reported. A failed build is reported as such
+/// even if Cargo's exit status indicates success.
+fn check_build_result(
+    build_success: Option<bool>,
+    status: std::process::ExitStatus,
+    last_error: Option<String>,
+) -> Result<(), CompileError> {
+    if build_success == Some(false) {
+        Err(CompileError::BuildFailed { last_error })
+    } else if !status.success() {
+        Err(CompileError::CargoFailed(status))
+    } else {
+        Ok(())
+    }
+}
+
+/// Find the executable invoked by running `command`, searching `path` (the
+/// value of `$PATH`) if `command` doesn't contain a path separator.
+fn find_command(command: &OsStr, path: Option<&OsStr>) -> Option<PathBuf> {
+    let is_executable = |path: &Path| match () {
+        #[cfg(unix)]
+        () => {
+            use std::os::unix::fs::PermissionsExt;
+            matches!(path.metadata(), Ok(m) if m.is_file() && m.permissions().mode() & 0o111 != 0)
+        }
+        #[cfg(not(unix))]
+        () => path.is_file() || path.with_extension(env::consts::EXE_EXTENSION).is_file(),
+    };
+
+    let command = Path::new(command);
+    if command.components().count() > 1 {
+        return Some(command.to_owned()).filter(|x| is_executable(x));
     }
+
+    env::split_paths(path?)
+        .map(|dir| dir.join(command))
+        .find(|x| is_executable(x))
 }
 
 fn cargo_bench_path_args() -> Result<(PathBuf, PathBuf, Vec<OsString>), &'static str> {
@@ -79,6 +320,59 @@ fn cargo_bench_path_args() -> Result<(PathBuf, PathBuf, Vec<OsString>), &'static
 
     let package_path = env::var_os("CARGO_MANIFEST_DIR").ok_or("$CARGO_MANIFEST_DIR is not set")?;
 
+    Ok((
+        cargo.into(),
+        package_path.into(),
+        vec![
+            "bench".into(),
+            "--bench".into(),
+            bench_target_name()?.into(),
+        ],
+    ))
+}
+
+/// Get the target directory of the package of the running executable, as
+/// reported by `cargo metadata`. Unlike assuming `target`, this respects
+/// workspaces, `$CARGO_TARGET_DIR`, and `build.target-dir`.
+#[cfg(feature = "role_proxy")]
+pub fn target_directory() -> Result<PathBuf, CompileError> {
+    let cargo = env::var_os("CARGO").ok_or(CompileError::UnknownCommand("$CARGO is not set"))?;
+    let package_path = env::var_os("CARGO_MANIFEST_DIR").ok_or(CompileError::UnknownCommand(
+        "$CARGO_MANIFEST_DIR is not set",
+    ))?;
+
+    let output = Command::new(cargo)
+        .current_dir(package_path)
+        .args(&["metadata", "--format-version", "1", "--no-deps"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(CompileError::Launch)?;
+    if !output.status.success() {
+        return Err(CompileError::CargoFailed(output.status));
+    }
+
+    parse_target_directory(&output.stdout)
+}
+
+/// The part of the output of `cargo metadata` we are interested in
+#[cfg(feature = "role_proxy")]
+#[derive(Deserialize)]
+struct Metadata {
+    target_directory: PathBuf,
+}
+
+#[cfg(feature = "role_proxy")]
+fn parse_target_directory(output: &[u8]) -> Result<PathBuf, CompileError> {
+    serde_json::from_slice::<Metadata>(output)
+        .map(|metadata| metadata.target_directory)
+        .map_err(|_| {
+            CompileError::MalformedMessage(String::from_utf8_lossy(output).trim_end().to_owned())
+        })
+}
+
+/// Get the name of the bench target of the currently running executable.
+fn bench_target_name() -> Result<String, &'static str> {
     let mut exe_path =
         env::current_exe().map_err(|_| "could not find the current executable name")?;
     exe_path.set_extension("");
@@ -93,13 +387,7 @@ fn cargo_bench_path_args() -> Result<(PathBuf, PathBuf, Vec<OsString>), &'static
     let i = exe_name
         .rfind("-")
         .ok_or("could not locate the crate disambiguator in the current executable name")?;
-    let target_name = &exe_name[0..i];
-
-    Ok((
-        cargo.into(),
-        package_path.into(),
-        vec!["bench".into(), "--bench".into(), target_name.into()],
-    ))
+    Ok(exe_name[0..i].to_owned())
 }
 
 // These structs match the parts of Cargo's message format that we care about.
@@ -109,8 +397,42 @@ struct Target {
     kind: Serde<Vec<Serde<String>>>,
 }
 
-/// Enum listing out the different types of messages that Cargo can send. We only care about the
-/// compiler-artifact message.
+/// The `profile` field of a `compiler-artifact` message.
+///
+/// Cargo doesn't report the LTO setting here, so it's not included.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "role_proxy", derive(Serialize))]
+pub struct ArtifactProfile {
+    pub opt_level: Serde<String>,
+    pub debuginfo: Option<u32>,
+    pub debug_assertions: bool,
+    pub overflow_checks: bool,
+}
+
+impl ArtifactProfile {
+    /// Get a flag indicating whether this profile is suitable for taking
+    /// measurements, i.e., the optimization level is at least 2 (`s` and `z`
+    /// count) and debug assertions are disabled.
+    #[cfg(feature = "role_proxy")]
+    pub fn is_release_like(&self) -> bool {
+        !matches!(&*self.opt_level.0, "0" | "1") && !self.debug_assertions
+    }
+}
+
+impl fmt::Display for ArtifactProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "opt-level = {}, debuginfo = {}, debug-assertions = {}, overflow-checks = {}",
+            self.opt_level.0,
+            self.debuginfo.unwrap_or(0),
+            self.debug_assertions,
+            self.overflow_checks,
+        )
+    }
+}
+
+/// Enum listing out the different types of messages that Cargo can send.
 #[derive(Debug)]
 enum Message {
     CompilerArtifact {
@@ -118,15 +440,30 @@ enum Message {
         // `PathBuf` does not have `impl Deserialize` when `serde` is built
         // without `serde/std`
         executable: Option<Serde<String>>,
+        profile: Option<ArtifactProfile>,
+        features: Option<Serde<Vec<Serde<String>>>>,
     },
 
-    CompilerMessage {},
+    CompilerMessage {
+        message: Diagnostic,
+    },
 
     BuildScriptExecuted {
         linked_paths: Serde<Vec<Serde<String>>>,
     },
 
-    BuildFinished {},
+    BuildFinished {
+        success: bool,
+    },
+}
+
+/// The `message` field of a `compiler-message` message
+#[derive(Deserialize, Debug)]
+struct Diagnostic {
+    /// `error`, `warning`, `note`, etc.
+    level: Serde<String>,
+    /// The diagnostic rendered by the compiler (escaped)
+    rendered: Option<Serde<String>>,
 }
 
 #[derive(Deserialize)]
@@ -134,7 +471,11 @@ struct MessageFlat {
     reason: MessageReason,
     target: Option<Target>,
     executable: Option<Serde<String>>,
+    profile: Option<ArtifactProfile>,
+    features: Option<Serde<Vec<Serde<String>>>>,
     linked_paths: Option<Serde<Vec<Serde<String>>>>,
+    message: Option<Diagnostic>,
+    success: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -162,31 +503,47 @@ impl<'de> de::Deserialize<'de> for Message {
             MessageReason::CompilerArtifact => Ok(Self::CompilerArtifact {
                 target: flat.target.ok_or(de::Error::missing_field("target"))?,
                 executable: flat.executable,
+                profile: flat.profile,
+                features: flat.features,
+            }),
+            MessageReason::CompilerMessage => Ok(Self::CompilerMessage {
+                message: flat.message.ok_or(de::Error::missing_field("message"))?,
             }),
-            MessageReason::CompilerMessage => Ok(Self::CompilerMessage {}),
             MessageReason::BuildScriptExecuted => Ok(Self::BuildScriptExecuted {
                 linked_paths: flat
                     .linked_paths
                     .ok_or(de::Error::missing_field("linked_paths"))?,
             }),
-            MessageReason::BuildFinished => Ok(Self::BuildFinished {}),
+            MessageReason::BuildFinished => Ok(Self::BuildFinished {
+                success: flat.success.ok_or(de::Error::missing_field("success"))?,
+            }),
         }
     }
 }
 
-/// Unescape a JSON string. (`serde_json_core` doesn't unescape them.)
-fn json_unescape(x: &str) -> String {
+/// Parse a JSON message emitted by Cargo.
+fn parse_message(line: &str) -> Result<Message, CompileError> {
+    serde_json_core::from_str(line)
+        .map(|(msg, _)| msg)
+        .map_err(|_| CompileError::MalformedMessage(line.to_owned()))
+}
+
+/// Unescape a JSON string found in `line`, a message emitted by Cargo.
+/// (`serde_json_core` doesn't unescape them.) Fails if `x` contains a
+/// malformed escape sequence or an unsupported one (`\u`).
+fn json_unescape(x: &str, line: &str) -> Result<String, CompileError> {
+    let malformed = || CompileError::MalformedMessage(line.to_owned());
     let mut out = String::with_capacity(x.len());
     let mut it = x.split("\\");
     out.push_str(it.next().unwrap());
     while let Some(part) = it.next() {
         if part.len() == 0 {
             // It's double backslash
-            let rest = it.next().expect("incomplete JSON string escape sequence");
+            let rest = it.next().ok_or_else(malformed)?;
             out.push_str("\\");
             out.push_str(rest);
         } else if let Some(_) = part.strip_prefix("u") {
-            todo!()
+            return Err(malformed());
         } else {
             let (rest, ch) = if let Some(rest) = part.strip_prefix("\"") {
                 (rest, "\"")
@@ -203,27 +560,200 @@ fn json_unescape(x: &str) -> String {
             } else if let Some(rest) = part.strip_prefix("t") {
                 (rest, "\t")
             } else {
-                panic!("unrecognized JSON string escape sequence");
+                return Err(malformed());
             };
 
             out.push_str(ch);
             out.push_str(rest);
         };
     }
-    out
+    Ok(out)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    #[cfg(feature = "role_proxy")]
+    fn prebuilt_name() {
+        let name = |path: &str| CompiledExecutable::prebuilt(PathBuf::from(path)).name;
+        assert_eq!(name("target/release/deps/sort-0123456789abcdef"), "sort");
+        assert_eq!(
+            name("target/release/deps/my_bench-0a1b2c3d.elf"),
+            "my_bench"
+        );
+        assert_eq!(name("firmware/my-bench"), "my-bench");
+        assert_eq!(name("firmware/my-bench-v2"), "my-bench-v2");
+        assert_eq!(name("firmware/bench"), "bench");
+    }
+
+    #[test]
+    fn find_build_command() {
+        let dir = env::temp_dir().join(format!("farcri-find-command-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("cross");
+        std::fs::write(&exe, "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let path = env::join_paths(&[Path::new("/nonexistent"), &dir]).unwrap();
+
+        assert_eq!(
+            find_command("cross".as_ref(), Some(&path)),
+            Some(exe.clone())
+        );
+        assert_eq!(find_command(exe.as_os_str(), None), Some(exe.clone()));
+        assert_eq!(find_command("cross".as_ref(), None), None);
+        assert_eq!(find_command("xargo".as_ref(), Some(&path)), None);
+        // A directory isn't a command
+        assert_eq!(find_command(dir.as_os_str(), None), None);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o644)).unwrap();
+            assert_eq!(find_command("cross".as_ref(), Some(&path)), None);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_json_unescape() {
-        assert_eq!(json_unescape(""), "");
-        assert_eq!(json_unescape("a"), "a");
-        assert_eq!(json_unescape(r"\n"), "\n");
-        assert_eq!(json_unescape(r"a\ra"), "a\ra");
-        assert_eq!(json_unescape(r"a\r\na"), "a\r\na");
-        assert_eq!(json_unescape(r"a\\\r\\a"), "a\\\r\\a");
+        let unescape = |x| json_unescape(x, "").unwrap();
+        assert_eq!(unescape(""), "");
+        assert_eq!(unescape("a"), "a");
+        assert_eq!(unescape(r"\n"), "\n");
+        assert_eq!(unescape(r"a\ra"), "a\ra");
+        assert_eq!(unescape(r"a\r\na"), "a\r\na");
+        assert_eq!(unescape(r"a\\\r\\a"), "a\\\r\\a");
+        assert!(json_unescape(r"a\u0001", "").is_err());
+    }
+
+    #[cfg(feature = "role_proxy")]
+    #[test]
+    fn target_directory_parsed() {
+        let line = r#"{"packages":[{"name":"a","targets":[{"kind":["bench"]}]}],"workspace_members":["a 0.1.0"],"target_directory":"C:\\ws\\target","version":1,"workspace_root":"C:\\ws"}"#;
+        assert_eq!(
+            parse_target_directory(line.as_bytes()).unwrap(),
+            PathBuf::from(r"C:\ws\target")
+        );
+        assert!(matches!(
+            parse_target_directory(br#"{"version":1}"#),
+            Err(CompileError::MalformedMessage(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_message() {
+        assert!(matches!(
+            parse_message(r#"{"reason":"compiler-artifact"}"#),
+            Err(CompileError::MalformedMessage(_))
+        ));
+        assert!(matches!(
+            json_unescape(r"a\q", "line"),
+            Err(CompileError::MalformedMessage(line)) if line == "line"
+        ));
+    }
+
+    /// A `compiler-artifact` message captured from `cargo bench --no-run
+    /// --message-format json-render-diagnostics` (Cargo 1.51.0)
+    const FIXTURE_ARTIFACT_RELEASE: &str = r#"{"reason":"compiler-artifact","package_id":"farcri_example 0.0.0 (path+file:///home/user/farcri-rs/example)","target":{"kind":["bench"],"crate_types":["bin"],"name":"sort","src_path":"/home/user/farcri-rs/example/benches/sort.rs","edition":"2018","doctest":false,"test":false},"profile":{"opt_level":"3","debuginfo":null,"debug_assertions":false,"overflow_checks":false,"test":false},"features":[],"filenames":["/home/user/farcri-rs/target/thumbv7em-none-eabihf/release/deps/sort-8f14de0564ff7f2f"],"executable":"/home/user/farcri-rs/target/thumbv7em-none-eabihf/release/deps/sort-8f14de0564ff7f2f","fresh":false}"#;
+
+    /// Same as [`FIXTURE_ARTIFACT_RELEASE`] but with `[profile.bench]`
+    /// overridden by `opt-level = 1` and `debug-assertions = true`
+    const FIXTURE_ARTIFACT_DEBUG: &str = r#"{"reason":"compiler-artifact","package_id":"farcri_example 0.0.0 (path+file:///home/user/farcri-rs/example)","target":{"kind":["bench"],"crate_types":["bin"],"name":"sort","src_path":"/home/user/farcri-rs/example/benches/sort.rs","edition":"2018","doctest":false,"test":false},"profile":{"opt_level":"1","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/home/user/farcri-rs/target/thumbv7em-none-eabihf/release/deps/sort-8f14de0564ff7f2f"],"executable":"/home/user/farcri-rs/target/thumbv7em-none-eabihf/release/deps/sort-8f14de0564ff7f2f","fresh":false}"#;
+
+    fn parse_artifact_profile(line: &str) -> ArtifactProfile {
+        match serde_json_core::from_str(line).unwrap().0 {
+            Message::CompilerArtifact {
+                profile: Some(profile),
+                ..
+            } => profile,
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn artifact_profile_release() {
+        let profile = parse_artifact_profile(FIXTURE_ARTIFACT_RELEASE);
+        assert_eq!(
+            profile,
+            ArtifactProfile {
+                opt_level: Serde("3".to_owned()),
+                debuginfo: None,
+                debug_assertions: false,
+                overflow_checks: false,
+            }
+        );
+        assert!(profile.is_release_like());
+    }
+
+    #[test]
+    fn artifact_profile_debug() {
+        let profile = parse_artifact_profile(FIXTURE_ARTIFACT_DEBUG);
+        assert_eq!(
+            profile,
+            ArtifactProfile {
+                opt_level: Serde("1".to_owned()),
+                debuginfo: Some(2),
+                debug_assertions: true,
+                overflow_checks: true,
+            }
+        );
+        assert!(!profile.is_release_like());
+    }
+
+    /// A `compiler-message` message captured from `cargo bench --no-run
+    /// --message-format json` (Cargo 1.51.0)
+    const FIXTURE_COMPILER_MESSAGE: &str = r#"{"reason":"compiler-message","package_id":"farcri_example 0.0.0 (path+file:///home/user/farcri-rs/example)","target":{"kind":["bench"],"crate_types":["bin"],"name":"sort","src_path":"/home/user/farcri-rs/example/benches/sort.rs","edition":"2018","doctest":false,"test":false},"message":{"rendered":"error[E0425]: cannot find value `x` in this scope\n --> example/benches/sort.rs:5:5\n  |\n5 |     x\n  |     ^ not found in this scope\n\n","children":[],"code":{"code":"E0425","explanation":"An unresolved name was used.\n"},"level":"error","message":"cannot find value `x` in this scope","spans":[{"byte_end":60,"byte_start":59,"column_end":6,"column_start":5,"expansion":null,"file_name":"example/benches/sort.rs","is_primary":true,"label":"not found in this scope","line_end":5,"line_start":5,"suggested_replacement":null,"suggestion_applicability":null,"text":[{"highlight_end":6,"highlight_start":5,"text":"    x"}]}]}}"#;
+
+    #[test]
+    fn compiler_message() {
+        match parse_message(FIXTURE_COMPILER_MESSAGE).unwrap() {
+            Message::CompilerMessage { message } => {
+                assert_eq!(message.level.0, "error");
+                assert_eq!(
+                    json_unescape(&message.rendered.unwrap().0, "").unwrap(),
+                    "error[E0425]: cannot find value `x` in this scope\n \
+                    --> example/benches/sort.rs:5:5\n  |\n5 |     x\n  |     \
+                    ^ not found in this scope\n\n"
+                );
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        assert!(matches!(
+            parse_message(r#"{"reason":"build-finished","success":false}"#).unwrap(),
+            Message::BuildFinished { success: false }
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn build_result() {
+        use std::os::unix::process::ExitStatusExt;
+        let exited = |code: i32| std::process::ExitStatus::from_raw(code << 8);
+
+        assert!(check_build_result(Some(true), exited(0), None).is_ok());
+        assert!(check_build_result(None, exited(0), None).is_ok());
+        assert!(matches!(
+            check_build_result(None, exited(101), None),
+            Err(CompileError::CargoFailed(_))
+        ));
+
+        let e = check_build_result(Some(false), exited(101), Some("error: x\n".to_owned()))
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "The build failed. The last error was:\n\nerror: x"
+        );
+        assert!(matches!(
+            check_build_result(Some(false), exited(0), None),
+            Err(CompileError::BuildFailed { last_error: None })
+        ));
     }
 }
@@ -14,12 +14,22 @@ pub fn main(compile_time_cargo_manifest_dir: &str) {
         std::env::set_var("CARGO_MANIFEST_DIR", compile_time_cargo_manifest_dir);
     }
 
-    let exe = super::cargo::compile_self(|cmd| {
+    // The Proxy program runs on the host, so it's always built by `$CARGO`
+    let exe = super::cargo::compile_self(None, |cmd| {
         cmd.args(&[
             // Invoke Proxy mode
             "--features",
             "farcri/role_proxy",
         ])
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to build the Proxy program: {}", e);
+        let mut source = std::error::Error::source(&e);
+        while let Some(e) = source {
+            eprintln!("  Caused by: {}", e);
+            source = e.source();
+        }
+        std::process::exit(1);
     });
 
     eprintln!("Invoking FarCri.rs Proxy mode by executing {:?}", exe.path);
@@ -37,7 +47,9 @@ pub fn main(compile_time_cargo_manifest_dir: &str) {
 
         #[cfg(not(unix))]
         () => {
-            cmd.spawn().unwrap().wait().unwrap();
+            // Forward the exit code (see `proxy::error::ExitCode`)
+            let status = cmd.spawn().unwrap().wait().unwrap();
+            std::process::exit(status.code().unwrap_or(1));
         }
     }
 }
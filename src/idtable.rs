@@ -0,0 +1,83 @@
+//! Static table of benchmark ID candidates, read by the Proxy program's
+//! `--farcri-list-static`
+//!
+//! `criterion_group!` and `#[farcri::bench]` emit a [`StaticId`] for each
+//! benchmark function into the link section `farcri_ids`. The Proxy program
+//! finds the section in the target executable and reads the names without
+//! running it, which is useful when the target hardware isn't available
+//! (e.g., for shell completion). Nothing in the Target program reads the
+//! entries, so the table only costs three words and the name per benchmark
+//! function.
+//!
+//! The benchmark IDs are only known at runtime in general, so the entries
+//! emitted by `criterion_group!` are merely the paths of the functions passed
+//! to it. Only `#[farcri::bench]` knows the exact ID of its benchmark.
+//!
+//! The linker is free to discard an unreferenced section, so [`retain`]
+//! references the section through the linker-defined `__start_farcri_ids`
+//! symbol (see `crate::registry` for why the section name must be a valid C
+//! identifier). [`SENTINEL`] makes sure that the section exists.
+use crate::bencher::protocol;
+
+/// An entry of the `farcri_ids` section. The layout is read by the Proxy
+/// program (`crate::proxy::idtable`) and must not be changed without updating
+/// it.
+#[doc(hidden)]
+#[repr(C)]
+#[allow(dead_code)] // only read from the executable
+pub struct StaticId {
+    /// One of `protocol::STATIC_ID_KIND_*`
+    kind: usize,
+    name: *const u8,
+    name_len: usize,
+}
+
+// Safety: `name` points to a `&'static str`
+unsafe impl Sync for StaticId {}
+
+impl StaticId {
+    /// An entry that doesn't represent anything
+    pub const SENTINEL: Self = Self::new(protocol::STATIC_ID_KIND_SENTINEL, "");
+
+    /// An exact benchmark ID
+    pub const fn benchmark(id: &'static str) -> Self {
+        Self::new(protocol::STATIC_ID_KIND_BENCHMARK, id)
+    }
+
+    /// The path of a function passed to `criterion_group!`
+    pub const fn function(path: &'static str) -> Self {
+        Self::new(protocol::STATIC_ID_KIND_FUNCTION, path)
+    }
+
+    const fn new(kind: usize, name: &'static str) -> Self {
+        Self {
+            kind,
+            name: name.as_ptr(),
+            name_len: name.len(),
+        }
+    }
+}
+
+#[cfg(all(feature = "role_target", any(target_os = "none", target_os = "linux")))]
+#[used]
+#[link_section = "farcri_ids"]
+static SENTINEL: StaticId = StaticId::SENTINEL;
+
+/// Keep the `farcri_ids` section from being discarded by the linker.
+pub(crate) fn retain() {
+    #[cfg(all(feature = "role_target", any(target_os = "none", target_os = "linux")))]
+    {
+        extern "Rust" {
+            static __start_farcri_ids: StaticId;
+        }
+
+        // Safety: The linker defines this symbol at the start of the
+        //         `farcri_ids` section, which contains at least `SENTINEL`.
+        //         The volatile loads make sure the references survive
+        //         optimization.
+        unsafe {
+            let _ = core::ptr::read_volatile(&(&__start_farcri_ids as *const StaticId));
+            let _ = core::ptr::read_volatile(&(&SENTINEL as *const StaticId));
+        }
+    }
+}
@@ -1,5 +1,5 @@
 //! FarCri.rs: Criterion.rs on Remote Target
-#![cfg_attr(feature = "role_target", no_std)]
+#![cfg_attr(all(feature = "role_target", not(feature = "target_std")), no_std)]
 #![cfg_attr(not(feature = "role_target"), crate_type = "dylib")]
 #![deny(unsafe_op_in_unsafe_fn)]
 
@@ -31,13 +31,62 @@ mod target;
 #[cfg(feature = "role_target")]
 pub use self::target::main;
 
+#[cfg(all(feature = "dwt_instr_estimate", feature = "cortex-m-rt"))]
+pub use self::target::dwt;
+
 // -------------------------------------------------------------------------
 
 mod bencher;
 pub use self::bencher::{
-    black_box, time, Bencher, BenchmarkGroup, BenchmarkId, Criterion, Throughput,
+    black_box, time, AsyncBencher, AsyncExecutor, Bencher, BenchmarkGroup, BenchmarkId, Criterion,
+    ExecutionMode, LinkLostAction, ProgressReporter, Throughput, ValueFormat,
 };
 
+/// The fixed-capacity string type used in the public API, e.g., by
+/// [`Throughput::Custom`]
+pub use arrayvec::ArrayString;
+
+/// The constants of the wire protocol between the Proxy program and the
+/// Target program, for implementing the Target program in another language.
+///
+/// The encodings of all messages are listed in
+/// `doc/protocol_vectors.cbor.txt` in the repository, which is checked
+/// against the implementation by a test. The protocol isn't stable across
+/// versions of FarCri.rs. The Proxy program sends its
+/// [`PROTOCOL_VERSION`](protocol_doc::PROTOCOL_VERSION) in the greeting, and
+/// the Target program must reject any other version.
+#[cfg(feature = "protocol_doc")]
+pub mod protocol_doc {
+    pub use crate::bencher::protocol::{
+        HANDSHAKE_END_MAGIC, HANDSHAKE_MAGIC, HANDSHAKE_NONCE_LEN, PROTOCOL_VERSION,
+        SLIP_FRAME_END, SLIP_FRAME_ESC, SLIP_FRAME_ESC_END, SLIP_FRAME_ESC_ESC,
+    };
+}
+
+// -------------------------------------------------------------------------
+// Attribute macros
+
+#[cfg(feature = "macros_attr")]
+mod registry;
+
+#[cfg(feature = "macros_attr")]
+pub use farcri_macros::bench;
+
+mod idtable;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::idtable::StaticId;
+
+    /// Appears in the error reported when a benchmark crate lacks
+    /// `#![no_main]`, which the targets without an operating system need.
+    /// Add `#![cfg_attr(target_os = "none", no_main)]` to the crate root.
+    pub enum MissingNoMainAttribute {}
+
+    #[cfg(feature = "macros_attr")]
+    pub use crate::registry::{run_bench_entries, BenchEntry};
+}
+
 mod utils {
     mod fmt;
     mod strs;
@@ -53,4 +102,9 @@ mod utils {
     mod futures;
     #[cfg(feature = "role_proxy")]
     pub use self::futures::*;
+
+    #[cfg(feature = "role_proxy")]
+    mod stablehash;
+    #[cfg(feature = "role_proxy")]
+    pub use self::stablehash::*;
 }
@@ -6,6 +6,20 @@ macro_rules! criterion_group {
                 $target(criterion);
             )+
         }
+
+        // Record the functions for `--farcri-list-static` (see
+        // `farcri::__private::StaticId`)
+        $(
+            const _: () = {
+                #[used]
+                #[cfg_attr(
+                    any(target_os = "none", target_os = "linux"),
+                    link_section = "farcri_ids"
+                )]
+                static ID: $crate::__private::StaticId =
+                    $crate::__private::StaticId::function(::core::stringify!($target));
+            };
+        )+
     };
     ($name:ident, $( $target:path ),+ $(,)*) => {
         $crate::criterion_group!{
@@ -73,8 +87,28 @@ macro_rules! criterion_main {
 #[cfg(feature = "cortex-m-rt")]
 macro_rules! criterion_main {
     ( $( $group:path ),+ $(,)* ) => {
+        $crate::__check_no_main!();
+
         #[$crate::cortex_m_rt::entry]
-        fn main() -> ! {
+        fn __farcri_main() -> ! {
+            $crate::main(|c| {
+                $(
+                    $group(c);
+                )+
+            });
+        }
+    }
+}
+
+#[macro_export]
+#[cfg(feature = "armv7a_rt")]
+macro_rules! criterion_main {
+    ( $( $group:path ),+ $(,)* ) => {
+        $crate::__check_no_main!();
+
+        // Called by `_start` (see `farcri::target::armv7a_rt`)
+        #[export_name = "farcri_main"]
+        extern "C" fn __farcri_main() -> ! {
             $crate::main(|c| {
                 $(
                     $group(c);
@@ -83,3 +117,95 @@ macro_rules! criterion_main {
         }
     }
 }
+
+/// Fail the build with an error naming
+/// [`MissingNoMainAttribute`](crate::__private::MissingNoMainAttribute) if
+/// the crate lacks `#![no_main]`. Otherwise, the compiler would only report
+/// that the crate has no `main` function.
+///
+/// ```compile_fail,E0580
+/// #![no_std]
+/// farcri::__check_no_main!();
+/// # #[panic_handler]
+/// # fn panic(_: &core::panic::PanicInfo) -> ! { loop {} }
+/// # // `fn main` is defined by the macro
+/// ```
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __check_no_main {
+    () => {
+        // This becomes the entry point unless `#![no_main]` is present, in
+        // which case it's just an unused function. `rustc` requires the entry
+        // point of a `no_std` crate to return `()`, so the error mentions the
+        // return type.
+        #[allow(dead_code)]
+        fn main() -> $crate::__private::MissingNoMainAttribute {
+            loop {}
+        }
+    };
+}
+
+// -------------------------------------------------------------------------
+// Common
+
+/// Import the items commonly used by benchmark code.
+///
+/// Together with the crate attributes, which a macro can't add, a benchmark
+/// file starts with:
+///
+/// ```rust
+/// #![cfg_attr(target_os = "none", no_std, no_main)]
+/// farcri::bench_prelude!();
+///
+/// fn bench(c: &mut Criterion) {
+///     c.bench_function("noop", |b| b.iter(|| black_box(())));
+/// }
+///
+/// criterion_group!(benches, bench);
+/// criterion_main!(benches);
+/// ```
+#[macro_export]
+macro_rules! bench_prelude {
+    () => {
+        #[allow(unused_imports)]
+        use $crate::{
+            black_box, criterion_group, criterion_main, Bencher, BenchmarkGroup, BenchmarkId,
+            Criterion, Throughput,
+        };
+    };
+}
+
+// -------------------------------------------------------------------------
+// Attribute macros
+
+/// Generate the entry point that runs all benchmark functions registered by
+/// `#[farcri::bench]`. This is an alternative to `criterion_group!` and
+/// `criterion_main!`.
+///
+/// Requires the `macros_attr` feature.
+#[macro_export]
+#[cfg(feature = "macros_attr")]
+macro_rules! bench_harness {
+    () => {
+        // Make sure the registry's link section exists even if there are no
+        // registered benchmarks
+        const _: () = {
+            #[used]
+            #[cfg_attr(
+                any(target_os = "none", target_os = "linux"),
+                link_section = "farcri_benches"
+            )]
+            static SENTINEL: $crate::__private::BenchEntry = $crate::__private::BenchEntry {
+                name: "",
+                func: None,
+                configure: None,
+            };
+        };
+
+        fn __farcri_bench_harness(c: &mut $crate::Criterion<'_>) {
+            $crate::__private::run_bench_entries(c);
+        }
+
+        $crate::criterion_main!(__farcri_bench_harness);
+    };
+}
@@ -0,0 +1,150 @@
+//! Checks that the Target program doesn't use a heap allocator
+//! (`--farcri-forbid-alloc`)
+//!
+//! The Target program is `no_std` and doesn't need `alloc`, but a dependency
+//! can pull it in through a Cargo feature (often `alloc` or `std`, which
+//! may be enabled by default). The build then fails late with an obscure
+//! error about `#[alloc_error_handler]` or a missing global allocator, or,
+//! if the benchmark crate provides one, silently links a heap allocator.
+//!
+//! This check looks for the allocator shim symbols (`__rust_alloc` etc.) in
+//! the target executable's symbol table and, if any are found, names the
+//! dependencies built with a feature that is likely to enable `alloc`.
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::cargo::ArtifactCrate;
+
+/// The symbols defined by the allocator shim and `#[global_allocator]`
+const ALLOC_SYMBOLS: &[&str] = &[
+    "__rust_alloc",
+    "__rust_alloc_zeroed",
+    "__rust_dealloc",
+    "__rust_realloc",
+    "__rust_alloc_error_handler",
+    "__rg_alloc",
+    "__rg_oom",
+];
+
+/// Check the executable at `exe`, which was built with `crates`. Returns a
+/// diagnostic message if it uses a heap allocator.
+pub(super) async fn check(exe: &Path, crates: &[ArtifactCrate]) -> Result<Option<String>> {
+    let elf_bytes = tokio::fs::read(exe)
+        .await
+        .with_context(|| format!("Failed to read '{}'.", exe.display()))?;
+    let elf = goblin::elf::Elf::parse(&elf_bytes).context("Failed to parse the executable.")?;
+
+    let mut found: Vec<&str> = (elf.syms.iter())
+        .filter_map(|sym| elf.strtab.get(sym.st_name)?.ok())
+        .filter(|name| ALLOC_SYMBOLS.contains(name))
+        .collect();
+    found.sort_unstable();
+    found.dedup();
+
+    Ok(diagnostic(&found, crates))
+}
+
+/// Get the features of `krate` that are likely to enable `alloc`.
+fn alloc_features(krate: &ArtifactCrate) -> Vec<&str> {
+    (krate.features.iter())
+        .map(String::as_str)
+        .filter(|feature| *feature == "std" || feature.contains("alloc"))
+        .collect()
+}
+
+fn diagnostic(found: &[&str], crates: &[ArtifactCrate]) -> Option<String> {
+    if found.is_empty() {
+        return None;
+    }
+
+    let mut msg = format!(
+        "The target executable uses a heap allocator ({} found). \
+        This is usually caused by a dependency built with `alloc` or `std`.",
+        found.join(", ")
+    );
+
+    let suspects: Vec<String> = (crates.iter())
+        .filter_map(|krate| {
+            let features = alloc_features(krate);
+            if features.is_empty() {
+                None
+            } else {
+                Some(format!(
+                    "{} (features: {})",
+                    krate.name,
+                    features.join(", ")
+                ))
+            }
+        })
+        .collect();
+
+    if crates.is_empty() {
+        msg.push_str(
+            " The dependencies of the executable are unknown because it was not \
+            built by this run (it was reused from the build cache or specified \
+            by `--farcri-elf`). Use `--farcri-no-build-cache` to list the \
+            likely offending crates.",
+        );
+    } else if suspects.is_empty() {
+        msg.push_str(" No dependency was built with a feature that obviously enables it.");
+    } else {
+        msg.push_str(" The likely offending crates are:");
+        for suspect in suspects {
+            msg.push_str("\n  - ");
+            msg.push_str(&suspect);
+        }
+        msg.push_str(
+            "\nDisable these features by specifying `default-features = false` \
+            in the declarations of the dependencies in `Cargo.toml` (or of the \
+            dependencies enabling them, see `cargo tree -e features`).",
+        );
+    }
+
+    Some(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn krate(name: &str, features: &[&str]) -> ArtifactCrate {
+        ArtifactCrate {
+            name: name.to_owned(),
+            features: features.iter().map(|x| (*x).to_owned()).collect(),
+        }
+    }
+
+    #[test]
+    fn suspects() {
+        let crates = [
+            krate("log", &[]),
+            krate("serde", &["alloc", "derive"]),
+            krate("heapless", &["use_alloc"]),
+            krate("memchr", &["std"]),
+        ];
+        assert_eq!(diagnostic(&[], &crates), None);
+
+        let msg = diagnostic(&["__rust_alloc", "__rust_dealloc"], &crates).unwrap();
+        assert!(
+            msg.starts_with(
+                "The target executable uses a heap allocator (__rust_alloc, \
+                __rust_dealloc found)."
+            ),
+            "{}",
+            msg
+        );
+        assert!(
+            msg.contains(
+                "\n  - serde (features: alloc)\
+                \n  - heapless (features: use_alloc)\
+                \n  - memchr (features: std)\n"
+            ),
+            "{}",
+            msg
+        );
+        assert!(!msg.contains("- log"), "{}", msg);
+
+        let msg = diagnostic(&["__rust_alloc"], &[]).unwrap();
+        assert!(msg.contains("`--farcri-no-build-cache`"), "{}", msg);
+    }
+}
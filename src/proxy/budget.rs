@@ -0,0 +1,131 @@
+//! The time budget of the whole run (`--farcri-suite-time-budget`)
+use std::time::{Duration, Instant};
+
+use crate::bencher::protocol;
+
+#[derive(Debug)]
+pub(super) struct SuiteBudget {
+    limit: Duration,
+    start: Instant,
+    /// The longest time a benchmark took so far, used as the estimate of the
+    /// time the next one will take
+    longest: Duration,
+    /// When the current benchmark (or its measurement at the current
+    /// frequency) started
+    current_since: Option<Instant>,
+    /// The benchmarks skipped because of the budget
+    skipped: Vec<String>,
+}
+
+impl SuiteBudget {
+    /// Construct a `SuiteBudget` allowing the run starting at `start` to take
+    /// `limit`.
+    pub(super) fn new(limit: Duration, start: Instant) -> Self {
+        Self {
+            limit,
+            start,
+            longest: Duration::default(),
+            current_since: None,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Record a message received at `at`.
+    pub(super) fn record_message<Values>(
+        &mut self,
+        msg: &protocol::UpstreamMessage<String, Values>,
+        at: Instant,
+    ) {
+        use protocol::UpstreamMessage::*;
+        match msg {
+            RunningBenchmark { .. } | BeginningBenchmark { .. } if self.current_since.is_none() => {
+                self.current_since = Some(at);
+            }
+            MeasurementComplete { .. } | BenchmarkFailed { .. } | FinishedBenchmarkGroup | End => {
+                if let Some(since) = self.current_since.take() {
+                    self.longest = self.longest.max(at.saturating_duration_since(since));
+                }
+            }
+            SkippingBenchmark {
+                id,
+                reason: protocol::SkipReason::Budget,
+            } => {
+                self.skipped.push(id.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    /// Check if running another benchmark at `now` would exceed the budget.
+    pub(super) fn is_exhausted(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.start) + self.longest > self.limit
+    }
+
+    /// Get the IDs of the benchmarks skipped because of the budget.
+    pub(super) fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(function_id: &str) -> protocol::RawBenchmarkId<String> {
+        protocol::RawBenchmarkId {
+            group_id: "g".to_owned(),
+            function_id: Some(function_id.to_owned()),
+            value_str: None,
+            throughput: None,
+        }
+    }
+
+    #[test]
+    fn estimate_from_longest_benchmark() {
+        use protocol::UpstreamMessage::*;
+        let origin = Instant::now();
+        let at = |ms| origin + Duration::from_millis(ms);
+        let mut budget = SuiteBudget::new(Duration::from_millis(1000), origin);
+        let record = |budget: &mut SuiteBudget, msg: protocol::UpstreamMessage<String, ()>, ms| {
+            budget.record_message(&msg, at(ms));
+        };
+
+        record(&mut budget, RunningBenchmark { id: id("a") }, 0);
+        record(&mut budget, BeginningBenchmark { id: id("a") }, 10);
+        record(
+            &mut budget,
+            MeasurementComplete {
+                num_iters_per_sample: 1,
+                values: (),
+                benchmark_config: Default::default(),
+            },
+            400,
+        );
+        assert!(!budget.is_exhausted(at(400)));
+        assert!(!budget.is_exhausted(at(600)));
+        assert!(budget.is_exhausted(at(601)));
+
+        // A shorter benchmark doesn't lower the estimate
+        record(&mut budget, RunningBenchmark { id: id("b") }, 500);
+        record(&mut budget, FinishedBenchmarkGroup, 600);
+        assert!(budget.is_exhausted(at(601)));
+
+        record(
+            &mut budget,
+            SkippingBenchmark {
+                id: id("c"),
+                reason: protocol::SkipReason::Filter,
+            },
+            700,
+        );
+        record(
+            &mut budget,
+            SkippingBenchmark {
+                id: id("d"),
+                reason: protocol::SkipReason::Budget,
+            },
+            700,
+        );
+        assert_eq!(budget.skipped(), ["g/d"]);
+    }
+}
@@ -0,0 +1,370 @@
+//! Remembers the target executable built by the previous run so that
+//! consecutive runs with different filters don't have to invoke Cargo again
+//!
+//! The cache is stored in `target/farcri/build_cache.json`. It has an entry
+//! for each pair of a bench executable and a target, holding the location of
+//! the target executable built for them and a key derived from everything
+//! that affects the build:
+//!
+//!  - The image stamp (see `stamp`), which covers the source files of the
+//!    bench package and the build configuration (e.g., `RUSTFLAGS` and
+//!    Cargo features)
+//!  - The contents of `Cargo.lock`
+//!  - The modification time of the running bench executable, which is
+//!    rebuilt by Cargo when the dependencies or the toolchain change
+//!
+//! An entry is only used if the key matches and the target executable still
+//! has the recorded modification time and contents.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    cargo::{ArtifactCrate, ArtifactProfile, CompiledExecutable, ImageFormat},
+    utils::StableHasher,
+};
+
+/// The file name of the build cache
+pub(super) const FILE_NAME: &str = "build_cache.json";
+
+pub(super) struct BuildCache {
+    /// The path of the cache file
+    path: PathBuf,
+    /// The file name of the running bench executable
+    bench: String,
+    target: String,
+    key: String,
+}
+
+/// The inputs of [`compute_key`]
+#[derive(Debug, Clone)]
+struct KeyInputs<'a> {
+    image_stamp: &'a str,
+    lockfile: Option<&'a [u8]>,
+    /// The modification time of the running bench executable
+    bench_mtime_ns: Option<u64>,
+}
+
+/// The contents of the cache file
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheFile {
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Entry {
+    bench: String,
+    target: String,
+    key: String,
+    /// The path of the target executable
+    path: PathBuf,
+    /// The name of the bench target
+    name: String,
+    mtime_ns: u64,
+    /// The hash of the target executable's contents
+    hash: String,
+    profile: Option<ArtifactProfile>,
+    /// See [`CompiledExecutable::library_paths`]
+    library_paths: Vec<PathBuf>,
+    /// See [`CompiledExecutable::crates`]
+    crates: Vec<ArtifactCrate>,
+}
+
+impl BuildCache {
+    /// Prepare to look up or update the entry for the running bench
+    /// executable and `target` in the cache file at `path`.
+    pub(super) async fn open(path: PathBuf, target: &str, image_stamp: &str) -> Result<Self> {
+        let bench_exe =
+            std::env::current_exe().context("Failed to get the path of the running executable.")?;
+        let bench = bench_exe
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let bench_mtime_ns = match tokio::fs::metadata(&bench_exe).await {
+            Ok(metadata) => mtime_ns(&metadata),
+            Err(_) => None,
+        };
+
+        let lockfile = match find_lockfile() {
+            Some(lockfile) => Some(
+                tokio::fs::read(&lockfile)
+                    .await
+                    .with_context(|| format!("Failed to read '{}'.", lockfile.display()))?,
+            ),
+            None => None,
+        };
+
+        let key = compute_key(&KeyInputs {
+            image_stamp,
+            lockfile: lockfile.as_deref(),
+            bench_mtime_ns,
+        });
+
+        Ok(Self {
+            path,
+            bench,
+            target: target.to_owned(),
+            key,
+        })
+    }
+
+    /// Get the target executable recorded by a previous run if it's still
+    /// valid.
+    pub(super) async fn lookup(&self) -> Option<CompiledExecutable> {
+        let entries = match self.load().await {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::debug!("Ignoring the build cache: {:?}", e);
+                return None;
+            }
+        };
+
+        let entry = entries
+            .into_iter()
+            .find(|e| e.bench == self.bench && e.target == self.target)?;
+        if entry.key != self.key {
+            log::debug!("The build cache is stale");
+            return None;
+        }
+
+        let path = entry.path;
+        let (mtime_ns, hash) = match inspect(&path).await {
+            Ok(x) => x,
+            Err(e) => {
+                log::debug!("The cached executable is unusable: {:?}", e);
+                return None;
+            }
+        };
+        if mtime_ns != entry.mtime_ns || hash != entry.hash {
+            log::debug!("The cached executable '{}' was modified", path.display());
+            return None;
+        }
+
+        Some(CompiledExecutable {
+            path,
+            name: entry.name,
+            library_paths: entry.library_paths,
+            profile: entry.profile,
+            crates: entry.crates,
+            format: ImageFormat::Elf,
+        })
+    }
+
+    /// Record `exe` as the target executable for the current key.
+    pub(super) async fn store(&self, exe: &CompiledExecutable) -> Result<()> {
+        let (mtime_ns, hash) = inspect(&exe.path).await?;
+
+        // Keep the entries for other benches and targets
+        let mut entries = self.load().await.unwrap_or_default();
+        entries.retain(|e| !(e.bench == self.bench && e.target == self.target));
+        entries.push(Entry {
+            bench: self.bench.clone(),
+            target: self.target.clone(),
+            key: self.key.clone(),
+            path: exe.path.clone(),
+            name: exe.name.clone(),
+            mtime_ns,
+            hash,
+            profile: exe.profile.clone(),
+            library_paths: exe.library_paths.clone(),
+            crates: exe.crates.clone(),
+        });
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create '{}'.", parent.display()))?;
+        }
+        let json = serde_json::to_vec(&CacheFile { entries })
+            .context("Failed to serialize the build cache.")?;
+        tokio::fs::write(&self.path, json)
+            .await
+            .with_context(|| format!("Failed to write '{}'.", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Read the cache file.
+    async fn load(&self) -> Result<Vec<Entry>> {
+        let json = tokio::fs::read(&self.path)
+            .await
+            .with_context(|| format!("Failed to read '{}'.", self.path.display()))?;
+        let file: CacheFile = serde_json::from_slice(&json)
+            .with_context(|| format!("Failed to parse '{}'.", self.path.display()))?;
+        Ok(file.entries)
+    }
+}
+
+fn compute_key(inputs: &KeyInputs<'_>) -> String {
+    let mut hasher = StableHasher::new();
+    hasher.write_str(inputs.image_stamp);
+    match inputs.lockfile {
+        Some(lockfile) => {
+            hasher.write_u64(1);
+            hasher.write_bytes(lockfile);
+        }
+        None => hasher.write_u64(0),
+    }
+    match inputs.bench_mtime_ns {
+        Some(mtime_ns) => {
+            hasher.write_u64(1);
+            hasher.write_u64(mtime_ns);
+        }
+        None => hasher.write_u64(0),
+    }
+    hasher.finish_hex()
+}
+
+/// Find the `Cargo.lock` used to build the bench package, which might be in
+/// an ancestor directory if the package is a member of a workspace.
+fn find_lockfile() -> Option<PathBuf> {
+    let package_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR")?);
+    package_dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.is_file())
+}
+
+fn mtime_ns(metadata: &std::fs::Metadata) -> Option<u64> {
+    let mtime = metadata.modified().ok()?;
+    Some(mtime.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+/// Get the modification time and the hash of the contents of a file.
+async fn inspect(path: &Path) -> Result<(u64, String)> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("Failed to access '{}'.", path.display()))?;
+    let mtime_ns = mtime_ns(&metadata).context("The modification time is unavailable.")?;
+
+    let contents = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read '{}'.", path.display()))?;
+    let mut hasher = StableHasher::new();
+    hasher.write_bytes(&contents);
+
+    Ok((mtime_ns, hasher.finish_hex()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Serde;
+
+    #[test]
+    fn key_covers_inputs() {
+        let inputs = KeyInputs {
+            image_stamp: "0123456789abcdef",
+            lockfile: Some(b"# lockfile"),
+            bench_mtime_ns: Some(1),
+        };
+        let key = compute_key(&inputs);
+        assert_eq!(key, compute_key(&inputs.clone()));
+
+        let variants = [
+            KeyInputs {
+                image_stamp: "0123456789abcdee",
+                ..inputs.clone()
+            },
+            KeyInputs {
+                lockfile: Some(b"# lockfile 2"),
+                ..inputs.clone()
+            },
+            KeyInputs {
+                lockfile: None,
+                ..inputs.clone()
+            },
+            KeyInputs {
+                bench_mtime_ns: Some(2),
+                ..inputs.clone()
+            },
+        ];
+        for variant in variants.iter() {
+            assert_ne!(compute_key(variant), key, "{:?}", variant);
+        }
+    }
+
+    fn cache(dir: &Path, target: &str, key: &str) -> BuildCache {
+        BuildCache {
+            path: dir.join("farcri").join(FILE_NAME),
+            bench: "sort-0123456789abcdef".to_owned(),
+            target: target.to_owned(),
+            key: key.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn store_and_validate() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        // Exercise the escaping of the path
+        let exe_path = dir.path().join("so\"rt\\1");
+        std::fs::write(&exe_path, b"ELF").unwrap();
+        let exe = CompiledExecutable {
+            path: exe_path.clone(),
+            name: "sort".to_owned(),
+            library_paths: vec![dir.path().join("deps"), dir.path().join("out\\\"")],
+            crates: vec![
+                ArtifactCrate {
+                    name: "farcri".to_owned(),
+                    features: vec!["role_target".to_owned(), "fpu".to_owned()],
+                },
+                ArtifactCrate {
+                    name: "b\"ar".to_owned(),
+                    features: Vec::new(),
+                },
+            ],
+            profile: Some(ArtifactProfile {
+                opt_level: Serde("3".to_owned()),
+                debuginfo: None,
+                debug_assertions: false,
+                overflow_checks: false,
+            }),
+            format: ImageFormat::Elf,
+        };
+
+        let host = cache(dir.path(), "host", "k1");
+        assert!(host.lookup().await.is_none());
+        host.store(&exe).await.unwrap();
+        cache(dir.path(), "qemu", "k1").store(&exe).await.unwrap();
+
+        let cached = host.lookup().await.unwrap();
+        assert_eq!(cached.path, exe.path);
+        assert_eq!(cached.name, exe.name);
+        assert_eq!(cached.profile, exe.profile);
+        assert_eq!(cached.library_paths, exe.library_paths);
+        assert_eq!(cached.crates, exe.crates);
+
+        // A different key (e.g., a modified source file)
+        assert!(cache(dir.path(), "host", "k2").lookup().await.is_none());
+
+        // The executable was modified
+        std::fs::write(&exe_path, b"ELF2").unwrap();
+        assert!(host.lookup().await.is_none());
+        host.store(&exe).await.unwrap();
+        assert!(host.lookup().await.is_some());
+
+        // The executable was removed
+        std::fs::remove_file(&exe_path).unwrap();
+        assert!(host.lookup().await.is_none());
+        assert!(cache(dir.path(), "qemu", "k1").lookup().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn corrupted_cache_file() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        let exe_path = dir.path().join("sort");
+        std::fs::write(&exe_path, b"ELF").unwrap();
+        let exe = CompiledExecutable::prebuilt(exe_path);
+
+        let host = cache(dir.path(), "host", "k1");
+        std::fs::create_dir_all(host.path.parent().unwrap()).unwrap();
+        std::fs::write(&host.path, "{\"entries\":[{").unwrap();
+        assert!(host.lookup().await.is_none());
+
+        // It's overwritten
+        host.store(&exe).await.unwrap();
+        assert!(host.lookup().await.is_some());
+    }
+}
@@ -0,0 +1,306 @@
+//! Build descriptors, which record how the Target program was built
+//!
+//! The Proxy program passes the build descriptor to the build through the
+//! environment variable [`protocol::BUILD_DESCRIPTOR_ENV`], and the Target
+//! program embeds it in the ELF note [`protocol::BUILD_NOTE_SECTION`]
+//! (`FARCRI_BUILD_NOTE`), so the executable describes itself. After building
+//! the executable, the Proxy program reads the note back and compares it
+//! with the build descriptor it computed, which catches a stale executable.
+//!
+//! The build descriptor is encoded as `key=value` lines, e.g.:
+//!
+//! ```text
+//! features=farcri/role_target,farcri/fpu,farcri/max_level_info
+//! rustflags=-C target-feature=+fp-armv8d16
+//! target=thumbv7em-none-eabihf
+//! build_std=true
+//! ```
+//!
+//! The note has a fixed size ([`protocol::BUILD_DESCRIPTOR_LEN`]). A build
+//! descriptor that doesn't fit isn't embedded.
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use tokio::task::spawn_blocking;
+
+use crate::bencher::protocol;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct BuildDescriptor {
+    /// The Cargo features passed to Cargo (e.g., `farcri/role_target`)
+    pub features: Vec<String>,
+    /// `RUSTFLAGS`
+    pub rustflags: String,
+    /// The target triple, or `None` for the host
+    pub target_triple: Option<String>,
+    /// Whether `-Zbuild-std=core` was specified
+    pub build_std: bool,
+}
+
+impl BuildDescriptor {
+    /// Encode `self`. Returns `None` if the result doesn't fit in the note.
+    pub(super) fn encode(&self) -> Option<String> {
+        let text = format!(
+            "features={}\nrustflags={}\ntarget={}\nbuild_std={}",
+            self.features.join(","),
+            self.rustflags.replace('\n', " "),
+            self.target_triple.as_deref().unwrap_or(""),
+            self.build_std,
+        );
+        if text.len() > protocol::BUILD_DESCRIPTOR_LEN {
+            None
+        } else {
+            Some(text)
+        }
+    }
+
+    /// Decode a build descriptor encoded by [`Self::encode`]. Unknown keys
+    /// are ignored.
+    pub(super) fn decode(text: &str) -> Result<Self> {
+        let mut features = None;
+        let mut rustflags = None;
+        let mut target_triple = None;
+        let mut build_std = None;
+        for line in text.lines() {
+            let (key, value) = match line.find('=') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => bail!("Malformed line: {:?}", line),
+            };
+            match key {
+                "features" => {
+                    features = Some(
+                        value
+                            .split(',')
+                            .filter(|x| !x.is_empty())
+                            .map(str::to_owned)
+                            .collect(),
+                    )
+                }
+                "rustflags" => rustflags = Some(value.to_owned()),
+                "target" if value.is_empty() => target_triple = Some(None),
+                "target" => target_triple = Some(Some(value.to_owned())),
+                "build_std" => {
+                    build_std = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("Invalid `build_std`: {:?}", value))?,
+                    )
+                }
+                _ => log::debug!("Ignoring an unknown build descriptor key {:?}", key),
+            }
+        }
+        let missing = |key: &str| anyhow::anyhow!("The key `{}` is missing.", key);
+        Ok(Self {
+            features: features.ok_or_else(|| missing("features"))?,
+            rustflags: rustflags.ok_or_else(|| missing("rustflags"))?,
+            target_triple: target_triple.ok_or_else(|| missing("target"))?,
+            build_std: build_std.ok_or_else(|| missing("build_std"))?,
+        })
+    }
+}
+
+/// Read the build descriptor embedded in the specified executable and
+/// compare it with `expected`, the one the Proxy program passed to the build.
+/// Returns the embedded build descriptor, or `expected` if there's none.
+pub(super) async fn check(
+    exe: &Path,
+    expected: Option<&BuildDescriptor>,
+) -> Result<Option<BuildDescriptor>> {
+    let elf_bytes = tokio::fs::read(exe)
+        .await
+        .with_context(|| format!("Failed to read '{}'.", exe.display()))?;
+    let exe_desc = spawn_blocking(move || read_from_elf(&elf_bytes))
+        .await
+        .unwrap()?;
+
+    match (exe_desc, expected) {
+        (None, Some(expected)) => {
+            log::warn!(
+                "Couldn't find the build descriptor in the executable. \
+                Skipping the build descriptor check."
+            );
+            Ok(Some(expected.clone()))
+        }
+        (None, None) => Ok(None),
+        // Compare the encoded forms, which `encode` may have normalized
+        (Some(exe_desc), Some(expected)) if exe_desc.encode() != expected.encode() => {
+            bail!(
+                "The executable '{}' was built with a different configuration \
+                ({:?}) than requested ({:?}). The executable might be stale.",
+                exe.display(),
+                exe_desc,
+                expected,
+            );
+        }
+        (Some(exe_desc), _) => {
+            log::debug!("Build descriptor: {:?}", exe_desc);
+            Ok(Some(exe_desc))
+        }
+    }
+}
+
+/// Read the build descriptor embedded in the specified ELF image. Returns
+/// `None` if there's none, or it's empty because it didn't fit in the note.
+fn read_from_elf(elf_bytes: &[u8]) -> Result<Option<BuildDescriptor>> {
+    let elf = match goblin::elf::Elf::parse(elf_bytes) {
+        Ok(elf) => elf,
+        // The executable built for the host might not be an ELF file
+        Err(goblin::error::Error::BadMagic(_)) => return Ok(None),
+        Err(e) => return Err(e).context("Failed to parse the executable."),
+    };
+
+    let notes = match elf.iter_note_sections(elf_bytes, Some(protocol::BUILD_NOTE_SECTION)) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    for note in notes {
+        let note = note.context("Failed to parse the build descriptor note.")?;
+        if note.name != protocol::BUILD_NOTE_NAME || note.n_type != protocol::BUILD_NOTE_TYPE {
+            continue;
+        }
+
+        let len = note
+            .desc
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(note.desc.len());
+        if len == 0 {
+            return Ok(None);
+        }
+        let text =
+            std::str::from_utf8(&note.desc[..len]).context("The build descriptor is malformed.")?;
+        return BuildDescriptor::decode(text)
+            .map(Some)
+            .context("The build descriptor is malformed.");
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::testelf::{self, ElfBuilder};
+    use goblin::elf::section_header::{SHT_NOTE, SHT_PROGBITS};
+
+    fn descriptor() -> BuildDescriptor {
+        BuildDescriptor {
+            features: vec![
+                "farcri/role_target".to_owned(),
+                "farcri/fpu".to_owned(),
+                "farcri/max_level_info".to_owned(),
+            ],
+            rustflags: "-C target-feature=+fp-armv8d16 -C link-arg=-Tlink.x".to_owned(),
+            target_triple: Some("thumbv7em-none-eabihf".to_owned()),
+            build_std: true,
+        }
+    }
+
+    /// Build a minimal executable image with a `.note.farcri` section laid
+    /// out in the same way as `FARCRI_BUILD_NOTE`, which holds `desc`.
+    fn fixture_image(little_endian: bool, name: &str, ty: u32, desc: &str) -> Vec<u8> {
+        let mut elf = ElfBuilder::new(false, little_endian);
+        let mut note = Vec::new();
+        elf.put(&mut note, name.len() as u64 + 1, 4);
+        elf.put(&mut note, protocol::BUILD_DESCRIPTOR_LEN as u64, 4);
+        elf.put(&mut note, ty.into(), 4);
+        note.extend_from_slice(name.as_bytes());
+        note.push(0);
+        testelf::pad(&mut note, 4);
+        note.extend_from_slice(desc.as_bytes());
+        note.resize(note.len() + protocol::BUILD_DESCRIPTOR_LEN - desc.len(), 0);
+
+        elf.section(".text", SHT_PROGBITS, 0x1000, vec![0; 16]);
+        elf.section(protocol::BUILD_NOTE_SECTION, SHT_NOTE, 0x2000, note);
+        elf.build()
+    }
+
+    #[test]
+    fn encode_decode() {
+        let desc = descriptor();
+        let text = desc.encode().unwrap();
+        assert_eq!(BuildDescriptor::decode(&text).unwrap(), desc);
+
+        let host = BuildDescriptor {
+            features: vec![],
+            rustflags: String::new(),
+            target_triple: None,
+            build_std: false,
+        };
+        let text = host.encode().unwrap();
+        assert_eq!(BuildDescriptor::decode(&text).unwrap(), host);
+
+        // Unknown keys are ignored, missing ones aren't
+        let text = format!("{}\nfuture_key=1", desc.encode().unwrap());
+        assert_eq!(BuildDescriptor::decode(&text).unwrap(), desc);
+        assert!(BuildDescriptor::decode("features=\nrustflags=").is_err());
+        assert!(BuildDescriptor::decode("garbage").is_err());
+    }
+
+    #[test]
+    fn too_long() {
+        let desc = BuildDescriptor {
+            features: vec!["x".repeat(protocol::BUILD_DESCRIPTOR_LEN)],
+            ..descriptor()
+        };
+        assert_eq!(desc.encode(), None);
+    }
+
+    #[test]
+    fn read_note() {
+        let desc = descriptor();
+        for &little_endian in &[false, true] {
+            let image = fixture_image(
+                little_endian,
+                protocol::BUILD_NOTE_NAME,
+                protocol::BUILD_NOTE_TYPE,
+                &desc.encode().unwrap(),
+            );
+            assert_eq!(
+                read_from_elf(&image).unwrap().as_ref(),
+                Some(&desc),
+                "little_endian = {}",
+                little_endian
+            );
+        }
+    }
+
+    #[test]
+    fn no_note() {
+        // Not an ELF file
+        let mut image = b"MZ".to_vec();
+        image.resize(256, 0);
+        assert_eq!(read_from_elf(&image).unwrap(), None);
+
+        // No `.note.farcri`
+        let mut elf = ElfBuilder::new(false, true);
+        elf.section(".text", SHT_PROGBITS, 0x1000, vec![0; 16]);
+        assert_eq!(read_from_elf(&elf.build()).unwrap(), None);
+
+        // Built without a build descriptor
+        let image = fixture_image(
+            true,
+            protocol::BUILD_NOTE_NAME,
+            protocol::BUILD_NOTE_TYPE,
+            "",
+        );
+        assert_eq!(read_from_elf(&image).unwrap(), None);
+
+        // Someone else's note
+        let text = descriptor().encode().unwrap();
+        let image = fixture_image(true, "GNU", protocol::BUILD_NOTE_TYPE, &text);
+        assert_eq!(read_from_elf(&image).unwrap(), None);
+        let image = fixture_image(true, protocol::BUILD_NOTE_NAME, 42, &text);
+        assert_eq!(read_from_elf(&image).unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_note() {
+        let image = fixture_image(
+            true,
+            protocol::BUILD_NOTE_NAME,
+            protocol::BUILD_NOTE_TYPE,
+            "features=farcri/role_target",
+        );
+        assert!(read_from_elf(&image).is_err());
+    }
+}
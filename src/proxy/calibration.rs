@@ -0,0 +1,222 @@
+//! Remembers the calibration measured by the Target program so that later
+//! runs don't have to measure it again
+//!
+//! The calibration of each target is stored in
+//! `target/farcri/calibration/<target>.json`. It currently consists of the
+//! loop overhead (`--farcri-loop-overhead`), which only depends on the
+//! target and the code generated for `Bencher::iter`. A stored calibration
+//! is only used if it was measured with the same version of FarCri.rs and
+//! the same build configuration (e.g., `RUSTFLAGS` and Cargo features), but
+//! not necessarily the same benchmark code. `--farcri-recalibrate` measures
+//! it again regardless.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use crate::bencher::protocol;
+
+/// The name of the directory containing the calibration files
+pub(super) const DIR_NAME: &str = "calibration";
+
+/// The values measured by the Target program
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct Calibration {
+    pub(super) loop_overhead: Option<protocol::LoopOverhead>,
+}
+
+/// The contents of a calibration file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct CalibrationFile {
+    /// The version of FarCri.rs that measured the values
+    farcri_version: String,
+    /// See [`build_key`]
+    build_key: String,
+    loop_overhead: Option<StoredLoopOverhead>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct StoredLoopOverhead {
+    iters: u64,
+    value: u64,
+}
+
+pub(super) struct CalibrationStore {
+    /// The path of the calibration file
+    path: PathBuf,
+    build_key: String,
+}
+
+impl CalibrationStore {
+    /// Prepare to load or store the calibration of `target` in `dir`.
+    /// `build_config` is the build configuration of the target executable.
+    pub(super) fn new(dir: &Path, target: &str, build_config: &[String]) -> Self {
+        Self {
+            path: dir.join(format!("{}.json", file_stem(target))),
+            build_key: build_key(build_config),
+        }
+    }
+
+    /// Get the calibration stored by a previous run if it's still valid.
+    pub(super) async fn load(&self) -> Option<Calibration> {
+        let file = match self.load_file().await {
+            Ok(file) => file,
+            Err(e) => {
+                log::debug!("Ignoring the stored calibration: {:?}", e);
+                return None;
+            }
+        };
+
+        if !is_fresh(&file, env!("CARGO_PKG_VERSION"), &self.build_key) {
+            log::debug!("The stored calibration is stale");
+            return None;
+        }
+
+        Some(Calibration {
+            loop_overhead: file.loop_overhead.map(|x| protocol::LoopOverhead {
+                iters: x.iters,
+                value: x.value,
+            }),
+        })
+    }
+
+    /// Replace the stored calibration with `calibration`.
+    pub(super) async fn store(&self, calibration: &Calibration) -> Result<()> {
+        let file = CalibrationFile {
+            farcri_version: env!("CARGO_PKG_VERSION").to_owned(),
+            build_key: self.build_key.clone(),
+            loop_overhead: calibration.loop_overhead.map(|x| StoredLoopOverhead {
+                iters: x.iters,
+                value: x.value,
+            }),
+        };
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create '{}'.", parent.display()))?;
+        }
+        let json = serde_json::to_vec(&file).context("Failed to serialize the calibration.")?;
+        tokio::fs::write(&self.path, json)
+            .await
+            .with_context(|| format!("Failed to write '{}'.", self.path.display()))?;
+        Ok(())
+    }
+
+    async fn load_file(&self) -> Result<CalibrationFile> {
+        let json = tokio::fs::read(&self.path)
+            .await
+            .with_context(|| format!("Failed to read '{}'.", self.path.display()))?;
+        serde_json::from_slice(&json)
+            .with_context(|| format!("Failed to parse '{}'.", self.path.display()))
+    }
+}
+
+/// Check if the calibration in `file` was measured by FarCri.rs
+/// `farcri_version` with the build configuration identified by `build_key`.
+fn is_fresh(file: &CalibrationFile, farcri_version: &str, build_key: &str) -> bool {
+    file.farcri_version == farcri_version && file.build_key == build_key
+}
+
+fn build_key(build_config: &[String]) -> String {
+    let mut hasher = DefaultHasher::new();
+    build_config.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Make a target name usable as a file name.
+fn file_stem(target: &str) -> String {
+    target
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(loop_overhead: Option<StoredLoopOverhead>) -> CalibrationFile {
+        CalibrationFile {
+            farcri_version: "0.1.0".to_owned(),
+            build_key: build_key(&["-C opt-level=3".to_owned()]),
+            loop_overhead,
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        for &loop_overhead in [
+            None,
+            Some(StoredLoopOverhead {
+                iters: 1000,
+                value: 4001,
+            }),
+        ]
+        .iter()
+        {
+            let file = file(loop_overhead);
+            let json = serde_json::to_string(&file).unwrap();
+            let parsed: CalibrationFile = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, file, "{}", json);
+        }
+    }
+
+    #[test]
+    fn staleness() {
+        let file = file(None);
+        let key = build_key(&["-C opt-level=3".to_owned()]);
+        assert!(is_fresh(&file, "0.1.0", &key));
+        assert!(!is_fresh(&file, "0.1.1", &key));
+        assert!(!is_fresh(
+            &file,
+            "0.1.0",
+            &build_key(&["-C opt-level=s".to_owned()])
+        ));
+    }
+
+    #[tokio::test]
+    async fn store_and_load() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        let build_config = ["-C opt-level=3".to_owned()];
+        let store = CalibrationStore::new(dir.path(), "probe-rs:STM32F401RE", &build_config);
+        assert_eq!(store.path, dir.path().join("probe-rs_STM32F401RE.json"));
+        assert!(store.load().await.is_none());
+
+        let loop_overhead = protocol::LoopOverhead {
+            iters: 1000,
+            value: 4001,
+        };
+        store
+            .store(&Calibration {
+                loop_overhead: Some(loop_overhead),
+            })
+            .await
+            .unwrap();
+        let loaded = store.load().await.unwrap().loop_overhead.unwrap();
+        assert_eq!((loaded.iters, loaded.value), (1000, 4001));
+
+        // A different build configuration
+        let other = CalibrationStore::new(
+            dir.path(),
+            "probe-rs:STM32F401RE",
+            &["-C opt-level=s".to_owned()],
+        );
+        assert!(other.load().await.is_none());
+
+        // A corrupted file is ignored and then overwritten
+        std::fs::write(&store.path, "{\"farcri_version\":").unwrap();
+        assert!(store.load().await.is_none());
+        store.store(&Calibration::default()).await.unwrap();
+        assert!(store.load().await.unwrap().loop_overhead.is_none());
+    }
+}
@@ -0,0 +1,129 @@
+//! Checks against the capacities of the Target program's buffers
+use crate::bencher::protocol;
+
+/// Get the warnings about the parts of `config` exceeding `capacities`.
+pub(super) fn check_config(
+    capacities: &protocol::TargetCapacities,
+    config: &protocol::TargetConfig<String>,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    // Streamed samples aren't limited by the sample buffer
+    let streamed = config.feature_bits & protocol::FEATURE_STREAMED_SAMPLES != 0;
+    if let Some(sample_size) = config.bench_config_overrides.sample_size {
+        if sample_size > capacities.max_samples as usize && !streamed {
+            warnings.push(format!(
+                "`--sample-size {}` exceeds the capacity of the Target program \
+                ({} samples). Each benchmark will collect {} samples instead.{}",
+                sample_size, capacities.max_samples, capacities.max_samples, LARGE_SAMPLES_HINT,
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Appended to a warning about the sample size. (`farcri` is built with the
+/// same features in both modes, so the `large_samples` suggestion is omitted
+/// if it's already taken.)
+pub(super) const LARGE_SAMPLES_HINT: &str = if cfg!(feature = "large_samples") {
+    " Pass `--farcri-stream-samples` to lift the limit."
+} else {
+    " Enable the `large_samples` feature of `farcri` to raise the limit, or \
+    pass `--farcri-stream-samples` to lift it."
+};
+
+/// Get the names of the components of `id` that might have been truncated
+/// because they fill up the Target program's buffers.
+pub(super) fn possibly_truncated_components(
+    capacities: &protocol::TargetCapacities,
+    id: &protocol::RawBenchmarkId<String>,
+) -> Vec<&'static str> {
+    // The Target program truncates strings at a scalar boundary, so up to 3
+    // bytes might be left unused
+    let fills = |s: &str, capacity: u32| s.len() + 3 >= capacity as usize;
+
+    let mut components = Vec::new();
+    if fills(&id.group_id, capacities.max_group_id_len) {
+        components.push("group name");
+    }
+    if let Some(x) = &id.function_id {
+        if fills(x, capacities.max_function_id_len) {
+            components.push("function name");
+        }
+    }
+    if let Some(x) = &id.value_str {
+        if fills(x, capacities.max_value_str_len) {
+            components.push("parameter");
+        }
+    }
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAPACITIES: protocol::TargetCapacities = protocol::TargetCapacities {
+        link_buffer_len: 1024,
+        max_samples: 128,
+        max_group_id_len: 8,
+        max_function_id_len: 8,
+        max_value_str_len: 8,
+    };
+
+    fn config(sample_size: Option<usize>, feature_bits: u32) -> protocol::TargetConfig<String> {
+        protocol::TargetConfig {
+            protocol_version: protocol::PROTOCOL_VERSION,
+            mode: protocol::Mode::Benchmark,
+            bench_config_overrides: protocol::BenchmarkConfigOverrides {
+                sample_size,
+                ..Default::default()
+            },
+            name_filters: Default::default(),
+            frequencies: Default::default(),
+            skip_benchmarks: 0,
+            feature_bits,
+        }
+    }
+
+    #[test]
+    fn sample_size() {
+        assert!(check_config(&CAPACITIES, &config(None, 0)).is_empty());
+        assert!(check_config(&CAPACITIES, &config(Some(128), 0)).is_empty());
+        let streamed = protocol::FEATURE_STREAMED_SAMPLES;
+        assert!(check_config(&CAPACITIES, &config(Some(129), streamed)).is_empty());
+
+        let warnings = check_config(&CAPACITIES, &config(Some(129), 0));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with(
+            "`--sample-size 129` exceeds the capacity of the Target program (128 samples)."
+        ));
+    }
+
+    #[test]
+    fn truncated_components() {
+        let id = |group_id: &str, function_id: Option<&str>, value_str: Option<&str>| {
+            protocol::RawBenchmarkId {
+                group_id: group_id.to_owned(),
+                function_id: function_id.map(str::to_owned),
+                value_str: value_str.map(str::to_owned),
+                throughput: None,
+            }
+        };
+
+        assert!(possibly_truncated_components(&CAPACITIES, &id("gggg", None, None)).is_empty());
+        assert!(
+            possibly_truncated_components(&CAPACITIES, &id("g", Some("ffff"), Some("pppp")))
+                .is_empty()
+        );
+        assert_eq!(
+            possibly_truncated_components(&CAPACITIES, &id("ggggg", None, None)),
+            ["group name"]
+        );
+        assert_eq!(
+            possibly_truncated_components(&CAPACITIES, &id("g", Some("ffffffff"), Some("ppppp"))),
+            ["function name", "parameter"]
+        );
+    }
+}
@@ -2,39 +2,73 @@
 //!
 //! [cargo-criterion]: https://github.com/bheisler/cargo-criterion
 use anyhow::{bail, Context, Result};
-use std::convert::TryFrom;
+use std::{convert::TryFrom, io};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream},
     net::TcpStream,
     time,
 };
 
-use crate::{bencher::protocol, proxy::targetlink::TargetLink};
+use crate::{
+    bencher::protocol,
+    proxy::{
+        error::ProtocolViolation,
+        formatter::{SelectedFormatter, ValueFormatter},
+        targetlink::{TargetLink, REPLY_TIMEOUT},
+    },
+};
 
-mod ccprotocol;
+pub(super) mod ccprotocol;
+#[cfg(test)]
+mod mock;
 
+/// Proxy the messages between the Target program and cargo-criterion.
+/// `formatter` answers cargo-criterion's requests to format measured values.
 pub(super) async fn run_frontend(
-    mut target_link: TargetLink<impl AsyncRead + AsyncWrite>,
-    mut cc_stream: TcpStream,
+    target_link: &mut TargetLink<impl AsyncRead + AsyncWrite>,
+    cc_stream: TcpStream,
+    formatter: &dyn ValueFormatter,
 ) -> Result<()> {
     let mut cc_link = CcLink::new(cc_stream).await?;
 
+    let result = proxy_messages(target_link, &mut cc_link, formatter).await;
+
+    if let Err(e) = &result {
+        if e.downcast_ref::<Disconnected>().is_some() {
+            // The Target program might be waiting for `Continue` after
+            // `MeasurementComplete` or `FinishedBenchmarkGroup`. Release it
+            // so that it doesn't hang. (If it isn't, `Continue` is just
+            // deferred and discarded.)
+            target_link
+                .send(&protocol::DownstreamMessage::Continue)
+                .await?;
+        }
+    }
+
+    result
+}
+
+async fn proxy_messages(
+    target_link: &mut TargetLink<impl AsyncRead + AsyncWrite>,
+    cc_link: &mut CcLink,
+    formatter: &dyn ValueFormatter,
+) -> Result<()> {
     // Start proxying messages
-    let origin = std::time::Instant::now();
     let mut current_group = None;
     let mut current_benchmark = None;
+    let mut frequency = None;
+    let mut value_format = protocol::ValueFormat::Native;
+    let mut sample_timestamps = None;
+    let mut effective_num_samples = None;
+    let mut test_failures = Vec::new();
+    let mut benchmark_failures = Vec::new();
     loop {
         // Read from target
-        let msg = time::timeout(time::Duration::from_secs(20), target_link.recv())
-            .await
-            .map_err(|_| anyhow::anyhow!("Timed out while waiting for a downstream message."))??;
+        let msg = target_link.recv_timeout(REPLY_TIMEOUT).await?;
 
         match msg {
             protocol::UpstreamMessage::GetInstant => {
-                let instant = protocol::Instant::from_nanos(origin.elapsed().as_nanos() as u64);
-                target_link
-                    .send(&protocol::DownstreamMessage::Instant(instant))
-                    .await?;
+                target_link.send_instant().await?;
                 continue;
             }
 
@@ -42,6 +76,22 @@ pub(super) async fn run_frontend(
                 break;
             }
 
+            protocol::UpstreamMessage::TestFailed { id, message } => {
+                // cargo-criterion doesn't run tests
+                test_failures.push((id.to_string(), message));
+            }
+
+            // `Panicked` is turned into an error and `SampleChunk` is merged
+            // into `MeasurementComplete` by `TargetLink::recv`
+            protocol::UpstreamMessage::GreetingReply { .. }
+            | protocol::UpstreamMessage::EchoReply { .. }
+            | protocol::UpstreamMessage::SampleChunk { .. }
+            | protocol::UpstreamMessage::Panicked { .. } => {
+                return Err(
+                    anyhow::anyhow!("Unexpected message: {:?}", msg).context(ProtocolViolation)
+                );
+            }
+
             protocol::UpstreamMessage::BeginningBenchmarkGroup { group } => {
                 cc_link
                     .send(&ccprotocol::OutgoingMessage::BeginningBenchmarkGroup { group: &group })
@@ -58,12 +108,26 @@ pub(super) async fn run_frontend(
                     })
                     .await?;
 
-                serve_value_formatter(&mut cc_link).await?;
+                serve_value_formatter(cc_link, &SelectedFormatter::new(formatter, value_format))
+                    .await?;
                 target_link
                     .send(&protocol::DownstreamMessage::Continue)
                     .await?;
             }
-            protocol::UpstreamMessage::BeginningBenchmark { id } => {
+            protocol::UpstreamMessage::FrequencyChanged { effective, .. } => {
+                frequency = Some(effective);
+            }
+            protocol::UpstreamMessage::ValueFormatChanged { format } => {
+                value_format = format;
+            }
+            protocol::UpstreamMessage::BenchmarkTags { .. } => {
+                // cargo-criterion doesn't know about this, and its output
+                // has no place for tags
+            }
+            protocol::UpstreamMessage::BeginningBenchmark { mut id } => {
+                if let Some(hz) = frequency.take() {
+                    super::tag_frequency(&mut id, hz);
+                }
                 let id = ccprotocol::RawBenchmarkId::from(&id);
 
                 cc_link
@@ -73,11 +137,28 @@ pub(super) async fn run_frontend(
                 assert!(current_benchmark.is_none());
                 current_benchmark = Some(id);
             }
-            protocol::UpstreamMessage::SkippingBenchmark { id } => {
+            protocol::UpstreamMessage::SkippingBenchmark { id, .. } => {
                 cc_link
                     .send(&ccprotocol::OutgoingMessage::SkippingBenchmark { id: (&id).into() })
                     .await?;
             }
+            protocol::UpstreamMessage::IdTruncated {
+                id,
+                group_id_len,
+                function_id_len,
+                value_str_len,
+            } => {
+                // cargo-criterion doesn't know about this
+                super::log_id_truncated(&id, group_id_len, function_id_len, value_str_len);
+            }
+            protocol::UpstreamMessage::RunningBenchmark { .. } => {
+                // Only used by `TargetLink` to track progress
+            }
+            protocol::UpstreamMessage::ListedBenchmark { id } => {
+                // cargo-criterion doesn't know about this. Print it in the
+                // same format as Criterion.rs.
+                println!("{}: benchmark", id);
+            }
             protocol::UpstreamMessage::Warmup {
                 warm_up_goal_duration,
             } => {
@@ -88,11 +169,16 @@ pub(super) async fn run_frontend(
                     })
                     .await?;
             }
+            protocol::UpstreamMessage::WarmupProgress { .. }
+            | protocol::UpstreamMessage::Progress { .. } => {
+                // cargo-criterion has no equivalent
+            }
             protocol::UpstreamMessage::MeasurementStart {
                 warm_up_iter_count,
                 warm_up_duration,
                 num_samples,
                 num_iters,
+                ..
             } => {
                 let ns_per_iter = warm_up_duration.as_nanos() as f64 / warm_up_iter_count as f64;
                 let estimate_ns = ns_per_iter * num_iters as f64;
@@ -105,11 +191,50 @@ pub(super) async fn run_frontend(
                     })
                     .await?;
             }
+            protocol::UpstreamMessage::SampleSizeClamped {
+                requested,
+                effective,
+            } => {
+                // cargo-criterion doesn't know about this
+                super::log_sample_size_clamped(requested, effective);
+                effective_num_samples = Some(effective);
+            }
+            protocol::UpstreamMessage::MeasurementWarning { kind } => {
+                // cargo-criterion doesn't know about this
+                super::log_measurement_warning(kind);
+            }
+            protocol::UpstreamMessage::SampleTimestamps { timestamps } => {
+                // cargo-criterion doesn't know about them
+                sample_timestamps = Some(timestamps);
+            }
+            protocol::UpstreamMessage::BenchmarkFailed { id, message } => {
+                // cargo-criterion doesn't know about this. It has already been
+                // told that the benchmark began, which it doesn't mind being
+                // left unfinished.
+                current_benchmark = None;
+                log::error!("The benchmark '{}' failed: {}", id, message);
+                benchmark_failures.push((id.to_string(), message));
+                sample_timestamps = None;
+                effective_num_samples = None;
+                target_link
+                    .send(&protocol::DownstreamMessage::Continue)
+                    .await?;
+            }
             protocol::UpstreamMessage::MeasurementComplete {
                 num_iters_per_sample,
                 values,
                 benchmark_config,
             } => {
+                super::log_sample_count(
+                    values.len(),
+                    effective_num_samples
+                        .take()
+                        .unwrap_or(benchmark_config.sample_size),
+                );
+                if let Some(timestamps) = sample_timestamps.take() {
+                    super::log_sample_drift(&values, &timestamps);
+                }
+
                 let iters = vec![num_iters_per_sample as f64; values.len()];
                 let times: Vec<_> = values.iter().map(|&x| x as f64).collect();
                 let plot_config = ccprotocol::PlotConfiguration {
@@ -127,7 +252,8 @@ pub(super) async fn run_frontend(
                     })
                     .await?;
 
-                serve_value_formatter(&mut cc_link).await?;
+                serve_value_formatter(cc_link, &SelectedFormatter::new(formatter, value_format))
+                    .await?;
                 target_link
                     .send(&protocol::DownstreamMessage::Continue)
                     .await?;
@@ -135,15 +261,13 @@ pub(super) async fn run_frontend(
         }
     }
 
-    Ok(())
+    let benchmark_result = super::report_benchmark_failures(&benchmark_failures);
+    super::report_test_failures(&test_failures).and(benchmark_result)
 }
 
-async fn serve_value_formatter(cc_link: &mut CcLink) -> Result<()> {
-    use super::formatter::ValueFormatter;
-    let formatter = super::formatter::CyclesFormatter;
-
+async fn serve_value_formatter(cc_link: &mut CcLink, formatter: &dyn ValueFormatter) -> Result<()> {
     loop {
-        let response = match cc_link.recv().await? {
+        let response = match cc_link.recv_formatter_request().await? {
             ccprotocol::IncomingMessage::FormatValue { value } => {
                 ccprotocol::OutgoingMessage::FormattedValue {
                     value: formatter.format_value(value),
@@ -151,7 +275,7 @@ async fn serve_value_formatter(cc_link: &mut CcLink) -> Result<()> {
             }
             ccprotocol::IncomingMessage::FormatThroughput { value, throughput } => {
                 ccprotocol::OutgoingMessage::FormattedValue {
-                    value: formatter.format_throughput(&throughput, value),
+                    value: formatter.format_throughput(&throughput.into(), value),
                 }
             }
             ccprotocol::IncomingMessage::ScaleValues {
@@ -169,7 +293,8 @@ async fn serve_value_formatter(cc_link: &mut CcLink) -> Result<()> {
                 throughput,
                 mut values,
             } => {
-                let unit = formatter.scale_throughputs(typical_value, &throughput, &mut values);
+                let unit =
+                    formatter.scale_throughputs(typical_value, &throughput.into(), &mut values);
                 ccprotocol::OutgoingMessage::ScaledValues {
                     unit,
                     scaled_values: values,
@@ -183,7 +308,11 @@ async fn serve_value_formatter(cc_link: &mut CcLink) -> Result<()> {
                 }
             }
             ccprotocol::IncomingMessage::Continue => break,
-            _ => panic!(),
+            ccprotocol::IncomingMessage::__Other => {
+                // `CcLink::recv` has already logged it. If it expects a
+                // response, we'll hit `CcLink::formatter_timeout`.
+                continue;
+            }
         };
 
         cc_link.send(&response).await?;
@@ -192,14 +321,51 @@ async fn serve_value_formatter(cc_link: &mut CcLink) -> Result<()> {
     Ok(())
 }
 
+/// The error returned by [`CcLink`]'s methods when cargo-criterion has
+/// closed the connection (e.g., because it crashed or was interrupted).
+#[derive(thiserror::Error, Debug)]
+#[error("cargo-criterion disconnected")]
+struct Disconnected(#[source] io::Error);
+
+/// Wrap `e` with [`Disconnected`] if it indicates a closed connection.
+fn check_disconnection(e: io::Error) -> anyhow::Error {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::WriteZero => Disconnected(e).into(),
+        _ => e.into(),
+    }
+}
+
+/// The default value of [`CcLink::formatter_timeout`]. cargo-criterion
+/// analyzes the samples and generates plots before sending value formatter
+/// requests, which can take a while.
+const FORMATTER_TIMEOUT: time::Duration = time::Duration::from_secs(120);
+
 struct CcLink {
     cc_stream: BufStream<TcpStream>,
     receive_buffer: Vec<u8>,
     send_buffer: Vec<u8>,
+    /// The maximum time to wait for a message from cargo-criterion during
+    /// the value formatter exchange. Waiting longer than this probably
+    /// indicates a protocol deadlock, i.e., both parties waiting for each
+    /// other.
+    formatter_timeout: time::Duration,
+    /// The last message exchanged and whether it was sent (`true`) or
+    /// received (`false`), reported on timeout
+    last_message: Option<(&'static str, bool)>,
 }
 
 impl CcLink {
     async fn new(cc_stream: TcpStream) -> Result<Self> {
+        // The value formatter requests are small and answered one by one.
+        // Don't let Nagle's algorithm hold back our responses.
+        cc_stream
+            .set_nodelay(true)
+            .context("Failed to set `TCP_NODELAY`.")?;
+
         let mut cc_stream = BufStream::new(cc_stream);
 
         // read the runner-hello
@@ -247,30 +413,562 @@ impl CcLink {
             cc_stream,
             receive_buffer: Vec::new(),
             send_buffer: Vec::new(),
+            formatter_timeout: FORMATTER_TIMEOUT,
+            last_message: None,
         })
     }
 
     async fn recv(&mut self) -> Result<ccprotocol::IncomingMessage> {
+        let value = self.recv_inner().await?;
+        log::debug!("recv: {:?}", value);
+        self.last_message = Some((value.name(), false));
+        Ok(value)
+    }
+
+    /// Receive a message during the value formatter exchange, giving up
+    /// after [`Self::formatter_timeout`].
+    async fn recv_formatter_request(&mut self) -> Result<ccprotocol::IncomingMessage> {
+        let formatter_timeout = self.formatter_timeout;
+        match time::timeout(formatter_timeout, self.recv()).await {
+            Ok(result) => result,
+            Err(_) => {
+                let last_message = match self.last_message {
+                    Some((name, true)) => format!("`{}` (sent)", name),
+                    Some((name, false)) => format!("`{}` (received)", name),
+                    None => "none".to_owned(),
+                };
+                bail!(
+                    "Timed out after {:?} while waiting for a message from cargo-criterion. \
+                    The last message exchanged: {}",
+                    formatter_timeout,
+                    last_message
+                );
+            }
+        }
+    }
+
+    async fn recv_inner(&mut self) -> Result<ccprotocol::IncomingMessage> {
         let mut length_buf = [0u8; 4];
-        self.cc_stream.read_exact(&mut length_buf).await?;
+        self.cc_stream
+            .read_exact(&mut length_buf)
+            .await
+            .map_err(check_disconnection)?;
         let length = u32::from_be_bytes(length_buf);
         self.receive_buffer.resize(length as usize, 0u8);
-        self.cc_stream.read_exact(&mut self.receive_buffer).await?;
-        let value = serde_cbor::from_slice(&self.receive_buffer)
-            .context("Failed to decode the received upstream message.")?;
-        log::debug!("recv: {:?}", value);
-        Ok(value)
+        self.cc_stream
+            .read_exact(&mut self.receive_buffer)
+            .await
+            .map_err(check_disconnection)?;
+        match serde_cbor::from_slice(&self.receive_buffer) {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                // It might be a message introduced by a newer version of
+                // cargo-criterion. Skip it if it's valid CBOR.
+                let value: serde_cbor::Value = serde_cbor::from_slice(&self.receive_buffer)
+                    .context("Failed to decode the received upstream message.")?;
+                log::warn!(
+                    "Ignoring an unknown message from cargo-criterion: {:?} ({})",
+                    value,
+                    e
+                );
+                Ok(ccprotocol::IncomingMessage::__Other)
+            }
+        }
     }
 
     async fn send(&mut self, message: &ccprotocol::OutgoingMessage<'_>) -> Result<()> {
         log::debug!("send: {:?}", message);
+        self.last_message = Some((message.name(), true));
+
+        // Assemble the length prefix and the payload in one buffer so that
+        // they are sent in one segment
         self.send_buffer.truncate(0);
+        self.send_buffer.extend_from_slice(&[0; 4]);
         serde_cbor::to_writer(&mut self.send_buffer, message)?;
-        let size = u32::try_from(self.send_buffer.len()).unwrap();
-        let length_buf = size.to_be_bytes();
-        self.cc_stream.write_all(&length_buf).await?;
-        self.cc_stream.write_all(&self.send_buffer).await?;
-        self.cc_stream.flush().await?;
+        let size = u32::try_from(self.send_buffer.len() - 4).unwrap();
+        self.send_buffer[..4].copy_from_slice(&size.to_be_bytes());
+
+        self.cc_stream
+            .write_all(&self.send_buffer)
+            .await
+            .map_err(check_disconnection)?;
+        self.cc_stream.flush().await.map_err(check_disconnection)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::MockCargoCriterion;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    use crate::proxy::{
+        formatter::CyclesFormatter,
+        tests::{fast_overrides, start_loopback},
+    };
+
+    #[tokio::test]
+    async fn hello_exchange() {
+        let mock = MockCargoCriterion::bind().await;
+        let addr = mock.addr();
+        let mock = tokio::spawn(mock.run());
+
+        let cc_link = CcLink::new(TcpStream::connect(addr).await.unwrap())
+            .await
+            .unwrap();
+        drop(cc_link);
+
+        let transcript = mock.await.unwrap();
+        let hello = &transcript.benchmark_hello;
+        assert_eq!(hello.len(), 16);
+        assert_eq!(&hello[..9], b"Criterion");
+        assert_eq!(
+            hello[9..12],
+            [
+                env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+                env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+                env!("CARGO_PKG_VERSION_PATCH").parse::<u8>().unwrap(),
+            ]
+        );
+        // Protocol version 1, protocol format 1 (CBOR), both big endian
+        assert_eq!(hello[12..], [0, 1, 0, 1]);
+        assert!(transcript.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn hello_rejects_unknown_runner() {
+        let mut listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream
+                .write_all(b"cargo-crit\0\0\0\0\0\0\0\0")
+                .await
+                .unwrap();
+        });
+
+        let result = CcLink::new(TcpStream::connect(addr).await.unwrap()).await;
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Not connected to cargo-criterion."
+        );
+        server.await.unwrap();
+    }
+
+    /// Run a session through the front-end and check every message
+    /// cargo-criterion receives.
+    #[tokio::test]
+    async fn full_session() {
+        let mut mock = MockCargoCriterion::bind().await;
+        mock.formatter_requests = vec![
+            ccprotocol::IncomingMessage::FormatValue { value: 42.0 },
+            ccprotocol::IncomingMessage::FormatThroughput {
+                value: 42.0,
+                throughput: ccprotocol::Throughput::Bytes(64),
+            },
+            ccprotocol::IncomingMessage::ScaleValues {
+                typical_value: 42.0,
+                values: vec![1.0, 42.0, 1000.0],
+            },
+            ccprotocol::IncomingMessage::ScaleThroughputs {
+                typical_value: 42.0,
+                values: vec![1.0, 42.0],
+                throughput: ccprotocol::Throughput::Elements(4),
+            },
+            ccprotocol::IncomingMessage::ScaleForMachines {
+                values: vec![1.0, 42.0],
+            },
+        ];
+        let event_log = Arc::clone(&mock.event_log);
+        let target_event_log = Arc::clone(&mock.event_log);
+        let addr = mock.addr();
+        let mock = tokio::spawn(mock.run());
+
+        let (mut target_link, target_thread) =
+            start_loopback(protocol::Mode::Benchmark, fast_overrides(), 0, move |c| {
+                let bench = |name: &'static str| {
+                    let event_log = Arc::clone(&target_event_log);
+                    move |b: &mut crate::Bencher| {
+                        event_log.lock().unwrap().push(name.to_owned());
+                        b.iter(|| ());
+                    }
+                };
+                let mut group = c.benchmark_group("g");
+                group.bench_function("a", bench("a"));
+                group.bench_function("b", bench("b"));
+                group.finish();
+                c.bench_function("s", bench("s"));
+            })
+            .await;
+
+        // The handshake is done before starting the front-end
+        let reply = target_link.recv().await.unwrap();
+        assert!(matches!(
+            reply,
+            protocol::UpstreamMessage::GreetingReply { .. }
+        ));
+
+        run_frontend(
+            &mut target_link,
+            TcpStream::connect(addr).await.unwrap(),
+            &CyclesFormatter,
+        )
+        .await
+        .unwrap();
+        drop(target_link);
+        target_thread.join().unwrap();
+
+        let transcript = mock.await.unwrap();
+        let messages = &transcript.messages;
+
+        let formatter_responses = [
+            "FormattedValue",
+            "FormattedValue",
+            "ScaledValues",
+            "ScaledValues",
+            "ScaledValues",
+        ];
+        let benchmark = |expected: &mut Vec<&str>| {
+            expected.extend_from_slice(&[
+                "BeginningBenchmark",
+                "Warmup",
+                "MeasurementStart",
+                "MeasurementComplete",
+            ]);
+            expected.extend_from_slice(&formatter_responses);
+        };
+        let mut expected = vec!["BeginningBenchmarkGroup"];
+        benchmark(&mut expected);
+        benchmark(&mut expected);
+        expected.push("FinishedBenchmarkGroup");
+        expected.extend_from_slice(&formatter_responses);
+        expected.push("BeginningBenchmarkGroup");
+        benchmark(&mut expected);
+        expected.push("FinishedBenchmarkGroup");
+        expected.extend_from_slice(&formatter_responses);
+
+        let variants: Vec<&str> = messages.iter().map(mock::variant).collect();
+        assert_eq!(variants, expected);
+
+        // Group names
+        let text = |s: &str| serde_cbor::Value::Text(s.to_owned());
+        let groups: Vec<_> = messages
+            .iter()
+            .filter(|msg| mock::variant(msg).ends_with("BenchmarkGroup"))
+            .map(|msg| mock::field(mock::payload(msg), "group"))
+            .collect();
+        assert_eq!(groups, [&text("g"), &text("g"), &text("s"), &text("s")]);
+
+        // Benchmark IDs
+        let id = mock::field(mock::payload(&messages[1]), "id");
+        assert_eq!(
+            mock::field_names(id),
+            ["function_id", "group_id", "throughput", "value_str"]
+        );
+        assert_eq!(mock::field(id, "group_id"), &text("g"));
+        assert_eq!(mock::field(id, "function_id"), &text("a"));
+        assert_eq!(mock::field(id, "value_str"), &serde_cbor::Value::Null);
+
+        // Measurements
+        let measurement_start = mock::payload(&messages[3]);
+        assert_eq!(
+            mock::field_names(measurement_start),
+            ["estimate_ns", "id", "iter_count", "sample_count"]
+        );
+        assert_eq!(
+            mock::field(measurement_start, "sample_count"),
+            &serde_cbor::Value::Integer(10)
+        );
+
+        let measurement_complete = mock::payload(&messages[4]);
+        assert_eq!(
+            mock::field_names(measurement_complete),
+            [
+                "benchmark_config",
+                "id",
+                "iters",
+                "plot_config",
+                "sampling_method",
+                "times"
+            ]
+        );
+        for &name in &["iters", "times"] {
+            match mock::field(measurement_complete, name) {
+                serde_cbor::Value::Array(values) => assert_eq!(values.len(), 10),
+                other => panic!("unexpected {}: {:?}", name, other),
+            }
+        }
+        assert_eq!(
+            mock::field(measurement_complete, "sampling_method"),
+            &text("Flat")
+        );
+        assert_eq!(
+            mock::field(
+                mock::field(measurement_complete, "plot_config"),
+                "summary_scale"
+            ),
+            &text("Linear")
+        );
+        let benchmark_config = mock::field(measurement_complete, "benchmark_config");
+        assert_eq!(
+            mock::field_names(benchmark_config),
+            [
+                "confidence_level",
+                "measurement_time",
+                "noise_threshold",
+                "nresamples",
+                "sample_size",
+                "significance_level",
+                "warm_up_time"
+            ]
+        );
+        assert_eq!(
+            mock::field_names(mock::field(benchmark_config, "measurement_time")),
+            ["nanos", "secs"]
+        );
+
+        // Value formatter responses
+        let response = mock::payload(&messages[5]);
+        assert_eq!(mock::field_names(response), ["value"]);
+        let response = mock::payload(&messages[7]);
+        assert_eq!(mock::field_names(response), ["scaled_values", "unit"]);
+        match mock::field(response, "scaled_values") {
+            serde_cbor::Value::Array(values) => assert_eq!(values.len(), 3),
+            other => panic!("unexpected scaled_values: {:?}", other),
+        }
+
+        // The Target program must not proceed to the next benchmark until
+        // cargo-criterion is done with the value formatter
+        let event_log = event_log.lock().unwrap();
+        let num_continues_before = |name: &str| {
+            let i = event_log.iter().position(|e| e == name).unwrap();
+            event_log[..i].iter().filter(|e| *e == "Continue").count()
+        };
+        assert_eq!(num_continues_before("a"), 0);
+        assert_eq!(num_continues_before("b"), 1);
+        assert_eq!(num_continues_before("s"), 3);
+    }
+
+    /// Messages unknown to us are skipped. cargo-criterion is expected to
+    /// add new message kinds in a compatible way.
+    #[tokio::test]
+    async fn unknown_messages_ignored() {
+        let mut mock = MockCargoCriterion::bind().await;
+        let addr = mock.addr();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = mock.accept().await;
+            let text = |s: &str| serde_cbor::Value::Text(s.to_owned());
+
+            // A unit variant and a struct variant
+            mock::send_raw(&mut stream, &text("FutureUnitMessage")).await;
+            let future_message = [(
+                text("FutureMessage"),
+                serde_cbor::Value::Map(
+                    [(text("x"), serde_cbor::Value::Integer(1))]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                ),
+            )];
+            let future_message = serde_cbor::Value::Map(future_message.iter().cloned().collect());
+            mock::send_raw(&mut stream, &future_message).await;
+
+            // The front-end should still be responsive. The first response
+            // should be for this.
+            mock::send(
+                &mut stream,
+                &ccprotocol::IncomingMessage::FormatValue { value: 42.0 },
+            )
+            .await;
+            let response = mock::recv(&mut stream).await.unwrap();
+            assert_eq!(mock::variant(&response), "FormattedValue");
+
+            mock::send(&mut stream, &ccprotocol::IncomingMessage::Continue).await;
+        });
+
+        let mut cc_link = CcLink::new(TcpStream::connect(addr).await.unwrap())
+            .await
+            .unwrap();
+        serve_value_formatter(&mut cc_link, &CyclesFormatter)
+            .await
+            .unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disconnection_detected() {
+        let mut mock = MockCargoCriterion::bind().await;
+        let addr = mock.addr();
+        let server = tokio::spawn(async move {
+            let (stream, _) = mock.accept().await;
+            drop(stream);
+        });
+
+        let mut cc_link = CcLink::new(TcpStream::connect(addr).await.unwrap())
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        let error = cc_link.recv().await.unwrap_err();
+        assert!(
+            error.downcast_ref::<Disconnected>().is_some(),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.to_string(), "cargo-criterion disconnected");
+    }
+
+    /// If cargo-criterion disconnects while the Target program is waiting
+    /// for `Continue`, the Target program is released.
+    #[tokio::test]
+    async fn disconnection_releases_target() {
+        let mut mock = MockCargoCriterion::bind().await;
+        let addr = mock.addr();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = mock.accept().await;
+            while let Some(msg) = mock::recv(&mut stream).await {
+                if mock::variant(&msg) == "MeasurementComplete" {
+                    break;
+                }
+            }
+        });
+
+        let (mut target_link, target_thread) =
+            start_loopback(protocol::Mode::Benchmark, fast_overrides(), 0, |c| {
+                c.bench_function("a", |b| b.iter(|| ()));
+            })
+            .await;
+        target_link.recv().await.unwrap(); // `GreetingReply`
+
+        let error = run_frontend(
+            &mut target_link,
+            TcpStream::connect(addr).await.unwrap(),
+            &CyclesFormatter,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.to_string(), "cargo-criterion disconnected");
+        server.await.unwrap();
+
+        // The Target program should proceed to finish the group
+        let msg = time::timeout(REPLY_TIMEOUT, target_link.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            matches!(msg, protocol::UpstreamMessage::FinishedBenchmarkGroup),
+            "{:?}",
+            msg
+        );
+        target_link
+            .send(&protocol::DownstreamMessage::Continue)
+            .await
+            .unwrap();
+        let msg = target_link.recv().await.unwrap();
+        assert!(matches!(msg, protocol::UpstreamMessage::End), "{:?}", msg);
+        target_thread.join().unwrap();
+    }
+
+    /// A protocol deadlock is reported as an error naming the last message.
+    #[tokio::test]
+    async fn value_formatter_timeout() {
+        let mut mock = MockCargoCriterion::bind().await;
+        let addr = mock.addr();
+        let (finish_send, finish_recv) = tokio::sync::oneshot::channel::<()>();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = mock.accept().await;
+            mock::send(
+                &mut stream,
+                &ccprotocol::IncomingMessage::FormatValue { value: 42.0 },
+            )
+            .await;
+            mock::recv(&mut stream).await.unwrap();
+
+            // Wait for `Continue` without sending it, as if we were waiting
+            // for a response to an unknown message
+            let _ = finish_recv.await;
+        });
+
+        let mut cc_link = CcLink::new(TcpStream::connect(addr).await.unwrap())
+            .await
+            .unwrap();
+        cc_link.formatter_timeout = time::Duration::from_millis(200);
+        let error = serve_value_formatter(&mut cc_link, &CyclesFormatter)
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().ends_with("`FormattedValue` (sent)"),
+            "{}",
+            error
+        );
+
+        drop(finish_send);
+        server.await.unwrap();
+    }
+
+    /// Measure the round-trip latency of the value formatter against a mock
+    /// cargo-criterion server. cargo-criterion sends one of these requests
+    /// for each statistic it reports, so a delay of tens of milliseconds
+    /// (e.g., caused by Nagle's algorithm interacting with delayed ACKs)
+    /// makes report generation visibly slow.
+    #[tokio::test]
+    async fn value_formatter_latency() {
+        const NUM_REQUESTS: u32 = 100;
+
+        let mut listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.set_nodelay(true).unwrap();
+
+            let mut hello = ccprotocol::RUNNER_MAGIC_NUMBER.as_bytes().to_vec();
+            hello.extend_from_slice(&mock::RUNNER_VERSION);
+            stream.write_all(&hello).await.unwrap();
+            let mut hello = [0u8; ccprotocol::BENCHMARK_HELLO_SIZE];
+            stream.read_exact(&mut hello).await.unwrap();
+
+            let start = std::time::Instant::now();
+            for i in 0..NUM_REQUESTS {
+                let request = if i % 2 == 0 {
+                    ccprotocol::IncomingMessage::FormatValue { value: 42.0 }
+                } else {
+                    ccprotocol::IncomingMessage::ScaleValues {
+                        typical_value: 42.0,
+                        values: vec![1.0, 42.0, 1000.0],
+                    }
+                };
+                mock::send(&mut stream, &request).await;
+                let response = mock::recv(&mut stream).await.unwrap();
+                assert!(
+                    matches!(response, serde_cbor::Value::Map(_)),
+                    "{:?}",
+                    response
+                );
+            }
+            let latency = start.elapsed() / NUM_REQUESTS;
+
+            mock::send(&mut stream, &ccprotocol::IncomingMessage::Continue).await;
+            latency
+        });
+
+        let mut cc_link = CcLink::new(TcpStream::connect(addr).await.unwrap())
+            .await
+            .unwrap();
+        serve_value_formatter(&mut cc_link, &CyclesFormatter)
+            .await
+            .unwrap();
+
+        let latency = server.await.unwrap();
+        log::info!("Value formatter round-trip latency: {:?}", latency);
+        assert!(
+            latency < std::time::Duration::from_millis(10),
+            "round-trip latency is too high: {:?}",
+            latency
+        );
+    }
+}
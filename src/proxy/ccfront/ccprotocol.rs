@@ -1,6 +1,6 @@
 //! cargo-criterion protocol
 use serde::{Deserialize, Serialize};
-use std::mem::size_of;
+use std::{borrow::Cow, mem::size_of};
 
 pub(crate) const RUNNER_MAGIC_NUMBER: &str = "cargo-criterion";
 pub(crate) const RUNNER_HELLO_SIZE: usize = 15 //RUNNER_MAGIC_NUMBER.len() // magic number
@@ -16,6 +16,7 @@ pub(crate) const PROTOCOL_FORMAT: u16 = 1;
 
 /// Enum defining the messages we can receive
 #[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
 pub(crate) enum IncomingMessage {
     // Value formatter requests
     FormatValue {
@@ -39,9 +40,27 @@ pub(crate) enum IncomingMessage {
     },
     Continue,
 
+    /// A message unknown to us, e.g., one introduced by a newer version of
+    /// cargo-criterion. `CcLink::recv` returns this for messages that fail
+    /// to deserialize.
     __Other,
 }
 
+impl IncomingMessage {
+    /// Get the variant name.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::FormatValue { .. } => "FormatValue",
+            Self::FormatThroughput { .. } => "FormatThroughput",
+            Self::ScaleValues { .. } => "ScaleValues",
+            Self::ScaleThroughputs { .. } => "ScaleThroughputs",
+            Self::ScaleForMachines { .. } => "ScaleForMachines",
+            Self::Continue => "Continue",
+            Self::__Other => "(unknown)",
+        }
+    }
+}
+
 /// Enum defining the messages we can send
 #[derive(Debug, Serialize)]
 pub(crate) enum OutgoingMessage<'a> {
@@ -81,10 +100,27 @@ pub(crate) enum OutgoingMessage<'a> {
     },
     ScaledValues {
         scaled_values: Vec<f64>,
-        unit: &'a str,
+        unit: Cow<'a, str>,
     },
 }
 
+impl OutgoingMessage<'_> {
+    /// Get the variant name.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::BeginningBenchmarkGroup { .. } => "BeginningBenchmarkGroup",
+            Self::FinishedBenchmarkGroup { .. } => "FinishedBenchmarkGroup",
+            Self::BeginningBenchmark { .. } => "BeginningBenchmark",
+            Self::SkippingBenchmark { .. } => "SkippingBenchmark",
+            Self::Warmup { .. } => "Warmup",
+            Self::MeasurementStart { .. } => "MeasurementStart",
+            Self::MeasurementComplete { .. } => "MeasurementComplete",
+            Self::FormattedValue { .. } => "FormattedValue",
+            Self::ScaledValues { .. } => "ScaledValues",
+        }
+    }
+}
+
 // Also define serializable variants of certain things, either to avoid leaking
 // serializability into the public interface or because the serialized form
 // is a bit different from the regular one.
@@ -105,7 +141,7 @@ where
             group_id: (&other.group_id).into(),
             function_id: other.function_id.as_ref().map(Into::into),
             value_str: other.value_str.as_ref().map(Into::into),
-            throughput: other.throughput.iter().cloned().collect(),
+            throughput: other.throughput.iter().map(Into::into).collect(),
         }
     }
 }
@@ -166,4 +202,42 @@ pub(crate) enum SamplingMethod {
     Flat,
 }
 
-pub(crate) type Throughput = crate::bencher::protocol::Throughput;
+/// cargo-criterion's representation of throughput. Unlike
+/// [`crate::bencher::protocol::Throughput`], this doesn't support custom units.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum Throughput {
+    Bytes(u64),
+    Elements(u64),
+}
+
+impl From<&crate::bencher::protocol::Throughput> for Throughput {
+    fn from(other: &crate::bencher::protocol::Throughput) -> Self {
+        use crate::bencher::protocol::Throughput as T;
+        match *other {
+            T::Bytes(x) => Self::Bytes(x),
+            T::Elements(x) => Self::Elements(x),
+            T::Custom { amount, unit } if ["B", "byte", "bytes"].contains(&&*unit) => {
+                Self::Bytes(amount)
+            }
+            T::Custom { amount, unit } => {
+                log::info!(
+                    "cargo-criterion doesn't support custom throughput units; \
+                    reporting {} {} as {} elements",
+                    amount,
+                    unit,
+                    amount
+                );
+                Self::Elements(amount)
+            }
+        }
+    }
+}
+
+impl From<Throughput> for crate::bencher::protocol::Throughput {
+    fn from(other: Throughput) -> Self {
+        match other {
+            Throughput::Bytes(x) => Self::Bytes(x),
+            Throughput::Elements(x) => Self::Elements(x),
+        }
+    }
+}
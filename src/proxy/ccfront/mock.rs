@@ -0,0 +1,165 @@
+//! Mock cargo-criterion server, used to test the front-end
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::ccprotocol;
+
+/// The runner version sent in the runner-hello
+pub(super) const RUNNER_VERSION: [u8; 3] = [1, 3, 0];
+
+pub(super) struct MockCargoCriterion {
+    listener: TcpListener,
+    /// The value formatter requests to send whenever the front-end waits for
+    /// them, i.e., after `MeasurementComplete` and `FinishedBenchmarkGroup`
+    pub(super) formatter_requests: Vec<ccprotocol::IncomingMessage>,
+    /// `"Continue"` is appended to this whenever the mock is about to send
+    /// `IncomingMessage::Continue`. The test code can add its own entries to
+    /// observe the ordering of events.
+    pub(super) event_log: Arc<Mutex<Vec<String>>>,
+}
+
+/// The data received by [`MockCargoCriterion::run`]
+#[derive(Debug)]
+pub(super) struct Transcript {
+    /// The benchmark-hello
+    pub(super) benchmark_hello: Vec<u8>,
+    /// The messages, including the value formatter responses, in the order
+    /// of reception
+    pub(super) messages: Vec<serde_cbor::Value>,
+}
+
+impl MockCargoCriterion {
+    /// Listen on an ephemeral port.
+    pub(super) async fn bind() -> Self {
+        Self {
+            listener: TcpListener::bind(("127.0.0.1", 0)).await.unwrap(),
+            formatter_requests: Vec::new(),
+            event_log: Default::default(),
+        }
+    }
+
+    pub(super) fn addr(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    /// Accept a connection and perform the hello exchange. Returns the
+    /// stream and the benchmark-hello.
+    pub(super) async fn accept(&mut self) -> (TcpStream, Vec<u8>) {
+        let (mut stream, _) = self.listener.accept().await.unwrap();
+
+        let mut hello = ccprotocol::RUNNER_MAGIC_NUMBER.as_bytes().to_vec();
+        hello.extend_from_slice(&RUNNER_VERSION);
+        stream.write_all(&hello).await.unwrap();
+
+        let mut benchmark_hello = vec![0u8; ccprotocol::BENCHMARK_HELLO_SIZE];
+        stream.read_exact(&mut benchmark_hello).await.unwrap();
+
+        (stream, benchmark_hello)
+    }
+
+    /// Accept a connection and play the role of cargo-criterion until the
+    /// front-end disconnects.
+    pub(super) async fn run(mut self) -> Transcript {
+        let (mut stream, benchmark_hello) = self.accept().await;
+
+        let mut messages = Vec::new();
+        while let Some(msg) = recv(&mut stream).await {
+            let pause = matches!(
+                variant(&msg),
+                "MeasurementComplete" | "FinishedBenchmarkGroup"
+            );
+            messages.push(msg);
+
+            if pause {
+                for request in self.formatter_requests.iter() {
+                    send(&mut stream, request).await;
+                    messages.push(recv(&mut stream).await.expect("unexpected EOF"));
+                }
+                self.event_log.lock().unwrap().push("Continue".to_owned());
+                send(&mut stream, &ccprotocol::IncomingMessage::Continue).await;
+            }
+        }
+
+        Transcript {
+            benchmark_hello,
+            messages,
+        }
+    }
+}
+
+/// Send a message in the same framing as cargo-criterion.
+pub(super) async fn send(stream: &mut TcpStream, message: &ccprotocol::IncomingMessage) {
+    send_raw(stream, &serde_cbor::value::to_value(message).unwrap()).await;
+}
+
+/// Send an arbitrary CBOR value in the same framing as cargo-criterion.
+pub(super) async fn send_raw(stream: &mut TcpStream, message: &serde_cbor::Value) {
+    let payload = serde_cbor::to_vec(message).unwrap();
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(&payload);
+    stream.write_all(&frame).await.unwrap();
+}
+
+/// Receive a message. Returns `None` on EOF.
+pub(super) async fn recv(stream: &mut TcpStream) -> Option<serde_cbor::Value> {
+    let mut length_buf = [0u8; 4];
+    if stream.read_exact(&mut length_buf).await.is_err() {
+        return None;
+    }
+    let mut payload = vec![0u8; u32::from_be_bytes(length_buf) as usize];
+    stream.read_exact(&mut payload).await.unwrap();
+    Some(serde_cbor::from_slice(&payload).unwrap())
+}
+
+/// Get the variant name of a message. serde encodes a unit variant as a
+/// string and other variants as a single-entry map.
+pub(super) fn variant(msg: &serde_cbor::Value) -> &str {
+    match msg {
+        serde_cbor::Value::Text(name) => name,
+        serde_cbor::Value::Map(map) if map.len() == 1 => match map.keys().next() {
+            Some(serde_cbor::Value::Text(name)) => name,
+            _ => panic!("malformed message: {:?}", msg),
+        },
+        _ => panic!("malformed message: {:?}", msg),
+    }
+}
+
+/// Get the payload of a struct variant.
+pub(super) fn payload(msg: &serde_cbor::Value) -> &serde_cbor::Value {
+    match msg {
+        serde_cbor::Value::Map(map) if map.len() == 1 => map.values().next().unwrap(),
+        _ => panic!("not a struct variant: {:?}", msg),
+    }
+}
+
+/// Get the field `name` of a struct.
+pub(super) fn field<'a>(value: &'a serde_cbor::Value, name: &str) -> &'a serde_cbor::Value {
+    match value {
+        serde_cbor::Value::Map(map) => map
+            .get(&serde_cbor::Value::Text(name.to_owned()))
+            .unwrap_or_else(|| panic!("no field '{}' in {:?}", name, value)),
+        _ => panic!("not a struct: {:?}", value),
+    }
+}
+
+/// Get the field names of a struct in lexicographical order.
+pub(super) fn field_names(value: &serde_cbor::Value) -> Vec<&str> {
+    let mut names: Vec<&str> = match value {
+        serde_cbor::Value::Map(map) => map
+            .keys()
+            .map(|key| match key {
+                serde_cbor::Value::Text(name) => &name[..],
+                _ => panic!("non-string key in {:?}", value),
+            })
+            .collect(),
+        _ => panic!("not a struct: {:?}", value),
+    };
+    names.sort_unstable();
+    names
+}
@@ -0,0 +1,188 @@
+//! The host clock read by the Target program through
+//! `UpstreamMessage::GetInstant`
+use std::time::{Duration, Instant};
+
+use super::formatter::ValueFormatter;
+use crate::bencher::protocol;
+
+/// Answers `GetInstant` with instants measured from a single origin for the
+/// whole run, so that they stay comparable across front-ends and restarts of
+/// the Target program.
+///
+/// The Target program receives a reply some time after it's sent. An
+/// instant is advanced by the estimated one-way delay, half of the shortest
+/// round trip observed so far (the time from sending a reply to receiving
+/// the Target program's next message, which is an upper bound of the link's
+/// round-trip latency). The true delay lies anywhere between zero and the
+/// round trip, so the estimate's uncertainty is also half of it.
+///
+/// It also collects the round trips measured by the Target program during
+/// warm-ups. Each pass of a warm-up takes two, so they dominate the warm-up
+/// of a benchmark whose iterations are much shorter.
+#[derive(Debug)]
+pub(super) struct ClockService {
+    origin: Instant,
+    /// The last issued instant, relative to `origin`. The issued instants
+    /// never go backward even if the delay estimate shrinks.
+    last_issued: Duration,
+    /// When the last reply was sent
+    reply_sent_at: Option<Instant>,
+    /// The shortest round trip observed so far
+    min_round_trip: Option<Duration>,
+    /// The number of issued instants
+    num_issued: u64,
+    /// The round trips measured by the Target program during warm-ups
+    target_round_trips: protocol::RoundTrips,
+}
+
+impl Default for ClockService {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl ClockService {
+    pub(super) fn new(origin: Instant) -> Self {
+        Self {
+            origin,
+            last_issued: Duration::default(),
+            reply_sent_at: None,
+            min_round_trip: None,
+            num_issued: 0,
+            target_round_trips: Default::default(),
+        }
+    }
+
+    /// Record that a reply was sent to the Target program at `at`.
+    pub(super) fn record_reply(&mut self, at: Instant) {
+        self.reply_sent_at = Some(at);
+    }
+
+    /// Record that a message was received from the Target program at `at`.
+    pub(super) fn record_message(&mut self, at: Instant) {
+        if let Some(sent_at) = self.reply_sent_at.take() {
+            let round_trip = at.saturating_duration_since(sent_at);
+            self.min_round_trip = Some(
+                self.min_round_trip
+                    .map_or(round_trip, |x| x.min(round_trip)),
+            );
+        }
+    }
+
+    /// Add the round trips measured by the Target program during a warm-up.
+    pub(super) fn record_target_round_trips(&mut self, round_trips: &protocol::RoundTrips) {
+        if round_trips.count == 0 {
+            return;
+        }
+        let all = &mut self.target_round_trips;
+        all.min = if all.count == 0 {
+            round_trips.min
+        } else {
+            all.min.min(round_trips.min)
+        };
+        all.count = all.count.saturating_add(round_trips.count);
+        all.total = all.total.saturating_add(round_trips.total);
+    }
+
+    /// Get the estimated delay until a reply reaches the Target program.
+    pub(super) fn one_way_delay(&self) -> Duration {
+        self.min_round_trip.unwrap_or_default() / 2
+    }
+
+    /// Get the instant to send in reply to `GetInstant` at `now`.
+    pub(super) fn issue(&mut self, now: Instant) -> protocol::Instant {
+        let elapsed = now.saturating_duration_since(self.origin) + self.one_way_delay();
+        self.last_issued = self.last_issued.max(elapsed);
+        self.num_issued += 1;
+        protocol::Instant::from_nanos(self.last_issued.as_nanos() as u64)
+    }
+
+    /// Print the number of issued instants, the delay estimate, and the
+    /// round trips measured by the Target program (formatted by
+    /// `formatter`) to stderr.
+    pub(super) fn print(&self, formatter: &dyn ValueFormatter) {
+        if self.num_issued == 0 {
+            return;
+        }
+        let delay = self.one_way_delay();
+        eprintln!(
+            "Host clock: {} instant(s) issued, advanced by {:.3}ms ± {:.3}ms for the link delay",
+            self.num_issued,
+            delay.as_secs_f64() * 1e3,
+            delay.as_secs_f64() * 1e3,
+        );
+
+        let round_trips = &self.target_round_trips;
+        if round_trips.count > 0 {
+            eprintln!(
+                "  Round trip measured by the target: min {}, mean {} ({} during warm-ups, \
+                two per warm-up pass)",
+                formatter.format_value(round_trips.min as f64).trim_start(),
+                formatter
+                    .format_value(round_trips.total as f64 / round_trips.count as f64)
+                    .trim_start(),
+                round_trips.count,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monotonic_across_handoffs() {
+        let origin = Instant::now();
+        let at = |ms| origin + Duration::from_millis(ms);
+        let issue = |clock: &mut ClockService, ms| clock.issue(at(ms)).as_nanos() / 1_000_000;
+
+        let mut clock = ClockService::new(origin);
+        assert_eq!(issue(&mut clock, 10), 10);
+
+        // Round trips of 8ms and 4ms
+        clock.record_reply(at(10));
+        clock.record_message(at(18));
+        clock.record_reply(at(20));
+        clock.record_message(at(24));
+        assert_eq!(clock.one_way_delay(), Duration::from_millis(2));
+        assert_eq!(issue(&mut clock, 30), 32);
+
+        // Hand the clock over to another front-end (or a restarted Target
+        // program)
+        let mut clock = std::mem::take(&mut clock);
+
+        // A shorter round trip lowers the estimate, but the issued instants
+        // don't go backward
+        clock.record_reply(at(40));
+        clock.record_message(at(40));
+        assert_eq!(clock.one_way_delay(), Duration::default());
+        assert_eq!(issue(&mut clock, 31), 32);
+        assert_eq!(issue(&mut clock, 33), 33);
+        assert_eq!(clock.num_issued, 4);
+    }
+
+    #[test]
+    fn target_round_trips() {
+        let mut clock = ClockService::default();
+        let warm_up = |values: &[u64]| {
+            let mut round_trips = protocol::RoundTrips::default();
+            for &value in values {
+                round_trips.record(value);
+            }
+            round_trips
+        };
+
+        clock.record_target_round_trips(&warm_up(&[30, 20]));
+        clock.record_target_round_trips(&warm_up(&[]));
+        clock.record_target_round_trips(&warm_up(&[40]));
+        assert_eq!(
+            clock.target_round_trips,
+            protocol::RoundTrips {
+                count: 3,
+                min: 20,
+                total: 90
+            }
+        );
+    }
+}
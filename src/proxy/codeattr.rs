@@ -0,0 +1,329 @@
+//! Code attribution analysis (`--farcri-code-attribution`)
+//!
+//! Estimates how much of the code reachable from each benchmark closure comes
+//! from each crate. This is done by disassembling the target executable,
+//! following direct calls and jumps from the closures' symbols, and
+//! attributing each reached function's size to the crate found in its symbol
+//! name.
+//!
+//! This is a heuristic analysis with the following limitations:
+//!
+//!  - Indirect calls (e.g., through trait objects or function pointers) are
+//!    not followed.
+//!  - Code inlined into a function is attributed to the crate of that
+//!    function. For example, a routine passed to `Bencher::iter` is usually
+//!    inlined into a monomorphized instance of `Bencher::iter`, so it's
+//!    attributed to `farcri`.
+//!  - Benchmarks are identified by their closures' symbol names (e.g.,
+//!    `sort::criterion_benchmark::{{closure}}`), not by benchmark IDs.
+//!  - Only the legacy symbol mangling scheme is understood.
+//!  - Only Arm targets are supported. For other targets or when no benchmark
+//!    closures could be found, a whole-image summary is reported instead.
+use anyhow::{Context, Result};
+use capstone::{
+    arch::{
+        arm::{ArchMode, ArmOperand, ArmOperandType},
+        ArchOperand, BuildsCapstone,
+    },
+    Capstone, InsnGroupType,
+};
+use std::{collections::BTreeMap, path::Path};
+use tokio::task::spawn_blocking;
+
+use super::symbols::legacy_symbol_path;
+
+struct Function {
+    addr: u64,
+    size: u64,
+    /// The demangled path, or `None` if the symbol isn't a mangled Rust
+    /// symbol
+    path: Option<Vec<String>>,
+    name: String,
+}
+
+impl Function {
+    fn crate_name(&self) -> &str {
+        match &self.path {
+            Some(path) => crate_of_path(path),
+            None => "(no_mangle)",
+        }
+    }
+}
+
+/// Analyze the specified executable and report the result through the log.
+pub(super) async fn report(exe: &Path, bench_crate: &str) -> Result<()> {
+    let elf_bytes = tokio::fs::read(exe)
+        .await
+        .with_context(|| format!("Failed to read '{}'.", exe.display()))?;
+    let bench_crate = bench_crate.replace('-', "_");
+    spawn_blocking(move || report_inner(&elf_bytes, &bench_crate))
+        .await
+        .unwrap()
+}
+
+fn report_inner(elf_bytes: &[u8], bench_crate: &str) -> Result<()> {
+    let elf = goblin::elf::Elf::parse(elf_bytes).context("Failed to parse the executable.")?;
+    let funcs = collect_functions(&elf);
+
+    let cs = if elf.header.e_machine == goblin::elf::header::EM_ARM {
+        Some(
+            Capstone::new()
+                .arm()
+                .mode(ArchMode::Thumb)
+                .detail(true)
+                .build()
+                .map_err(|e| anyhow::anyhow!("Failed to initialize the disassembler: {}", e))?,
+        )
+    } else {
+        log::warn!(
+            "Code attribution isn't supported for this architecture (e_machine = {}). \
+            Reporting a whole-image summary instead.",
+            elf.header.e_machine
+        );
+        None
+    };
+
+    let roots: Vec<usize> = (funcs.iter().enumerate())
+        .filter(|(_, f)| {
+            f.path
+                .as_ref()
+                .map_or(false, |p| is_bench_closure(p, bench_crate))
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    let cs = match cs {
+        Some(cs) if !roots.is_empty() => cs,
+        Some(_) => {
+            log::warn!(
+                "Couldn't find benchmark closures in the executable. \
+                Reporting a whole-image summary instead."
+            );
+            log_summary("whole image", funcs.iter());
+            return Ok(());
+        }
+        None => {
+            log_summary("whole image", funcs.iter());
+            return Ok(());
+        }
+    };
+
+    // Find direct callees of every function
+    let callees: Vec<Vec<usize>> = funcs
+        .iter()
+        .map(|f| match function_bytes(&elf, elf_bytes, f) {
+            Some(code) => direct_callees(&cs, code, f, &funcs),
+            None => Vec::new(),
+        })
+        .collect();
+
+    for &root in roots.iter() {
+        // Depth-first search
+        let mut reached = vec![false; funcs.len()];
+        let mut stack = vec![root];
+        reached[root] = true;
+        while let Some(i) = stack.pop() {
+            for &k in callees[i].iter() {
+                if !reached[k] {
+                    reached[k] = true;
+                    stack.push(k);
+                }
+            }
+        }
+
+        let label = funcs[root].path.as_ref().unwrap().join("::");
+        log_summary(
+            &label,
+            (funcs.iter().zip(reached.iter()))
+                .filter(|(_, &reached)| reached)
+                .map(|(f, _)| f),
+        );
+    }
+
+    Ok(())
+}
+
+fn log_summary<'a>(label: &str, funcs: impl Iterator<Item = &'a Function>) {
+    let mut by_crate = BTreeMap::new();
+    let mut total = 0;
+    for f in funcs {
+        log::trace!("  {} ({} bytes)", f.name, f.size);
+        *by_crate.entry(f.crate_name().to_owned()).or_insert(0u64) += f.size;
+        total += f.size;
+    }
+
+    let mut by_crate: Vec<_> = by_crate.into_iter().collect();
+    by_crate.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+
+    let mut out = format!("Code attribution for '{}' ({} bytes):", label, total);
+    for (crate_name, size) in by_crate {
+        out.push_str(&format!("\n  {:>8} bytes  {}", size, crate_name));
+    }
+    log::info!("{}", out);
+}
+
+/// Collect function symbols, sorted by address.
+fn collect_functions(elf: &goblin::elf::Elf<'_>) -> Vec<Function> {
+    let mut funcs: Vec<Function> = elf
+        .syms
+        .iter()
+        .filter(|sym| sym.st_type() == goblin::elf::sym::STT_FUNC && sym.st_size > 0)
+        .filter_map(|sym| {
+            let name = elf.strtab.get(sym.st_name)?.ok()?;
+            Some(Function {
+                // Clear the Thumb bit
+                addr: sym.st_value & !1,
+                size: sym.st_size,
+                path: legacy_symbol_path(name),
+                name: name.to_owned(),
+            })
+        })
+        .collect();
+
+    funcs.sort_by_key(|f| f.addr);
+    // Remove aliases
+    funcs.dedup_by_key(|f| f.addr);
+    funcs
+}
+
+fn function_bytes<'a>(
+    elf: &goblin::elf::Elf<'_>,
+    elf_bytes: &'a [u8],
+    f: &Function,
+) -> Option<&'a [u8]> {
+    let sh = elf.section_headers.iter().find(|sh| {
+        sh.sh_type != goblin::elf::section_header::SHT_NOBITS
+            && (sh.sh_addr..sh.sh_addr + sh.sh_size).contains(&f.addr)
+    })?;
+    let start = (sh.sh_offset + (f.addr - sh.sh_addr)) as usize;
+    elf_bytes.get(start..start + f.size as usize)
+}
+
+/// Find the functions directly called or jumped to by `f`.
+fn direct_callees(cs: &Capstone, code: &[u8], f: &Function, funcs: &[Function]) -> Vec<usize> {
+    let insns = match cs.disasm_all(code, f.addr) {
+        Ok(x) => x,
+        Err(e) => {
+            log::debug!("Failed to disassemble {}: {}", f.name, e);
+            return Vec::new();
+        }
+    };
+
+    let mut out = Vec::new();
+    for insn in insns.iter() {
+        let detail = match cs.insn_detail(&insn) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        let is_branch = detail.groups().any(|g| {
+            matches!(
+                g.0 as InsnGroupType::Type,
+                InsnGroupType::CS_GRP_JUMP | InsnGroupType::CS_GRP_CALL
+            )
+        });
+        if !is_branch {
+            continue;
+        }
+
+        for op in detail.arch_detail().operands() {
+            if let ArchOperand::ArmOperand(ArmOperand {
+                op_type: ArmOperandType::Imm(target),
+                ..
+            }) = op
+            {
+                let target = target as u32 as u64;
+                if let Some(i) = function_containing(funcs, target) {
+                    if funcs[i].addr != f.addr && !out.contains(&i) {
+                        out.push(i);
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn function_containing(funcs: &[Function], addr: u64) -> Option<usize> {
+    let i = match funcs.binary_search_by_key(&addr, |f| f.addr) {
+        Ok(i) => i,
+        Err(0) => return None,
+        Err(i) => i - 1,
+    };
+    (addr < funcs[i].addr + funcs[i].size).then(|| i)
+}
+
+/// Get a flag indicating whether `path` refers to a closure defined directly
+/// in a function of the benchmark crate.
+fn is_bench_closure(path: &[String], bench_crate: &str) -> bool {
+    match path {
+        [crate_name, .., parent, last] => {
+            crate_name == bench_crate && last == "{{closure}}" && parent != "{{closure}}"
+        }
+        _ => false,
+    }
+}
+
+/// Get the crate name from a decoded symbol path.
+fn crate_of_path(path: &[String]) -> &str {
+    let first = &path[0];
+    if let Some(qualified) = first.strip_prefix('<') {
+        // `<T as Trait>` - attribute it to the crate of `T`
+        let self_ty = qualified.trim_start_matches(&['&', '*'][..]);
+        let self_ty = self_ty
+            .strip_prefix("mut ")
+            .or_else(|| self_ty.strip_prefix("const "))
+            .unwrap_or(self_ty);
+        match self_ty.find("::") {
+            Some(i)
+                if self_ty[..i]
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_') =>
+            {
+                &self_ty[..i]
+            }
+            _ => "(unknown)",
+        }
+    } else {
+        first
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crate_of_trait_impl() {
+        let path = legacy_symbol_path(
+            "_ZN61_$LT$farcri..bencher..Bencher$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE",
+        )
+        .unwrap();
+        assert_eq!(path[0], "<farcri::bencher::Bencher as core::fmt::Debug>");
+        assert_eq!(crate_of_path(&path), "farcri");
+
+        let path = legacy_symbol_path(
+            "_ZN44_$LT$$RF$T$u20$as$u20$core..fmt..Display$GT$3fmt17h0123456789abcdefE",
+        )
+        .unwrap();
+        assert_eq!(crate_of_path(&path), "(unknown)");
+    }
+
+    #[test]
+    fn bench_closure() {
+        let path = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert!(is_bench_closure(
+            &path(&["sort", "criterion_benchmark", "{{closure}}"]),
+            "sort"
+        ));
+        assert!(!is_bench_closure(
+            &path(&["sort", "criterion_benchmark", "{{closure}}", "{{closure}}"]),
+            "sort"
+        ));
+        assert!(!is_bench_closure(
+            &path(&["core", "criterion_benchmark", "{{closure}}"]),
+            "sort"
+        ));
+        assert!(!is_bench_closure(&path(&["sort", "noop"]), "sort"));
+    }
+}
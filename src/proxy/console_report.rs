@@ -0,0 +1,432 @@
+//! Prints the analysis of each measurement in the same format as
+//! Criterion.rs, used by the dumb front-end
+//!
+//! ```text
+//! sort/16                 time:   [1.2280 Kcycles 1.2301 Kcycles 1.2323 Kcycles]
+//!                         change: [-0.5087% -0.2349% +0.0381%] (p = 0.10 > 0.05)
+//!                         No change in performance detected.
+//! ```
+//!
+//! The sample of each benchmark is saved in `<dir>/<directory_name>/last.cbor`
+//! (see `jsonout` for `<directory_name>`) and compared against in the next
+//! run.
+use anyhow::{Context, Result};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write, path::PathBuf};
+
+use super::{
+    formatter::ValueFormatter,
+    jsonout::directory_name,
+    resultsfile::write_and_sync,
+    stats::{self, Estimate},
+    style::{self, Style},
+};
+use crate::bencher::protocol;
+
+/// Criterion.rs's default `confidence_level`
+pub(super) const CONFIDENCE_LEVEL: f64 = 0.95;
+/// Criterion.rs's default `significance_level`
+const SIGNIFICANCE_LEVEL: f64 = 0.05;
+/// Criterion.rs's default `noise_threshold`
+const NOISE_THRESHOLD: f64 = 0.01;
+
+/// The file name of a saved sample
+const SAVED_SAMPLE_FILE_NAME: &str = "last.cbor";
+
+/// The width of the column holding benchmark IDs
+pub(super) const ID_WIDTH: usize = 24;
+
+pub(super) struct ConsoleReport {
+    /// The directory to save samples in
+    dir: PathBuf,
+    /// Use ANSI escape sequences
+    color: bool,
+    rng: StdRng,
+    /// The IDs of the benchmarks whose performance has regressed
+    regressions: Vec<String>,
+}
+
+/// The contents of [`SAVED_SAMPLE_FILE_NAME`]
+#[derive(Serialize, Deserialize)]
+struct SavedSample {
+    /// The per-iteration values
+    values: Vec<f64>,
+    mean: Estimate,
+}
+
+/// The comparison against the previous run
+struct Comparison {
+    /// The relative change of the mean
+    change: Estimate,
+    p_value: f64,
+}
+
+impl Comparison {
+    /// Get a flag indicating whether the mean is significantly different.
+    fn different_mean(&self) -> bool {
+        self.p_value < SIGNIFICANCE_LEVEL
+    }
+
+    /// Get a flag indicating whether this is reported as "Performance has
+    /// regressed."
+    fn regressed(&self) -> bool {
+        self.different_mean() && self.change.lower > NOISE_THRESHOLD
+    }
+}
+
+impl ConsoleReport {
+    /// Construct a `ConsoleReport` that saves samples in `dir`. The output is
+    /// colored as decided by [`style::init`].
+    pub(super) fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            color: style::stderr().color(),
+            rng: StdRng::from_entropy(),
+            regressions: Vec::new(),
+        }
+    }
+
+    /// Get the IDs of the benchmarks reported as "Performance has
+    /// regressed." so far.
+    pub(super) fn regressions(&self) -> &[String] {
+        &self.regressions
+    }
+
+    /// Analyze the result of a measurement, compare it against the previous
+    /// run, and print the report to stderr.
+    pub(super) async fn measurement_complete(
+        &mut self,
+        id: &protocol::RawBenchmarkId<String>,
+        num_iters_per_sample: u64,
+        values: &[u64],
+        benchmark_config: &protocol::BenchmarkConfig,
+        formatter: &dyn ValueFormatter,
+    ) {
+        let report = self
+            .analyze(
+                id,
+                num_iters_per_sample,
+                values,
+                benchmark_config,
+                formatter,
+            )
+            .await;
+        eprint!("{}", report);
+    }
+
+    async fn analyze(
+        &mut self,
+        id: &protocol::RawBenchmarkId<String>,
+        num_iters_per_sample: u64,
+        values: &[u64],
+        benchmark_config: &protocol::BenchmarkConfig,
+        formatter: &dyn ValueFormatter,
+    ) -> String {
+        if values.is_empty() {
+            return String::new();
+        }
+
+        let values: Vec<f64> = values
+            .iter()
+            .map(|&x| x as f64 / num_iters_per_sample as f64)
+            .collect();
+        let nresamples = benchmark_config.nresamples;
+        let mean = stats::bootstrap_mean(&values, nresamples, CONFIDENCE_LEVEL, &mut self.rng);
+
+        let path = self
+            .dir
+            .join(directory_name(id))
+            .join(SAVED_SAMPLE_FILE_NAME);
+
+        // Compare against the previous run. Having no usable previous run
+        // isn't an error.
+        let comparison = match load_sample(&path).await {
+            Ok(Some(old)) if old.values.len() > 1 && values.len() > 1 => Some(Comparison {
+                change: stats::bootstrap_mean_change(
+                    &old.values,
+                    &values,
+                    nresamples,
+                    CONFIDENCE_LEVEL,
+                    &mut self.rng,
+                ),
+                p_value: stats::t_test(&old.values, &values, nresamples, &mut self.rng),
+            }),
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("{:?}", e);
+                None
+            }
+        };
+
+        if let Err(e) = save_sample(&path, &SavedSample { values, mean }).await {
+            log::warn!("{:?}", e);
+        }
+
+        if matches!(&comparison, Some(c) if c.regressed()) {
+            self.regressions.push(id.to_string());
+        }
+
+        format_report(
+            &id.to_string(),
+            &mean,
+            id.throughput.as_ref(),
+            comparison.as_ref(),
+            formatter,
+            self.color,
+        )
+    }
+}
+
+async fn load_sample(path: &std::path::Path) -> Result<Option<SavedSample>> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read '{}'.", path.display())),
+    };
+    serde_cbor::from_slice(&bytes)
+        .with_context(|| format!("Failed to parse '{}'. Ignoring it.", path.display()))
+}
+
+async fn save_sample(path: &std::path::Path, sample: &SavedSample) -> Result<()> {
+    let dir = path.parent().unwrap();
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create '{}'.", dir.display()))?;
+    let bytes = serde_cbor::to_vec(sample).unwrap();
+    write_and_sync(path, bytes).await
+}
+
+fn format_report(
+    id: &str,
+    mean: &Estimate,
+    throughput: Option<&protocol::Throughput>,
+    comparison: Option<&Comparison>,
+    formatter: &dyn ValueFormatter,
+    color: bool,
+) -> String {
+    let style = Style::new(color);
+    let mut out = String::new();
+
+    // Put the ID on its own line if it doesn't fit in the column
+    let id_len = id.chars().count();
+    if id_len < ID_WIDTH {
+        write!(out, "{}{}", style.green(id), " ".repeat(ID_WIDTH - id_len)).unwrap();
+    } else {
+        writeln!(out, "{}", style.green(id)).unwrap();
+        out.push_str(&" ".repeat(ID_WIDTH));
+    }
+
+    writeln!(
+        out,
+        "time:   [{} {} {}]",
+        formatter.format_value(mean.lower),
+        style.bold(&formatter.format_value(mean.point)),
+        formatter.format_value(mean.upper),
+    )
+    .unwrap();
+
+    if let Some(throughput) = throughput {
+        // The formatters in this crate report throughputs in cost per unit,
+        // which grows with the measured value, so the order is preserved
+        writeln!(
+            out,
+            "{:w$}thrpt:  [{} {} {}]",
+            "",
+            formatter.format_throughput(throughput, mean.lower),
+            style.bold(&formatter.format_throughput(throughput, mean.point)),
+            formatter.format_throughput(throughput, mean.upper),
+            w = ID_WIDTH,
+        )
+        .unwrap();
+    }
+
+    if let Some(comparison) = comparison {
+        let Comparison { change, p_value } = comparison;
+        let different_mean = comparison.different_mean();
+        writeln!(
+            out,
+            "{:w$}change: [{} {} {}] (p = {:.2} {} {:.2})",
+            "",
+            format_change(change.lower),
+            style.bold(&format_change(change.point)),
+            format_change(change.upper),
+            p_value,
+            if different_mean { "<" } else { ">" },
+            SIGNIFICANCE_LEVEL,
+            w = ID_WIDTH,
+        )
+        .unwrap();
+
+        let explanation = if !different_mean {
+            "No change in performance detected.".to_owned()
+        } else if comparison.regressed() {
+            style.red("Performance has regressed.")
+        } else if change.upper < -NOISE_THRESHOLD {
+            style.green("Performance has improved.")
+        } else {
+            "Change within noise threshold.".to_owned()
+        };
+        writeln!(out, "{:w$}{}", "", explanation, w = ID_WIDTH).unwrap();
+    }
+
+    out
+}
+
+/// Format a relative change as a percentage. Mirrors `criterion::format::
+/// change`.
+fn format_change(change: f64) -> String {
+    format!("{:>6}%", signed_short(change * 100.0))
+}
+
+/// Mirrors `criterion::format::signed_short`.
+fn signed_short(n: f64) -> String {
+    let n_abs = n.abs();
+    if n_abs < 10.0 {
+        format!("{:+.4}", n)
+    } else if n_abs < 100.0 {
+        format!("{:+.3}", n)
+    } else if n_abs < 1000.0 {
+        format!("{:+.2}", n)
+    } else if n_abs < 10000.0 {
+        format!("{:+.1}", n)
+    } else {
+        format!("{:+.0}", n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::formatter::CyclesFormatter;
+
+    const CONFIG: protocol::BenchmarkConfig = protocol::BenchmarkConfig {
+        measurement_time: protocol::Duration::from_nanos(1_000_000_000),
+        nresamples: 1000,
+        sample_size: 20,
+        warm_up_time: protocol::Duration::from_nanos(1_000_000_000),
+    };
+
+    fn report_with_seed(dir: &std::path::Path) -> ConsoleReport {
+        ConsoleReport {
+            dir: dir.to_owned(),
+            color: false,
+            rng: StdRng::seed_from_u64(42),
+            regressions: Vec::new(),
+        }
+    }
+
+    /// A deterministic, slightly noisy sample (in cycles per 100 iterations)
+    fn sample(center: u64) -> Vec<u64> {
+        (0..20).map(|i| center + (i * 37) % 50).collect()
+    }
+
+    fn id(
+        function_id: &str,
+        throughput: Option<protocol::Throughput>,
+    ) -> protocol::RawBenchmarkId<String> {
+        protocol::RawBenchmarkId {
+            group_id: "sort".to_owned(),
+            function_id: Some(function_id.to_owned()),
+            value_str: None,
+            throughput,
+        }
+    }
+
+    #[tokio::test]
+    async fn golden_output() {
+        let dir = tempdir::TempDir::new("farcri-test").unwrap();
+        let mut report = report_with_seed(dir.path());
+
+        let id = id("16", Some(protocol::Throughput::Elements(16)));
+        let first = report
+            .analyze(&id, 100, &sample(120_000), &CONFIG, &CyclesFormatter)
+            .await;
+        let unchanged = report
+            .analyze(&id, 100, &sample(120_000), &CONFIG, &CyclesFormatter)
+            .await;
+        let regressed = report
+            .analyze(&id, 100, &sample(132_000), &CONFIG, &CyclesFormatter)
+            .await;
+        let improved = report
+            .analyze(&id, 100, &sample(120_000), &CONFIG, &CyclesFormatter)
+            .await;
+
+        assert_eq!(
+            first,
+            "sort/16                 time:   [1.2002 Kcycles 1.2002 Kcycles 1.2003 Kcycles]\n\
+            \x20                       thrpt:  [75.011  cycles/elem 75.015  cycles/elem 75.019  cycles/elem]\n"
+        );
+        assert_eq!(
+            unchanged,
+            "sort/16                 time:   [1.2002 Kcycles 1.2002 Kcycles 1.2003 Kcycles]\n\
+            \x20                       thrpt:  [75.011  cycles/elem 75.015  cycles/elem 75.019  cycles/elem]\n\
+            \x20                       change: [-0.0073% +0.0000% +0.0071%] (p = 1.00 > 0.05)\n\
+            \x20                       No change in performance detected.\n"
+        );
+        assert_eq!(
+            regressed,
+            "sort/16                 time:   [1.3202 Kcycles 1.3202 Kcycles 1.3203 Kcycles]\n\
+            \x20                       thrpt:  [82.511  cycles/elem 82.515  cycles/elem 82.519  cycles/elem]\n\
+            \x20                       change: [+9.9903% +9.9980% +10.005%] (p = 0.00 < 0.05)\n\
+            \x20                       Performance has regressed.\n"
+        );
+        assert_eq!(
+            improved,
+            "sort/16                 time:   [1.2002 Kcycles 1.2002 Kcycles 1.2003 Kcycles]\n\
+            \x20                       thrpt:  [75.011  cycles/elem 75.015  cycles/elem 75.019  cycles/elem]\n\
+            \x20                       change: [-9.0959% -9.0893% -9.0827%] (p = 0.00 < 0.05)\n\
+            \x20                       Performance has improved.\n"
+        );
+
+        assert_eq!(report.regressions(), ["sort/16"]);
+    }
+
+    #[test]
+    fn long_id() {
+        let mean = Estimate {
+            point: 1230.0,
+            lower: 1200.0,
+            upper: 1250.0,
+        };
+        let report = format_report(
+            "a_very_long_group_name/function",
+            &mean,
+            None,
+            None,
+            &CyclesFormatter,
+            false,
+        );
+        assert_eq!(
+            report,
+            "a_very_long_group_name/function\n\
+            \x20                       time:   [1.2000 Kcycles 1.2300 Kcycles 1.2500 Kcycles]\n"
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let mean = Estimate {
+            point: 100.0,
+            lower: 99.0,
+            upper: 101.0,
+        };
+        let comparison = Comparison {
+            change: Estimate {
+                point: -0.5,
+                lower: -0.6,
+                upper: -0.4,
+            },
+            p_value: 0.0,
+        };
+        let report = format_report("f", &mean, None, Some(&comparison), &CyclesFormatter, true);
+        assert_eq!(
+            report,
+            "\x1b[32mf\x1b[0m                       \
+            time:   [99.000 cycles \x1b[1m100.00 cycles\x1b[0m 101.00 cycles]\n\
+            \x20                       change: [-60.000% \x1b[1m-50.000%\x1b[0m -40.000%] \
+            (p = 0.00 < 0.05)\n\
+            \x20                       \x1b[32mPerformance has improved.\x1b[0m\n"
+        );
+    }
+}
@@ -1,43 +1,786 @@
 //! Dumb (text-only) front-end, used when cargo-criterion is unavailable
-use anyhow::Result;
-use tokio::{
-    io::{AsyncRead, AsyncWrite},
-    time,
+use anyhow::{Context, Result};
+use std::{
+    io::Write,
+    time::{Duration, Instant},
 };
+use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::{bencher::protocol, proxy::targetlink::TargetLink};
+use crate::{
+    bencher::protocol,
+    proxy::{
+        console_report::{ConsoleReport, ID_WIDTH},
+        footprint::{FlashFootprints, Footprint},
+        formatter::{self, SelectedFormatter, ValueFormatter},
+        jsonout::JsonWriter,
+        resultsfile::ResultsFile,
+        stability::{self, Stability},
+        style::{self, Style},
+        targetlink::{TargetLink, REPLY_TIMEOUT},
+    },
+};
 
-pub(super) async fn run_frontend(
-    mut target_link: TargetLink<impl AsyncRead + AsyncWrite>,
-) -> Result<()> {
-    let origin = std::time::Instant::now();
+/// The dumb front-end. It can process multiple runs of the Target program
+/// (see `--farcri-keep-going`), accumulating test failures across them.
+pub(super) struct Frontend<'a> {
+    json_writer: Option<&'a JsonWriter>,
+    results_file: Option<&'a mut ResultsFile>,
+    console_report: Option<&'a mut ConsoleReport>,
+    footprints: Option<&'a FlashFootprints>,
+    formatter: &'a dyn ValueFormatter,
+    test_failures: Vec<(String, String)>,
+    /// Reported by `BenchmarkFailed`
+    benchmark_failures: Vec<(String, String)>,
+    /// Print the outcome of each test in the same format as libtest
+    test_report: Option<TestReport>,
+    /// Print listed benchmarks without the `: benchmark` suffix
+    bare_list: bool,
+    /// Print the throughput per second derived from the measured cycles
+    show_rates: bool,
+    /// List the samples flagged by `stability` in addition to the summary
+    stability_details: bool,
+    /// The core clock frequency used for the benchmarks not preceded by
+    /// `FrequencyChanged`
+    core_clock: Option<u32>,
+    /// Show the warm-up progress on the last line of stderr, which is a
+    /// terminal
+    progress_line: bool,
+    /// A progress line is being shown and must be cleared before printing
+    /// anything else
+    showing_progress: bool,
+}
 
-    loop {
-        let msg = time::timeout(time::Duration::from_secs(20), target_link.recv())
-            .await
-            .map_err(|_| anyhow::anyhow!("Timed out while waiting for a message."))??;
+impl<'a> Frontend<'a> {
+    /// Construct a `Frontend`. If `json_writer` is given, the measurement
+    /// results are saved through it. If `results_file` is given, they are
+    /// also appended to it as each benchmark completes. If `console_report` is given, the
+    /// analysis of each measurement is printed through it, using `formatter`
+    /// to present the measured values. If `footprints` is given, the flash
+    /// footprint of each benchmark is printed along with it.
+    pub(super) fn new(
+        json_writer: Option<&'a JsonWriter>,
+        results_file: Option<&'a mut ResultsFile>,
+        console_report: Option<&'a mut ConsoleReport>,
+        footprints: Option<&'a FlashFootprints>,
+        formatter: &'a dyn ValueFormatter,
+    ) -> Self {
+        Self {
+            json_writer,
+            results_file,
+            console_report,
+            footprints,
+            formatter,
+            test_failures: Vec::new(),
+            benchmark_failures: Vec::new(),
+            test_report: None,
+            bare_list: false,
+            show_rates: false,
+            stability_details: false,
+            core_clock: None,
+            progress_line: style::stderr_is_terminal(),
+            showing_progress: false,
+        }
+    }
 
-        if let protocol::UpstreamMessage::GetInstant = msg {
-            let instant = protocol::Instant::from_nanos(origin.elapsed().as_nanos() as u64);
-            target_link
-                .send(&protocol::DownstreamMessage::Instant(instant))
-                .await?;
-            continue;
+    fn show_progress(&mut self, line: &str) {
+        if self.progress_line {
+            // Overwrite the current line
+            eprint!("\r\x1b[K{}", style::stderr().dim(line));
+            let _ = std::io::stderr().flush();
+            self.showing_progress = true;
+        } else {
+            log::debug!("{}", line);
         }
+    }
 
-        // TODO: Do better
-        log::info!("{:?}", msg);
+    fn clear_progress(&mut self) {
+        if self.showing_progress {
+            eprint!("\r\x1b[K");
+            self.showing_progress = false;
+        }
+    }
+
+    /// Print a line for each test and the summary at the end in the same
+    /// format as libtest. Used in test mode.
+    pub(super) fn enable_test_report(&mut self) {
+        self.test_report = Some(TestReport::default());
+    }
+
+    /// Print only the ID of each listed benchmark, one per line. Used by
+    /// `--farcri-list-static`.
+    pub(super) fn enable_bare_list(&mut self) {
+        self.bare_list = true;
+    }
+
+    /// List each sample deviating far from the median in addition to the
+    /// one-line summary printed for each benchmark.
+    pub(super) fn enable_stability_details(&mut self) {
+        self.stability_details = true;
+    }
 
-        if let protocol::UpstreamMessage::MeasurementComplete { .. } = msg {
-            target_link
-                .send(&protocol::DownstreamMessage::Continue)
-                .await?;
+    /// Print the throughput per second of each benchmark at the core clock
+    /// frequency set by the Target program (`--farcri-frequencies`) or
+    /// `core_clock`. The measured values must be in cycles.
+    pub(super) fn enable_rates(&mut self, core_clock: Option<u32>) {
+        self.show_rates = true;
+        self.core_clock = core_clock;
+    }
+
+    /// Record that the Target program faulted while running the current
+    /// test, which is reported as failed.
+    pub(super) fn record_fault(&mut self) {
+        if let Some(test_report) = &mut self.test_report {
+            test_report.finish_current(true, Instant::now());
+        }
+    }
+
+    /// Report the test failures, failing if there are any.
+    pub(super) fn finish(self) -> Result<()> {
+        let benchmark_result = super::report_benchmark_failures(&self.benchmark_failures);
+        let result = super::report_test_failures(&self.test_failures).and(benchmark_result);
+        if let Some(test_report) = &self.test_report {
+            println!("{}", test_report.summary(Instant::now()));
+            println!();
         }
+        result
+    }
+
+    /// Process the messages from the Target program until it finishes.
+    pub(super) async fn run(
+        &mut self,
+        target_link: &mut TargetLink<impl AsyncRead + AsyncWrite>,
+    ) -> Result<()> {
+        let mut current_benchmark = None;
+        let mut current_footprint = None;
+        let mut current_frequency = None;
+        let mut current_tags = arrayvec::ArrayVec::new();
+        let mut frequency = None;
+        let mut tags = None;
+        let mut value_format = protocol::ValueFormat::Native;
+        let mut sample_timestamps = None;
+        let mut effective_num_samples = None;
+        let mut warm_up_goal = None;
+
+        loop {
+            let msg = target_link.recv_timeout(REPLY_TIMEOUT).await?;
+
+            if let protocol::UpstreamMessage::GetInstant = msg {
+                target_link.send_instant().await?;
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::WarmupProgress {
+                iters_so_far,
+                elapsed,
+            } = msg
+            {
+                let line = format_warm_up_progress(
+                    current_benchmark.as_ref(),
+                    iters_so_far,
+                    elapsed,
+                    warm_up_goal,
+                );
+                self.show_progress(&line);
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::Progress { iters_done, iters } = msg {
+                let line = format_progress(current_benchmark.as_ref(), iters_done, iters);
+                self.show_progress(&line);
+                continue;
+            }
+
+            self.clear_progress();
 
-        if let protocol::UpstreamMessage::End = msg {
-            break;
+            if let Some(test_report) = &mut self.test_report {
+                test_report.process(&msg, Instant::now());
+            }
+
+            if let protocol::UpstreamMessage::ListedBenchmark { id } = &msg {
+                if self.bare_list {
+                    println!("{}", id);
+                } else {
+                    // Print it in the same format as Criterion.rs
+                    println!("{}: benchmark", id);
+                }
+                continue;
+            }
+
+            // TODO: Do better
+            log::info!("{:?}", msg);
+
+            if let protocol::UpstreamMessage::SampleSizeClamped {
+                requested,
+                effective,
+            } = msg
+            {
+                super::log_sample_size_clamped(requested, effective);
+                effective_num_samples = Some(effective);
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::IdTruncated {
+                id,
+                group_id_len,
+                function_id_len,
+                value_str_len,
+            } = &msg
+            {
+                super::log_id_truncated(id, *group_id_len, *function_id_len, *value_str_len);
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::MeasurementWarning { kind } = msg {
+                super::log_measurement_warning(kind);
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::SampleTimestamps { timestamps } = msg {
+                sample_timestamps = Some(timestamps);
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::TestFailed { id, message } = msg {
+                self.test_failures.push((id.to_string(), message));
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::BenchmarkFailed { id, message } = msg {
+                // Prefer the ID tagged with the frequency
+                let id = current_benchmark.take().unwrap_or(id).to_string();
+                log::error!("The benchmark '{}' failed: {}", id, message);
+                self.benchmark_failures.push((id, message));
+                current_footprint = None;
+                current_frequency = None;
+                sample_timestamps = None;
+                effective_num_samples = None;
+                target_link
+                    .send(&protocol::DownstreamMessage::Continue)
+                    .await?;
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::FrequencyChanged { effective, .. } = msg {
+                frequency = Some(effective);
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::BenchmarkTags { tags: x } = msg {
+                tags = Some(x);
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::ValueFormatChanged { format } = msg {
+                value_format = format;
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::Warmup {
+                warm_up_goal_duration,
+            } = msg
+            {
+                warm_up_goal = Some(warm_up_goal_duration);
+                continue;
+            }
+
+            if let protocol::UpstreamMessage::BeginningBenchmark { id } = &msg {
+                // Look it up before tagging the frequency
+                current_footprint = self.footprints.and_then(|x| x.lookup(id));
+                let mut id = id.clone();
+                let hz = frequency.take();
+                if let Some(hz) = hz {
+                    super::tag_frequency(&mut id, hz);
+                }
+                current_frequency = hz.or(self.core_clock);
+                current_tags = tags.take().unwrap_or_default();
+                current_benchmark = Some(id);
+            }
+
+            if let protocol::UpstreamMessage::FinishedBenchmarkGroup = msg {
+                target_link
+                    .send(&protocol::DownstreamMessage::Continue)
+                    .await?;
+            }
+
+            if let protocol::UpstreamMessage::MeasurementComplete {
+                num_iters_per_sample,
+                values,
+                benchmark_config,
+            } = &msg
+            {
+                super::log_sample_count(
+                    values.len(),
+                    effective_num_samples
+                        .take()
+                        .unwrap_or(benchmark_config.sample_size),
+                );
+                let timestamps = sample_timestamps.take();
+                if let Some(timestamps) = &timestamps {
+                    super::log_sample_drift(values, timestamps);
+                }
+
+                if let Some(id) = current_benchmark.take() {
+                    if let Some(console_report) = self.console_report.as_deref_mut() {
+                        console_report
+                            .measurement_complete(
+                                &id,
+                                *num_iters_per_sample,
+                                values,
+                                benchmark_config,
+                                &SelectedFormatter::new(self.formatter, value_format),
+                            )
+                            .await;
+                    }
+
+                    if let Some((path, footprint)) = current_footprint.take() {
+                        eprintln!("{}", format_footprint(&path, footprint));
+                    }
+
+                    if let Some(hz) = current_frequency.take().filter(|_| self.show_rates) {
+                        let iters = *num_iters_per_sample as f64 * values.len() as f64;
+                        let mean_cycles = values.iter().map(|&x| x as f64).sum::<f64>() / iters;
+                        let throughput = id.throughput.as_ref();
+                        if let Some(line) = format_rate(mean_cycles, throughput, &value_format, hz)
+                        {
+                            eprintln!("{}", line);
+                        }
+                    }
+
+                    if self.console_report.is_some() {
+                        if let Some(stability) = stability::analyze(values) {
+                            eprint!(
+                                "{}",
+                                format_stability(
+                                    &stability,
+                                    self.stability_details,
+                                    style::stderr()
+                                )
+                            );
+                        }
+                    }
+
+                    if let Some(json_writer) = self.json_writer {
+                        json_writer
+                            .write_measurement(
+                                &id,
+                                &current_tags,
+                                *num_iters_per_sample,
+                                values,
+                                timestamps.as_deref(),
+                                benchmark_config,
+                            )
+                            .await
+                            .context("Failed to save the measurement result.")?;
+                    }
+
+                    if let Some(results_file) = self.results_file.as_deref_mut() {
+                        results_file
+                            .write_measurement(&id, &current_tags, *num_iters_per_sample, values)
+                            .await
+                            .context("Failed to save the measurement result.")?;
+                    }
+                }
+
+                target_link
+                    .send(&protocol::DownstreamMessage::Continue)
+                    .await?;
+            }
+
+            if let protocol::UpstreamMessage::End = msg {
+                if let Some(results_file) = self.results_file.as_deref_mut() {
+                    results_file
+                        .finish()
+                        .await
+                        .context("Failed to finish the results file.")?;
+                }
+                break;
+            }
         }
+
+        Ok(())
     }
+}
 
-    Ok(())
+/// The outcome of the tests run in test mode. The Target program only
+/// reports failed tests, so a test is considered passed if the next
+/// benchmark or the end of its group is reached without a failure.
+#[derive(Debug, Default)]
+struct TestReport {
+    /// When the first test started
+    start: Option<Instant>,
+    /// The running test and when it started
+    current: Option<(String, Instant)>,
+    num_passed: usize,
+    num_failed: usize,
+    num_ignored: usize,
+    num_filtered_out: usize,
+}
+
+impl TestReport {
+    fn process(&mut self, msg: &protocol::UpstreamMessage<String, Vec<u64>>, now: Instant) {
+        match msg {
+            protocol::UpstreamMessage::RunningBenchmark { id } => {
+                self.finish_current(false, now);
+                self.start.get_or_insert(now);
+                self.current = Some((id.to_string(), now));
+            }
+            protocol::UpstreamMessage::TestFailed { .. } => self.finish_current(true, now),
+            protocol::UpstreamMessage::SkippingBenchmark { id, reason } => {
+                self.finish_current(false, now);
+                match reason {
+                    protocol::SkipReason::Filter => self.num_filtered_out += 1,
+                    protocol::SkipReason::Budget => {
+                        println!("test {} ... ignored", id);
+                        self.num_ignored += 1;
+                    }
+                    // Already reported by the previous run of the Target
+                    // program
+                    protocol::SkipReason::Resume => {}
+                }
+            }
+            protocol::UpstreamMessage::FinishedBenchmarkGroup | protocol::UpstreamMessage::End => {
+                self.finish_current(false, now)
+            }
+            _ => {}
+        }
+    }
+
+    /// Print the outcome of the running test, if any.
+    fn finish_current(&mut self, failed: bool, now: Instant) {
+        if let Some((id, start)) = self.current.take() {
+            println!("{}", format_test_outcome(&id, failed, now - start));
+            if failed {
+                self.num_failed += 1;
+            } else {
+                self.num_passed += 1;
+            }
+        }
+    }
+
+    /// Format the summary line, e.g., `test result: ok. 3 passed; 0 failed;
+    /// 0 ignored; 0 measured; 1 filtered out; finished in 0.52s`.
+    fn summary(&self, now: Instant) -> String {
+        let elapsed = self.start.map_or(Duration::default(), |start| now - start);
+        format!(
+            "test result: {}. {} passed; {} failed; {} ignored; 0 measured; \
+            {} filtered out; finished in {:.2}s",
+            if self.num_failed > 0 { "FAILED" } else { "ok" },
+            self.num_passed,
+            self.num_failed,
+            self.num_ignored,
+            self.num_filtered_out,
+            elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// Format the outcome of a test in the same format as libtest's
+/// `--report-time`, e.g., `test sort/16 ... ok <0.013s>`. `elapsed` is
+/// measured by the host and includes the communication with the Target
+/// program.
+fn format_test_outcome(id: &str, failed: bool, elapsed: Duration) -> String {
+    format!(
+        "test {} ... {} <{:.3}s>",
+        id,
+        if failed { "FAILED" } else { "ok" },
+        elapsed.as_secs_f64()
+    )
+}
+
+/// Format the flash footprint of a benchmark in the same layout as
+/// `ConsoleReport`, e.g., `flash:  220 bytes (sort::insertion_sort, 2
+/// symbols)`.
+fn format_footprint(path: &str, footprint: Footprint) -> String {
+    let mut line = format!(
+        "{:w$}flash:  {} bytes ({}",
+        "",
+        footprint.size,
+        path,
+        w = ID_WIDTH
+    );
+    if footprint.num_symbols > 1 {
+        line += &format!(", {} symbols", footprint.num_symbols);
+    }
+    line.push(')');
+    line
+}
+
+/// Format the throughput per second of a benchmark taking `mean_cycles`
+/// cycles per iteration when the core clock runs at `hz`, in the same layout
+/// as `ConsoleReport`, e.g., `rate:   1.2000 Melem/s @ 84 MHz`. The throughput
+/// of an iteration is given by `throughput` or, failing that,
+/// `value_format`; otherwise, iterations are counted as `ops`.
+fn format_rate(
+    mean_cycles: f64,
+    throughput: Option<&protocol::Throughput>,
+    value_format: &protocol::ValueFormat,
+    hz: u32,
+) -> Option<String> {
+    if mean_cycles.is_nan() || mean_cycles <= 0.0 {
+        return None;
+    }
+
+    let (amount, unit) = match (throughput, value_format) {
+        (Some(protocol::Throughput::Bytes(n)), _) => (*n, "B"),
+        (Some(protocol::Throughput::Elements(n)), _) => (*n, "elem"),
+        (Some(protocol::Throughput::Custom { amount, unit }), _)
+        | (None, protocol::ValueFormat::PerUnit { amount, unit }) => (*amount, unit.as_str()),
+        (None, protocol::ValueFormat::Native) => (1, "ops"),
+    };
+
+    let rate = amount as f64 * hz as f64 / mean_cycles;
+    let (denominator, prefix) = if rate < 1e3 {
+        (1.0, "")
+    } else if rate < 1e6 {
+        (1e3, "K")
+    } else if rate < 1e9 {
+        (1e6, "M")
+    } else {
+        (1e9, "G")
+    };
+
+    Some(format!(
+        "{:w$}rate:   {} {}{}/s @ {}",
+        "",
+        formatter::short(rate / denominator),
+        prefix,
+        unit,
+        crate::utils::Frequency(hz),
+        w = ID_WIDTH
+    ))
+}
+
+/// Format the result of `stability::analyze` in the same layout as
+/// `ConsoleReport`, e.g., `noise:  moderate (1 of 100 samples off by >5% from
+/// the median)`. If `details` is `true`, the flagged samples are listed on
+/// the following lines.
+fn format_stability(stability: &Stability, details: bool, style: Style) -> String {
+    let noise = stability.noise();
+    let level = match noise {
+        stability::Noise::Low => style.green(noise.as_str()),
+        stability::Noise::Moderate => style.yellow(noise.as_str()),
+        stability::Noise::High => style.red(noise.as_str()),
+    };
+    let mut out = format!(
+        "{:w$}noise:  {} ({} of {} samples off by >{}% from the median)\n",
+        "",
+        level,
+        stability.deviants.len(),
+        stability.num_samples,
+        stability::DEVIATION_THRESHOLD * 100.0,
+        w = ID_WIDTH
+    );
+    if details {
+        for deviant in stability.deviants.iter() {
+            out += &format!(
+                "{:w$}        sample #{}: {} ({:+.1}%)\n",
+                "",
+                deviant.index,
+                deviant.value,
+                deviant.deviation * 100.0,
+                w = ID_WIDTH
+            );
+        }
+    }
+    out
+}
+
+/// Format the warm-up progress reported by `WarmupProgress`, e.g.,
+/// `sort/16: warming up: 4096 iters, 1.8s/3.0s`.
+fn format_warm_up_progress(
+    id: Option<&protocol::RawBenchmarkId<String>>,
+    iters_so_far: u64,
+    elapsed: protocol::Duration,
+    goal: Option<protocol::Duration>,
+) -> String {
+    let secs = |x: protocol::Duration| x.as_nanos() as f64 * 1e-9;
+    let mut line = match id {
+        Some(id) => format!("{}: ", id),
+        None => String::new(),
+    };
+    line += &format!("warming up: {} iters, {:.1}s", iters_so_far, secs(elapsed));
+    if let Some(goal) = goal {
+        line += &format!("/{:.1}s", secs(goal));
+    }
+    line
+}
+
+/// Format the progress reported by `Progress`, e.g.,
+/// `sort/16: running: 3000/8192 iters`.
+fn format_progress(
+    id: Option<&protocol::RawBenchmarkId<String>>,
+    iters_done: u64,
+    iters: u64,
+) -> String {
+    let mut line = match id {
+        Some(id) => format!("{}: ", id),
+        None => String::new(),
+    };
+    line += &format!("running: {}/{} iters", iters_done, iters);
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stability() {
+        let mut values = vec![1000; 40];
+        values[3] = 1200;
+        values[17] = 940;
+        let stability = stability::analyze(&values).unwrap();
+        let pad = " ".repeat(ID_WIDTH);
+
+        assert_eq!(
+            format_stability(&stability, false, Style::new(false)),
+            format!(
+                "{}noise:  moderate (2 of 40 samples off by >5% from the median)\n",
+                pad
+            )
+        );
+        assert_eq!(
+            format_stability(&stability, true, Style::new(false)),
+            format!(
+                "{0}noise:  moderate (2 of 40 samples off by >5% from the median)\n\
+                {0}        sample #3: 1200 (+20.0%)\n\
+                {0}        sample #17: 940 (-6.0%)\n",
+                pad
+            )
+        );
+
+        let stability = stability::analyze(&[1000; 40]).unwrap();
+        assert_eq!(
+            format_stability(&stability, true, Style::new(true)),
+            format!(
+                "{}noise:  \x1b[32mlow\x1b[0m (0 of 40 samples off by >5% from the median)\n",
+                pad
+            )
+        );
+    }
+
+    #[test]
+    fn warm_up_progress() {
+        let id = protocol::RawBenchmarkId {
+            group_id: "sort".to_owned(),
+            function_id: Some("16".to_owned()),
+            value_str: None,
+            throughput: None,
+        };
+        assert_eq!(
+            format_warm_up_progress(
+                Some(&id),
+                4096,
+                protocol::Duration::from_nanos(1_800_000_000),
+                Some(protocol::Duration::from_nanos(3_000_000_000)),
+            ),
+            "sort/16: warming up: 4096 iters, 1.8s/3.0s"
+        );
+        assert_eq!(
+            format_warm_up_progress(None, 1, protocol::Duration::from_nanos(300_000_000), None),
+            "warming up: 1 iters, 0.3s"
+        );
+    }
+
+    #[test]
+    fn progress() {
+        let id = protocol::RawBenchmarkId {
+            group_id: "sort".to_owned(),
+            function_id: Some("16".to_owned()),
+            value_str: None,
+            throughput: None,
+        };
+        assert_eq!(
+            format_progress(Some(&id), 3000, 8192),
+            "sort/16: running: 3000/8192 iters"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let id = |function_id: &str| protocol::RawBenchmarkId {
+            group_id: "sort".to_owned(),
+            function_id: Some(function_id.to_owned()),
+            value_str: None,
+            throughput: None,
+        };
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let mut report = TestReport::default();
+        let messages = [
+            protocol::UpstreamMessage::RunningBenchmark { id: id("16") },
+            protocol::UpstreamMessage::RunningBenchmark { id: id("32") },
+            protocol::UpstreamMessage::TestFailed {
+                id: id("32"),
+                message: "assertion failed".to_owned(),
+            },
+            protocol::UpstreamMessage::SkippingBenchmark {
+                id: id("64"),
+                reason: protocol::SkipReason::Filter,
+            },
+            protocol::UpstreamMessage::RunningBenchmark { id: id("128") },
+            protocol::UpstreamMessage::End,
+        ];
+        for (i, msg) in messages.iter().enumerate() {
+            report.process(msg, at(i as u64 * 10));
+        }
+
+        assert_eq!((report.num_passed, report.num_failed), (2, 1));
+        assert_eq!(
+            report.summary(at(1500)),
+            "test result: FAILED. 2 passed; 1 failed; 0 ignored; 0 measured; \
+            1 filtered out; finished in 1.50s"
+        );
+        assert_eq!(
+            format_test_outcome("sort/16", false, Duration::from_millis(13)),
+            "test sort/16 ... ok <0.013s>"
+        );
+    }
+
+    #[test]
+    fn rate() {
+        let native = protocol::ValueFormat::Native;
+        let rate = |mean_cycles, throughput, value_format, hz| {
+            format_rate(mean_cycles, throughput, value_format, hz)
+                .map(|line| line.trim_start().to_owned())
+        };
+
+        assert_eq!(
+            rate(70.0, None, &native, 84_000_000).as_deref(),
+            Some("rate:   1.2000 Mops/s @ 84 MHz")
+        );
+        assert_eq!(
+            rate(
+                1024.0,
+                Some(&protocol::Throughput::Bytes(64)),
+                &native,
+                16_000_000
+            )
+            .as_deref(),
+            Some("rate:   1.0000 MB/s @ 16 MHz")
+        );
+        let per_block = protocol::ValueFormat::PerUnit {
+            amount: 4,
+            unit: arrayvec::ArrayString::from("block").unwrap(),
+        };
+        assert_eq!(
+            rate(8e9, None, &per_block, 1_000_000).as_deref(),
+            Some("rate:   0.0005 block/s @ 1 MHz")
+        );
+        assert_eq!(rate(0.0, None, &native, 1_000_000), None);
+    }
+
+    #[test]
+    fn footprint() {
+        let footprint = |size, num_symbols| Footprint { size, num_symbols };
+        assert_eq!(
+            format_footprint("sort::noop", footprint(4, 1)),
+            "                        flash:  4 bytes (sort::noop)"
+        );
+        assert_eq!(
+            format_footprint("sort::insertion_sort", footprint(220, 2)),
+            "                        flash:  220 bytes (sort::insertion_sort, 2 symbols)"
+        );
+    }
 }
@@ -0,0 +1,176 @@
+//! The categorized failures of the Proxy program and their exit codes
+use super::targetlink::TargetFault;
+
+/// The description of the exit codes included in `--help`
+pub(super) const EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    Success
+    1    Infrastructure failure (invalid options, build, probe, flashing, I/O)
+    2    Performance regression detected by `--farcri-fail-on-regression`
+    3    The Target program faulted, panicked, or failed a test
+    4    The Target program violated the communication protocol
+    5    Benchmarks were skipped by `--farcri-suite-time-budget` with
+         `--farcri-budget-strict`";
+
+/// The exit codes of the Proxy program. See [`EXIT_CODES_HELP`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ExitCode {
+    Success = 0,
+    Infrastructure = 1,
+    Regression = 2,
+    TargetFault = 3,
+    ProtocolViolation = 4,
+    BudgetExhausted = 5,
+}
+
+/// A failure of the Proxy program, categorized by the exit code it's
+/// reported with
+#[derive(thiserror::Error, Debug)]
+pub(super) enum ProxyError {
+    /// Invalid options, or a failure of the build, the debug probe, the
+    /// host's I/O, etc.
+    #[error(transparent)]
+    Infrastructure(anyhow::Error),
+    /// `--farcri-fail-on-regression` detected a performance regression.
+    #[error(transparent)]
+    Regression(anyhow::Error),
+    /// The Target program faulted, panicked, or failed a test.
+    #[error(transparent)]
+    TargetFault(anyhow::Error),
+    /// The Target program sent something unexpected.
+    #[error(transparent)]
+    Protocol(anyhow::Error),
+    /// `--farcri-budget-strict` and some benchmarks didn't fit in
+    /// `--farcri-suite-time-budget`.
+    #[error(transparent)]
+    Budget(anyhow::Error),
+}
+
+/// Attached to an error as its context to indicate that the Target program
+/// violated the communication protocol
+#[derive(thiserror::Error, Debug)]
+#[error("The Target program violated the communication protocol.")]
+pub(super) struct ProtocolViolation;
+
+/// Indicates that benchmark or test code failed on the Target system without
+/// breaking the communication (e.g., a failed test)
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+pub(super) struct TargetFailure(pub(super) String);
+
+/// Indicates that `--farcri-fail-on-regression` detected a regression
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+pub(super) struct RegressionDetected(pub(super) String);
+
+/// Indicates that `--farcri-budget-strict` is in effect and some benchmarks
+/// were skipped by `--farcri-suite-time-budget`
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+pub(super) struct BudgetExhausted(pub(super) String);
+
+impl From<anyhow::Error> for ProxyError {
+    /// Categorize an error by the markers found in its chain. A protocol
+    /// violation takes precedence over [`TargetFault`] because the latter is
+    /// attached to any error raised while receiving a message.
+    fn from(e: anyhow::Error) -> Self {
+        // `downcast_ref` looks through all layers of context
+        if e.downcast_ref::<ProtocolViolation>().is_some() {
+            Self::Protocol(e)
+        } else if e.downcast_ref::<TargetFault>().is_some()
+            || e.downcast_ref::<TargetFailure>().is_some()
+        {
+            Self::TargetFault(e)
+        } else if e.downcast_ref::<RegressionDetected>().is_some() {
+            Self::Regression(e)
+        } else if e.downcast_ref::<BudgetExhausted>().is_some() {
+            Self::Budget(e)
+        } else {
+            Self::Infrastructure(e)
+        }
+    }
+}
+
+impl ProxyError {
+    pub(super) fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::Infrastructure(_) => ExitCode::Infrastructure,
+            Self::Regression(_) => ExitCode::Regression,
+            Self::TargetFault(_) => ExitCode::TargetFault,
+            Self::Protocol(_) => ExitCode::ProtocolViolation,
+            Self::Budget(_) => ExitCode::BudgetExhausted,
+        }
+    }
+
+    /// Get the underlying error, which is printed with its causes when the
+    /// Proxy program exits.
+    pub(super) fn inner(&self) -> &anyhow::Error {
+        match self {
+            Self::Infrastructure(e)
+            | Self::Regression(e)
+            | Self::TargetFault(e)
+            | Self::Protocol(e)
+            | Self::Budget(e) => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn exit_code_of(e: anyhow::Error) -> i32 {
+        ProxyError::from(e).exit_code() as i32
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(ExitCode::Success as i32, 0);
+
+        assert_eq!(exit_code_of(anyhow::anyhow!("cargo failed")), 1);
+        assert_eq!(
+            exit_code_of(
+                Err::<(), _>(anyhow::anyhow!("no probe"))
+                    .context("Failed to connect to the target.")
+                    .unwrap_err()
+            ),
+            1
+        );
+        assert_eq!(
+            exit_code_of(RegressionDetected("1 benchmark(s) regressed.".into()).into()),
+            2
+        );
+        assert_eq!(exit_code_of(TargetFault::Timeout.into()), 3);
+        assert_eq!(
+            exit_code_of(TargetFailure("1 test(s) failed.".into()).into()),
+            3
+        );
+        assert_eq!(
+            exit_code_of(anyhow::anyhow!("out of order").context(ProtocolViolation)),
+            4
+        );
+        assert_eq!(
+            exit_code_of(BudgetExhausted("2 benchmark(s) not run.".into()).into()),
+            5
+        );
+    }
+
+    #[test]
+    fn protocol_violation_takes_precedence() {
+        // `TargetLink::recv` attaches `TargetFault::Link` to any error
+        let e = anyhow::anyhow!("out of order")
+            .context(ProtocolViolation)
+            .context(TargetFault::Link);
+        assert_eq!(exit_code_of(e), 4);
+
+        let e = anyhow::anyhow!("EOF").context(TargetFault::Link);
+        assert_eq!(exit_code_of(e), 3);
+    }
+
+    #[test]
+    fn inner_error_is_preserved() {
+        let e = ProxyError::from(anyhow::anyhow!("foo").context("bar"));
+        assert_eq!(format!("{:#}", e.inner()), "bar: foo");
+        assert_eq!(e.to_string(), "bar");
+    }
+}
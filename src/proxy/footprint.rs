@@ -0,0 +1,189 @@
+//! Per-benchmark flash footprint (`--farcri-flash-footprint`)
+//!
+//! Reports the size of the function implementing each benchmark, taken from
+//! the target executable's symbol table. A benchmark is mapped to functions
+//! defined in the benchmark crate by the following naming convention:
+//!
+//!  - The benchmark's function ID (e.g., `insertion_sort` in
+//!    `BenchmarkId::new("insertion_sort", 16)`) or, if it doesn't match
+//!    anything, its group ID is looked up.
+//!  - The name matches a function whose path is equal to the name (e.g.,
+//!    `sort::noop` registered by `#[farcri::bench]`) or to the name prefixed
+//!    by the benchmark crate's name (e.g., `noop` matches `sort::noop`).
+//!
+//! This has the following limitations:
+//!
+//!  - Only the named function is measured, not its callees. Functions
+//!    inlined into it are included, so mark the benchmarked function
+//!    `#[inline(never)]` to make it have its own symbol.
+//!  - All monomorphized instances of a generic function share a path and
+//!    are summed up.
+//!  - Closures can't be named, so benchmarks defined by closures are not
+//!    reported.
+//!  - Only the legacy symbol mangling scheme is understood.
+use anyhow::{Context, Result};
+use std::{collections::HashMap, path::Path};
+
+use super::symbols::legacy_symbol_path;
+use crate::bencher::protocol;
+
+/// The sizes of the functions defined in the benchmark crate
+pub(super) struct FlashFootprints {
+    bench_crate: String,
+    /// Indexed by a path (e.g., `sort::noop`)
+    by_path: HashMap<String, Footprint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Footprint {
+    /// The total size in bytes
+    pub(super) size: u64,
+    /// The number of the functions with the path
+    pub(super) num_symbols: usize,
+}
+
+impl FlashFootprints {
+    /// Read the symbol table of the specified executable.
+    pub(super) async fn load(exe: &Path, bench_crate: &str) -> Result<Self> {
+        let elf_bytes = tokio::fs::read(exe)
+            .await
+            .with_context(|| format!("Failed to read '{}'.", exe.display()))?;
+        let elf = goblin::elf::Elf::parse(&elf_bytes).context("Failed to parse the executable.")?;
+
+        let mut symbols: Vec<(u64, u64, &str)> = (elf.syms.iter())
+            .filter(|sym| sym.st_type() == goblin::elf::sym::STT_FUNC && sym.st_size > 0)
+            .filter_map(|sym| {
+                let name = elf.strtab.get(sym.st_name)?.ok()?;
+                // Clear the Thumb bit
+                Some((sym.st_value & !1, sym.st_size, name))
+            })
+            .collect();
+
+        // Remove aliases
+        symbols.sort_by_key(|&(addr, _, _)| addr);
+        symbols.dedup_by_key(|&mut (addr, _, _)| addr);
+
+        Ok(Self::from_symbols(
+            symbols.into_iter().map(|(_, size, name)| (name, size)),
+            bench_crate,
+        ))
+    }
+
+    /// Construct `FlashFootprints` from pairs of a mangled symbol name and a
+    /// size.
+    fn from_symbols<'a>(
+        symbols: impl IntoIterator<Item = (&'a str, u64)>,
+        bench_crate: &str,
+    ) -> Self {
+        let bench_crate = bench_crate.replace('-', "_");
+        let mut by_path = HashMap::new();
+
+        for (name, size) in symbols {
+            let path = match legacy_symbol_path(name) {
+                Some(path) if path[0] == bench_crate => path,
+                _ => continue,
+            };
+            let footprint = by_path.entry(path.join("::")).or_insert(Footprint {
+                size: 0,
+                num_symbols: 0,
+            });
+            footprint.size += size;
+            footprint.num_symbols += 1;
+        }
+
+        Self {
+            bench_crate,
+            by_path,
+        }
+    }
+
+    /// Get the number of the functions found in the benchmark crate.
+    pub(super) fn len(&self) -> usize {
+        self.by_path.len()
+    }
+
+    /// Find the function implementing the specified benchmark. Returns the
+    /// function's path and footprint.
+    pub(super) fn lookup(
+        &self,
+        id: &protocol::RawBenchmarkId<String>,
+    ) -> Option<(String, Footprint)> {
+        let names = id.function_id.iter().chain(std::iter::once(&id.group_id));
+        for name in names {
+            for path in [name.clone(), format!("{}::{}", self.bench_crate, name)].iter() {
+                if let Some(&footprint) = self.by_path.get(path) {
+                    return Some((path.clone(), footprint));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(group_id: &str, function_id: Option<&str>) -> protocol::RawBenchmarkId<String> {
+        protocol::RawBenchmarkId {
+            group_id: group_id.to_owned(),
+            function_id: function_id.map(ToOwned::to_owned),
+            value_str: None,
+            throughput: None,
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let footprints = FlashFootprints::from_symbols(
+            vec![
+                ("_ZN4sort4noop17h0123456789abcdefE", 4),
+                // Two instances of a generic function
+                ("_ZN4sort14insertion_sort17h0123456789abcdefE", 100),
+                ("_ZN4sort14insertion_sort17hfedcba9876543210E", 120),
+                ("_ZN4core5slice4sort7recurse17h0123456789abcdefE", 1000),
+                ("__aeabi_memcpy", 10),
+            ],
+            "sort",
+        );
+        assert_eq!(footprints.len(), 2);
+
+        // `#[farcri::bench]`
+        assert_eq!(
+            footprints.lookup(&id("sort::noop", None)),
+            Some((
+                "sort::noop".to_owned(),
+                Footprint {
+                    size: 4,
+                    num_symbols: 1
+                }
+            ))
+        );
+        // `c.bench_function("noop", noop)`
+        assert_eq!(
+            footprints.lookup(&id("noop", None)).unwrap().0,
+            "sort::noop"
+        );
+        // The function ID takes precedence
+        assert_eq!(
+            footprints.lookup(&id("noop", Some("insertion_sort"))),
+            Some((
+                "sort::insertion_sort".to_owned(),
+                Footprint {
+                    size: 220,
+                    num_symbols: 2
+                }
+            ))
+        );
+        assert_eq!(
+            footprints.lookup(&id("noop", Some("16"))).unwrap().0,
+            "sort::noop"
+        );
+        // Functions outside the benchmark crate are ignored
+        assert_eq!(
+            footprints.lookup(&id("core::slice::sort::recurse", None)),
+            None
+        );
+        assert_eq!(footprints.lookup(&id("__aeabi_memcpy", None)), None);
+    }
+}
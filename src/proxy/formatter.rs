@@ -1,4 +1,6 @@
-use crate::bencher::protocol::Throughput;
+use std::borrow::Cow;
+
+use crate::bencher::protocol::{Throughput, ValueFormat};
 
 /// Trait providing functions to format measured values to string so that they can be displayed on
 /// the command line or in the reports. The functions of this trait take measured values in f64
@@ -32,7 +34,7 @@ pub(crate) trait ValueFormatter {
     /// multiple times with different datasets; the typical value will remain the same to ensure
     /// that the units remain consistent within a graph. The typical value will not be NaN.
     /// Values will not contain NaN as input, and the transformed values must not contain NaN.
-    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> &'static str;
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> Cow<'static, str>;
 
     /// Convert the given measured values into throughput numbers based on the given throughput
     /// value, scale them to some appropriate unit, and return the unit string.
@@ -46,98 +48,353 @@ pub(crate) trait ValueFormatter {
         typical_value: f64,
         throughput: &Throughput,
         values: &mut [f64],
-    ) -> &'static str;
+    ) -> Cow<'static, str>;
 
     /// Scale the values and return a unit string designed for machines.
     ///
     /// For example, this is used for the CSV file output. Implementations should modify the given
     /// values slice to apply the desired scaling (if any) and return a string representing the unit
     /// the modified values are in.
-    fn scale_for_machines(&self, values: &mut [f64]) -> &'static str;
+    fn scale_for_machines(&self, values: &mut [f64]) -> Cow<'static, str>;
+}
+
+/// A fixed unit for [`ValueFormatter::scale_for_machines`], chosen by
+/// `--farcri-machine-unit`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct MachineUnit {
+    pub(crate) name: &'static str,
+    /// The factor to multiply the values in the native unit by
+    factor: f64,
 }
 
+impl MachineUnit {
+    const fn new(name: &'static str, factor: f64) -> Self {
+        Self { name, factor }
+    }
+}
+
+/// The units [`CyclesFormatter`] can be made to scale machine-readable values
+/// to
+pub(crate) const CYCLES_MACHINE_UNITS: &[MachineUnit] = &[
+    MachineUnit::new("cycles", 1.0),
+    MachineUnit::new("Kcycles", 1e-3),
+    MachineUnit::new("Mcycles", 1e-6),
+    MachineUnit::new("Gcycles", 1e-9),
+];
+
+/// The units [`WallTimeFormatter`] can be made to scale machine-readable
+/// values to
+pub(crate) const WALL_TIME_MACHINE_UNITS: &[MachineUnit] = &[
+    MachineUnit::new("ps", 1e3),
+    MachineUnit::new("ns", 1.0),
+    MachineUnit::new("us", 1e-3),
+    MachineUnit::new("ms", 1e-6),
+    MachineUnit::new("s", 1e-9),
+];
+
 pub(crate) struct CyclesFormatter;
 
+/// Divide `values` (in cycles per iteration) by `amount`, the number of units
+/// processed by one iteration, and scale them by a power of 1000 chosen
+/// based on `typical`. Returns the exponent of the power (0–3), which indexes
+/// the units' metric prefix.
+fn scale_cycles_per(amount: f64, typical: f64, values: &mut [f64]) -> usize {
+    let cycles_per_unit = typical / amount;
+    let (denominator, exponent) = if cycles_per_unit < 1000.0 {
+        (1.0, 0)
+    } else if cycles_per_unit < 1000.0 * 1000.0 {
+        (1000.0, 1)
+    } else if cycles_per_unit < 1000.0 * 1000.0 * 1000.0 {
+        (1000.0 * 1000.0, 2)
+    } else {
+        (1000.0 * 1000.0 * 1000.0, 3)
+    };
+
+    for val in values {
+        let cycles_per_unit = *val / amount;
+        *val = cycles_per_unit / denominator;
+    }
+
+    exponent
+}
+
 impl CyclesFormatter {
     fn cycles_per_byte(&self, bytes: f64, typical: f64, values: &mut [f64]) -> &'static str {
-        let cycles_per_byte = typical / bytes;
-        let (denominator, unit) = if cycles_per_byte < 1000.0 {
-            (1.0, "  cycles/B")
-        } else if cycles_per_byte < 1000.0 * 1000.0 {
-            (1000.0, "Kcycles/B")
-        } else if cycles_per_byte < 1000.0 * 1000.0 * 1000.0 {
-            (1000.0 * 1000.0, "Mcycles/B")
+        ["  cycles/B", "Kcycles/B", "Mcycles/B", "Gcycles/B"]
+            [scale_cycles_per(bytes, typical, values)]
+    }
+
+    fn cycles_per_element(&self, elems: f64, typical: f64, values: &mut [f64]) -> &'static str {
+        [
+            " cycles/elem",
+            "Kcycles/elem",
+            "Mcycles/elem",
+            "Gcycles/elem",
+        ][scale_cycles_per(elems, typical, values)]
+    }
+
+    fn cycles_per_custom_unit(
+        &self,
+        amount: f64,
+        unit: &str,
+        typical: f64,
+        values: &mut [f64],
+    ) -> String {
+        let prefix = [" ", "K", "M", "G"][scale_cycles_per(amount, typical, values)];
+        format!("{}cycles/{}", prefix, unit)
+    }
+}
+
+impl ValueFormatter for CyclesFormatter {
+    fn scale_throughputs(
+        &self,
+        typical: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> Cow<'static, str> {
+        match *throughput {
+            Throughput::Bytes(bytes) => self.cycles_per_byte(bytes as f64, typical, values).into(),
+            Throughput::Elements(elems) => self
+                .cycles_per_element(elems as f64, typical, values)
+                .into(),
+            Throughput::Custom { amount, unit } => self
+                .cycles_per_custom_unit(amount as f64, &unit, typical, values)
+                .into(),
+        }
+    }
+
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> Cow<'static, str> {
+        let (factor, unit) = if typical_value < 10f64.powi(3) {
+            (10f64.powi(0), "cycles")
+        } else if typical_value < 10f64.powi(6) {
+            (10f64.powi(-3), "Kcycles")
+        } else if typical_value < 10f64.powi(9) {
+            (10f64.powi(-6), "Mcycles")
+        } else {
+            (10f64.powi(-9), "Gcycles")
+        };
+
+        for val in values {
+            *val *= factor;
+        }
+
+        unit.into()
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> Cow<'static, str> {
+        // no scaling is needed
+        "cycles".into()
+    }
+}
+
+/// Formats values in nanoseconds, e.g., those measured by the `std` port
+/// (`target_std`)
+pub(crate) struct WallTimeFormatter;
+
+impl WallTimeFormatter {
+    fn bytes_per_second(&self, bytes: f64, typical: f64, values: &mut [f64]) -> &'static str {
+        let bytes_per_second = bytes * (1e9 / typical);
+        let (denominator, unit) = if bytes_per_second < 1024.0 {
+            (1.0, "  B/s")
+        } else if bytes_per_second < 1024.0 * 1024.0 {
+            (1024.0, "KiB/s")
+        } else if bytes_per_second < 1024.0 * 1024.0 * 1024.0 {
+            (1024.0 * 1024.0, "MiB/s")
         } else {
-            (1000.0 * 1000.0 * 1000.0, "Gcycles/B")
+            (1024.0 * 1024.0 * 1024.0, "GiB/s")
         };
 
         for val in values {
-            let cycles_per_byte = *val / bytes;
-            *val = cycles_per_byte / denominator;
+            let bytes_per_second = bytes * (1e9 / *val);
+            *val = bytes_per_second / denominator;
         }
 
         unit
     }
 
-    fn cycles_per_element(&self, elems: f64, typical: f64, values: &mut [f64]) -> &'static str {
-        let cycles_per_element = typical / elems;
-        let (denominator, unit) = if cycles_per_element < 1000.0 {
-            (1.0, " cycles/elem")
-        } else if cycles_per_element < 1000.0 * 1000.0 {
-            (1000.0, "Kcycles/elem")
-        } else if cycles_per_element < 1000.0 * 1000.0 * 1000.0 {
-            (1000.0 * 1000.0, "Mcycles/elem")
+    fn elements_per_second(&self, elems: f64, typical: f64, values: &mut [f64]) -> &'static str {
+        let elems_per_second = elems * (1e9 / typical);
+        let (denominator, unit) = if elems_per_second < 1000.0 {
+            (1.0, " elem/s")
+        } else if elems_per_second < 1000.0 * 1000.0 {
+            (1000.0, "Kelem/s")
+        } else if elems_per_second < 1000.0 * 1000.0 * 1000.0 {
+            (1000.0 * 1000.0, "Melem/s")
         } else {
-            (1000.0 * 1000.0 * 1000.0, "Gcycles/elem")
+            (1000.0 * 1000.0 * 1000.0, "Gelem/s")
         };
 
         for val in values {
-            let cycles_per_element = *val / elems;
-            *val = cycles_per_element / denominator;
+            let elems_per_second = elems * (1e9 / *val);
+            *val = elems_per_second / denominator;
         }
 
         unit
     }
+
+    fn custom_units_per_second(
+        &self,
+        amount: f64,
+        unit: &str,
+        typical: f64,
+        values: &mut [f64],
+    ) -> String {
+        let units_per_second = amount * (1e9 / typical);
+        let (denominator, prefix) = if units_per_second < 1000.0 {
+            (1.0, " ")
+        } else if units_per_second < 1000.0 * 1000.0 {
+            (1000.0, "K")
+        } else if units_per_second < 1000.0 * 1000.0 * 1000.0 {
+            (1000.0 * 1000.0, "M")
+        } else {
+            (1000.0 * 1000.0 * 1000.0, "G")
+        };
+
+        for val in values {
+            let units_per_second = amount * (1e9 / *val);
+            *val = units_per_second / denominator;
+        }
+
+        format!("{}{}/s", prefix, unit)
+    }
 }
 
-impl ValueFormatter for CyclesFormatter {
+impl ValueFormatter for WallTimeFormatter {
     fn scale_throughputs(
         &self,
         typical: f64,
         throughput: &Throughput,
         values: &mut [f64],
-    ) -> &'static str {
+    ) -> Cow<'static, str> {
         match *throughput {
-            Throughput::Bytes(bytes) => self.cycles_per_byte(bytes as f64, typical, values),
-            Throughput::Elements(elems) => self.cycles_per_element(elems as f64, typical, values),
+            Throughput::Bytes(bytes) => self.bytes_per_second(bytes as f64, typical, values).into(),
+            Throughput::Elements(elems) => self
+                .elements_per_second(elems as f64, typical, values)
+                .into(),
+            Throughput::Custom { amount, unit } => self
+                .custom_units_per_second(amount as f64, &unit, typical, values)
+                .into(),
         }
     }
 
-    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> &'static str {
-        let (factor, unit) = if typical_value < 10f64.powi(3) {
-            (10f64.powi(0), "cycles")
-        } else if typical_value < 10f64.powi(6) {
-            (10f64.powi(-3), "Kcycles")
-        } else if typical_value < 10f64.powi(9) {
-            (10f64.powi(-6), "Mcycles")
+    fn scale_values(&self, ns: f64, values: &mut [f64]) -> Cow<'static, str> {
+        let (factor, unit) = if ns < 10f64.powi(0) {
+            (10f64.powi(3), "ps")
+        } else if ns < 10f64.powi(3) {
+            (10f64.powi(0), "ns")
+        } else if ns < 10f64.powi(6) {
+            (10f64.powi(-3), "us")
+        } else if ns < 10f64.powi(9) {
+            (10f64.powi(-6), "ms")
         } else {
-            (10f64.powi(-9), "Gcycles")
+            (10f64.powi(-9), "s")
         };
 
         for val in values {
             *val *= factor;
         }
 
-        unit
+        unit.into()
     }
 
-    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+    fn scale_for_machines(&self, _values: &mut [f64]) -> Cow<'static, str> {
         // no scaling is needed
-        "cycles"
+        "ns".into()
+    }
+}
+
+/// Makes [`ValueFormatter::scale_for_machines`] scale the values to a fixed
+/// unit so that they are comparable across benchmarks. Everything else is
+/// forwarded to the formatter for the Target program's native unit.
+pub(crate) struct MachineUnitFormatter<'a> {
+    base: &'a dyn ValueFormatter,
+    unit: MachineUnit,
+}
+
+impl<'a> MachineUnitFormatter<'a> {
+    /// Construct a `MachineUnitFormatter`. `unit` must be one of the units
+    /// for `base`'s native unit.
+    pub(crate) fn new(base: &'a dyn ValueFormatter, unit: MachineUnit) -> Self {
+        Self { base, unit }
     }
 }
 
-fn short(n: f64) -> String {
+impl ValueFormatter for MachineUnitFormatter<'_> {
+    fn scale_throughputs(
+        &self,
+        typical: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> Cow<'static, str> {
+        self.base.scale_throughputs(typical, throughput, values)
+    }
+
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> Cow<'static, str> {
+        self.base.scale_values(typical_value, values)
+    }
+
+    fn scale_for_machines(&self, values: &mut [f64]) -> Cow<'static, str> {
+        for val in values {
+            *val *= self.unit.factor;
+        }
+        self.unit.name.into()
+    }
+}
+
+/// Applies a [`ValueFormat`] selected by the benchmark code on top of the
+/// formatter for the Target program's native unit
+pub(crate) struct SelectedFormatter<'a> {
+    base: &'a dyn ValueFormatter,
+    format: ValueFormat,
+}
+
+impl<'a> SelectedFormatter<'a> {
+    pub(crate) fn new(base: &'a dyn ValueFormatter, format: ValueFormat) -> Self {
+        Self { base, format }
+    }
+}
+
+impl ValueFormatter for SelectedFormatter<'_> {
+    fn scale_throughputs(
+        &self,
+        typical: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> Cow<'static, str> {
+        // The throughput is relative to an iteration, not to `unit`
+        self.base.scale_throughputs(typical, throughput, values)
+    }
+
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> Cow<'static, str> {
+        match self.format {
+            ValueFormat::Native => self.base.scale_values(typical_value, values),
+            ValueFormat::PerUnit { amount, unit } => {
+                let amount = amount as f64;
+                for val in values.iter_mut() {
+                    *val /= amount;
+                }
+                let base_unit = self.base.scale_values(typical_value / amount, values);
+                format!("{}/{}", base_unit, unit).into()
+            }
+        }
+    }
+
+    fn scale_for_machines(&self, values: &mut [f64]) -> Cow<'static, str> {
+        match self.format {
+            ValueFormat::Native => self.base.scale_for_machines(values),
+            ValueFormat::PerUnit { amount, unit } => {
+                let amount = amount as f64;
+                for val in values.iter_mut() {
+                    *val /= amount;
+                }
+                let base_unit = self.base.scale_for_machines(values);
+                format!("{}/{}", base_unit, unit).into()
+            }
+        }
+    }
+}
+
+pub(super) fn short(n: f64) -> String {
     if n < 10.0 {
         format!("{:.4}", n)
     } else if n < 100.0 {
@@ -150,3 +407,98 @@ fn short(n: f64) -> String {
         format!("{:.0}", n)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wall_time() {
+        let f = WallTimeFormatter;
+        assert_eq!(f.format_value(0.5), "500.00 ps");
+        assert_eq!(f.format_value(12.0), "12.000 ns");
+        assert_eq!(f.format_value(1_500.0), "1.5000 us");
+        assert_eq!(f.format_value(2_000_000_000.0), "2.0000 s");
+        assert_eq!(
+            f.format_throughput(&Throughput::Bytes(2048), 1_000.0),
+            "1.9073 GiB/s"
+        );
+        assert_eq!(
+            f.format_throughput(&Throughput::Elements(10), 1_000.0),
+            "10.000 Melem/s"
+        );
+    }
+
+    #[test]
+    fn cycles_throughput() {
+        let f = CyclesFormatter;
+        assert_eq!(
+            f.format_throughput(&Throughput::Bytes(4), 2_000.0),
+            "500.00   cycles/B"
+        );
+        assert_eq!(
+            f.format_throughput(&Throughput::Elements(2), 4_000.0),
+            "2.0000 Kcycles/elem"
+        );
+        assert_eq!(
+            f.format_throughput(
+                &Throughput::Custom {
+                    amount: 1,
+                    unit: arrayvec::ArrayString::from("pkt").unwrap(),
+                },
+                3e9
+            ),
+            "3.0000 Gcycles/pkt"
+        );
+    }
+
+    #[test]
+    fn per_unit() {
+        let f = SelectedFormatter::new(
+            &CyclesFormatter,
+            ValueFormat::PerUnit {
+                amount: 4,
+                unit: arrayvec::ArrayString::from("KiB").unwrap(),
+            },
+        );
+        assert_eq!(f.format_value(2_000.0), "500.00 cycles/KiB");
+        assert_eq!(f.format_value(8_000.0), "2.0000 Kcycles/KiB");
+
+        let mut values = [400.0, 800.0];
+        assert_eq!(f.scale_for_machines(&mut values), "cycles/KiB");
+        assert_eq!(values, [100.0, 200.0]);
+
+        // Throughputs are still computed per iteration
+        assert_eq!(
+            f.format_throughput(&Throughput::Bytes(4096), 4096.0),
+            CyclesFormatter.format_throughput(&Throughput::Bytes(4096), 4096.0)
+        );
+
+        let f = SelectedFormatter::new(&CyclesFormatter, ValueFormat::Native);
+        assert_eq!(f.format_value(2_000.0), "2.0000 Kcycles");
+    }
+
+    #[test]
+    fn machine_unit() {
+        let kcycles = CYCLES_MACHINE_UNITS[1];
+        let f = MachineUnitFormatter::new(&CyclesFormatter, kcycles);
+        let mut values = [500.0, 2_000_000.0];
+        assert_eq!(f.scale_for_machines(&mut values), "Kcycles");
+        assert_eq!(values, [0.5, 2_000.0]);
+
+        // Human-readable output is unaffected
+        assert_eq!(f.format_value(500.0), "500.00 cycles");
+
+        // The benchmark's value format still applies
+        let f = SelectedFormatter::new(
+            &f,
+            ValueFormat::PerUnit {
+                amount: 2,
+                unit: arrayvec::ArrayString::from("B").unwrap(),
+            },
+        );
+        let mut values = [4_000.0];
+        assert_eq!(f.scale_for_machines(&mut values), "Kcycles/B");
+        assert_eq!(values, [2.0]);
+    }
+}
@@ -0,0 +1,93 @@
+//! Decoder for the frame trace emitted by the Target program when the
+//! `frame_trace` feature is enabled
+//!
+//! See [`protocol::FRAME_TRACE_CHANNEL_NAME`] for the format.
+use crate::bencher::protocol;
+
+const HEADER_LEN: usize = 3;
+
+/// Extracts frame trace records from a byte stream. The stream can be
+/// supplied in arbitrary chunks.
+#[derive(Debug, Default)]
+pub(super) struct FrameTraceDecoder {
+    /// The bytes of the incomplete record
+    buf: Vec<u8>,
+}
+
+impl FrameTraceDecoder {
+    /// Decode `bytes` and call `on_record` for each complete record with
+    /// its direction byte and frame.
+    pub(super) fn decode(&mut self, bytes: &[u8], mut on_record: impl FnMut(u8, &[u8])) {
+        self.buf.extend_from_slice(bytes);
+
+        let mut pos = 0;
+        while let Some(header) = self.buf.get(pos..pos + HEADER_LEN) {
+            let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+            let frame = match self.buf.get(pos + HEADER_LEN..pos + HEADER_LEN + len) {
+                Some(x) => x,
+                None => break,
+            };
+            on_record(header[0], frame);
+            pos += HEADER_LEN + len;
+        }
+
+        self.buf.drain(..pos);
+    }
+
+    /// Decode `bytes` and log the messages in the complete records.
+    pub(super) fn decode_and_log(&mut self, bytes: &[u8]) {
+        self.decode(bytes, log_record);
+    }
+}
+
+fn log_record(direction: u8, frame: &[u8]) {
+    match direction {
+        protocol::FRAME_TRACE_DOWNSTREAM => {
+            match serde_cbor::from_slice::<protocol::DownstreamMessage<String>>(frame) {
+                Ok(msg) => log::info!("[frame trace] recv: {:?}", msg),
+                Err(_) => log::info!("[frame trace] recv (undecodable): {:02x?}", frame),
+            }
+        }
+        protocol::FRAME_TRACE_UPSTREAM => {
+            match serde_cbor::from_slice::<protocol::UpstreamMessage<String, Vec<u64>>>(frame) {
+                Ok(msg) => log::info!("[frame trace] send: {:?}", msg),
+                Err(_) => log::info!("[frame trace] send (undecodable): {:02x?}", frame),
+            }
+        }
+        _ => log::warn!(
+            "[frame trace] unknown direction {} for {:02x?}",
+            direction,
+            frame
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_records() {
+        let stream: &[u8] = &[
+            0, 2, 0, 0xaa, 0xbb, // downstream, 2 bytes
+            1, 0, 0, // upstream, empty
+            1, 3, 0, 0xcc, 0xdd, 0xee, // upstream, 3 bytes
+        ];
+        let expected: &[(u8, &[u8])] = &[(0, &[0xaa, 0xbb]), (1, &[]), (1, &[0xcc, 0xdd, 0xee])];
+
+        // The result shouldn't depend on how the stream is split
+        for i in 0..=stream.len() {
+            let (a, b) = stream.split_at(i);
+            let mut decoder = FrameTraceDecoder::default();
+            let mut records = Vec::new();
+            for chunk in [a, b].iter() {
+                decoder.decode(chunk, |direction, frame| {
+                    records.push((direction, frame.to_owned()))
+                });
+            }
+            let records: Vec<(u8, &[u8])> = records.iter().map(|(d, f)| (*d, &f[..])).collect();
+            assert_eq!(records, expected, "split at {}", i);
+            assert!(decoder.buf.is_empty());
+        }
+    }
+}
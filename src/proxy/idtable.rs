@@ -0,0 +1,201 @@
+//! Reads the benchmark ID candidates embedded in the target executable
+//! (`crate::idtable`) for `--farcri-list-static`
+use anyhow::{Context, Result};
+use std::{convert::TryInto, path::Path};
+use tokio::task::spawn_blocking;
+
+use crate::bencher::protocol;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum StaticId {
+    /// An exact benchmark ID (`#[farcri::bench]`)
+    Benchmark(String),
+    /// The path of a function passed to `criterion_group!`
+    Function(String),
+}
+
+impl StaticId {
+    pub(super) fn name(&self) -> &str {
+        match self {
+            Self::Benchmark(x) | Self::Function(x) => x,
+        }
+    }
+}
+
+/// Read the benchmark ID candidates embedded in the specified executable.
+/// Returns `None` if the executable doesn't have them (e.g., because it's
+/// not an ELF file).
+pub(super) async fn load(exe: &Path) -> Result<Option<Vec<StaticId>>> {
+    let elf_bytes = tokio::fs::read(exe)
+        .await
+        .with_context(|| format!("Failed to read '{}'.", exe.display()))?;
+    spawn_blocking(move || read_from_elf(&elf_bytes))
+        .await
+        .unwrap()
+}
+
+/// Read the benchmark ID candidates from the specified ELF image. The
+/// result is sorted and doesn't contain duplicates.
+fn read_from_elf(elf_bytes: &[u8]) -> Result<Option<Vec<StaticId>>> {
+    let elf = match goblin::elf::Elf::parse(elf_bytes) {
+        Ok(elf) => elf,
+        // The executable built for the host might not be an ELF file
+        Err(goblin::error::Error::BadMagic(_)) => return Ok(None),
+        Err(e) => return Err(e).context("Failed to parse the executable."),
+    };
+
+    let section = elf.section_headers.iter().find(|sh| {
+        matches!(elf.shdr_strtab.get(sh.sh_name), Some(Ok(name)) if name == protocol::STATIC_IDS_SECTION)
+    });
+    let section = match section {
+        Some(sh) if sh.sh_type != goblin::elf::section_header::SHT_NOBITS => sh,
+        _ => return Ok(None),
+    };
+    let table = elf_bytes
+        .get(section.sh_offset as usize..)
+        .and_then(|x| x.get(..section.sh_size as usize))
+        .with_context(|| {
+            format!(
+                "The section '{}' is out of bounds.",
+                protocol::STATIC_IDS_SECTION
+            )
+        })?;
+
+    // Each entry consists of three target words: the kind, the address of
+    // the name, and the length of the name
+    let word_len = if elf.is_64 { 8 } else { 4 };
+    let read_word = |bytes: &[u8]| -> u64 {
+        match (word_len, elf.little_endian) {
+            (8, true) => u64::from_le_bytes(bytes.try_into().unwrap()),
+            (8, false) => u64::from_be_bytes(bytes.try_into().unwrap()),
+            (_, true) => u32::from_le_bytes(bytes.try_into().unwrap()).into(),
+            (_, false) => u32::from_be_bytes(bytes.try_into().unwrap()).into(),
+        }
+    };
+
+    let mut ids = Vec::new();
+    for entry in table.chunks_exact(word_len * 3) {
+        let mut words = entry.chunks_exact(word_len).map(read_word);
+        let (kind, addr, len) = (
+            words.next().unwrap(),
+            words.next().unwrap(),
+            words.next().unwrap(),
+        );
+        let make_id: fn(String) -> StaticId = match kind as usize {
+            protocol::STATIC_ID_KIND_BENCHMARK => StaticId::Benchmark,
+            protocol::STATIC_ID_KIND_FUNCTION => StaticId::Function,
+            // This includes zero-filled padding
+            protocol::STATIC_ID_KIND_SENTINEL => continue,
+            _ => {
+                log::debug!("Ignoring an ID table entry of unknown kind {}", kind);
+                continue;
+            }
+        };
+
+        // Convert the address to a file offset
+        let name = elf
+            .section_headers
+            .iter()
+            .find(|sh| {
+                sh.sh_type != goblin::elf::section_header::SHT_NOBITS
+                    && sh.sh_addr <= addr
+                    && matches!(addr.checked_add(len), Some(end) if end <= sh.sh_addr + sh.sh_size)
+            })
+            .and_then(|sh| {
+                let start = (sh.sh_offset + (addr - sh.sh_addr)) as usize;
+                elf_bytes.get(start..start + len as usize)
+            })
+            .context("An ID table entry points to an invalid location.")?;
+        let name = std::str::from_utf8(name).context("An ID table entry is malformed.")?;
+
+        ids.push(make_id(name.to_owned()));
+    }
+
+    ids.sort();
+    ids.dedup();
+
+    Ok(Some(ids))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::testelf::ElfBuilder;
+    use goblin::elf::section_header::SHT_PROGBITS;
+
+    /// Build a minimal executable image with a `farcri_ids` section.
+    /// `entries` is a list of `(kind, name)`. The names are placed in
+    /// `.rodata`, which is mapped at `0x1000`.
+    fn fixture_image(is_64: bool, little_endian: bool, entries: &[(usize, &str)]) -> Vec<u8> {
+        let mut elf = ElfBuilder::new(is_64, little_endian);
+        let word_len = elf.word_len();
+        let rodata_addr = 0x1000u64;
+        let mut rodata = Vec::new();
+        let mut table = Vec::new();
+        for &(kind, name) in entries {
+            elf.put(&mut table, kind as u64, word_len);
+            elf.put(&mut table, rodata_addr + rodata.len() as u64, word_len);
+            elf.put(&mut table, name.len() as u64, word_len);
+            rodata.extend_from_slice(name.as_bytes());
+        }
+        elf.section(".rodata", SHT_PROGBITS, rodata_addr, rodata);
+        elf.section(protocol::STATIC_IDS_SECTION, SHT_PROGBITS, 0x2000, table);
+        elf.build()
+    }
+
+    #[test]
+    fn read_ids() {
+        let entries = [
+            (protocol::STATIC_ID_KIND_SENTINEL, ""),
+            (protocol::STATIC_ID_KIND_FUNCTION, "benches::sort"),
+            (protocol::STATIC_ID_KIND_BENCHMARK, "my_bench::noop"),
+            (0xbad, "unknown"),
+            // Emitted twice because of appearing in two `criterion_group!`s
+            (protocol::STATIC_ID_KIND_FUNCTION, "benches::sort"),
+            (protocol::STATIC_ID_KIND_FUNCTION, "fib"),
+        ];
+        let expected = vec![
+            StaticId::Benchmark("my_bench::noop".to_owned()),
+            StaticId::Function("benches::sort".to_owned()),
+            StaticId::Function("fib".to_owned()),
+        ];
+
+        for &is_64 in &[false, true] {
+            for &little_endian in &[false, true] {
+                let image = fixture_image(is_64, little_endian, &entries);
+                assert_eq!(
+                    read_from_elf(&image).unwrap().as_ref(),
+                    Some(&expected),
+                    "is_64 = {}, little_endian = {}",
+                    is_64,
+                    little_endian
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn no_ids() {
+        // Not an ELF file
+        let mut image = b"MZ".to_vec();
+        image.resize(256, 0);
+        assert_eq!(read_from_elf(&image).unwrap(), None);
+
+        // An empty table is different from a missing one
+        let image = fixture_image(false, true, &[]);
+        assert_eq!(read_from_elf(&image).unwrap(), Some(vec![]));
+    }
+
+    #[test]
+    fn invalid_name() {
+        let mut elf = ElfBuilder::new(false, true);
+        elf.section(".rodata", SHT_PROGBITS, 0x1000, b"benches::sort".to_vec());
+        // The name extends past the end of `.rodata`
+        let mut table = Vec::new();
+        for &word in &[protocol::STATIC_ID_KIND_FUNCTION as u64, 0x1000, 14] {
+            elf.put(&mut table, word, 4);
+        }
+        elf.section(protocol::STATIC_IDS_SECTION, SHT_PROGBITS, 0x2000, table);
+        assert!(read_from_elf(&elf.build()).is_err());
+    }
+}
@@ -0,0 +1,588 @@
+//! Saves measurement results as JSON files, used by the dumb front-end
+//!
+//! The files are laid out in the same way as cargo-criterion's data
+//! directory (`target/criterion/data`), with the history of each benchmark
+//! in the `main` timeline, so tools reading the measurement history from it
+//! can be used. Unlike cargo-criterion, which encodes the records in CBOR,
+//! they are written in JSON. For each benchmark, the following files are
+//! written:
+//!
+//!  - `<dir>/data/main/<directory_name>/measurement_<yymmddhhmmss>.json`:
+//!    The record of a measurement, named after the time (in UTC) it
+//!    completed. The fields follow cargo-criterion's `SavedStatistics`:
+//!    `iterations[i]` is the iteration count of the `i`-th sample,
+//!    `values[i]` is the measured value (in the target's unit, e.g.,
+//!    cycles, *not* nanoseconds) of the sample, and `avg_values[i]` is
+//!    `values[i] / iterations[i]`. `changes`, `change_direction`,
+//!    `history_id`, and `history_description` are always `null`. Example:
+//!    `{"datetime":"2021-05-01T12:34:56.789Z","iterations":[100.0,100.0],
+//!    "values":[1201.0,1198.0],"avg_values":[12.01,11.98],
+//!    "estimates":null,"throughput":{"Elements":16},"changes":null,
+//!    "change_direction":null,"history_id":null,
+//!    "history_description":null}`
+//!
+//!    `estimates` is only populated if enabled by `--farcri-save-estimates`
+//!    and there are at least two samples. It holds the statistics of
+//!    `avg_values`, computed by bootstrapping with the benchmark's
+//!    `nresamples` at the 95% confidence level, in the same way as
+//!    cargo-criterion, except that `slope` is always `null` because all
+//!    samples have the same iteration count. Non-finite numbers are written
+//!    as `null`.
+//!
+//!    The following fields, which cargo-criterion doesn't have, are added
+//!    when available:
+//!
+//!     - `sample_timestamps`: The target-local timestamp (in the target's
+//!       unit) at the start of each sample, recorded if enabled by
+//!       `BenchmarkGroup::record_sample_timestamps`
+//!     - `drift`: The change of the measured values over the measurement
+//!       relative to their mean (e.g., `0.05` for +5%), estimated from
+//!       `sample_timestamps` by linear regression
+//!     - `build`: How the target executable was built (`builddesc`).
+//!       Example: `{"features":["farcri/role_target"],
+//!       "rustflags":"-C target-feature=+fp-armv8d16",
+//!       "target":"thumbv7em-none-eabihf","build_std":true}`
+//!
+//!  - `<dir>/data/main/<directory_name>/benchmark.json`: The benchmark ID
+//!    and the path of the latest measurement record, following
+//!    cargo-criterion's `BenchmarkRecord`. Example: `{"id":{"group_id":
+//!    "sort","function_id":"16","value_str":null,"throughput":
+//!    {"Elements":16}},"latest_record":"target/criterion/data/main/sort/16/
+//!    measurement_210501123456.json"}`. If the benchmark has tags
+//!    (`BenchmarkGroup::tags`), they are added as an object, e.g.,
+//!    `"tags":{"algorithm":"quicksort"}`, which cargo-criterion doesn't
+//!    have.
+//!
+//! `<directory_name>` is made of the components of the benchmark ID
+//! (`group_id/function_id/value_str`, omitting absent ones), each made safe
+//! to use as a file name in the same way as Criterion.rs. The records of the
+//! previous runs are kept. A record written within the same second as the
+//! previous one replaces it.
+//!
+//! At the end of a run, the wall-clock breakdown of the run (printed to
+//! stderr as "Timing breakdown") is written to `<dir>/timings.json`,
+//! replacing the previous run's. The phases preceding the execution of
+//! benchmarks (e.g., `build`, `flash`, `RTT attach`) and the stages of each
+//! benchmark are recorded in seconds. Example: `{"phases":[{"name":
+//! "build","duration":12.5}],"probe_reconnects":0,"benchmarks":[{"id":
+//! "sort/16","setup":0.01,"warm_up":3.0,"measurement":5.02,
+//! "overhead":0.1}]}`
+use anyhow::{Context, Result};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Serialize, Serializer};
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    builddesc::BuildDescriptor,
+    ccfront::ccprotocol,
+    console_report::CONFIDENCE_LEVEL,
+    resultsfile::write_and_sync,
+    stats::{self, Estimate},
+    timing::Timings,
+};
+use crate::bencher::protocol;
+
+/// The maximum length of each path component, from Criterion.rs
+const MAX_DIRECTORY_NAME_LEN: usize = 64;
+
+/// cargo-criterion's default timeline
+const TIMELINE: &str = "main";
+
+pub(super) struct JsonWriter {
+    dir: PathBuf,
+    /// Populate `estimates`
+    estimates: bool,
+    build: Option<BuildInfo>,
+    /// Get the current time. Replaced by tests.
+    now: fn() -> SystemTime,
+}
+
+/// `benchmark.json`
+#[derive(Serialize)]
+struct BenchmarkRecord<'a> {
+    id: SavedBenchmarkId<'a>,
+    latest_record: &'a Path,
+    #[serde(skip_serializing_if = "Tags::is_empty")]
+    tags: Tags<'a>,
+}
+
+#[derive(Serialize)]
+struct SavedBenchmarkId<'a> {
+    group_id: &'a str,
+    function_id: Option<&'a str>,
+    value_str: Option<&'a str>,
+    throughput: Option<ccprotocol::Throughput>,
+}
+
+/// `measurement_<yymmddhhmmss>.json`
+#[derive(Serialize)]
+struct SavedStatistics<'a> {
+    datetime: String,
+    iterations: Vec<f64>,
+    values: Vec<f64>,
+    avg_values: Vec<f64>,
+    estimates: Option<Estimates>,
+    throughput: Option<ccprotocol::Throughput>,
+    // Comparisons and user-provided identifiers aren't supported
+    changes: Option<()>,
+    change_direction: Option<()>,
+    history_id: Option<()>,
+    history_description: Option<()>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_timestamps: Option<&'a [u64]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drift: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build: Option<&'a BuildInfo>,
+}
+
+#[derive(Serialize)]
+struct Estimates {
+    mean: JsonEstimate,
+    median: JsonEstimate,
+    median_abs_dev: JsonEstimate,
+    slope: Option<JsonEstimate>,
+    std_dev: JsonEstimate,
+}
+
+/// An estimate and its standard error, like cargo-criterion's `Estimate`
+#[derive(Serialize)]
+struct JsonEstimate {
+    confidence_interval: ConfidenceInterval,
+    point_estimate: f64,
+    standard_error: f64,
+}
+
+#[derive(Serialize)]
+struct ConfidenceInterval {
+    confidence_level: f64,
+    lower_bound: f64,
+    upper_bound: f64,
+}
+
+/// The `build` field
+#[derive(Serialize, Debug, Clone)]
+struct BuildInfo {
+    features: Vec<String>,
+    rustflags: String,
+    target: Option<String>,
+    build_std: bool,
+}
+
+impl JsonWriter {
+    /// Construct a `JsonWriter` writing to `dir`. `estimates` are populated
+    /// if `estimates` is `true`.
+    pub(super) fn new(dir: impl Into<PathBuf>, estimates: bool) -> Self {
+        Self {
+            dir: dir.into(),
+            estimates,
+            build: None,
+            now: SystemTime::now,
+        }
+    }
+
+    /// Include `build_descriptor` in each measurement record.
+    pub(super) fn set_build_descriptor(&mut self, build_descriptor: &BuildDescriptor) {
+        self.build = Some(BuildInfo {
+            features: build_descriptor.features.clone(),
+            rustflags: build_descriptor.rustflags.clone(),
+            target: build_descriptor.target_triple.clone(),
+            build_std: build_descriptor.build_std,
+        });
+    }
+
+    /// Write the result of a measurement. `sample_timestamps` is ignored
+    /// unless it has one element for each sample.
+    pub(super) async fn write_measurement(
+        &self,
+        id: &protocol::RawBenchmarkId<String>,
+        tags: &[(String, String)],
+        num_iters_per_sample: u64,
+        values: &[u64],
+        sample_timestamps: Option<&[u64]>,
+        benchmark_config: &protocol::BenchmarkConfig,
+    ) -> Result<()> {
+        let bench_dir = self
+            .dir
+            .join("data")
+            .join(TIMELINE)
+            .join(directory_name(id));
+        tokio::fs::create_dir_all(&bench_dir)
+            .await
+            .with_context(|| format!("Failed to create '{}'.", bench_dir.display()))?;
+
+        let (datetime, file_time) = format_datetime((self.now)());
+        let throughput = id.throughput.as_ref().map(ccprotocol::Throughput::from);
+        let iters = num_iters_per_sample as f64;
+        let avg_values: Vec<f64> = values.iter().map(|&x| x as f64 / iters).collect();
+        let estimates = if self.estimates && values.len() > 1 {
+            Some(estimates(&avg_values, benchmark_config.nresamples))
+        } else {
+            None
+        };
+        let sample_timestamps = sample_timestamps.filter(|x| x.len() == values.len());
+        let statistics = SavedStatistics {
+            datetime,
+            iterations: vec![iters; values.len()],
+            values: values.iter().map(|&x| x as f64).collect(),
+            avg_values,
+            estimates,
+            throughput,
+            changes: None,
+            change_direction: None,
+            history_id: None,
+            history_description: None,
+            sample_timestamps,
+            drift: sample_timestamps.and_then(|x| super::sample_drift(values, x)),
+            build: self.build.as_ref(),
+        };
+        let measurement_path = bench_dir.join(format!("measurement_{}.json", file_time));
+        write_and_sync(&measurement_path, serde_json::to_vec(&statistics)?).await?;
+
+        let record = BenchmarkRecord {
+            id: SavedBenchmarkId {
+                group_id: &id.group_id,
+                function_id: id.function_id.as_deref(),
+                value_str: id.value_str.as_deref(),
+                throughput,
+            },
+            latest_record: &measurement_path,
+            tags: Tags(tags),
+        };
+        let record_path = bench_dir.join("benchmark.json");
+        write_and_sync(&record_path, serde_json::to_vec(&record)?).await?;
+
+        log::debug!(
+            "Saved the samples of '{}' in '{}'",
+            id,
+            measurement_path.display()
+        );
+        Ok(())
+    }
+
+    /// Write the wall-clock breakdown of the run to `timings.json`.
+    pub(super) async fn write_timings(&self, timings: &Timings) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create '{}'.", self.dir.display()))?;
+        let path = self.dir.join("timings.json");
+        write_and_sync(&path, serde_json::to_vec(timings)?).await?;
+        log::debug!("Saved the timing breakdown in '{}'", path.display());
+        Ok(())
+    }
+}
+
+pub(super) fn directory_name(id: &protocol::RawBenchmarkId<String>) -> String {
+    let components = [
+        Some(&id.group_id),
+        id.function_id.as_ref(),
+        id.value_str.as_ref(),
+    ];
+    let components: Vec<String> = components
+        .iter()
+        .filter_map(|&x| x)
+        .map(|x| make_filename_safe(x))
+        .collect();
+    components.join("/")
+}
+
+/// Replace the characters unsafe for file names and truncate the string.
+/// Mirrors `criterion::report::make_filename_safe`.
+fn make_filename_safe(s: &str) -> String {
+    let mut s = s.replace(
+        &['?', '"', '/', '\\', '*', '<', '>', ':', '|', '^'][..],
+        "_",
+    );
+
+    if s.len() > MAX_DIRECTORY_NAME_LEN {
+        let mut len = MAX_DIRECTORY_NAME_LEN;
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        s.truncate(len);
+    }
+
+    s.trim().to_owned()
+}
+
+/// Format a point in time as an RFC 3339 timestamp in UTC (e.g.,
+/// `2021-05-01T12:34:56.789Z`) and as `yymmddhhmmss` used in the names of
+/// measurement records.
+fn format_datetime(time: SystemTime) -> (String, String) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    let (hour, minute, second) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+
+    // Convert the number of days since 1970-01-01 to a civil date
+    // <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    let datetime = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        since_epoch.subsec_millis()
+    );
+    let file_time = format!(
+        "{:02}{:02}{:02}{:02}{:02}{:02}",
+        year % 100,
+        month,
+        day,
+        hour,
+        minute,
+        second
+    );
+    (datetime, file_time)
+}
+
+/// Calculate the statistics of the per-iteration values `values`.
+fn estimates(values: &[f64], nresamples: usize) -> Estimates {
+    let mut rng = StdRng::from_entropy();
+    let mut bootstrap = |statistic: fn(&mut [f64]) -> f64| {
+        let (estimate, standard_error) =
+            stats::bootstrap(values, nresamples, CONFIDENCE_LEVEL, &mut rng, statistic);
+        JsonEstimate::new(estimate, standard_error)
+    };
+    Estimates {
+        mean: bootstrap(|x| stats::mean(x)),
+        median: bootstrap(stats::median),
+        median_abs_dev: bootstrap(stats::median_abs_dev),
+        slope: None,
+        std_dev: bootstrap(|x| stats::std_dev(x)),
+    }
+}
+
+impl JsonEstimate {
+    fn new(estimate: Estimate, standard_error: f64) -> Self {
+        Self {
+            confidence_interval: ConfidenceInterval {
+                confidence_level: CONFIDENCE_LEVEL,
+                lower_bound: estimate.lower,
+                upper_bound: estimate.upper,
+            },
+            point_estimate: estimate.point,
+            standard_error,
+        }
+    }
+}
+
+/// Serializes benchmark tags as an object, e.g., `{"opt":"O2"}`, keeping
+/// their order.
+pub(super) struct Tags<'a>(pub(super) &'a [(String, String)]);
+
+impl Tags<'_> {
+    pub(super) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Serialize for Tags<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(
+        group_id: &str,
+        function_id: Option<&str>,
+        value_str: Option<&str>,
+    ) -> protocol::RawBenchmarkId<String> {
+        protocol::RawBenchmarkId {
+            group_id: group_id.to_owned(),
+            function_id: function_id.map(Into::into),
+            value_str: value_str.map(Into::into),
+            throughput: None,
+        }
+    }
+
+    const CONFIG: protocol::BenchmarkConfig = protocol::BenchmarkConfig {
+        measurement_time: protocol::Duration::from_nanos(1_000_000_000),
+        nresamples: 100,
+        sample_size: 20,
+        warm_up_time: protocol::Duration::from_nanos(1_000_000_000),
+    };
+
+    #[test]
+    fn directory_names() {
+        assert_eq!(directory_name(&id("sort", Some("16"), None)), "sort/16");
+        assert_eq!(
+            directory_name(&id("a/b", Some("c:d"), Some(" e "))),
+            "a_b/c_d/e"
+        );
+        assert_eq!(
+            directory_name(&id(&"\u{3042}".repeat(30), None, None)),
+            "\u{3042}".repeat(21)
+        );
+    }
+
+    #[test]
+    fn datetimes() {
+        let at = |ms| UNIX_EPOCH + std::time::Duration::from_millis(ms);
+        assert_eq!(
+            format_datetime(at(1_619_872_496_789)),
+            (
+                "2021-05-01T12:34:56.789Z".to_owned(),
+                "210501123456".to_owned()
+            )
+        );
+        assert_eq!(
+            format_datetime(at(951_782_399_000)).0,
+            "2000-02-28T23:59:59.000Z"
+        );
+        assert_eq!(
+            format_datetime(at(951_782_400_000)).0,
+            "2000-02-29T00:00:00.000Z"
+        );
+        assert_eq!(format_datetime(UNIX_EPOCH).0, "1970-01-01T00:00:00.000Z");
+    }
+
+    fn writer(dir: &Path, estimates: bool) -> JsonWriter {
+        JsonWriter {
+            now: || UNIX_EPOCH + std::time::Duration::from_secs(1_619_872_496),
+            ..JsonWriter::new(dir, estimates)
+        }
+    }
+
+    fn read(dir: &Path, path: &str) -> String {
+        std::fs::read_to_string(dir.join("data/main").join(path)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn write_twice() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        let mut writer = writer(dir.path(), false);
+        let id = protocol::RawBenchmarkId {
+            throughput: Some(protocol::Throughput::Elements(16)),
+            ..id("sort", Some("16"), None)
+        };
+        let tags = [
+            ("algorithm".to_owned(), "quicksort".to_owned()),
+            ("opt".to_owned(), "\"O2\"".to_owned()),
+        ];
+
+        writer
+            .write_measurement(&id, &[], 100, &[1201, 1198], None, &CONFIG)
+            .await
+            .unwrap();
+        writer.now = || UNIX_EPOCH + std::time::Duration::from_secs(1_619_872_497);
+        writer
+            .write_measurement(&id, &tags, 200, &[1900, 2100], Some(&[0, 5]), &CONFIG)
+            .await
+            .unwrap();
+
+        // The previous record is kept
+        assert_eq!(
+            read(dir.path(), "sort/16/measurement_210501123456.json"),
+            r#"{"datetime":"2021-05-01T12:34:56.000Z","iterations":[100.0,100.0],"values":[1201.0,1198.0],"avg_values":[12.01,11.98],"estimates":null,"throughput":{"Elements":16},"changes":null,"change_direction":null,"history_id":null,"history_description":null}"#
+        );
+        assert_eq!(
+            read(dir.path(), "sort/16/measurement_210501123457.json"),
+            r#"{"datetime":"2021-05-01T12:34:57.000Z","iterations":[200.0,200.0],"values":[1900.0,2100.0],"avg_values":[9.5,10.5],"estimates":null,"throughput":{"Elements":16},"changes":null,"change_direction":null,"history_id":null,"history_description":null,"sample_timestamps":[0,5],"drift":0.1}"#
+        );
+
+        let record: serde_json::Value =
+            serde_json::from_str(&read(dir.path(), "sort/16/benchmark.json")).unwrap();
+        assert_eq!(
+            record["id"].to_string(),
+            r#"{"function_id":"16","group_id":"sort","throughput":{"Elements":16},"value_str":null}"#
+        );
+        assert_eq!(
+            record["tags"].to_string(),
+            r#"{"algorithm":"quicksort","opt":"\"O2\""}"#
+        );
+        assert_eq!(
+            Path::new(record["latest_record"].as_str().unwrap()),
+            dir.path()
+                .join("data/main/sort/16/measurement_210501123457.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn mismatched_sample_timestamps() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        let id = id("sort", Some("16"), None);
+
+        writer(dir.path(), false)
+            .write_measurement(&id, &[], 10, &[1000, 1000], Some(&[5]), &CONFIG)
+            .await
+            .unwrap();
+        assert!(!read(dir.path(), "sort/16/measurement_210501123456.json")
+            .contains("sample_timestamps"));
+    }
+
+    #[tokio::test]
+    async fn estimates() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        let writer = writer(dir.path(), true);
+        let id = id("sort", Some("16"), None);
+
+        writer
+            .write_measurement(&id, &[], 10, &[1000, 1000, 1000], None, &CONFIG)
+            .await
+            .unwrap();
+
+        let estimate = r#"{"confidence_interval":{"confidence_level":0.95,"lower_bound":100.0,"upper_bound":100.0},"point_estimate":100.0,"standard_error":0.0}"#;
+        let deviation = r#"{"confidence_interval":{"confidence_level":0.95,"lower_bound":0.0,"upper_bound":0.0},"point_estimate":0.0,"standard_error":0.0}"#;
+        assert!(
+            read(dir.path(), "sort/16/measurement_210501123456.json").contains(&format!(
+                r#""estimates":{{"mean":{0},"median":{0},"median_abs_dev":{1},"slope":null,"std_dev":{1}}}"#,
+                estimate, deviation
+            ))
+        );
+
+        // Not enough samples
+        writer
+            .write_measurement(&id, &[], 10, &[1000], None, &CONFIG)
+            .await
+            .unwrap();
+        assert!(read(dir.path(), "sort/16/measurement_210501123456.json")
+            .contains(r#""estimates":null"#));
+    }
+
+    #[tokio::test]
+    async fn build() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        let mut writer = writer(dir.path(), false);
+        let id = id("sort", Some("16"), None);
+
+        writer
+            .write_measurement(&id, &[], 10, &[1000], None, &CONFIG)
+            .await
+            .unwrap();
+        assert!(!read(dir.path(), "sort/16/measurement_210501123456.json").contains("build"));
+
+        writer.set_build_descriptor(&BuildDescriptor {
+            features: vec!["farcri/role_target".to_owned(), "farcri/fpu".to_owned()],
+            rustflags: "-C target-feature=+fp-armv8d16".to_owned(),
+            target_triple: None,
+            build_std: false,
+        });
+        writer
+            .write_measurement(&id, &[], 10, &[1000], None, &CONFIG)
+            .await
+            .unwrap();
+        assert!(read(dir.path(), "sort/16/measurement_210501123456.json").ends_with(
+            r#""build":{"features":["farcri/role_target","farcri/fpu"],"rustflags":"-C target-feature=+fp-armv8d16","target":null,"build_std":false}}"#
+        ));
+    }
+}
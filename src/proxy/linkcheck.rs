@@ -0,0 +1,138 @@
+//! The link throughput check requested by `--farcri-link-check`
+use anyhow::{Context as _, Result};
+use std::time::{Duration, Instant};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
+
+use super::{
+    error::ProtocolViolation,
+    targetlink::{TargetLink, REPLY_TIMEOUT},
+};
+use crate::bencher::protocol;
+
+/// The total length of the payloads sent to measure the throughput
+const TOTAL_PAYLOAD_LEN: usize = 4096;
+
+/// The number of empty echo requests sent to measure the round-trip latency
+const NUM_LATENCY_PROBES: usize = 8;
+
+/// The throughput (in bytes per second) below which a warning is issued
+const MIN_THROUGHPUT: f64 = 10.0 * 1024.0;
+
+/// The result of [`run`]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct LinkCheckReport {
+    /// The mean round-trip time of an empty echo request
+    pub(super) latency: Duration,
+    /// The number of payload bytes transferred per second in both directions
+    pub(super) throughput: f64,
+}
+
+impl LinkCheckReport {
+    /// Get a warning to show if the link is too slow to be practical.
+    pub(super) fn warning(&self) -> Option<String> {
+        if self.throughput >= MIN_THROUGHPUT {
+            return None;
+        }
+        Some(format!(
+            "The link to the target is slow ({:.1} KiB/s, expected at least \
+            {:.0} KiB/s). Benchmarks will spend much of their time waiting \
+            for the link. Try raising the debug interface's clock frequency \
+            (`--farcri-probe-speed-khz`), connecting the debug probe directly \
+            instead of through a USB hub, or using a shorter cable.",
+            self.throughput / 1024.0,
+            MIN_THROUGHPUT / 1024.0,
+        ))
+    }
+}
+
+/// Measure the link's round-trip latency and throughput by exchanging
+/// `EchoRequest` and `EchoReply` messages. The Target program must be waiting
+/// for them (i.e., the greeting requested [`protocol::FEATURE_LINK_CHECK`]).
+/// Doesn't end the check by sending `Continue`.
+pub(super) async fn run<Stream: AsyncRead + AsyncWrite>(
+    target_link: &mut TargetLink<Stream>,
+    capacities: &protocol::TargetCapacities,
+) -> Result<LinkCheckReport> {
+    let chunk_len = protocol::max_echo_payload_len(capacities.link_buffer_len as usize);
+    anyhow::ensure!(
+        chunk_len > 0,
+        "The Target program's link buffer ({} bytes) is too small for the link check.",
+        capacities.link_buffer_len
+    );
+
+    let mut latency = Duration::from_secs(0);
+    for _ in 0..NUM_LATENCY_PROBES {
+        latency += echo(target_link, String::new()).await?;
+    }
+    latency /= NUM_LATENCY_PROBES as u32;
+
+    let payload: String = (0..TOTAL_PAYLOAD_LEN)
+        .map(|i| (b'!' + (i % 94) as u8) as char)
+        .collect();
+    let mut elapsed = Duration::from_secs(0);
+    for chunk in payload.as_bytes().chunks(chunk_len) {
+        // `payload` is ASCII, so any chunk is valid UTF-8
+        let chunk = std::str::from_utf8(chunk).unwrap();
+        elapsed += echo(target_link, chunk.to_owned()).await?;
+    }
+    let throughput = (TOTAL_PAYLOAD_LEN * 2) as f64 / elapsed.as_secs_f64();
+
+    Ok(LinkCheckReport {
+        latency,
+        throughput,
+    })
+}
+
+/// Send `payload` by `EchoRequest` and wait for the Target program to send it
+/// back. Returns the round-trip time.
+async fn echo<Stream: AsyncRead + AsyncWrite>(
+    target_link: &mut TargetLink<Stream>,
+    payload: String,
+) -> Result<Duration> {
+    let start = Instant::now();
+    target_link
+        .send(&protocol::DownstreamMessage::EchoRequest {
+            payload: payload.clone(),
+        })
+        .await
+        .context("Failed to send an echo request.")?;
+
+    match time::timeout(REPLY_TIMEOUT, target_link.recv())
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out while waiting for an echo reply."))?
+        .context("Failed to receive an echo reply.")?
+    {
+        protocol::UpstreamMessage::EchoReply { payload: reply } if reply == payload => {
+            Ok(start.elapsed())
+        }
+        protocol::UpstreamMessage::EchoReply { .. } => {
+            Err(anyhow::anyhow!("The echo reply doesn't match the request.")
+                .context(ProtocolViolation))
+        }
+        other => Err(
+            anyhow::anyhow!("Unexpected response to an echo request: {:?}", other)
+                .context(ProtocolViolation),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warning_threshold() {
+        let report = |throughput| LinkCheckReport {
+            latency: Duration::from_millis(1),
+            throughput,
+        };
+        assert!(report(MIN_THROUGHPUT * 2.0).warning().is_none());
+        assert!(report(MIN_THROUGHPUT / 2.0)
+            .warning()
+            .unwrap()
+            .contains("--farcri-probe-speed-khz"));
+    }
+}
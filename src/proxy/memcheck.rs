@@ -0,0 +1,185 @@
+//! Checks that the target executable was linked for the memory map in the
+//! `memory.x` provided by the target
+//!
+//! `cortex-m-rt`'s `link.x` includes the first `memory.x` found in the
+//! linker's search paths. If the benchmark crate or one of its dependencies
+//! (typically a board support crate) also provides one, it can take
+//! precedence over ours, and the executable is silently linked for another
+//! memory map. Flashing it then fails, or the Target program crashes before
+//! it can report anything.
+//!
+//! This check compares the loadable segments of the executable against the
+//! regions of our `memory.x` and, if any segment falls outside them, names
+//! the other directories containing `memory.x`. `--farcri-no-memory-x`
+//! disables our `memory.x` (and this check) to use the other one
+//! intentionally.
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use super::targets::{MemoryRegion, MemoryX};
+
+/// A loadable segment (`PT_LOAD`) of an executable
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    vaddr: u64,
+    paddr: u64,
+    file_size: u64,
+    mem_size: u64,
+}
+
+impl Segment {
+    /// Check if this segment's run-time and load addresses are both in
+    /// `regions`.
+    fn is_in(&self, regions: &[MemoryRegion]) -> bool {
+        let contains = |start, len| regions.iter().any(|r| r.contains(start, len));
+        (self.mem_size == 0 || contains(self.vaddr, self.mem_size))
+            && (self.file_size == 0 || contains(self.paddr, self.file_size))
+    }
+}
+
+/// Check the executable at `exe` against `memory_x`. `library_paths` are the
+/// linker search paths reported by the build scripts. Returns a diagnostic
+/// message if the executable doesn't fit in the memory map.
+pub(super) async fn check(
+    exe: &Path,
+    memory_x: &MemoryX,
+    library_paths: &[PathBuf],
+) -> Result<Option<String>> {
+    let elf_bytes = tokio::fs::read(exe)
+        .await
+        .with_context(|| format!("Failed to read '{}'.", exe.display()))?;
+    let elf = goblin::elf::Elf::parse(&elf_bytes).context("Failed to parse the executable.")?;
+
+    let segments: Vec<Segment> = (elf.program_headers.iter())
+        .filter(|ph| ph.p_type == goblin::elf::program_header::PT_LOAD)
+        .map(|ph| Segment {
+            vaddr: ph.p_vaddr,
+            paddr: ph.p_paddr,
+            file_size: ph.p_filesz,
+            mem_size: ph.p_memsz,
+        })
+        .collect();
+
+    // In the order the linker searches them
+    let mut providers: Vec<&Path> = Vec::new();
+    for dir in library_paths.iter().map(PathBuf::as_path) {
+        if dir != memory_x.dir && !providers.contains(&dir) && dir.join("memory.x").is_file() {
+            providers.push(dir);
+        }
+    }
+
+    Ok(diagnostic(&segments, memory_x, &providers))
+}
+
+fn diagnostic(segments: &[Segment], memory_x: &MemoryX, providers: &[&Path]) -> Option<String> {
+    if segments.iter().all(|s| s.is_in(&memory_x.regions)) {
+        return None;
+    }
+
+    let mut msg = String::from(
+        "The target executable wasn't linked for the memory map of the target. \
+        Another `memory.x` probably took precedence over the one provided by \
+        FarCri.rs.\nExpected (`memory.x` provided by FarCri.rs):",
+    );
+    for region in memory_x.regions.iter() {
+        msg.push_str(&format!("\n  {}", region));
+    }
+
+    msg.push_str("\nLinked (loadable segments):");
+    for segment in segments.iter() {
+        msg.push_str(&format!(
+            "\n  0x{:08x}..0x{:08x}",
+            segment.vaddr,
+            segment.vaddr + segment.mem_size
+        ));
+        if segment.file_size != 0 && segment.paddr != segment.vaddr {
+            msg.push_str(&format!(" (loaded at 0x{:08x})", segment.paddr));
+        }
+        if !segment.is_in(&memory_x.regions) {
+            msg.push_str(" <- outside the memory map");
+        }
+    }
+
+    if providers.is_empty() {
+        msg.push_str(
+            "\nNo other `memory.x` was found in the linker search paths reported \
+            by the build scripts (they are unknown if the executable was \
+            reused from the build cache).",
+        );
+    } else {
+        msg.push_str("\n`memory.x` is also provided in:");
+        for dir in providers {
+            msg.push_str(&format!("\n  - {}", dir.display()));
+        }
+    }
+
+    msg.push_str(
+        "\nRemove the other `memory.x` (or disable the dependency feature that \
+        provides it), or pass `--farcri-no-memory-x` to use it intentionally.",
+    );
+
+    Some(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outside_memory_map() {
+        let memory_x = MemoryX {
+            dir: PathBuf::from("/tmp/farcri-rs.abc"),
+            regions: vec![
+                MemoryRegion {
+                    name: "FLASH".to_owned(),
+                    origin: 0x0800_0000,
+                    length: 0x8_0000,
+                },
+                MemoryRegion {
+                    name: "RAM".to_owned(),
+                    origin: 0x2000_0000,
+                    length: 0x1_8000,
+                },
+            ],
+        };
+        let segment = |vaddr, paddr, file_size, mem_size| Segment {
+            vaddr,
+            paddr,
+            file_size,
+            mem_size,
+        };
+
+        let fitting = [
+            segment(0x0800_0000, 0x0800_0000, 0x1000, 0x1000),
+            // `.data`, loaded from the flash
+            segment(0x2000_0000, 0x0800_1000, 0x100, 0x100),
+            // `.bss`
+            segment(0x2000_0100, 0x2000_0100, 0, 0x1000),
+        ];
+        assert_eq!(diagnostic(&fitting, &memory_x, &[]), None);
+
+        // Linked for a chip with more RAM
+        let mut overflowing = fitting;
+        overflowing[2].mem_size = 0x2_0000;
+        let provider = Path::new("/target/thumbv7em-none-eabihf/release/build/bsp-1234/out");
+        let msg = diagnostic(&overflowing, &memory_x, &[provider]).unwrap();
+        assert!(
+            msg.contains(
+                "\n  FLASH  0x08000000..0x08080000 (512 KiB)\
+                \n  RAM    0x20000000..0x20018000 (96 KiB)\n"
+            ),
+            "{}",
+            msg
+        );
+        assert!(
+            msg.contains(
+                "\n  0x20000000..0x20000100 (loaded at 0x08001000)\
+                \n  0x20000100..0x20020100 <- outside the memory map\n"
+            ),
+            "{}",
+            msg
+        );
+        assert!(msg.contains("bsp-1234/out"), "{}", msg);
+        assert!(msg.contains("`--farcri-no-memory-x`"), "{}", msg);
+    }
+}
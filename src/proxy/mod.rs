@@ -1,28 +1,93 @@
 //! Proxy mode entry point
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
 use clap::Clap;
+use std::{sync::Arc, time::Instant};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
 
+use self::error::{ExitCode, ProxyError};
 use crate::bencher::protocol;
 
+mod alloccheck;
+mod budget;
+mod buildcache;
+mod builddesc;
+mod calibration;
+mod capacity;
 mod ccfront;
+mod clock;
+#[cfg(feature = "code_attribution")]
+mod codeattr;
+mod console_report;
 mod dumbfront;
+mod error;
+mod footprint;
 mod formatter;
+mod frametrace;
+mod idtable;
+mod jsonout;
+mod linkcheck;
+mod memcheck;
+mod progress;
+mod resultsfile;
+mod simulate;
+mod stability;
+mod stamp;
+mod stats;
+mod style;
+mod symbols;
 mod targetlink;
 mod targets;
+#[cfg(test)]
+mod testelf;
+mod timing;
 
 #[doc(hidden)]
 #[tokio::main]
 pub async fn main() {
+    // Parse arguments. `--color` applies to the log messages, so the logger
+    // is initialized after this.
+    let opts = match Opts::try_parse() {
+        Ok(x) => Ok(x),
+        // `--help` or `--version`
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => Err(e),
+    };
+
+    let color = opts
+        .as_ref()
+        .map_or(style::ColorChoice::Auto, |opts| opts.color);
+    let write_style = if style::init(color).color() {
+        env_logger::WriteStyle::Always
+    } else {
+        env_logger::WriteStyle::Never
+    };
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("farcri=info"))
+        .write_style(write_style)
         .init();
 
-    if let Err(e) = main_inner().await {
-        log::error!("Command failed.\n{:?}", e);
-        std::process::exit(1);
+    let result = match opts {
+        Ok(opts) => main_inner(opts).await,
+        // Clap would exit with code 2, which we use for regressions
+        Err(e) => Err(anyhow::anyhow!("{}", e)),
+    };
+    let exit_code = match result {
+        Ok(()) => ExitCode::Success,
+        Err(e) => {
+            let e = ProxyError::from(e);
+            log::error!("Command failed.\n{:?}", e.inner());
+            e.exit_code()
+        }
+    };
+    if exit_code != ExitCode::Success {
+        std::process::exit(exit_code as i32);
     }
 }
 
 #[derive(Clap, Debug)]
+#[clap(after_help = error::EXIT_CODES_HELP)]
 struct Opts {
     // ----------------------------------------------------------------
     // Standard Cargo test harness parameters
@@ -34,17 +99,44 @@ struct Opts {
     #[clap(long = "bench")]
     bench: bool,
 
+    /// List benchmarks without running them
+    #[clap(long = "list")]
+    list: bool,
+
+    /// Run each benchmark for the specified number of seconds without
+    /// measuring it, so that an external profiler can observe it
+    #[clap(long = "profile-time")]
+    profile_time: Option<f64>,
+
+    /// Change the default size of the sample for this run
+    #[clap(long = "sample-size")]
+    sample_size: Option<usize>,
+
+    /// Only run the benchmarks whose IDs contain any of these strings
     test_selector: Vec<String>,
 
+    /// Measure each benchmark at each of the specified clock frequencies (in
+    /// hertz, comma-separated). The benchmark code must register a
+    /// board-specific frequency setter by `Criterion::frequency_setter`.
+    #[clap(long = "farcri-frequencies", require_delimiter = true)]
+    frequencies: Vec<u32>,
+
+    /// The core clock frequency of the target in hertz. If specified, the
+    /// console output includes the throughput per second at this frequency
+    /// (or the one set by `--farcri-frequencies`), derived from the
+    /// measured cycles. Ignored by the targets measuring wall time.
+    #[clap(long = "farcri-core-clock")]
+    core_clock: Option<u32>,
+
     // ----------------------------------------------------------------
     /// Target chip/board, can also be specified by `$FARCRI_TARGET`
     #[clap(
         long = "farcri-target",
         parse(try_from_str = try_parse_target),
         possible_values(&TARGET_POSSIBLE_VALUES),
-        default_value(default_from_env("FARCRI_TARGET")),
+        env = "FARCRI_TARGET",
     )]
-    target: &'static dyn targets::Target,
+    target: Option<(&'static str, Arc<dyn targets::Target>)>,
 
     /// Override target architecture, can also be specified by `$FARCRI_ARCH`
     ///
@@ -55,10 +147,285 @@ struct Opts {
     )]
     arch: Option<targets::Arch>,
 
+    /// The data path used to receive data from the target. `swo` requires
+    /// SWO to be wired to the debug probe.
+    #[clap(
+        long = "farcri-transport",
+        possible_values(&targets::Transport::variants()),
+        case_insensitive = true,
+        default_value = "rtt"
+    )]
+    transport: targets::Transport,
+
+    /// The clock frequency of the debug interface (e.g., SWD) in kilohertz.
+    /// Defaults to a target-specific value or the debug probe's default.
+    /// Ignored by the targets without a debug probe.
+    #[clap(long = "farcri-probe-speed-khz")]
+    probe_speed_khz: Option<u32>,
+
+    /// Attach to the target while holding it in reset ("connect under
+    /// reset"). Needed if the firmware left on the target disables the debug
+    /// pins or keeps the core asleep. Ignored by the targets without a debug
+    /// probe.
+    #[clap(long = "farcri-connect-under-reset")]
+    connect_under_reset: bool,
+
+    /// How to program the flash memory. `sectors` erases and programs the
+    /// sectors covered by the executable. `preverify` reads back the flash
+    /// memory first and skips programming if it already contains the
+    /// executable, which is faster when it hasn't changed since the last
+    /// run. Ignored by the targets without a debug probe.
+    #[clap(
+        long = "farcri-flash-mode",
+        possible_values(&targets::FlashMode::variants()),
+        case_insensitive = true,
+        default_value = "sectors"
+    )]
+    flash_mode: targets::FlashMode,
+
+    /// The index of the core to run the Target program on, for multi-core
+    /// targets. Defaults to a target-specific value (usually `0`). Ignored
+    /// by the targets without a debug probe.
+    #[clap(long = "farcri-core")]
+    core_index: Option<usize>,
+
+    /// Halt the other cores of a multi-core target while benchmarking so
+    /// that they don't contend for the flash memory or SRAM. Enabled by
+    /// default for some targets. Ignored by the targets without a debug
+    /// probe.
+    #[clap(long = "farcri-halt-other-cores")]
+    halt_other_cores: bool,
+
+    /// Make the target send a compact binary trace of the protocol frames,
+    /// which is decoded and printed by the host. This is cheaper for the
+    /// target than logging the frames at the trace level. Incompatible with
+    /// `--farcri-transport swo`.
+    #[clap(long = "farcri-frame-trace")]
+    frame_trace: bool,
+
+    /// Make the target send the measurement results through a dedicated RTT
+    /// channel, separate from the control messages. This uses 4 KiB more of
+    /// the target's RAM. Incompatible with `--farcri-transport swo`.
+    #[clap(long = "farcri-rtt-bulk-channel")]
+    rtt_bulk_channel: bool,
+
+    /// Make the target report a panic and reset itself instead of halting,
+    /// so that the remaining benchmarks can run with `--farcri-keep-going`.
+    /// Only supported by the Cortex-M targets. Incompatible with
+    /// `--farcri-transport swo`.
+    #[clap(long = "farcri-panic-reset")]
+    panic_reset: bool,
+
+    /// Discard the Target program's log output while it's taking
+    /// measurements. This minimizes the debug probe's traffic, which might
+    /// disturb the measurements. Only effective with the targets using a
+    /// debug probe.
+    #[clap(long = "farcri-mute-target-logs-during-measurement")]
+    mute_target_logs: bool,
+
+    /// Enable the specified Cargo feature of the benchmark crate in the
+    /// target build, in addition to the ones FarCri.rs enables. Can be
+    /// specified multiple times.
+    #[clap(long = "farcri-feature", number_of_values = 1)]
+    features: Vec<String>,
+
+    /// Don't rebuild `core` (`-Zbuild-std=core`) even if the target
+    /// architecture requires a custom target feature set. This allows using
+    /// a stable toolchain when the precompiled `core` is sufficient.
+    #[clap(long = "farcri-no-build-std")]
+    no_build_std: bool,
+
     /// Dry run - specifies not to download or execute the benchmark code on the
-    /// target.
-    #[clap(long = "farcri-dry-run")]
-    dry_run: bool,
+    /// target. With `=simulate`, a simulated Target program running a fixed
+    /// set of benchmarks on the host takes the place of the target, so that
+    /// the front-end, the output files, and the exit code can be checked
+    /// without a debug probe.
+    #[clap(
+        long = "farcri-dry-run",
+        require_equals = true,
+        possible_values(&DryRun::variants()),
+        case_insensitive = true
+    )]
+    dry_run: Option<Option<DryRun>>,
+
+    /// Use the specified executable instead of building the benchmark code
+    /// with Cargo. The executable must be built with FarCri.rs's target
+    /// features for the selected target.
+    #[clap(long = "farcri-elf", parse(from_os_str))]
+    elf: Option<std::path::PathBuf>,
+
+    /// Build the benchmark code with the specified command instead of
+    /// `$CARGO` (e.g., `cross`). It must accept the same arguments as
+    /// `cargo`. Can also be specified by `$FARCRI_CARGO`.
+    #[clap(long = "farcri-cargo", parse(from_os_str), env = "FARCRI_CARGO")]
+    cargo: Option<std::ffi::OsString>,
+
+    /// Always build the benchmark code with Cargo. By default, the target
+    /// executable built by the previous run is reused if nothing affecting
+    /// the build has changed since then, which is remembered in
+    /// `target/farcri/build_cache.json`.
+    #[clap(long = "farcri-no-build-cache")]
+    no_build_cache: bool,
+
+    /// Measure the overhead of `Bencher::iter`'s loop with a no-op routine
+    /// before running benchmarks and print it. This is a reference point for
+    /// judging whether tiny measurements are meaningful.
+    ///
+    /// The measured value is stored in `target/farcri/calibration` and
+    /// reused by later runs with the same build configuration.
+    #[clap(long = "farcri-loop-overhead")]
+    loop_overhead: bool,
+
+    /// Measure the calibration (e.g., `--farcri-loop-overhead`) again instead
+    /// of reusing the one stored by a previous run.
+    #[clap(long = "farcri-recalibrate")]
+    recalibrate: bool,
+
+    /// Measure the latency and throughput of the link to the target after
+    /// connecting to it, and warn if the link is too slow. A slow link
+    /// (e.g., because of a low SWD clock frequency) makes runs take much
+    /// longer than the benchmarks themselves.
+    #[clap(long = "farcri-link-check")]
+    link_check: bool,
+
+    /// Collect as many samples as requested even if they exceed the Target
+    /// program's sample buffer. The Target program sends the samples in
+    /// chunks as the buffer fills up instead of clamping the sample size.
+    #[clap(long = "farcri-stream-samples")]
+    stream_samples: bool,
+
+    /// Reset the target if a single benchmark doesn't complete within the
+    /// specified number of seconds. The benchmark is reported as faulted,
+    /// and the run is aborted unless `--farcri-keep-going` is present.
+    #[clap(long = "farcri-bench-timeout")]
+    bench_timeout: Option<f64>,
+
+    /// Limit the total time of the run to the specified number of seconds.
+    /// Before starting each benchmark, its duration is estimated from the
+    /// longest benchmark so far, and the remaining benchmarks are skipped if
+    /// it wouldn't fit in the budget. The skipped benchmarks are reported at
+    /// the end.
+    #[clap(long = "farcri-suite-time-budget")]
+    suite_time_budget: Option<f64>,
+
+    /// Exit with code 5 if `--farcri-suite-time-budget` caused any benchmark
+    /// to be skipped.
+    #[clap(long = "farcri-budget-strict")]
+    budget_strict: bool,
+
+    /// List the samples deviating by more than 5% from the median sample of
+    /// each benchmark. By default, only their number is shown along with a
+    /// noise level. Only effective when not run by cargo-criterion.
+    #[clap(long = "farcri-stability-details")]
+    stability_details: bool,
+
+    /// Save the measurement history of each benchmark as JSON files in the
+    /// specified directory, laid out like cargo-criterion's output directory
+    /// (e.g., `target/criterion`), and the timing breakdown of the run as
+    /// `timings.json`. Only effective when not run by cargo-criterion, which
+    /// saves the results by itself.
+    #[clap(long = "farcri-save-json", parse(from_os_str))]
+    save_json: Option<std::path::PathBuf>,
+
+    /// Also save the mean, median, and standard deviation of each benchmark
+    /// in the `estimates` field of its measurement record like
+    /// cargo-criterion, so that tools reading them can be used. Requires
+    /// `--farcri-save-json`.
+    #[clap(long = "farcri-save-estimates")]
+    save_estimates: bool,
+
+    /// Append the samples of each benchmark to the specified JSON Lines file
+    /// as soon as it completes, so that they survive a run that doesn't
+    /// finish. When the run finishes, they are also collected into the file
+    /// of the same name with the extension `.json`. If the file was left by
+    /// an unfinished run, it's renamed by appending `.partial` to its name.
+    /// Only effective when not run by cargo-criterion.
+    #[clap(long = "farcri-results", parse(from_os_str))]
+    results: Option<std::path::PathBuf>,
+
+    /// Append to the file specified by `--farcri-results` instead of
+    /// renaming it if it was left by an unfinished run.
+    #[clap(long = "farcri-results-resume")]
+    results_resume: bool,
+
+    /// Exit with code 2 if any benchmark's performance has regressed
+    /// compared to the previous run. Only supported by the dumb front-end.
+    #[clap(long = "farcri-fail-on-regression")]
+    fail_on_regression: bool,
+
+    /// Scale the values in cargo-criterion's machine-readable output (e.g.,
+    /// CSV files) to the specified unit for all benchmarks, e.g., `Kcycles`.
+    /// By default, they are in the target's native unit (`cycles` or `ns`).
+    /// Only effective when run by cargo-criterion.
+    #[clap(long = "farcri-machine-unit")]
+    machine_unit: Option<String>,
+
+    /// If the target faults or hangs while running a benchmark, reset it and
+    /// resume from the next benchmark instead of aborting. The skipped
+    /// benchmarks are reported at the end, and the run still fails. Only
+    /// effective when not run by cargo-criterion. A panic is only detected
+    /// by a timeout unless `--farcri-panic-reset` is given.
+    #[clap(long = "farcri-keep-going")]
+    keep_going: bool,
+
+    /// Fail if the benchmark code is built with an optimization level lower
+    /// than 2 or with debug assertions enabled.
+    #[clap(long = "farcri-require-release")]
+    require_release: bool,
+
+    /// Fail if the target executable uses a heap allocator, e.g., because a
+    /// dependency was built with its `alloc` feature. By default, this is
+    /// only a warning. Ignored for the host target.
+    #[clap(long = "farcri-forbid-alloc")]
+    forbid_alloc: bool,
+
+    /// Don't provide the target's `memory.x` to the linker, so that the one
+    /// provided by the benchmark crate or its dependencies is used. Without
+    /// this, the target executable is checked against the target's memory
+    /// map.
+    #[clap(long = "farcri-no-memory-x")]
+    no_memory_x: bool,
+
+    /// Estimate the code size reachable from each benchmark closure broken
+    /// down by crate. Requires the `code_attribution` feature.
+    #[clap(long = "farcri-code-attribution")]
+    code_attribution: bool,
+
+    /// Report the size of the function implementing each benchmark, found
+    /// by matching the benchmark's function or group ID against the symbol
+    /// names in the benchmark crate (e.g., `noop` matches `my_bench::noop`).
+    /// Mark the function `#[inline(never)]` so that it has a symbol.
+    #[clap(long = "farcri-flash-footprint")]
+    flash_footprint: bool,
+
+    /// Print the versions of FarCri.rs and its components and the list of
+    /// connected debug probes, and exit without building anything. The
+    /// probes used by the target specified by `--farcri-target` are opened
+    /// to query their names.
+    #[clap(long = "farcri-info")]
+    info: bool,
+
+    /// Print the list of the supported targets and exit
+    #[clap(long = "farcri-list-targets")]
+    list_targets: bool,
+
+    /// Print the ID of each benchmark on a line, for shell completion and
+    /// IDE integration. The Target program is run in list mode if the
+    /// target doesn't need any hardware (e.g., `host` and QEMU). Otherwise,
+    /// the candidates recorded in the executable are printed instead, which
+    /// are the exact IDs for `#[farcri::bench]` and the paths of the
+    /// functions passed to `criterion_group!`.
+    #[clap(long = "farcri-list-static")]
+    list_static: bool,
+
+    /// Use colors in the console output: `auto` (default), `always`, or
+    /// `never`. With `auto`, colors are used if the output is a terminal,
+    /// unless disabled by `$NO_COLOR` or `$CLICOLOR=0` or forced by
+    /// `$CLICOLOR_FORCE`.
+    #[clap(long = "color",
+        possible_values(&style::ColorChoice::variants()), case_insensitive = true,
+        default_value = "auto")]
+    color: style::ColorChoice,
 
     /// Log level of the test program
     #[clap(long = "farcri-log-level",
@@ -67,11 +434,9 @@ struct Opts {
     log_level: LogLevel,
 }
 
-fn default_from_env(name: &str) -> &'static str {
-    std::env::var(name)
-        .ok()
-        .map(|x| &**Box::leak(Box::new(x)))
-        .unwrap_or("")
+#[derive(Debug, Clone, Copy, PartialEq, arg_enum_proc_macro::ArgEnum)]
+enum DryRun {
+    Simulate,
 }
 
 #[derive(Debug, Clone, Copy, arg_enum_proc_macro::ArgEnum)]
@@ -85,42 +450,769 @@ enum LogLevel {
 }
 
 lazy_static::lazy_static! {
-    static ref TARGET_POSSIBLE_VALUES: Vec<&'static str> =
-        targets::TARGETS.iter().map(|x|x.0).collect();
+    static ref TARGET_POSSIBLE_VALUES: Vec<&'static str> = targets::backends()
+        .iter()
+        .flat_map(|b| b.targets().iter().map(|x| x.0))
+        .collect();
+}
+
+fn try_parse_target(arg_target: &str) -> Result<(&'static str, Arc<dyn targets::Target>)> {
+    let (name, factory) =
+        targets::find_target(arg_target).ok_or_else(|| anyhow::anyhow!("no such target"))?;
+    let target =
+        factory().with_context(|| format!("Failed to configure the target '{}'.", name))?;
+    Ok((name, target))
+}
+
+/// Print the information requested by `--farcri-info`.
+fn print_info(selected_target: Option<&str>) {
+    println!("FarCri.rs {}", env!("CARGO_PKG_VERSION"));
+    println!("Host: {}-{}", std::env::consts::ARCH, std::env::consts::OS);
+    targets::print_info(selected_target);
+}
+
+/// Report the number of samples in a `MeasurementComplete` message, warning
+/// if it's much smaller or larger than requested.
+fn log_sample_count(num_samples: usize, requested_num_samples: usize) {
+    log::info!("{} samples", num_samples);
+
+    // Allow up to 10% of discrepancy
+    let diff = (num_samples as f64 - requested_num_samples as f64).abs();
+    if diff > requested_num_samples as f64 * 0.1 {
+        log::warn!(
+            "{} samples were collected while {} samples were requested. \
+            The statistical analysis of this benchmark may be less reliable \
+            than expected.",
+            num_samples,
+            requested_num_samples
+        );
+    }
+}
+
+/// Label a benchmark ID with the clock frequency reported by a
+/// `FrequencyChanged` message so that the results for different frequencies
+/// are kept apart. The label goes to the function ID so that the results
+/// stay in the same group and can be compared with each other.
+fn tag_frequency(id: &mut protocol::RawBenchmarkId<String>, hz: u32) {
+    let tag = crate::utils::Frequency(hz);
+    id.function_id = Some(match id.function_id.take() {
+        Some(function_id) => format!("{} @ {}", function_id, tag),
+        None => tag.to_string(),
+    });
+}
+
+/// Report a `SampleSizeClamped` message.
+fn log_sample_size_clamped(requested_num_samples: usize, effective_num_samples: usize) {
+    log::warn!(
+        "{} samples were requested, but the target can only hold {} samples. \
+        Collecting {} samples instead.{}",
+        requested_num_samples,
+        effective_num_samples,
+        effective_num_samples,
+        capacity::LARGE_SAMPLES_HINT,
+    );
+}
+
+/// Report an `IdTruncated` message.
+fn log_id_truncated(
+    id: &protocol::RawBenchmarkId<String>,
+    group_id_len: u32,
+    function_id_len: u32,
+    value_str_len: u32,
+) {
+    let components = [
+        ("group name", Some(&id.group_id), group_id_len),
+        ("function name", id.function_id.as_ref(), function_id_len),
+        ("parameter", id.value_str.as_ref(), value_str_len),
+    ];
+    for &(what, truncated, original_len) in components.iter() {
+        let truncated = truncated.map_or("", String::as_str);
+        if original_len as usize > truncated.len() {
+            log::warn!(
+                "The {} of '{}' is {} bytes long and was truncated to {} bytes by \
+                the target ('{}'). Shorten it so that the results don't get mixed \
+                up with those of another benchmark.",
+                what,
+                id,
+                original_len,
+                truncated.len(),
+                truncated
+            );
+        }
+    }
+}
+
+/// Report a `MeasurementWarning` message.
+fn log_measurement_warning(kind: protocol::MeasurementWarningKind) {
+    match kind {
+        protocol::MeasurementWarningKind::TimerTampered => log::warn!(
+            "The time source of the target was reprogrammed while the benchmark \
+            was running, so some of the measured values are unreliable. This is \
+            usually caused by a HAL delay implementation or an RTOS configuring \
+            SysTick for its own use (often only in one of debug and release \
+            builds). The benchmark code should not use SysTick."
+        ),
+        protocol::MeasurementWarningKind::WarmUpCapped => log::warn!(
+            "The warm-up reached the maximum number of iteration count \
+            doublings (`BenchmarkGroup::max_warm_up_doublings`) before running \
+            for the warm-up time, so the measured values are unreliable. The \
+            time source of the target is probably stopped or misconfigured."
+        ),
+    }
+}
+
+/// Calculate the drift of the measured values within a benchmark from the
+/// per-sample timestamps (`SampleTimestamps`) by linear regression. Returns
+/// the change over the measurement relative to the mean value (e.g., `0.05`
+/// for +5%), or `None` if it can't be calculated.
+fn sample_drift(values: &[u64], timestamps: &[u64]) -> Option<f64> {
+    if values.len() != timestamps.len() || values.len() < 2 {
+        return None;
+    }
+
+    let n = values.len() as f64;
+    let mean_t = timestamps.iter().map(|&t| t as f64).sum::<f64>() / n;
+    let mean_v = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let (mut cov, mut var_t) = (0.0, 0.0);
+    for (&t, &v) in timestamps.iter().zip(values.iter()) {
+        let (dt, dv) = (t as f64 - mean_t, v as f64 - mean_v);
+        cov += dt * dv;
+        var_t += dt * dt;
+    }
+    if var_t == 0.0 || mean_v == 0.0 {
+        return None;
+    }
+
+    let span = (timestamps[timestamps.len() - 1] - timestamps[0]) as f64;
+    Some(cov / var_t * span / mean_v)
+}
+
+/// Report the drift of the measured values within a benchmark (see
+/// [`sample_drift`]).
+fn log_sample_drift(values: &[u64], timestamps: &[u64]) {
+    if values.len() != timestamps.len() {
+        log::warn!(
+            "Got {} sample timestamps for {} samples. Ignoring them.",
+            timestamps.len(),
+            values.len()
+        );
+        return;
+    }
+    let drift = match sample_drift(values, timestamps) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let span = timestamps[timestamps.len() - 1] - timestamps[0];
+    log::info!("Drift: {:+.2}% over {} cycles", drift * 100.0, span);
+
+    if drift.abs() > 0.05 {
+        log::warn!(
+            "The measured values drifted by {:+.2}% during the measurement. \
+            Something (e.g., thermal throttling or a background activity) \
+            might be affecting the benchmark.",
+            drift * 100.0
+        );
+    }
+}
+
+/// Print the test failures reported by `TestFailed` messages in the same way
+/// as `cargo test`, and fail if there are any.
+fn report_test_failures(failures: &[(String, String)]) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!("failures:");
+    for (id, message) in failures {
+        println!("    {}: {}", id, message);
+    }
+    println!();
+
+    Err(error::TargetFailure(format!("{} test(s) failed.", failures.len())).into())
+}
+
+/// Report the benchmarks aborted by `BenchmarkFailed` messages, failing if
+/// there are any.
+fn report_benchmark_failures(failures: &[(String, String)]) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!("{}", style::stdout().red("failed benchmarks:"));
+    for (id, message) in failures {
+        println!("    {}: {}", id, message);
+    }
+    println!();
+
+    Err(error::TargetFailure(format!("{} benchmark(s) failed.", failures.len())).into())
+}
+
+/// Report the benchmarks skipped by `--farcri-keep-going`, failing if there
+/// are any.
+fn report_faults(faulted: &[String]) -> Result<()> {
+    if faulted.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!("{}", style::stdout().red("skipped due to faults:"));
+    for id in faulted {
+        println!("    {}", id);
+    }
+    println!();
+
+    Err(error::TargetFailure(format!("{} benchmark(s) faulted.", faulted.len())).into())
+}
+
+/// Report the benchmarks found to have regressed by `--farcri-fail-on-regression`,
+/// failing if there are any.
+fn report_regressions(regressed: &[String]) -> Result<()> {
+    if regressed.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!("{}", style::stdout().red("regressed:"));
+    for id in regressed {
+        println!("    {}", id);
+    }
+    println!();
+
+    Err(error::RegressionDetected(format!("{} benchmark(s) regressed.", regressed.len())).into())
 }
 
-fn try_parse_target(arg_target: &str) -> Result<&'static dyn targets::Target, &'static str> {
-    targets::TARGETS
+/// Report the benchmarks skipped by `--farcri-suite-time-budget`, failing if
+/// there are any and `--farcri-budget-strict` is present.
+fn report_budget(budget: Option<&budget::SuiteBudget>, strict: bool) -> Result<()> {
+    let skipped = match budget {
+        Some(budget) if !budget.skipped().is_empty() => budget.skipped(),
+        _ => return Ok(()),
+    };
+
+    println!();
+    println!("{}", style::stdout().yellow("not run (budget):"));
+    for id in skipped {
+        println!("    {}", id);
+    }
+    println!();
+
+    let message = format!(
+        "{} benchmark(s) didn't fit in the time budget.",
+        skipped.len()
+    );
+    if strict {
+        Err(error::BudgetExhausted(message).into())
+    } else {
+        log::warn!("{}", message);
+        Ok(())
+    }
+}
+
+/// Locate the directory where FarCri.rs stores its files, `farcri` in the
+/// target directory.
+async fn farcri_dir() -> Result<std::path::PathBuf> {
+    let e = match tokio::task::spawn_blocking(crate::cargo::target_directory)
+        .await
+        .unwrap()
+    {
+        Ok(target_dir) => return Ok(target_dir.join("farcri")),
+        Err(e) => e,
+    };
+
+    // The running executable is `<target dir>/<profile>/deps/<name>`
+    let exe =
+        std::env::current_exe().context("Failed to get the path of the running executable.")?;
+    let target_dir = exe
+        .parent()
+        .filter(|deps| deps.ends_with("deps"))
+        .and_then(|deps| deps.parent()?.parent())
+        .with_context(|| {
+            format!(
+                "Failed to locate the target directory. `cargo metadata` \
+                failed ({}), and the running executable '{}' isn't in a \
+                target directory.",
+                e,
+                exe.display()
+            )
+        })?;
+    log::debug!(
+        "`cargo metadata` failed ({}). Assuming the target directory '{}'",
+        e,
+        target_dir.display()
+    );
+    Ok(target_dir.join("farcri"))
+}
+
+/// Print the benchmark ID candidates embedded in the target executable for
+/// `--farcri-list-static`.
+async fn print_static_ids(exe: &std::path::Path) -> Result<()> {
+    let ids = idtable::load(exe)
+        .await
+        .context("Failed to read the benchmark IDs from the target executable.")?
+        .with_context(|| {
+            format!(
+                "The target executable '{}' doesn't contain the benchmark ID \
+                table. It might have been built by an older version of FarCri.rs.",
+                exe.display()
+            )
+        })?;
+
+    if ids
         .iter()
-        .find(|x| x.0 == arg_target)
-        .ok_or("no such target")
-        .map(|x| x.1)
+        .any(|id| matches!(id, idtable::StaticId::Function(_)))
+    {
+        log::info!(
+            "Listing the benchmarks found in the target executable without \
+            running it. The benchmarks defined through `criterion_group!` \
+            are listed by the paths of their functions, which might differ \
+            from their IDs."
+        );
+    }
+
+    for id in ids.iter() {
+        println!("{}", id.name());
+    }
+
+    Ok(())
+}
+
+/// Print the list of targets requested by `--farcri-list-targets`.
+fn print_targets() {
+    println!("Built-in targets (specify by `--farcri-target`):");
+    for backend in targets::backends() {
+        for (name, factory) in backend.targets() {
+            // A target needing runtime configuration might not be
+            // constructible here
+            let arch = match factory() {
+                Ok(target) => target.target_arch().to_string(),
+                Err(_) => "(unconfigured)".to_owned(),
+            };
+            println!("  {:<20} {:<20} {}", name, arch, backend.name());
+        }
+    }
+
+    // There's no generic probe-rs target yet, but this might help people
+    // looking for a chip to port FarCri.rs to
+    println!();
+    println!("Generic targets: (none yet)");
+    targets::print_generic_targets();
+}
+
+/// The values derived from [`Opts`] by [`check_opts`]
+struct CheckedOpts {
+    bench_timeout: Option<std::time::Duration>,
+    suite_time_budget: Option<std::time::Duration>,
+    name_filters: arrayvec::ArrayVec<String, { protocol::MAX_NAME_FILTERS }>,
+}
+
+/// Check the combination and the ranges of the command-line options.
+fn check_opts(opts: &Opts) -> Result<CheckedOpts> {
+    if opts.code_attribution && !cfg!(feature = "code_attribution") {
+        bail!(
+            "`--farcri-code-attribution` requires the `code_attribution` \
+            feature of `farcri` to be enabled."
+        );
+    }
+
+    if opts.frame_trace && matches!(opts.transport, targets::Transport::Swo) {
+        bail!("`--farcri-frame-trace` can't be used with `--farcri-transport swo`.");
+    }
+    if opts.rtt_bulk_channel && matches!(opts.transport, targets::Transport::Swo) {
+        bail!("`--farcri-rtt-bulk-channel` can't be used with `--farcri-transport swo`.");
+    }
+    if opts.panic_reset && matches!(opts.transport, targets::Transport::Swo) {
+        bail!("`--farcri-panic-reset` can't be used with `--farcri-transport swo`.");
+    }
+
+    // The SWO baud rate is derived from the core clock
+    if !opts.frequencies.is_empty() && matches!(opts.transport, targets::Transport::Swo) {
+        bail!("`--farcri-frequencies` can't be used with `--farcri-transport swo`.");
+    }
+
+    if opts.elf.is_some() && opts.require_release {
+        bail!(
+            "`--farcri-require-release` can't be used with `--farcri-elf` \
+            because the build profile of a prebuilt executable is unknown."
+        );
+    }
+
+    if opts.save_estimates && opts.save_json.is_none() {
+        bail!("`--farcri-save-estimates` requires `--farcri-save-json`.");
+    }
+
+    let bench_timeout = match opts.bench_timeout {
+        Some(secs) if !(secs > 0.0 && secs.is_finite()) => {
+            bail!("`--farcri-bench-timeout` must be a positive number of seconds.");
+        }
+        secs => secs.map(std::time::Duration::from_secs_f64),
+    };
+
+    if opts.results_resume && opts.results.is_none() {
+        bail!("`--farcri-results-resume` requires `--farcri-results`.");
+    }
+
+    let suite_time_budget = match opts.suite_time_budget {
+        Some(secs) if !(secs > 0.0 && secs.is_finite()) => {
+            bail!("`--farcri-suite-time-budget` must be a positive number of seconds.");
+        }
+        secs => secs.map(std::time::Duration::from_secs_f64),
+    };
+    if opts.budget_strict && suite_time_budget.is_none() {
+        bail!("`--farcri-budget-strict` requires `--farcri-suite-time-budget`.");
+    }
+
+    // The name filters are copied to the Target program's fixed-size buffer
+    let mut name_filters = arrayvec::ArrayVec::new();
+    for filter in opts.test_selector.iter() {
+        if filter.len() > protocol::MAX_NAME_FILTER_LEN {
+            bail!(
+                "The filter {:?} is too long (must be no longer than {} bytes).",
+                filter,
+                protocol::MAX_NAME_FILTER_LEN
+            );
+        }
+        name_filters.try_push(filter.clone()).map_err(|_| {
+            anyhow::anyhow!(
+                "Too many filters are specified (at most {} are allowed).",
+                protocol::MAX_NAME_FILTERS
+            )
+        })?;
+    }
+
+    if opts.frequencies.len() > protocol::MAX_FREQUENCIES {
+        bail!(
+            "Too many frequencies are specified (at most {} are allowed).",
+            protocol::MAX_FREQUENCIES
+        );
+    }
+    if opts.frequencies.contains(&0) || opts.core_clock == Some(0) {
+        bail!("Frequencies must be non-zero.");
+    }
+
+    Ok(CheckedOpts {
+        bench_timeout,
+        suite_time_budget,
+        name_filters,
+    })
 }
 
-async fn main_inner() -> Result<()> {
-    // Parse arguments
-    let opts: Opts = Clap::parse();
+async fn main_inner(opts: Opts) -> Result<()> {
     log::debug!("opts = {:#?}", opts);
 
-    if !opts.bench {
-        log::info!("Exiting because `--bench` is not specified");
+    if opts.info {
+        print_info(opts.target.as_ref().map(|(name, _)| *name));
         return Ok(());
     }
 
-    if !opts.test_selector.is_empty() {
-        log::warn!("Test names are specified but we don't currently support them");
+    if opts.list_targets {
+        print_targets();
+        return Ok(());
+    }
+
+    if !opts.bench && !opts.test && !opts.list && !opts.list_static {
+        log::info!("Exiting because none of `--bench`, `--test`, and `--list` is specified");
+        return Ok(());
     }
 
-    let target = opts.target;
-    let build_setup = target
+    let CheckedOpts {
+        bench_timeout,
+        suite_time_budget,
+        name_filters,
+    } = check_opts(&opts)?;
+
+    let (target_name, target) = opts.target.clone().context(
+        "No target is specified. Specify one by `--farcri-target` or \
+        `$FARCRI_TARGET`. `--farcri-list-targets` shows the list of \
+        the supported targets.",
+    )?;
+
+    // The simulated target measures nanoseconds regardless of the target
+    let simulate = opts.dry_run == Some(Some(DryRun::Simulate));
+
+    // `--farcri-list-static` reads the benchmark IDs from the executable
+    // unless the Target program can be run without hardware. The simulated
+    // target doesn't run the bench target's benchmarks.
+    let scan_ids = opts.list_static && (opts.dry_run.is_some() || !target.runs_without_hardware());
+    let value_unit = if simulate {
+        targets::ValueUnit::Nanoseconds
+    } else {
+        target.value_unit()
+    };
+
+    let machine_unit = match &opts.machine_unit {
+        Some(name) => {
+            let units = value_unit.machine_units();
+            let unit = units
+                .iter()
+                .find(|unit| unit.name == name)
+                .with_context(|| {
+                    format!(
+                        "`--farcri-machine-unit {}` isn't supported by this target. \
+                    The supported units are: {}.",
+                        name,
+                        crate::utils::CommaSeparated(units.iter().map(|unit| unit.name)),
+                    )
+                })?;
+            Some(*unit)
+        }
+        None => None,
+    };
+    let mut build_setup = target
         .prepare_build()
         .await
         .context("Failed to setup a build environment")?;
+    if opts.no_memory_x && !build_setup.disable_memory_x() {
+        log::warn!("Ignoring `--farcri-no-memory-x` because the target doesn't provide `memory.x`");
+    }
+
+    let plan = plan_build(&opts, &*target, &*build_setup)?;
+
+    // The durations of the phases preceding the execution of benchmarks
+    let mut phases = Vec::new();
+    let run_start = Instant::now();
+
+    let measurement_flag = if opts.mute_target_logs {
+        Some(targets::MeasurementFlag::default())
+    } else {
+        None
+    };
+    let reconnects = targets::ReconnectCounter::default();
+    let probe_phases = targets::PhaseLog::default();
+
+    // Connect to the target now. Fail-fast so that the user can divert
+    // attention without risking wasting time.
+    let start = Instant::now();
+    let probe = if scan_ids {
+        None
+    } else if simulate {
+        Some(Box::new(simulate::SimulatedDebugProbe) as Box<dyn targets::DebugProbe>)
+    } else if opts.dry_run.is_some() {
+        None
+    } else {
+        Some(
+            target
+                .connect(&targets::ConnectOptions {
+                    probe_speed_khz: opts
+                        .probe_speed_khz
+                        .or_else(|| target.default_probe_speed_khz()),
+                    connect_under_reset: opts.connect_under_reset
+                        || target.requires_connect_under_reset(),
+                    core_index: opts.core_index.unwrap_or_else(|| target.core_index()),
+                    halt_other_cores: opts.halt_other_cores || target.halt_other_cores(),
+                    flash_mode: opts.flash_mode,
+                    mute_logs: measurement_flag.clone(),
+                    reconnects: reconnects.clone(),
+                    phases: probe_phases.clone(),
+                })
+                .await
+                .context("Failed to connect to the target.")?,
+        )
+    };
+    phases.push(("connect", start.elapsed()));
+
+    let start = Instant::now();
+
+    let farcri_dir = farcri_dir().await?;
+    log::debug!("farcri_dir = {:?}", farcri_dir);
+
+    let exe = obtain_executable(&opts, target_name, &farcri_dir, &plan, &*build_setup).await?;
+    phases.push(("build", start.elapsed()));
+
+    let build_descriptor = check_executable(&opts, &plan, &exe, &*build_setup).await?;
+
+    let exe = build_setup
+        .post_process(exe)
+        .await
+        .context("Failed to post-process the target executable.")?;
+
+    #[cfg(feature = "code_attribution")]
+    if opts.code_attribution {
+        codeattr::report(&exe.path, &exe.name)
+            .await
+            .context("Code attribution analysis failed.")?;
+    }
+
+    let footprints = if opts.flash_footprint {
+        let footprints = footprint::FlashFootprints::load(&exe.path, &exe.name)
+            .await
+            .context("Failed to read the symbol table of the target executable.")?;
+        log::debug!(
+            "Found {} function(s) in the benchmark crate for `--farcri-flash-footprint`",
+            footprints.len()
+        );
+        Some(footprints)
+    } else {
+        None
+    };
+
+    if scan_ids {
+        return print_static_ids(&exe.path).await;
+    }
+
+    let mut probe = if let Some(probe) = probe {
+        probe
+    } else {
+        log::warn!("Exiting now because a `--farcry-dry-run` option is present.");
+        return Ok(());
+    };
+
+    let (mode, bench_config_overrides) = mode_and_overrides(&opts);
+
+    // The build configuration of a prebuilt executable is unknown, and the
+    // simulated target doesn't measure the target
+    let calibration_store = if opts.elf.is_none() && !simulate {
+        Some(calibration::CalibrationStore::new(
+            &farcri_dir.join(calibration::DIR_NAME),
+            target_name,
+            &plan.build_config,
+        ))
+    } else {
+        None
+    };
+    let calibration = match &calibration_store {
+        Some(store) if !opts.recalibrate => store.load().await.unwrap_or_default(),
+        _ => calibration::Calibration::default(),
+    };
+
+    let mut feature_bits = 0;
+    if opts.loop_overhead {
+        if let Some(loop_overhead) = &calibration.loop_overhead {
+            log_loop_overhead(loop_overhead, value_unit, true);
+        } else {
+            feature_bits |= protocol::FEATURE_LOOP_OVERHEAD;
+        }
+    }
+    if opts.link_check {
+        feature_bits |= protocol::FEATURE_LINK_CHECK;
+    }
+    if opts.stream_samples {
+        feature_bits |= protocol::FEATURE_STREAMED_SAMPLES;
+    }
+    let config = protocol::TargetConfig {
+        protocol_version: protocol::PROTOCOL_VERSION,
+        mode,
+        bench_config_overrides,
+        name_filters,
+        frequencies: opts.frequencies.iter().cloned().collect(),
+        skip_benchmarks: 0,
+        feature_bits,
+    };
+
+    let mut run = Run {
+        exe: &exe,
+        transport: opts.transport,
+        simulate,
+        value_unit,
+        config,
+        bench_timeout,
+        measurement_flag,
+        calibration_store,
+        calibration,
+        // The budget covers the whole run, including the build and flashing
+        suite_budget: suite_time_budget.map(|limit| budget::SuiteBudget::new(limit, run_start)),
+        phases,
+        probe_phases,
+        reconnects,
+    };
+
+    // The output of `--farcri-list-static` is meant for tools other than
+    // cargo-criterion
+    let cc_port = std::env::var("CARGO_CRITERION_PORT")
+        .ok()
+        .filter(|_| !opts.list_static);
+    if let Some(port) = cc_port {
+        let port: u16 = port.parse().with_context(|| {
+            format!(
+                "Could not parse the value of `CARGO_CRITERION_PORT` ({:?})",
+                port
+            )
+        })?;
+        if footprints.is_some() {
+            log::warn!(
+                "Ignoring `--farcri-flash-footprint` because cargo-criterion \
+                can't display it"
+            );
+        }
+        run_cc_frontend(&opts, &mut run, &mut *probe, port, machine_unit).await
+    } else {
+        log::info!("`CARGO_CRITERION_PORT` is not set; using the dumb front-end");
+        if machine_unit.is_some() {
+            log::warn!(
+                "Ignoring `--farcri-machine-unit` because it only applies to \
+                cargo-criterion's machine-readable output"
+            );
+        }
+        let json_writer = opts.save_json.as_ref().map(|dir| {
+            let mut json_writer = jsonout::JsonWriter::new(dir, opts.save_estimates);
+            if let Some(build_descriptor) = &build_descriptor {
+                json_writer.set_build_descriptor(build_descriptor);
+            }
+            json_writer
+        });
+        run_dumb_frontend(
+            &opts,
+            &mut run,
+            &mut *probe,
+            json_writer.as_ref(),
+            footprints.as_ref(),
+            farcri_dir.join(target_name),
+        )
+        .await
+    }
+}
+
+/// How to build the Target program, derived from the command-line options
+/// and the target
+struct BuildPlan {
+    arch: targets::Arch,
+    /// `None` means the host.
+    target_triple: Option<&'static str>,
+    rustflags: String,
+    /// Specify `-Zbuild-std=core`
+    build_std: bool,
+    /// The complete list of the Cargo features to build the Target program
+    /// with
+    features: Vec<String>,
+    build_descriptor: builddesc::BuildDescriptor,
+    /// `build_descriptor` encoded for embedding in the executable, or `None`
+    /// if it's too long
+    encoded_build_descriptor: Option<String>,
+    /// Everything that affects the build except for the source files
+    build_config: Vec<String>,
+}
 
+/// Derive the target architecture, `RUSTFLAGS`, and the Cargo features to
+/// build the Target program with.
+fn plan_build(
+    opts: &Opts,
+    target: &dyn targets::Target,
+    build_setup: &dyn targets::BuildSetup,
+) -> Result<BuildPlan> {
     // Derive the target architecture information
     let arch = opts.arch.unwrap_or_else(|| target.target_arch());
     log::debug!("arch = {}", arch);
 
+    // The target's Cargo features enable code written for its own
+    // architecture
+    let conflicts = arch.override_conflicts(&target.target_arch());
+    if !conflicts.is_empty() {
+        let mut msg = format!(
+            "`--farcri-arch {}` is inconsistent with the architecture of the \
+            target ('{}'), which its Cargo features ({}) are meant for:",
+            arch,
+            target.target_arch(),
+            crate::utils::CommaSeparated(target.cargo_features()),
+        );
+        for conflict in conflicts {
+            msg.push_str("\n  - ");
+            msg.push_str(&conflict);
+        }
+        log::warn!("{}", msg);
+    }
+
     let arch_opt = arch.build_opt().with_context(|| {
         format!(
             "The target architecture '{}' is invalid or unsupported.",
@@ -144,73 +1236,591 @@ async fn main_inner() -> Result<()> {
     }
     log::debug!("rustflags = {:?}", rustflags);
 
+    let build_std = !target_features.is_empty() && !opts.no_build_std;
+    if opts.no_build_std && arch_opt.requires_build_std() {
+        log::warn!(
+            "`--farcri-no-build-std` is present, but the target feature set \
+            ({}) disables some features of '{}'. The precompiled `core` might \
+            contain instructions unsupported by the target.",
+            target_features,
+            arch_opt.target_triple.unwrap_or("the host")
+        );
+    }
+
     log::debug!("cargo_features = {:?}", target.cargo_features());
 
-    // Connect to the target now. Fail-fast so that the user can divert
-    // attention without risking wasting time.
-    let probe = if opts.dry_run {
+    let features: Vec<String> = std::iter::once("farcri/role_target".to_owned())
+        .chain(
+            (target.cargo_features().iter())
+                .chain(opts.transport.cargo_features())
+                .map(|f| format!("farcri/{}", f)),
+        )
+        .chain(if arch.has_fpu() {
+            Some("farcri/fpu".to_owned())
+        } else {
+            None
+        })
+        .chain(if opts.frame_trace {
+            Some("farcri/frame_trace".to_owned())
+        } else {
+            None
+        })
+        .chain(if opts.rtt_bulk_channel {
+            Some("farcri/rtt_bulk_channel".to_owned())
+        } else {
+            None
+        })
+        .chain(if opts.panic_reset {
+            Some("farcri/panic_reset".to_owned())
+        } else {
+            None
+        })
+        .chain(opts.features.iter().cloned())
+        .chain(std::iter::once(
+            match opts.log_level {
+                LogLevel::Off => "farcri/max_level_off",
+                LogLevel::Error => "farcri/max_level_error",
+                LogLevel::Warn => "farcri/max_level_warn",
+                LogLevel::Info => "farcri/max_level_info",
+                LogLevel::Debug => "farcri/max_level_debug",
+                LogLevel::Trace => "farcri/max_level_trace",
+            }
+            .to_owned(),
+        ))
+        .collect();
+
+    let build_descriptor = builddesc::BuildDescriptor {
+        features: features.clone(),
+        rustflags: rustflags.clone(),
+        target_triple: arch_opt.target_triple.map(str::to_owned),
+        build_std,
+    };
+    let encoded_build_descriptor = build_descriptor.encode();
+    if encoded_build_descriptor.is_none() && opts.elf.is_none() {
+        log::warn!(
+            "The build descriptor is too long to embed in the target \
+            executable. Skipping the build descriptor check."
+        );
+    }
+
+    let build_config = vec![
+        rustflags.clone(),
+        format!("{:?}", arch_opt.target_triple),
+        format!("{:?}", target.cargo_features()),
+        format!("{:?}", opts.transport),
+        format!("{:?}", opts.frame_trace),
+        format!("{:?}", opts.rtt_bulk_channel),
+        format!("{:?}", opts.panic_reset),
+        format!("{:?}", opts.no_memory_x),
+        format!("{:?}", opts.features),
+        format!("{:?}", build_std),
+        format!("{:?}", opts.log_level),
+        format!("{:?}", opts.cargo),
+    ];
+
+    Ok(BuildPlan {
+        arch,
+        target_triple: arch_opt.target_triple,
+        rustflags,
+        build_std,
+        features,
+        build_descriptor,
+        encoded_build_descriptor,
+        build_config,
+    })
+}
+
+/// Get the target executable: the prebuilt one specified by `--farcri-elf`,
+/// the one built by a previous run if it's still valid, or a new one built by
+/// Cargo.
+async fn obtain_executable(
+    opts: &Opts,
+    target_name: &str,
+    farcri_dir: &std::path::Path,
+    plan: &BuildPlan,
+    build_setup: &dyn targets::BuildSetup,
+) -> Result<crate::cargo::CompiledExecutable> {
+    if let Some(elf) = &opts.elf {
+        tokio::fs::metadata(elf)
+            .await
+            .with_context(|| format!("Failed to access '{}'.", elf.display()))?;
+        log::info!("Using the prebuilt executable '{}'", elf.display());
+        return Ok(crate::cargo::CompiledExecutable::prebuilt(elf.clone()));
+    }
+
+    let image_stamp = stamp::compute(plan.build_config.clone())
+        .await
+        .context("Failed to compute the image stamp.")?;
+    log::debug!("image_stamp = {:?}", image_stamp);
+
+    let build_cache = if opts.no_build_cache {
         None
     } else {
-        Some(
-            target
-                .connect()
-                .await
-                .context("Failed to connect to the target.")?,
-        )
+        let path = farcri_dir.join(buildcache::FILE_NAME);
+        match buildcache::BuildCache::open(path, target_name, &image_stamp).await {
+            Ok(x) => Some(x),
+            Err(e) => {
+                log::warn!("Not using the build cache: {:?}", e);
+                None
+            }
+        }
+    };
+    let cached_exe = match &build_cache {
+        Some(build_cache) => build_cache.lookup().await,
+        None => None,
     };
 
+    if let Some(exe) = cached_exe {
+        log::info!(
+            "Reusing the target executable '{}' built by a previous run",
+            exe.path.display()
+        );
+        return Ok(exe);
+    }
+
     log::info!("Building the target executable");
-    let exe = crate::cargo::compile_self(|cmd| {
-        cmd.arg("--features=farcri/role_target")
-            .args(
-                target
-                    .cargo_features()
-                    .iter()
-                    .map(|f| format!("--features=farcri/{}", f)),
-            )
-            .arg(match opts.log_level {
-                LogLevel::Off => "--features=farcri/max_level_off",
-                LogLevel::Error => "--features=farcri/max_level_error",
-                LogLevel::Warn => "--features=farcri/max_level_warn",
-                LogLevel::Info => "--features=farcri/max_level_info",
-                LogLevel::Debug => "--features=farcri/max_level_debug",
-                LogLevel::Trace => "--features=farcri/max_level_trace",
-            })
-            .arg("--target")
-            .arg(&arch_opt.target_triple)
-            .args(if target_features.is_empty() {
-                None
-            } else {
+    let exe = crate::cargo::compile_self(opts.cargo.as_deref(), |cmd| {
+        cmd.args(plan.features.iter().map(|f| format!("--features={}", f)))
+            .args((plan.target_triple.iter()).map(|triple| format!("--target={}", triple)))
+            .args(if plan.build_std {
                 log::debug!("Specifying `-Zbuild-std=core` because of a custom target feature set");
                 Some("-Zbuild-std=core")
+            } else {
+                None
             })
-            .env("RUSTFLAGS", &rustflags)
+            .env("RUSTFLAGS", &plan.rustflags)
+            .env(protocol::IMAGE_STAMP_SYMBOL, &image_stamp)
+            .env(
+                protocol::BUILD_DESCRIPTOR_ENV,
+                plan.encoded_build_descriptor.as_deref().unwrap_or(""),
+            )
             .envs(build_setup.build_envs())
-    });
+    })
+    .context("Failed to build the target executable.")?;
 
-    let mut probe = if let Some(probe) = probe {
-        probe
+    if let Some(build_cache) = &build_cache {
+        if let Err(e) = build_cache.store(&exe).await {
+            log::warn!("Failed to update the build cache: {:?}", e);
+        }
+    }
+    Ok(exe)
+}
+
+/// Check the target executable's build profile, build descriptor, heap
+/// allocator, and memory layout. Returns the build descriptor found in the
+/// executable.
+async fn check_executable(
+    opts: &Opts,
+    plan: &BuildPlan,
+    exe: &crate::cargo::CompiledExecutable,
+    build_setup: &dyn targets::BuildSetup,
+) -> Result<Option<builddesc::BuildDescriptor>> {
+    if let Some(profile) = &exe.profile {
+        log::info!("Build profile: {}", profile);
+        if !profile.is_release_like() {
+            if opts.require_release {
+                bail!(
+                    "The benchmark code was not built with a release-like profile \
+                    ({}), and `--farcri-require-release` is present.",
+                    profile
+                );
+            }
+            log::warn!(
+                "The benchmark code was not built with a release-like profile \
+                ({}). The measurements will not be representative of \
+                optimized code. Check `[profile.bench]` and profile \
+                overrides in `Cargo.toml`.",
+                profile
+            );
+        }
+    } else if opts.elf.is_none() {
+        log::warn!("Cargo did not report the build profile of the benchmark code");
+    }
+
+    // A prebuilt executable was built by someone else, so there's nothing to
+    // compare its build descriptor with
+    let expected_build_descriptor = if opts.elf.is_none() && plan.encoded_build_descriptor.is_some()
+    {
+        Some(&plan.build_descriptor)
     } else {
-        log::warn!("Exiting now because a `--farcry-dry-run` option is present.");
-        return Ok(());
+        None
     };
-
-    let target_stream = probe
-        .program_and_get_output(&exe)
+    let build_descriptor = builddesc::check(&exe.path, expected_build_descriptor)
         .await
-        .context("Failed to load the benchmark application to the target.")?;
+        .context("Failed to check the build descriptor of the target executable.")?;
+    if let (Some(build_descriptor), Some(_)) = (&build_descriptor, &opts.elf) {
+        log::info!(
+            "The prebuilt executable was built with the Cargo features: {}",
+            crate::utils::CommaSeparated(&build_descriptor.features)
+        );
+    }
 
-    let mut target_link = targetlink::TargetLink::new(target_stream).await?;
+    // The host target uses `std`, which always links an allocator
+    if plan.arch != targets::Arch::Host {
+        let diagnostic = alloccheck::check(&exe.path, &exe.crates)
+            .await
+            .context("Failed to check the target executable for a heap allocator.")?;
+        if let Some(diagnostic) = diagnostic {
+            if opts.forbid_alloc {
+                bail!("{}\n`--farcri-forbid-alloc` is present.", diagnostic);
+            }
+            log::warn!("{}", diagnostic);
+        }
+    }
 
-    // Send the greeting message
-    let mode = if opts.bench {
-        protocol::Mode::Benchmark
-    } else {
+    if let Some(memory_x) = build_setup.memory_x() {
+        let diagnostic = memcheck::check(&exe.path, &memory_x, &exe.library_paths)
+            .await
+            .context("Failed to check the memory layout of the target executable.")?;
+        if let Some(diagnostic) = diagnostic {
+            bail!("{}", diagnostic);
+        }
+    }
+
+    Ok(build_descriptor)
+}
+
+/// Determine the execution mode and the benchmark configuration overrides
+/// to send in the greeting message.
+fn mode_and_overrides(opts: &Opts) -> (protocol::Mode, protocol::BenchmarkConfigOverrides) {
+    // Like Criterion.rs, `--list` takes precedence over `--test`, which takes
+    // precedence over `--profile-time` and `--bench`
+    let mut bench_config_overrides = protocol::BenchmarkConfigOverrides {
+        sample_size: opts.sample_size,
+        ..Default::default()
+    };
+    let mode = if opts.list || opts.list_static {
+        protocol::Mode::List
+    } else if opts.test {
         protocol::Mode::Test
+    } else if let Some(profile_time) = opts.profile_time {
+        bench_config_overrides.measurement_time =
+            Some(protocol::Duration::from_nanos((profile_time * 1e9) as u64));
+        protocol::Mode::Profile
+    } else {
+        protocol::Mode::Benchmark
+    };
+    (mode, bench_config_overrides)
+}
+
+/// Run the Target program with cargo-criterion listening on `port` as the
+/// front-end.
+async fn run_cc_frontend(
+    opts: &Opts,
+    run: &mut Run<'_>,
+    probe: &mut dyn targets::DebugProbe,
+    port: u16,
+    machine_unit: Option<formatter::MachineUnit>,
+) -> Result<()> {
+    let mut target_link = run.start(&mut *probe).await?;
+
+    log::info!("Using the CC front-end. Connecting to localhost:{}", port);
+
+    let cc_stream = tokio::net::TcpStream::connect(("localhost", port))
+        .await
+        .with_context(|| format!("Failed to connect to localhost:{}.", port))?;
+
+    if opts.save_json.is_some() {
+        log::warn!("Ignoring `--farcri-save-json` because cargo-criterion saves the results");
+    }
+    if opts.results.is_some() {
+        log::warn!("Ignoring `--farcri-results` because cargo-criterion saves the results");
+    }
+    if opts.fail_on_regression {
+        log::warn!(
+            "Ignoring `--farcri-fail-on-regression` because cargo-criterion \
+            analyzes the results"
+        );
+    }
+    if opts.keep_going {
+        log::warn!(
+            "Ignoring `--farcri-keep-going` because cargo-criterion can't \
+            handle a benchmark that never completes"
+        );
+    }
+    if opts.core_clock.is_some() {
+        log::warn!(
+            "Ignoring `--farcri-core-clock` because cargo-criterion \
+            presents the results"
+        );
+    }
+
+    let base_formatter = run.value_unit.formatter();
+    let machine_unit_formatter;
+    let formatter = match machine_unit {
+        Some(unit) => {
+            machine_unit_formatter = formatter::MachineUnitFormatter::new(base_formatter, unit);
+            &machine_unit_formatter as &dyn formatter::ValueFormatter
+        }
+        None => base_formatter,
+    };
+
+    let result = ccfront::run_frontend(&mut target_link, cc_stream, formatter).await;
+    let suite_budget = finish_run(target_link, run.value_unit, None).await;
+    if let Err(e) = &result {
+        reset_on_timeout(&mut *probe, run, e).await;
+    }
+    result?;
+    report_budget(suite_budget.as_ref(), opts.budget_strict)
+}
+
+/// Run the Target program with the dumb front-end, which presents the
+/// results by itself. The console report's history is stored in
+/// `report_dir`.
+async fn run_dumb_frontend(
+    opts: &Opts,
+    run: &mut Run<'_>,
+    probe: &mut dyn targets::DebugProbe,
+    json_writer: Option<&jsonout::JsonWriter>,
+    footprints: Option<&footprint::FlashFootprints>,
+    report_dir: std::path::PathBuf,
+) -> Result<()> {
+    let value_unit = run.value_unit;
+    let mut results_file = match &opts.results {
+        Some(path) => Some(resultsfile::ResultsFile::open(path, opts.results_resume).await?),
+        None => None,
+    };
+    let mut console_report = console_report::ConsoleReport::new(report_dir);
+    let mut frontend = dumbfront::Frontend::new(
+        json_writer,
+        results_file.as_mut(),
+        Some(&mut console_report),
+        footprints,
+        value_unit.formatter(),
+    );
+    if matches!(run.config.mode, protocol::Mode::Test) {
+        frontend.enable_test_report();
+    }
+    if opts.list_static {
+        frontend.enable_bare_list();
+    }
+    if opts.stability_details {
+        frontend.enable_stability_details();
+    }
+    match value_unit {
+        targets::ValueUnit::Cycles => frontend.enable_rates(opts.core_clock),
+        targets::ValueUnit::Nanoseconds if opts.core_clock.is_some() => {
+            log::warn!(
+                "Ignoring `--farcri-core-clock` because the target doesn't \
+                measure cycles"
+            );
+        }
+        targets::ValueUnit::Nanoseconds => {}
+    }
+
+    let (result, faulted, suite_budget) = run_resuming(
+        run,
+        &mut *probe,
+        &mut frontend,
+        json_writer,
+        opts.keep_going,
+    )
+    .await;
+    result?;
+    let test_result = frontend.finish();
+    report_faults(&faulted).and(test_result)?;
+    report_budget(suite_budget.as_ref(), opts.budget_strict)?;
+
+    if opts.fail_on_regression {
+        report_regressions(console_report.regressions())?;
+    }
+    Ok(())
+}
+
+/// The parts of a run shared by its sessions with the Target program. A run
+/// has more than one session if the Target program is restarted after a
+/// fault (`--farcri-keep-going`).
+struct Run<'a> {
+    exe: &'a crate::cargo::CompiledExecutable,
+    transport: targets::Transport,
+    simulate: bool,
+    value_unit: targets::ValueUnit,
+    config: protocol::TargetConfig<String>,
+    bench_timeout: Option<std::time::Duration>,
+    measurement_flag: Option<targets::MeasurementFlag>,
+    calibration_store: Option<calibration::CalibrationStore>,
+    calibration: calibration::Calibration,
+    /// Moved to the first session
+    suite_budget: Option<budget::SuiteBudget>,
+    /// The durations of the phases preceding the first session
+    phases: Vec<(&'static str, std::time::Duration)>,
+    /// The breakdown of loading the Target program reported by the probe
+    probe_phases: targets::PhaseLog,
+    reconnects: targets::ReconnectCounter,
+}
+
+impl Run<'_> {
+    /// Load the Target program to the target and start the first session.
+    async fn start<'p>(
+        &mut self,
+        probe: &'p mut dyn targets::DebugProbe,
+    ) -> Result<targetlink::TargetLink<targets::DynAsyncReadWrite<'p>>> {
+        let start = Instant::now();
+        let target_stream = probe
+            .program_and_get_output(self.exe, self.transport)
+            .await
+            .context("Failed to load the benchmark application to the target.")?;
+        let probe_phases = self.probe_phases.take();
+        if probe_phases.is_empty() {
+            self.phases.push(("flash and attach", start.elapsed()));
+        } else {
+            self.phases.extend(probe_phases);
+        }
+
+        let start = Instant::now();
+        let (mut target_link, loop_overhead) = start_session(
+            target_stream,
+            &self.config,
+            (!self.simulate).then(|| self.exe),
+        )
+        .await?;
+        if let Some(loop_overhead) = loop_overhead {
+            log_loop_overhead(&loop_overhead, self.value_unit, false);
+            self.calibration.loop_overhead = Some(loop_overhead);
+            if let Some(store) = &self.calibration_store {
+                if let Err(e) = store.store(&self.calibration).await {
+                    log::warn!("Failed to store the calibration: {:?}", e);
+                }
+            }
+        }
+        target_link.set_bench_timeout(self.bench_timeout);
+        target_link.set_measurement_flag(self.measurement_flag.clone());
+        target_link.set_suite_budget(self.suite_budget.take());
+        self.phases.push(("handshake", start.elapsed()));
+
+        for (name, duration) in self.phases.drain(..) {
+            target_link.timings_mut().record_phase(name, duration);
+        }
+        target_link
+            .timings_mut()
+            .record_probe_reconnects(self.reconnects.get());
+
+        Ok(target_link)
+    }
+}
+
+/// Start a run and process it with the dumb front-end. If `keep_going` is
+/// set and the Target program faults while running a benchmark, restart the
+/// Target program and resume the run from the next benchmark. Returns the
+/// IDs of the benchmarks skipped this way and the remaining suite budget.
+/// The timing breakdown of the run is saved by `json_writer` if given.
+async fn run_resuming(
+    run: &mut Run<'_>,
+    probe: &mut dyn targets::DebugProbe,
+    frontend: &mut dumbfront::Frontend<'_>,
+    json_writer: Option<&jsonout::JsonWriter>,
+    keep_going: bool,
+) -> (Result<()>, Vec<String>, Option<budget::SuiteBudget>) {
+    let mut target_link = match run.start(probe).await {
+        Ok(x) => x,
+        Err(e) => return (Err(e), Vec::new(), None),
     };
+
+    // The benchmarks skipped because the Target program faulted while
+    // running them
+    let mut faulted = Vec::new();
+
+    let result = loop {
+        let e = match frontend.run(&mut target_link).await {
+            Ok(()) => break Ok(()),
+            Err(e) => e,
+        };
+
+        let id = match target_link.progress().current() {
+            Some(id) if keep_going && e.downcast_ref::<targetlink::TargetFault>().is_some() => {
+                id.to_owned()
+            }
+            _ => break Err(e),
+        };
+        log::error!("The Target program faulted while running {}: {:?}", id, e);
+        log::info!("Skipping {} and resuming the run", id);
+        frontend.record_fault();
+        faulted.push(id);
+
+        // Restart the Target program, skipping the benchmarks that have
+        // been started so far
+        run.config.skip_benchmarks += target_link.progress().num_started();
+        run.config.feature_bits &=
+            !(protocol::FEATURE_LOOP_OVERHEAD | protocol::FEATURE_LINK_CHECK);
+        let timings = std::mem::take(target_link.timings_mut());
+        let clock = std::mem::take(target_link.clock_mut());
+        let suite_budget = target_link.take_suite_budget();
+        drop(target_link);
+
+        let target_stream = match probe
+            .reset_and_get_output(run.exe, run.transport)
+            .await
+            .context("Failed to restart the target.")
+        {
+            Ok(x) => x,
+            Err(e) => return (Err(e), faulted, suite_budget),
+        };
+        target_link =
+            match start_session(target_stream, &run.config, (!run.simulate).then(|| run.exe)).await
+            {
+                Ok((x, _)) => x,
+                Err(e) => return (Err(e), faulted, suite_budget),
+            };
+        target_link.set_bench_timeout(run.bench_timeout);
+        target_link.set_measurement_flag(run.measurement_flag.clone());
+        target_link.set_suite_budget(suite_budget);
+        *target_link.timings_mut() = timings;
+        *target_link.clock_mut() = clock;
+    };
+
+    let suite_budget = finish_run(target_link, run.value_unit, json_writer).await;
+    if let Err(e) = &result {
+        reset_on_timeout(probe, run, e).await;
+    }
+    (result, faulted, suite_budget)
+}
+
+/// Reset the target if `e` is a [`targetlink::TargetFault::BenchTimeout`]
+/// so that the benchmark doesn't keep running after the run is aborted.
+async fn reset_on_timeout(probe: &mut dyn targets::DebugProbe, run: &Run<'_>, e: &anyhow::Error) {
+    if let Some(targetlink::TargetFault::BenchTimeout { .. }) = e.downcast_ref() {
+        log::info!("Resetting the target");
+        // The restarted Target program waits for a greeting, which never
+        // comes
+        if let Err(e) = probe.reset_and_get_output(run.exe, run.transport).await {
+            log::warn!("Failed to reset the target: {:?}", e);
+        }
+    }
+}
+
+/// Print the statistics of a finished run and save its timing breakdown by
+/// `json_writer` if given. Returns the remaining suite budget.
+async fn finish_run<Stream: AsyncRead + AsyncWrite>(
+    mut target_link: targetlink::TargetLink<Stream>,
+    value_unit: targets::ValueUnit,
+    json_writer: Option<&jsonout::JsonWriter>,
+) -> Option<budget::SuiteBudget> {
+    target_link.timings().print();
+    if let Some(json_writer) = json_writer {
+        if let Err(e) = json_writer.write_timings(target_link.timings()).await {
+            log::warn!("Failed to save the timing breakdown: {:?}", e);
+        }
+    }
+    target_link.clock().print(value_unit.formatter());
+    target_link.take_suite_budget()
+}
+
+/// Send a greeting message to the Target program through a newly opened
+/// stream and check its reply. The Target program's image stamp is checked
+/// against `exe` unless it's `None` (the simulated target). Returns the loop
+/// overhead if requested by `config`.
+async fn start_session<Stream: AsyncRead + AsyncWrite>(
+    target_stream: Stream,
+    config: &protocol::TargetConfig<String>,
+    exe: Option<&crate::cargo::CompiledExecutable>,
+) -> Result<(
+    targetlink::TargetLink<Stream>,
+    Option<protocol::LoopOverhead>,
+)> {
+    let mut target_link = targetlink::TargetLink::new(target_stream).await?;
+
     let greeting = protocol::DownstreamMessage::Greeting {
-        _unused: Default::default(),
-        mode,
+        config: config.clone(),
     };
     log::info!("Options: {:?}", greeting);
     target_link
@@ -218,25 +1828,584 @@ async fn main_inner() -> Result<()> {
         .await
         .context("Failed to send the greeting message.")?;
 
-    if let Ok(port) = std::env::var("CARGO_CRITERION_PORT") {
-        let port: u16 = port.parse().with_context(|| {
-            format!(
-                "Could not parse the value of `CARGO_CRITERION_PORT` ({:?})",
-                port
-            )
-        })?;
+    // Make sure the target is running the executable we loaded
+    let (image_stamp, mode, loop_overhead, capacities) =
+        match time::timeout(targetlink::REPLY_TIMEOUT, target_link.recv())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out while waiting for a greeting reply."))?
+            .context("Failed to receive a greeting reply.")?
+        {
+            protocol::UpstreamMessage::GreetingReply {
+                image_stamp,
+                mode,
+                loop_overhead,
+                capacities,
+            } => (image_stamp, mode, loop_overhead, capacities),
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Unexpected response to the greeting message: {:?}",
+                    other
+                )
+                .context(error::ProtocolViolation))
+            }
+        };
+    if let Some(exe) = exe {
+        stamp::check(&exe.path, &image_stamp).await?;
+    }
+    check_mode(config.mode, mode)?;
 
-        log::info!("Using the CC front-end. Connecting to localhost:{}", port);
+    log::debug!("capacities = {:?}", capacities);
+    for warning in capacity::check_config(&capacities, config) {
+        log::warn!("{}", warning);
+    }
 
-        let cc_stream = tokio::net::TcpStream::connect(("localhost", port))
+    if config.feature_bits & protocol::FEATURE_LINK_CHECK != 0 {
+        let report = linkcheck::run(&mut target_link, &capacities)
             .await
-            .with_context(|| format!("Failed to connect to localhost:{}.", port))?;
+            .context("The link check failed.")?;
+        log::info!(
+            "Link: {:.2} ms round-trip latency, {:.1} KiB/s",
+            report.latency.as_secs_f64() * 1e3,
+            report.throughput / 1024.0
+        );
+        if let Some(warning) = report.warning() {
+            log::warn!("{}", warning);
+        }
 
-        ccfront::run_frontend(target_link, cc_stream).await?;
-    } else {
-        log::info!("`CARGO_CRITERION_PORT` is not set; using the dumb front-end");
-        dumbfront::run_frontend(target_link).await?;
+        target_link
+            .send(&protocol::DownstreamMessage::Continue)
+            .await
+            .context("Failed to end the link check.")?;
     }
 
-    Ok(())
+    Ok((target_link, loop_overhead))
+}
+
+/// Check that the Target program runs in the mode requested by the greeting
+/// message.
+fn check_mode(requested: protocol::Mode, reported: protocol::Mode) -> Result<()> {
+    if requested == reported {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "The Target program reported running in mode {:?}, but mode {:?} was \
+        requested. This indicates a version skew between the Proxy program and \
+        the Target program or a bug in the message encoding.",
+        reported,
+        requested
+    )
+    .context(error::ProtocolViolation))
+}
+
+/// Report the loop overhead measured by the Target program (`stored`: by a
+/// previous run).
+fn log_loop_overhead(
+    loop_overhead: &protocol::LoopOverhead,
+    value_unit: targets::ValueUnit,
+    stored: bool,
+) {
+    let per_iter = loop_overhead.value as f64 / loop_overhead.iters as f64;
+    log::info!(
+        "loop overhead: {}/iter{}",
+        value_unit.formatter().format_value(per_iter).trim_start(),
+        if stored {
+            " (measured by a previous run; `--farcri-recalibrate` to measure again)"
+        } else {
+            ""
+        }
+    );
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::thread;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::{bencher::Criterion, target::loopback};
+
+    pub(crate) fn loopback_config(
+        mode: protocol::Mode,
+        bench_config_overrides: protocol::BenchmarkConfigOverrides,
+        feature_bits: u32,
+    ) -> protocol::TargetConfig<String> {
+        protocol::TargetConfig {
+            protocol_version: protocol::PROTOCOL_VERSION,
+            mode,
+            bench_config_overrides,
+            name_filters: Default::default(),
+            frequencies: Default::default(),
+            skip_benchmarks: 0,
+            feature_bits,
+        }
+    }
+
+    /// Start the Target program on a separate thread and send a greeting
+    /// to it.
+    pub(super) async fn start_loopback(
+        mode: protocol::Mode,
+        bench_config_overrides: protocol::BenchmarkConfigOverrides,
+        feature_bits: u32,
+        groups: impl FnOnce(&mut Criterion) + Send + 'static,
+    ) -> (targetlink::TargetLink<TcpStream>, thread::JoinHandle<()>) {
+        start_loopback_with_config(
+            loopback_config(mode, bench_config_overrides, feature_bits),
+            groups,
+        )
+        .await
+    }
+
+    async fn start_loopback_with_config(
+        config: protocol::TargetConfig<String>,
+        groups: impl FnOnce(&mut Criterion) + Send + 'static,
+    ) -> (targetlink::TargetLink<TcpStream>, thread::JoinHandle<()>) {
+        start_loopback_on(config, |stream| stream, |stream| stream, groups).await
+    }
+
+    /// [`start_loopback_with_config`] over the streams made by `target_end`
+    /// and `proxy_end` from the two ends of a TCP connection
+    pub(super) async fn start_loopback_on<T, S>(
+        config: protocol::TargetConfig<String>,
+        target_end: impl FnOnce(std::net::TcpStream) -> T + Send + 'static,
+        proxy_end: impl FnOnce(TcpStream) -> S,
+        groups: impl FnOnce(&mut Criterion) + Send + 'static,
+    ) -> (targetlink::TargetLink<S>, thread::JoinHandle<()>)
+    where
+        T: std::io::Read + std::io::Write + 'static,
+        S: AsyncRead + AsyncWrite,
+    {
+        let mut listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let target_thread = thread::spawn(move || {
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            loopback::run(target_end(stream), groups);
+        });
+        let (stream, _): (TcpStream, _) = listener.accept().await.unwrap();
+
+        let mut target_link = targetlink::TargetLink::new(proxy_end(stream))
+            .await
+            .unwrap();
+        target_link
+            .send(&protocol::DownstreamMessage::Greeting { config })
+            .await
+            .unwrap();
+
+        (target_link, target_thread)
+    }
+
+    /// Run the Target program on a separate thread and collect the messages
+    /// it sends, responding to them in the same way as the front-ends.
+    pub(crate) async fn run_loopback(
+        mode: protocol::Mode,
+        bench_config_overrides: protocol::BenchmarkConfigOverrides,
+        feature_bits: u32,
+        groups: impl FnOnce(&mut Criterion) + Send + 'static,
+    ) -> Vec<protocol::UpstreamMessage<String, Vec<u64>>> {
+        run_loopback_with_config(
+            loopback_config(mode, bench_config_overrides, feature_bits),
+            groups,
+        )
+        .await
+    }
+
+    /// The benchmark settings used by tests that run benchmarks but don't
+    /// care about the measurements. They keep the tests fast.
+    pub(crate) fn fast_overrides() -> protocol::BenchmarkConfigOverrides {
+        protocol::BenchmarkConfigOverrides {
+            measurement_time: Some(protocol::Duration::from_nanos(10_000_000)),
+            sample_size: Some(10),
+            warm_up_time: Some(protocol::Duration::from_nanos(1_000_000)),
+            ..Default::default()
+        }
+    }
+
+    /// [`run_loopback`] in the benchmark mode with [`fast_overrides`]
+    pub(crate) async fn run_fast_loopback(
+        groups: impl FnOnce(&mut Criterion) + Send + 'static,
+    ) -> Vec<protocol::UpstreamMessage<String, Vec<u64>>> {
+        run_loopback(protocol::Mode::Benchmark, fast_overrides(), 0, groups).await
+    }
+
+    /// Run the Target program on a separate thread until it stops talking,
+    /// e.g., because it panicked. Returns the messages it sent and the
+    /// result of the thread.
+    pub(crate) async fn run_loopback_until_silent(
+        mode: protocol::Mode,
+        groups: impl FnOnce(&mut Criterion) + Send + 'static,
+    ) -> (
+        Vec<protocol::UpstreamMessage<String, Vec<u64>>>,
+        thread::Result<()>,
+    ) {
+        let (mut target_link, target_thread) =
+            start_loopback(mode, Default::default(), 0, groups).await;
+
+        let mut messages = Vec::new();
+        while let Ok(msg) = time::timeout(targetlink::REPLY_TIMEOUT, target_link.recv())
+            .await
+            .unwrap()
+        {
+            if let protocol::UpstreamMessage::GetInstant = msg {
+                target_link.send_instant().await.unwrap();
+            }
+            messages.push(msg);
+        }
+
+        (messages, target_thread.join())
+    }
+
+    pub(crate) async fn run_loopback_with_config(
+        config: protocol::TargetConfig<String>,
+        groups: impl FnOnce(&mut Criterion) + Send + 'static,
+    ) -> Vec<protocol::UpstreamMessage<String, Vec<u64>>> {
+        let (mut target_link, target_thread) = start_loopback_with_config(config, groups).await;
+
+        let mut messages = Vec::new();
+        loop {
+            let msg = time::timeout(targetlink::REPLY_TIMEOUT, target_link.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            match &msg {
+                protocol::UpstreamMessage::GetInstant => {
+                    target_link.send_instant().await.unwrap();
+                    continue;
+                }
+                protocol::UpstreamMessage::MeasurementComplete { .. }
+                | protocol::UpstreamMessage::BenchmarkFailed { .. }
+                | protocol::UpstreamMessage::FinishedBenchmarkGroup => {
+                    target_link
+                        .send(&protocol::DownstreamMessage::Continue)
+                        .await
+                        .unwrap();
+                }
+                protocol::UpstreamMessage::End => break,
+                _ => {}
+            }
+            messages.push(msg);
+        }
+
+        target_thread.join().unwrap();
+        messages
+    }
+
+    #[test]
+    fn frequency_tags() {
+        let tagged = |function_id: Option<&str>, hz| {
+            let mut id = protocol::RawBenchmarkId {
+                group_id: "group".to_owned(),
+                function_id: function_id.map(str::to_owned),
+                value_str: Some("42".to_owned()),
+                throughput: None,
+            };
+            tag_frequency(&mut id, hz);
+            id.to_string()
+        };
+        assert_eq!(tagged(Some("sort"), 84_000_000), "group/sort @ 84 MHz/42");
+        assert_eq!(tagged(None, 16_000_000), "group/16 MHz/42");
+    }
+
+    #[tokio::test]
+    async fn link_check_echoes() {
+        let (mut target_link, target_thread) = start_loopback(
+            protocol::Mode::List,
+            Default::default(),
+            protocol::FEATURE_LINK_CHECK,
+            |c| {
+                c.bench_function("a", |b| b.iter(|| ()));
+            },
+        )
+        .await;
+
+        let capacities = match target_link.recv().await.unwrap() {
+            protocol::UpstreamMessage::GreetingReply { capacities, .. } => capacities,
+            other => panic!("unexpected message: {:?}", other),
+        };
+        let report = linkcheck::run(&mut target_link, &capacities).await.unwrap();
+        assert!(report.throughput > 0.0, "{:?}", report);
+
+        // The Target program should proceed to the benchmarks after
+        // `Continue`
+        target_link
+            .send(&protocol::DownstreamMessage::Continue)
+            .await
+            .unwrap();
+        let mut listed = Vec::new();
+        loop {
+            match target_link.recv().await.unwrap() {
+                protocol::UpstreamMessage::ListedBenchmark { id } => listed.push(id.to_string()),
+                protocol::UpstreamMessage::FinishedBenchmarkGroup => target_link
+                    .send(&protocol::DownstreamMessage::Continue)
+                    .await
+                    .unwrap(),
+                protocol::UpstreamMessage::End => break,
+                _ => {}
+            }
+        }
+        assert_eq!(listed, ["a"]);
+        target_thread.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn bench_timeout_exceeded() {
+        let (mut target_link, target_thread) =
+            start_loopback(protocol::Mode::Test, Default::default(), 0, |c| {
+                c.bench_function("slow", |b| {
+                    b.iter(|| thread::sleep(std::time::Duration::from_secs(1)))
+                });
+            })
+            .await;
+        target_link.set_bench_timeout(Some(time::Duration::from_millis(100)));
+
+        let error = loop {
+            match target_link.recv_timeout(targetlink::REPLY_TIMEOUT).await {
+                Ok(_) => {}
+                Err(e) => break e,
+            }
+        };
+        match error.downcast_ref::<targetlink::TargetFault>() {
+            Some(targetlink::TargetFault::BenchTimeout { id, .. }) => assert_eq!(id, "slow"),
+            _ => panic!("unexpected error: {:?}", error),
+        }
+
+        // The Target program fails when it finds the link closed
+        drop(target_link);
+        let _ = target_thread.join();
+    }
+
+    #[tokio::test]
+    async fn panic_reset_reported() {
+        // Test mode would catch the panic
+        let (mut target_link, target_thread) =
+            start_loopback(protocol::Mode::Benchmark, Default::default(), 0, |c| {
+                c.bench_function("panicky", |b| {
+                    b.iter(|| {
+                        // What the panic handler of `panic_reset` does
+                        // before resetting the target
+                        loopback::report_panic(&"x".repeat(200));
+                        panic!("reset");
+                    })
+                });
+            })
+            .await;
+
+        let mut frontend = dumbfront::Frontend::new(
+            None,
+            None,
+            None,
+            None,
+            &crate::proxy::formatter::CyclesFormatter,
+        );
+        let error = frontend.run(&mut target_link).await.unwrap_err();
+        match error.downcast_ref::<targetlink::TargetFault>() {
+            Some(targetlink::TargetFault::Panicked { message }) => {
+                assert_eq!(*message, "x".repeat(protocol::MAX_PANIC_MESSAGE_LEN))
+            }
+            _ => panic!("unexpected error: {:?}", error),
+        }
+        assert_eq!(target_link.progress().current(), Some("panicky"));
+        target_thread.join().unwrap_err();
+    }
+
+    fn faulty_benchmarks(c: &mut Criterion) {
+        let mut group = c.benchmark_group("group");
+        group.bench_function("a", |b| b.iter(|| ()));
+        group.bench_function("b", |b| b.iter(|| panic!("boom")));
+        group.bench_function("c", |b| b.iter(|| ()));
+    }
+
+    #[tokio::test]
+    async fn resume_after_fault() {
+        let mut config = loopback_config(protocol::Mode::Benchmark, fast_overrides(), 0);
+
+        // The Target program faults while running `group/b`
+        let (mut target_link, target_thread) =
+            start_loopback_with_config(config.clone(), faulty_benchmarks).await;
+        let mut frontend = dumbfront::Frontend::new(
+            None,
+            None,
+            None,
+            None,
+            &crate::proxy::formatter::CyclesFormatter,
+        );
+        let e = frontend.run(&mut target_link).await.unwrap_err();
+        assert!(
+            e.downcast_ref::<targetlink::TargetFault>().is_some(),
+            "{:?}",
+            e
+        );
+        assert_eq!(target_link.progress().current(), Some("group/b"));
+        assert_eq!(target_link.progress().num_started(), 2);
+        target_thread.join().unwrap_err();
+
+        // Resume from `group/c`
+        config.skip_benchmarks = target_link.progress().num_started();
+        let messages = run_loopback_with_config(config, faulty_benchmarks).await;
+        let events: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                protocol::UpstreamMessage::SkippingBenchmark { id, .. } => {
+                    Some(format!("skip {}", id))
+                }
+                protocol::UpstreamMessage::RunningBenchmark { id } => Some(format!("run {}", id)),
+                protocol::UpstreamMessage::MeasurementComplete { .. } => Some("done".to_owned()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            events,
+            ["skip group/a", "skip group/b", "run group/c", "done"]
+        );
+    }
+
+    /// Drive a whole benchmark session through both ends of the link,
+    /// checking each message in the order the Target program sends it.
+    #[tokio::test]
+    async fn protocol_round_trip() {
+        let (mut target_link, target_thread) =
+            start_loopback(protocol::Mode::Benchmark, fast_overrides(), 0, |c| {
+                let mut group = c.benchmark_group("round_trip");
+                group.throughput(crate::Throughput::Bytes(4));
+                group.bench_with_input(crate::BenchmarkId::new(&"f", &42), &42u32, |b, &x| {
+                    b.iter(|| crate::black_box(x))
+                });
+                group.finish();
+            })
+            .await;
+
+        // Receive the next message, answering the clock queries and skipping
+        // the progress reports, which depend on timing
+        async fn next(
+            target_link: &mut targetlink::TargetLink<TcpStream>,
+        ) -> protocol::UpstreamMessage<String, Vec<u64>> {
+            loop {
+                let msg = time::timeout(targetlink::REPLY_TIMEOUT, target_link.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                match msg {
+                    protocol::UpstreamMessage::GetInstant => {
+                        target_link.send_instant().await.unwrap()
+                    }
+                    protocol::UpstreamMessage::WarmupProgress { .. }
+                    | protocol::UpstreamMessage::Progress { .. } => {}
+                    msg => return msg,
+                }
+            }
+        }
+        let check_id = |id: protocol::RawBenchmarkId<String>| {
+            assert_eq!(id.to_string(), "round_trip/f/42");
+            assert_eq!(id.throughput, Some(protocol::Throughput::Bytes(4)));
+        };
+
+        // Greeting
+        match next(&mut target_link).await {
+            protocol::UpstreamMessage::GreetingReply {
+                mode,
+                loop_overhead,
+                capacities,
+                ..
+            } => {
+                assert_eq!(mode, protocol::Mode::Benchmark);
+                assert!(loop_overhead.is_none());
+                assert!(capacities.max_samples >= 10, "{:?}", capacities);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // `BeginningBenchmarkGroup`
+        match next(&mut target_link).await {
+            protocol::UpstreamMessage::BeginningBenchmarkGroup { group } => {
+                assert_eq!(group, "round_trip");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // `RunningBenchmark`
+        match next(&mut target_link).await {
+            protocol::UpstreamMessage::RunningBenchmark { id } => check_id(id),
+            other => panic!("{:?}", other),
+        }
+
+        // `BeginningBenchmark`
+        match next(&mut target_link).await {
+            protocol::UpstreamMessage::BeginningBenchmark { id } => check_id(id),
+            other => panic!("{:?}", other),
+        }
+
+        // `Warmup`
+        match next(&mut target_link).await {
+            protocol::UpstreamMessage::Warmup {
+                warm_up_goal_duration,
+            } => assert_eq!(warm_up_goal_duration.as_nanos(), 1_000_000),
+            other => panic!("{:?}", other),
+        }
+
+        // `MeasurementStart`
+        let num_iters = match next(&mut target_link).await {
+            protocol::UpstreamMessage::MeasurementStart {
+                warm_up_iter_count,
+                num_samples,
+                num_iters,
+                ..
+            } => {
+                assert!(warm_up_iter_count > 0);
+                assert_eq!(num_samples, 10);
+                num_iters
+            }
+            other => panic!("{:?}", other),
+        };
+
+        // `MeasurementComplete`
+        match next(&mut target_link).await {
+            protocol::UpstreamMessage::MeasurementComplete {
+                num_iters_per_sample,
+                values,
+                benchmark_config,
+            } => {
+                assert_eq!(num_iters_per_sample * 10, num_iters);
+                assert_eq!(values.len(), 10);
+                assert_eq!(benchmark_config.sample_size, 10);
+                assert_eq!(
+                    Some(benchmark_config.measurement_time),
+                    fast_overrides().measurement_time
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+        target_link
+            .send(&protocol::DownstreamMessage::Continue)
+            .await
+            .unwrap();
+
+        // `FinishedBenchmarkGroup`
+        match next(&mut target_link).await {
+            protocol::UpstreamMessage::FinishedBenchmarkGroup => {}
+            other => panic!("{:?}", other),
+        }
+        target_link
+            .send(&protocol::DownstreamMessage::Continue)
+            .await
+            .unwrap();
+
+        // `End`
+        match next(&mut target_link).await {
+            protocol::UpstreamMessage::End => {}
+            other => panic!("{:?}", other),
+        }
+
+        target_thread.join().unwrap();
+    }
+
+    #[test]
+    fn mode_mismatch() {
+        assert!(check_mode(protocol::Mode::Test, protocol::Mode::Test).is_ok());
+
+        let e = check_mode(protocol::Mode::Test, protocol::Mode::Benchmark).unwrap_err();
+        assert!(e.downcast_ref::<error::ProtocolViolation>().is_some());
+        assert!(
+            format!("{:?}", e).contains("mode Benchmark, but mode Test was requested"),
+            "{:?}",
+            e
+        );
+    }
 }
@@ -0,0 +1,105 @@
+//! Tracks how far the Target program got, so that a run can be resumed
+//! after a fault (`--farcri-keep-going`)
+use std::{borrow::Borrow, time::Instant};
+
+use crate::bencher::protocol;
+
+#[derive(Debug, Default)]
+pub(super) struct Progress {
+    /// The number of benchmarks the Target program started running
+    num_started: u32,
+    /// The benchmark the Target program is running, if any, and when it
+    /// started
+    current: Option<(String, Instant)>,
+}
+
+impl Progress {
+    /// Record a message received at `at`.
+    pub(super) fn record_message<Str: Borrow<str>, Values>(
+        &mut self,
+        msg: &protocol::UpstreamMessage<Str, Values>,
+        at: Instant,
+    ) {
+        use protocol::UpstreamMessage::*;
+        match msg {
+            RunningBenchmark { id } => {
+                self.num_started += 1;
+                self.current = Some((id.to_string(), at));
+            }
+            // A benchmark is considered to be running until the next one
+            // starts or its group finishes because there's no message
+            // marking the end of a benchmark in Test mode
+            FinishedBenchmarkGroup | End => {
+                self.current = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Get the number of benchmarks the Target program started running,
+    /// including the current one.
+    pub(super) fn num_started(&self) -> u32 {
+        self.num_started
+    }
+
+    /// Get the ID of the benchmark the Target program is running.
+    pub(super) fn current(&self) -> Option<&str> {
+        self.current.as_ref().map(|(id, _)| &id[..])
+    }
+
+    /// Get when the Target program started running the current benchmark.
+    pub(super) fn current_since(&self) -> Option<Instant> {
+        self.current.as_ref().map(|&(_, since)| since)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(function_id: &str) -> protocol::RawBenchmarkId<&str> {
+        protocol::RawBenchmarkId {
+            group_id: "g",
+            function_id: Some(function_id),
+            value_str: None,
+            throughput: None,
+        }
+    }
+
+    #[test]
+    fn track() {
+        use protocol::UpstreamMessage::*;
+        let mut progress = super::Progress::default();
+        let origin = Instant::now();
+        let mut record = |msg: protocol::UpstreamMessage<&str, &[u64]>| {
+            progress.record_message(&msg, origin);
+            (
+                progress.num_started(),
+                progress.current().map(str::to_owned),
+            )
+        };
+
+        assert_eq!(record(BeginningBenchmarkGroup { group: "g" }), (0, None));
+        assert_eq!(
+            record(SkippingBenchmark {
+                id: id("a"),
+                reason: protocol::SkipReason::Filter
+            }),
+            (0, None)
+        );
+        assert_eq!(
+            record(RunningBenchmark { id: id("b") }),
+            (1, Some("g/b".to_owned()))
+        );
+        assert_eq!(
+            record(BeginningBenchmark { id: id("b") }),
+            (1, Some("g/b".to_owned()))
+        );
+        assert_eq!(
+            record(RunningBenchmark { id: id("c") }),
+            (2, Some("g/c".to_owned()))
+        );
+        assert_eq!(record(FinishedBenchmarkGroup), (2, None));
+        assert_eq!(record(End), (2, None));
+    }
+}
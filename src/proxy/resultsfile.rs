@@ -0,0 +1,389 @@
+//! Writes the measurement results to a JSON Lines file as the benchmarks
+//! complete (`--farcri-results`), used by the dumb front-end
+//!
+//! Each line of the file is one of the following:
+//!
+//!  - The record of a benchmark, written and synced to the disk as soon as
+//!    its measurement completes. `times[i]` is the measured value (in the
+//!    target's unit) of the `i`-th sample. Example: `{"id":"sort/16",
+//!    "group_id":"sort","function_id":"16","value_str":null,
+//!    "iters_per_sample":100,"times":[1201,1198]}`. If the benchmark has
+//!    tags (`BenchmarkGroup::tags`), they are added as an object at the end,
+//!    e.g., `"tags":{"algorithm":"quicksort"}`.
+//!
+//!  - The footer, written when the Target program finishes. `count` is the
+//!    number of the records in the file. Example: `{"end":true,"count":40}`
+//!
+//! A file without the footer was left by a run that didn't finish, e.g.,
+//! because the Proxy program crashed. When a run starts, such a file is
+//! renamed by appending `.partial` to its name, or, if
+//! `--farcri-results-resume` is present, kept and appended to. A file with
+//! the footer is overwritten.
+//!
+//! When the run finishes, the records are also collected into a JSON array
+//! in the file of the same name with the extension `.json` (e.g.,
+//! `results.json` for `results.jsonl`). A benchmark recorded more than once
+//! (e.g., because it was run again after resuming) is included only once
+//! with its last record.
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+
+use super::jsonout::Tags;
+use crate::bencher::protocol;
+
+/// The record of a benchmark
+#[derive(Serialize)]
+struct Record<'a> {
+    id: String,
+    group_id: &'a str,
+    function_id: Option<&'a str>,
+    value_str: Option<&'a str>,
+    iters_per_sample: u64,
+    times: &'a [u64],
+    #[serde(skip_serializing_if = "Tags::is_empty")]
+    tags: Tags<'a>,
+}
+
+/// The part of a record used to identify the benchmark
+#[derive(Deserialize)]
+struct RecordId {
+    id: String,
+}
+
+/// The footer
+#[derive(Serialize, Deserialize)]
+struct Footer {
+    end: bool,
+    count: usize,
+}
+
+pub(super) struct ResultsFile {
+    path: PathBuf,
+    file: tokio::fs::File,
+    /// The number of the records in the file
+    count: usize,
+}
+
+impl ResultsFile {
+    /// Open the results file at `path`, handling a file left by an unfinished
+    /// run as described in the module documentation.
+    pub(super) async fn open(path: impl Into<PathBuf>, resume: bool) -> Result<Self> {
+        let path = path.into();
+        if path.extension() == Some("json".as_ref()) {
+            bail!(
+                "The results file '{}' must not have the extension `.json`, which is \
+                used for the summary.",
+                path.display()
+            );
+        }
+
+        let previous = match tokio::fs::read_to_string(&path).await {
+            Ok(x) => Some(x),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read '{}'.", path.display()))
+            }
+        };
+
+        let (file, count) = match previous {
+            Some(contents) if !is_complete(&contents) && resume => {
+                // Drop the last line if it was torn by the crash
+                let len = contents.rfind('\n').map_or(0, |i| i + 1);
+                let count = contents[..len].lines().filter_map(record_id).count();
+                log::info!(
+                    "Resuming the results file '{}' of an unfinished run ({} record(s))",
+                    path.display(),
+                    count
+                );
+
+                let mut file = tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .await
+                    .with_context(|| format!("Failed to open '{}'.", path.display()))?;
+                file.set_len(len as u64)
+                    .await
+                    .with_context(|| format!("Failed to truncate '{}'.", path.display()))?;
+                (file, count)
+            }
+            previous => {
+                if matches!(&previous, Some(contents) if !is_complete(contents)) {
+                    let partial_path = partial_path(&path);
+                    tokio::fs::rename(&path, &partial_path)
+                        .await
+                        .with_context(|| format!("Failed to rename '{}'.", path.display()))?;
+                    log::warn!(
+                        "'{}' was left by an unfinished run. Renamed it to '{}'. \
+                        Use `--farcri-results-resume` to append to it instead.",
+                        path.display(),
+                        partial_path.display()
+                    );
+                }
+
+                let file = tokio::fs::File::create(&path)
+                    .await
+                    .with_context(|| format!("Failed to create '{}'.", path.display()))?;
+                (file, 0)
+            }
+        };
+
+        Ok(Self { path, file, count })
+    }
+
+    /// Append the record of a benchmark and sync it to the disk.
+    pub(super) async fn write_measurement(
+        &mut self,
+        id: &protocol::RawBenchmarkId<String>,
+        tags: &[(String, String)],
+        num_iters_per_sample: u64,
+        values: &[u64],
+    ) -> Result<()> {
+        self.write_line(&record_json(id, tags, num_iters_per_sample, values))
+            .await?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Append the footer and write the summary.
+    pub(super) async fn finish(&mut self) -> Result<()> {
+        let footer = Footer {
+            end: true,
+            count: self.count,
+        };
+        self.write_line(&serde_json::to_string(&footer)?).await?;
+
+        let contents = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("Failed to read '{}'.", self.path.display()))?;
+        let summary_path = self.path.with_extension("json");
+        write_and_sync(&summary_path, summary_json(&contents)).await?;
+
+        log::debug!(
+            "Wrote {} record(s) to '{}' and '{}'",
+            self.count,
+            self.path.display(),
+            summary_path.display()
+        );
+        Ok(())
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<()> {
+        let path = &self.path;
+        let file = &mut self.file;
+        async {
+            file.write_all(format!("{}\n", line).as_bytes()).await?;
+            file.flush().await?;
+            file.sync_data().await
+        }
+        .await
+        .with_context(|| format!("Failed to write '{}'.", path.display()))
+    }
+}
+
+/// Write `contents` to `path` and sync it to the disk, so that it survives
+/// a crash of the host system.
+pub(super) async fn write_and_sync(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    async {
+        let mut file = tokio::fs::File::create(path).await?;
+        file.write_all(contents.as_ref()).await?;
+        file.sync_all().await
+    }
+    .await
+    .with_context(|| format!("Failed to write '{}'.", path.display()))
+}
+
+/// Get the path `path` is renamed to if it was left by an unfinished run.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+/// Check if a results file has the footer.
+fn is_complete(contents: &str) -> bool {
+    let last = contents.lines().rev().find(|line| !line.is_empty());
+    matches!(last, Some(line) if serde_json::from_str::<Footer>(line).is_ok())
+}
+
+fn record_json(
+    id: &protocol::RawBenchmarkId<String>,
+    tags: &[(String, String)],
+    num_iters_per_sample: u64,
+    values: &[u64],
+) -> String {
+    let record = Record {
+        id: id.to_string(),
+        group_id: &id.group_id,
+        function_id: id.function_id.as_deref(),
+        value_str: id.value_str.as_deref(),
+        iters_per_sample: num_iters_per_sample,
+        times: values,
+        tags: Tags(tags),
+    };
+    serde_json::to_string(&record).unwrap()
+}
+
+/// Get the benchmark ID of a record line. Returns `None` if `line` isn't a
+/// complete record.
+fn record_id(line: &str) -> Option<String> {
+    serde_json::from_str::<RecordId>(line).ok().map(|x| x.id)
+}
+
+/// Collect the records in a results file into a JSON array, keeping only the
+/// last record of each benchmark.
+fn summary_json(contents: &str) -> String {
+    let mut order = Vec::new();
+    let mut records = HashMap::new();
+    for line in contents.lines() {
+        if let Some(id) = record_id(line) {
+            if records.insert(id.clone(), line).is_none() {
+                order.push(id);
+            }
+        }
+    }
+
+    let mut out = String::from("[");
+    for (i, id) in order.iter().enumerate() {
+        if i != 0 {
+            out.push(',');
+        }
+        out.push_str("\n  ");
+        out.push_str(records[id]);
+    }
+    out.push_str("\n]\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(function_id: &str) -> protocol::RawBenchmarkId<String> {
+        protocol::RawBenchmarkId {
+            group_id: "sort".to_owned(),
+            function_id: Some(function_id.to_owned()),
+            value_str: None,
+            throughput: None,
+        }
+    }
+
+    #[test]
+    fn record_ids() {
+        let line = record_json(&id("a\"b"), &[], 1, &[2]);
+        assert_eq!(record_id(&line).as_deref(), Some("sort/a\"b"));
+        // Torn by a crash
+        assert_eq!(record_id(&line[..line.len() - 1]), None);
+        assert_eq!(record_id(&line[..12]), None);
+        assert_eq!(record_id(r#"{"end":true,"count":1}"#), None);
+
+        let line = record_json(&id("16"), &[("opt".to_owned(), "O2".to_owned())], 1, &[2]);
+        assert_eq!(
+            line,
+            r#"{"id":"sort/16","group_id":"sort","function_id":"16","value_str":null,"iters_per_sample":1,"times":[2],"tags":{"opt":"O2"}}"#
+        );
+        assert_eq!(record_id(&line).as_deref(), Some("sort/16"));
+    }
+
+    #[tokio::test]
+    async fn rotate_unfinished() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        let path = dir.path().join("results.jsonl");
+
+        let mut file = ResultsFile::open(&path, false).await.unwrap();
+        file.write_measurement(&id("16"), &[], 100, &[1201, 1198])
+            .await
+            .unwrap();
+        drop(file);
+
+        let mut file = ResultsFile::open(&path, false).await.unwrap();
+        file.write_measurement(&id("32"), &[], 50, &[2400])
+            .await
+            .unwrap();
+        file.finish().await.unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(
+            read("results.jsonl.partial"),
+            concat!(
+                r#"{"id":"sort/16","group_id":"sort","function_id":"16","value_str":null,"iters_per_sample":100,"times":[1201,1198]}"#,
+                "\n"
+            )
+        );
+        assert_eq!(
+            read("results.jsonl"),
+            concat!(
+                r#"{"id":"sort/32","group_id":"sort","function_id":"32","value_str":null,"iters_per_sample":50,"times":[2400]}"#,
+                "\n",
+                r#"{"end":true,"count":1}"#,
+                "\n"
+            )
+        );
+        assert_eq!(
+            read("results.json"),
+            concat!(
+                "[\n  ",
+                r#"{"id":"sort/32","group_id":"sort","function_id":"32","value_str":null,"iters_per_sample":50,"times":[2400]}"#,
+                "\n]\n"
+            )
+        );
+
+        // A finished file is overwritten
+        let file = ResultsFile::open(&path, true).await.unwrap();
+        drop(file);
+        assert_eq!(read("results.jsonl"), "");
+    }
+
+    #[tokio::test]
+    async fn resume_unfinished() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        let path = dir.path().join("results.jsonl");
+
+        let mut file = ResultsFile::open(&path, false).await.unwrap();
+        file.write_measurement(&id("16"), &[], 100, &[1])
+            .await
+            .unwrap();
+        file.write_measurement(&id("32"), &[], 100, &[2])
+            .await
+            .unwrap();
+        drop(file);
+        // Simulate a line torn by a crash
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str(r#"{"id":"sort/64","#);
+        std::fs::write(&path, contents).unwrap();
+
+        let mut file = ResultsFile::open(&path, true).await.unwrap();
+        file.write_measurement(&id("32"), &[], 100, &[3])
+            .await
+            .unwrap();
+        file.finish().await.unwrap();
+
+        assert!(!dir.path().join("results.jsonl.partial").exists());
+        let lines: Vec<String> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[3], r#"{"end":true,"count":3}"#);
+
+        let summary = std::fs::read_to_string(dir.path().join("results.json")).unwrap();
+        let summary: Vec<&str> = summary.lines().collect();
+        assert_eq!(summary.len(), 4);
+        assert!(summary[1].contains(r#""id":"sort/16""#));
+        assert!(summary[2].contains(r#""id":"sort/32""#));
+        assert!(summary[2].contains(r#""times":[3]"#));
+    }
+
+    #[tokio::test]
+    async fn reject_json_extension() {
+        let dir = tempdir::TempDir::new("farcri-rs").unwrap();
+        assert!(ResultsFile::open(dir.path().join("results.json"), false)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Simulated target for `--farcri-dry-run=simulate`
+//!
+//! The Target program runs on a thread of the Proxy program through the
+//! loopback port (`crate::target::loopback`), which is also used by the unit
+//! tests, and talks to the rest of the Proxy program through a local TCP
+//! connection. This exercises the greeting, the front-ends, the writers, and
+//! the exit code without a debug probe.
+//!
+//! The benchmarks of the bench target can only be enumerated by the Target
+//! program built from it, so the simulated Target program runs a fixed set
+//! of benchmarks instead. The measured values are in nanoseconds.
+use anyhow::{Context as _, Result};
+use std::{future::Future, pin::Pin, thread, time::Duration};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::targets::{AsyncReadWrite, DebugProbe, Transport};
+use crate::{
+    bencher::{black_box, BenchmarkId, Criterion, Throughput},
+    cargo::CompiledExecutable,
+    target::loopback,
+};
+
+/// A [`DebugProbe`] that runs the simulated Target program instead of
+/// loading the executable
+pub(super) struct SimulatedDebugProbe;
+
+impl DebugProbe for SimulatedDebugProbe {
+    fn program_and_get_output(
+        &mut self,
+        _exe: &CompiledExecutable,
+        _transport: Transport,
+    ) -> Pin<Box<dyn Future<Output = Result<Pin<Box<dyn AsyncReadWrite + '_>>>> + '_>> {
+        Box::pin(async {
+            let mut listener = TcpListener::bind(("127.0.0.1", 0))
+                .await
+                .context("Failed to open a port for the simulated target.")?;
+            let addr = listener.local_addr()?;
+
+            log::info!("Starting the simulated Target program");
+
+            // The thread exits when the Proxy program closes the stream or
+            // the simulated Target program finishes
+            thread::Builder::new()
+                .name("simulated target".to_owned())
+                .spawn(move || {
+                    if let Ok(stream) = std::net::TcpStream::connect(addr) {
+                        loopback::run(stream, canned_groups);
+                    }
+                })
+                .context("Failed to start the simulated target.")?;
+
+            let (stream, _): (TcpStream, _) = listener
+                .accept()
+                .await
+                .context("Failed to accept a connection from the simulated target.")?;
+
+            Ok(Box::pin(stream) as _)
+        })
+    }
+}
+
+/// The benchmarks run by the simulated Target program. They are kept short
+/// so that a dry run finishes in a few seconds.
+fn canned_groups(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulated");
+    group
+        .sample_size(10)
+        .warm_up_time(Duration::from_millis(10))
+        .measurement_time(Duration::from_millis(50));
+
+    group.bench_named("noop", |b| b.iter(|| ()));
+
+    for &len in &[16usize, 256] {
+        group.throughput(Throughput::Elements(len as u64));
+        group.bench_with_input(BenchmarkId::new(&"sum", &len), &len, |b, &len| {
+            b.iter(|| (0..len as u32).map(black_box).fold(0u32, u32::wrapping_add))
+        });
+    }
+
+    group.finish();
+}
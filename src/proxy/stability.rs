@@ -0,0 +1,149 @@
+//! Flags the samples far from the median, which suggests that something
+//! (e.g., DMA, interrupts, or thermal throttling) interfered with the
+//! measurement
+//!
+//! All samples of a measurement run the same number of iterations, so each
+//! sample is expected to take about as long as the median sample. The samples
+//! deviating from it by more than [`DEVIATION_THRESHOLD`] are flagged, and
+//! the number of flagged samples determines the [`Noise`] level.
+use super::stats;
+
+/// The relative deviation from the median above which a sample is flagged
+pub(super) const DEVIATION_THRESHOLD: f64 = 0.05;
+
+/// The fraction of flagged samples up to which the noise level is
+/// [`Noise::Moderate`]
+const MODERATE_NOISE_FRACTION: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Stability {
+    pub(super) num_samples: usize,
+    pub(super) median: f64,
+    /// The flagged samples in the order of measurement
+    pub(super) deviants: Vec<Deviant>,
+}
+
+/// A sample deviating from the median by more than [`DEVIATION_THRESHOLD`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Deviant {
+    /// The index of the sample
+    pub(super) index: usize,
+    pub(super) value: u64,
+    /// `(value - median) / median`
+    pub(super) deviation: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Noise {
+    /// No samples were flagged.
+    Low,
+    /// A few samples were flagged.
+    Moderate,
+    /// Many samples were flagged.
+    High,
+}
+
+impl Noise {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Moderate => "moderate",
+            Self::High => "high",
+        }
+    }
+}
+
+/// Analyze the samples of a measurement. Returns `None` if there are too few
+/// samples or the median is zero.
+pub(super) fn analyze(values: &[u64]) -> Option<Stability> {
+    if values.len() < 2 {
+        return None;
+    }
+
+    let mut sorted: Vec<f64> = values.iter().map(|&x| x as f64).collect();
+    let median = stats::median(&mut sorted);
+    if median.is_nan() || median <= 0.0 {
+        return None;
+    }
+
+    let deviants = values
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| Deviant {
+            index,
+            value,
+            deviation: (value as f64 - median) / median,
+        })
+        .filter(|d| d.deviation.abs() > DEVIATION_THRESHOLD)
+        .collect();
+
+    Some(Stability {
+        num_samples: values.len(),
+        median,
+        deviants,
+    })
+}
+
+impl Stability {
+    pub(super) fn noise(&self) -> Noise {
+        if self.deviants.is_empty() {
+            Noise::Low
+        } else if self.deviants.len() as f64 <= self.num_samples as f64 * MODERATE_NOISE_FRACTION {
+            Noise::Moderate
+        } else {
+            Noise::High
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flag_deviants() {
+        let mut values = vec![1000; 40];
+        values[3] = 1200;
+        values[17] = 940;
+        // Within the threshold
+        values[20] = 1040;
+        let stability = analyze(&values).unwrap();
+        assert_eq!(stability.num_samples, 40);
+        assert_eq!(stability.median, 1000.0);
+        assert_eq!(
+            stability.deviants,
+            vec![
+                Deviant {
+                    index: 3,
+                    value: 1200,
+                    deviation: 0.2
+                },
+                Deviant {
+                    index: 17,
+                    value: 940,
+                    deviation: -0.06
+                },
+            ]
+        );
+        assert_eq!(stability.noise(), Noise::Moderate);
+    }
+
+    #[test]
+    fn noise_levels() {
+        let noise = |values: &[u64]| analyze(values).unwrap().noise();
+        assert_eq!(noise(&[1000; 20]), Noise::Low);
+
+        let mut values = [1000; 20];
+        values[0] = 2000;
+        assert_eq!(noise(&values), Noise::Moderate);
+        values[1] = 2000;
+        assert_eq!(noise(&values), Noise::High);
+    }
+
+    #[test]
+    fn too_few_samples() {
+        assert_eq!(analyze(&[]), None);
+        assert_eq!(analyze(&[1000]), None);
+        assert_eq!(analyze(&[0, 0, 1]), None);
+    }
+}
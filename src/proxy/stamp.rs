@@ -0,0 +1,206 @@
+//! Image stamps, used to detect a mismatch between the executable the Proxy
+//! program loaded and the Target program actually running on the target
+//!
+//! A stamp is a hash of the inputs of the build, not a per-build nonce. Two
+//! builds with the same stamp are built from the same sources with the same
+//! configuration, so a target still running the image of an earlier such
+//! build behaves identically and doesn't need to be reported. A nonce would
+//! also change `FARCRI_IMAGE_STAMP` on every run, forcing Cargo to rebuild
+//! this crate and the bench executable each time.
+//!
+//! Only the files Cargo treats as build inputs of the bench package are
+//! hashed, so editing anything else in the package (e.g., a README) doesn't
+//! change the stamp and doesn't trigger a rebuild through `option_env!`.
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::task::spawn_blocking;
+
+use crate::{bencher::protocol, utils::StableHasher};
+
+/// The files in the package directory that are inputs of the build
+const INPUT_FILES: &[&str] = &["Cargo.toml", "build.rs"];
+
+/// The directories in the package directory from which Cargo discovers
+/// targets and their modules
+const INPUT_DIRS: &[&str] = &["src", "benches", "examples", "tests"];
+
+/// Compute an image stamp from the source files of the bench package and the
+/// given build configuration.
+pub(super) async fn compute(build_config: Vec<String>) -> Result<String> {
+    let package_dir = std::env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from);
+
+    spawn_blocking(move || compute_sync(package_dir.as_deref(), &build_config))
+        .await
+        .unwrap()
+}
+
+fn compute_sync(package_dir: Option<&Path>, build_config: &[String]) -> Result<String> {
+    let mut hasher = StableHasher::new();
+    hasher.write_str(env!("CARGO_PKG_VERSION"));
+    hasher.write_u64(build_config.len() as u64);
+    for x in build_config {
+        hasher.write_str(x);
+    }
+    if let Some(package_dir) = package_dir {
+        for &name in INPUT_FILES {
+            hash_file_if_exists(&mut hasher, &package_dir.join(name), Path::new(name))?;
+        }
+
+        // The lockfile is in the workspace root, which may be an ancestor
+        if let Some(lockfile) = (package_dir.ancestors())
+            .map(|dir| dir.join("Cargo.lock"))
+            .find(|path| path.is_file())
+        {
+            hash_file_if_exists(&mut hasher, &lockfile, Path::new("Cargo.lock"))?;
+        }
+
+        for &name in INPUT_DIRS {
+            if package_dir.join(name).is_dir() {
+                hash_dir(&mut hasher, package_dir, Path::new(name))?;
+            }
+        }
+    }
+    Ok(hasher.finish_hex())
+}
+
+fn hash_file_if_exists(hasher: &mut StableHasher, path: &Path, rel_path: &Path) -> Result<()> {
+    if path.is_file() {
+        let contents =
+            std::fs::read(path).with_context(|| format!("Failed to read '{}'.", path.display()))?;
+        hasher.write_str(&rel_path.to_string_lossy());
+        hasher.write_bytes(&contents);
+    }
+    Ok(())
+}
+
+fn hash_dir(hasher: &mut StableHasher, root: &Path, rel_path: &Path) -> Result<()> {
+    let dir = root.join(rel_path);
+    let mut entries = std::fs::read_dir(&dir)
+        .and_then(|it| it.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to list the files in '{}'.", dir.display()))?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        let rel_path = rel_path.join(&name);
+        let file_type = entry
+            .file_type()
+            .with_context(|| format!("Failed to stat '{}'.", entry.path().display()))?;
+
+        if file_type.is_dir() {
+            // Skip hidden directories (e.g., `.git`)
+            if name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            hash_dir(hasher, root, &rel_path)?;
+        } else if file_type.is_file() {
+            hash_file_if_exists(hasher, &entry.path(), &rel_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Compare the image stamp reported by the Target program with the one
+/// embedded in the executable that was loaded to the target.
+pub(super) async fn check(exe: &Path, target_stamp: &str) -> Result<()> {
+    let elf_bytes = tokio::fs::read(exe)
+        .await
+        .with_context(|| format!("Failed to read '{}'.", exe.display()))?;
+    let exe_stamp = spawn_blocking(move || read_from_elf(&elf_bytes))
+        .await
+        .unwrap()?;
+
+    match exe_stamp {
+        None => {
+            log::warn!(
+                "Couldn't find the image stamp in the executable. \
+                Skipping the image stamp check."
+            );
+        }
+        Some(exe_stamp) if exe_stamp == target_stamp => {
+            log::debug!("Image stamp: {:?}", target_stamp);
+        }
+        Some(exe_stamp) => {
+            bail!(
+                "The program running on the target (image stamp {:?}) doesn't \
+                match the executable '{}' (image stamp {:?}). The target might \
+                be running a stale image.",
+                target_stamp,
+                exe.display(),
+                exe_stamp,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the image stamp embedded in the specified executable.
+fn read_from_elf(elf_bytes: &[u8]) -> Result<Option<String>> {
+    let elf = match goblin::elf::Elf::parse(elf_bytes) {
+        Ok(elf) => elf,
+        // The executable built for the host might not be an ELF file
+        Err(goblin::error::Error::BadMagic(_)) => return Ok(None),
+        Err(e) => return Err(e).context("Failed to parse the executable."),
+    };
+
+    let sym = elf.syms.iter().find(|sym| {
+        matches!(elf.strtab.get(sym.st_name), Some(Ok(name)) if name == protocol::IMAGE_STAMP_SYMBOL)
+    });
+    let addr = match sym {
+        Some(sym) => sym.st_value,
+        None => return Ok(None),
+    };
+
+    // Convert the address to a file offset
+    let bytes = elf
+        .section_headers
+        .iter()
+        .find(|sh| {
+            sh.sh_type != goblin::elf::section_header::SHT_NOBITS
+                && (sh.sh_addr..sh.sh_addr + sh.sh_size).contains(&addr)
+        })
+        .and_then(|sh| {
+            let start = (sh.sh_offset + (addr - sh.sh_addr)) as usize;
+            elf_bytes.get(start..start + protocol::IMAGE_STAMP_LEN)
+        })
+        .context("The image stamp symbol points to an invalid location.")?;
+
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let stamp = std::str::from_utf8(&bytes[..len]).context("The image stamp is malformed.")?;
+
+    Ok(Some(stamp.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_build_inputs_hashed() {
+        let dir = std::env::temp_dir().join(format!("farcri-stamp-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "[package]").unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+        let config = vec!["release".to_owned()];
+
+        let stamp = || compute_sync(Some(&dir), &config).unwrap();
+        let original = stamp();
+
+        std::fs::write(dir.join("README.md"), "hello").unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("target/out"), "").unwrap();
+        assert_eq!(stamp(), original);
+
+        std::fs::write(dir.join("src/lib.rs"), "//").unwrap();
+        let modified = stamp();
+        assert_ne!(modified, original);
+
+        std::fs::write(dir.join("build.rs"), "").unwrap();
+        assert_ne!(stamp(), modified);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
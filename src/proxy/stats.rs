@@ -0,0 +1,294 @@
+//! Bootstrap statistics, a small subset of Criterion.rs's `stats` module
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// A point estimate and its confidence interval
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(super) struct Estimate {
+    pub(super) point: f64,
+    pub(super) lower: f64,
+    pub(super) upper: f64,
+}
+
+pub(super) fn mean(sample: &[f64]) -> f64 {
+    sample.iter().sum::<f64>() / sample.len() as f64
+}
+
+/// The unbiased sample variance. Zero for a sample with fewer than two
+/// elements, which has no spread.
+fn variance(sample: &[f64]) -> f64 {
+    if sample.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(sample);
+    let sum_sq: f64 = sample.iter().map(|&x| (x - mean) * (x - mean)).sum();
+    sum_sq / (sample.len() - 1) as f64
+}
+
+pub(super) fn std_dev(sample: &[f64]) -> f64 {
+    variance(sample).sqrt()
+}
+
+/// Get the median. Sorts `sample`.
+pub(super) fn median(sample: &mut [f64]) -> f64 {
+    sample.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    percentile(sample, 0.5)
+}
+
+/// Get the median absolute deviation, scaled to be a consistent estimator
+/// of the standard deviation for normal distributions like Criterion.rs's.
+/// Sorts `sample`.
+pub(super) fn median_abs_dev(sample: &mut [f64]) -> f64 {
+    let median = median(sample);
+    let mut deviations: Vec<f64> = sample.iter().map(|&x| (x - median).abs()).collect();
+    self::median(&mut deviations) * 1.4826
+}
+
+/// Draw an element of `sample` with replacement `len` times and calculate
+/// the mean of the drawn elements.
+fn resample_mean(sample: &[f64], len: usize, rng: &mut impl Rng) -> f64 {
+    let sum: f64 = (0..len)
+        .map(|_| sample[rng.gen_range(0..sample.len())])
+        .sum();
+    sum / len as f64
+}
+
+/// Get the `confidence_level` confidence interval from a bootstrap
+/// distribution. Sorts `distribution`.
+fn confidence_interval(distribution: &mut [f64], confidence_level: f64) -> (f64, f64) {
+    distribution.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    let alpha = 1.0 - confidence_level;
+    (
+        percentile(distribution, alpha / 2.0),
+        percentile(distribution, 1.0 - alpha / 2.0),
+    )
+}
+
+/// Get the `p`-quantile (`0 ≤ p ≤ 1`) of a sorted sample by linear
+/// interpolation. NaN for an empty sample (e.g., a bootstrap distribution
+/// with `nresamples = 0`).
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = p * (sorted.len() - 1) as f64;
+    let i = rank.floor() as usize;
+    match sorted.get(i + 1) {
+        Some(&next) => sorted[i] + (next - sorted[i]) * (rank - i as f64),
+        None => sorted[i],
+    }
+}
+
+/// Estimate a statistic of the population by bootstrapping. `statistic` may
+/// reorder the elements of the slice passed to it. Returns the estimate and
+/// its standard error (the standard deviation of the bootstrap
+/// distribution).
+pub(super) fn bootstrap(
+    sample: &[f64],
+    nresamples: usize,
+    confidence_level: f64,
+    rng: &mut impl Rng,
+    mut statistic: impl FnMut(&mut [f64]) -> f64,
+) -> (Estimate, f64) {
+    let mut resampled = vec![0.0; sample.len()];
+    let mut distribution: Vec<f64> = (0..nresamples)
+        .map(|_| {
+            for x in resampled.iter_mut() {
+                *x = sample[rng.gen_range(0..sample.len())];
+            }
+            statistic(&mut resampled)
+        })
+        .collect();
+    let standard_error = std_dev(&distribution);
+    let (lower, upper) = confidence_interval(&mut distribution, confidence_level);
+
+    resampled.copy_from_slice(sample);
+    let estimate = Estimate {
+        point: statistic(&mut resampled),
+        lower,
+        upper,
+    };
+    (estimate, standard_error)
+}
+
+/// Estimate the mean of the population.
+pub(super) fn bootstrap_mean(
+    sample: &[f64],
+    nresamples: usize,
+    confidence_level: f64,
+    rng: &mut impl Rng,
+) -> Estimate {
+    let mut distribution: Vec<f64> = (0..nresamples)
+        .map(|_| resample_mean(sample, sample.len(), rng))
+        .collect();
+    let (lower, upper) = confidence_interval(&mut distribution, confidence_level);
+    Estimate {
+        point: mean(sample),
+        lower,
+        upper,
+    }
+}
+
+/// Estimate the relative change of the population mean from `old` to `new`
+/// (e.g., `0.1` means a 10% increase).
+pub(super) fn bootstrap_mean_change(
+    old: &[f64],
+    new: &[f64],
+    nresamples: usize,
+    confidence_level: f64,
+    rng: &mut impl Rng,
+) -> Estimate {
+    let mut distribution: Vec<f64> = (0..nresamples)
+        .map(|_| resample_mean(new, new.len(), rng) / resample_mean(old, old.len(), rng) - 1.0)
+        .collect();
+    let (lower, upper) = confidence_interval(&mut distribution, confidence_level);
+    Estimate {
+        point: mean(new) / mean(old) - 1.0,
+        lower,
+        upper,
+    }
+}
+
+/// Welch's t statistic
+fn welch_t(a: &[f64], b: &[f64]) -> f64 {
+    let diff = mean(a) - mean(b);
+    let std_err = (variance(a) / a.len() as f64 + variance(b) / b.len() as f64).sqrt();
+    if diff == 0.0 {
+        // Don't let it be NaN when both samples are constant
+        0.0
+    } else {
+        diff / std_err
+    }
+}
+
+/// Calculate the two-tailed p-value of Welch's t-test for the null
+/// hypothesis that `old` and `new` have the same mean. Like Criterion.rs,
+/// the distribution of the t statistic under the null hypothesis is
+/// obtained by bootstrapping from the two samples mixed together.
+pub(super) fn t_test(old: &[f64], new: &[f64], nresamples: usize, rng: &mut impl Rng) -> f64 {
+    let t = welch_t(old, new).abs();
+
+    let mixed: Vec<f64> = old.iter().chain(new.iter()).cloned().collect();
+    let mut a = vec![0.0; old.len()];
+    let mut b = vec![0.0; new.len()];
+    let num_extreme = (0..nresamples)
+        .filter(|_| {
+            for x in a.iter_mut().chain(b.iter_mut()) {
+                *x = mixed[rng.gen_range(0..mixed.len())];
+            }
+            welch_t(&a, &b).abs() >= t
+        })
+        .count();
+
+    num_extreme as f64 / nresamples as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// A deterministic, slightly noisy sample around `center`
+    fn sample(center: f64) -> Vec<f64> {
+        (0..50)
+            .map(|i| center + ((i * 7) % 11) as f64 - 5.0)
+            .collect()
+    }
+
+    #[test]
+    fn percentiles() {
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 0.5), 3.0);
+        assert_eq!(percentile(&sorted, 0.625), 3.5);
+        assert_eq!(percentile(&sorted, 1.0), 5.0);
+        assert_eq!(percentile(&[7.0], 0.5), 7.0);
+        assert!(percentile(&[], 0.5).is_nan());
+    }
+
+    #[test]
+    fn tiny_samples() {
+        assert_eq!(variance(&[]), 0.0);
+        assert_eq!(std_dev(&[42.0]), 0.0);
+        assert_eq!(std_dev(&[1.0, 3.0]), 2f64.sqrt());
+
+        // No resamples
+        let mut rng = StdRng::seed_from_u64(1);
+        let estimate = bootstrap_mean(&[1.0, 3.0], 0, 0.95, &mut rng);
+        assert_eq!(estimate.point, 2.0);
+        assert!(estimate.lower.is_nan() && estimate.upper.is_nan());
+        let (estimate, standard_error) = bootstrap(&[1.0, 3.0], 0, 0.95, &mut rng, |x| mean(x));
+        assert_eq!(estimate.point, 2.0);
+        assert_eq!(standard_error, 0.0);
+    }
+
+    #[test]
+    fn mean_estimate() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let sample = sample(1000.0);
+        let estimate = bootstrap_mean(&sample, 1000, 0.95, &mut rng);
+        assert_eq!(estimate.point, mean(&sample));
+        assert!(estimate.lower < estimate.point && estimate.point < estimate.upper);
+        assert!(estimate.upper - estimate.lower < 5.0, "{:?}", estimate);
+    }
+
+    #[test]
+    fn robust_statistics() {
+        let mut sample = [5.0, 1.0, 4.0, 2.0, 3.0, 100.0];
+        assert_eq!(median(&mut sample.clone()), 3.5);
+        // Deviations: 0.5, 0.5, 1.5, 1.5, 2.5, 96.5
+        assert!((median_abs_dev(&mut sample) - 1.5 * 1.4826).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bootstrap_statistic() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let sample = sample(1000.0);
+        let (estimate, standard_error) = bootstrap(&sample, 1000, 0.95, &mut rng, median);
+        assert_eq!(estimate.point, median(&mut sample.clone()));
+        assert!(estimate.lower <= estimate.point && estimate.point <= estimate.upper);
+        assert!(
+            standard_error > 0.0 && standard_error < 5.0,
+            "{}",
+            standard_error
+        );
+    }
+
+    #[test]
+    fn constant_sample() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let sample = [42.0; 10];
+        let estimate = bootstrap_mean(&sample, 100, 0.95, &mut rng);
+        assert_eq!(
+            estimate,
+            Estimate {
+                point: 42.0,
+                lower: 42.0,
+                upper: 42.0
+            }
+        );
+        assert_eq!(t_test(&sample, &sample, 100, &mut rng), 1.0);
+    }
+
+    #[test]
+    fn change_detected() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let old = sample(1000.0);
+        let new = sample(1100.0);
+        let change = bootstrap_mean_change(&old, &new, 1000, 0.95, &mut rng);
+        assert!((change.point - 0.1).abs() < 1e-3, "{:?}", change);
+        assert!(change.lower > 0.09 && change.upper < 0.11, "{:?}", change);
+        assert!(t_test(&old, &new, 1000, &mut rng) < 0.01);
+    }
+
+    #[test]
+    fn no_change_detected() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let old = sample(1000.0);
+        let mut new = old.clone();
+        new.reverse();
+        let change = bootstrap_mean_change(&old, &new, 1000, 0.95, &mut rng);
+        assert!(change.lower < 0.0 && change.upper > 0.0, "{:?}", change);
+        assert!(t_test(&old, &new, 1000, &mut rng) > 0.5);
+    }
+}
@@ -0,0 +1,220 @@
+//! Decides whether to color the console output and provides styled text
+//!
+//! The decision follows `--color` and the conventional environment variables
+//! in this order of precedence:
+//!
+//!  1. `--color=always` and `--color=never`
+//!  2. `NO_COLOR` (<https://no-color.org>), if set to a non-empty value,
+//!     disables colors.
+//!  3. `CLICOLOR_FORCE`, if set to anything other than `0`, enables colors.
+//!  4. `CLICOLOR=0` disables colors.
+//!  5. Otherwise, colors are used if the stream is a terminal.
+//!
+//! The decision is made once by [`init`] for each of stdout and stderr and
+//! also applies to the log messages, so that `env_logger` and the rest of
+//! the Proxy program never disagree.
+use std::{
+    ffi::OsStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// When to use colors (`--color`)
+#[derive(Debug, Clone, Copy, PartialEq, arg_enum_proc_macro::ArgEnum)]
+pub(super) enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/// The decisions made by [`init`]
+static COLORS: AtomicU8 = AtomicU8::new(0);
+const COLORS_STDOUT: u8 = 1;
+const COLORS_STDERR: u8 = 1 << 1;
+
+/// The values of the color-related environment variables
+#[derive(Debug, Default, Clone, Copy)]
+struct ColorEnv {
+    no_color: bool,
+    clicolor_force: bool,
+    clicolor_disabled: bool,
+}
+
+impl ColorEnv {
+    fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var_os(name))
+    }
+
+    fn from_vars<T: AsRef<OsStr>>(mut var: impl FnMut(&str) -> Option<T>) -> Self {
+        let mut is =
+            |name, pred: fn(&OsStr) -> bool| matches!(var(name), Some(x) if pred(x.as_ref()));
+        Self {
+            no_color: is("NO_COLOR", |x| !x.is_empty()),
+            clicolor_force: is("CLICOLOR_FORCE", |x| x != OsStr::new("0")),
+            clicolor_disabled: is("CLICOLOR", |x| x == OsStr::new("0")),
+        }
+    }
+}
+
+/// Decide whether to color the output to a stream.
+fn use_color(choice: ColorChoice, env: ColorEnv, is_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto if env.no_color => false,
+        ColorChoice::Auto if env.clicolor_force => true,
+        ColorChoice::Auto if env.clicolor_disabled => false,
+        ColorChoice::Auto => is_terminal,
+    }
+}
+
+/// Decide whether to color stdout and stderr. Returns the [`Style`] for
+/// stderr, which is where the log messages go.
+///
+/// Until this is called, the output isn't colored.
+pub(super) fn init(choice: ColorChoice) -> Style {
+    let env = ColorEnv::from_env();
+    let mut colors = 0;
+    if use_color(choice, env, atty::is(atty::Stream::Stdout)) {
+        colors |= COLORS_STDOUT;
+    }
+    if use_color(choice, env, atty::is(atty::Stream::Stderr)) {
+        colors |= COLORS_STDERR;
+    }
+    COLORS.store(colors, Ordering::Relaxed);
+    stderr()
+}
+
+/// Get the [`Style`] for stdout.
+pub(super) fn stdout() -> Style {
+    Style::new(COLORS.load(Ordering::Relaxed) & COLORS_STDOUT != 0)
+}
+
+/// Get the [`Style`] for stderr.
+pub(super) fn stderr() -> Style {
+    Style::new(COLORS.load(Ordering::Relaxed) & COLORS_STDERR != 0)
+}
+
+/// Check if stderr is a terminal, on which a progress line can be
+/// overwritten. This doesn't depend on the color settings.
+pub(super) fn stderr_is_terminal() -> bool {
+    atty::is(atty::Stream::Stderr)
+}
+
+/// Applies ANSI escape sequences to text if colors are enabled
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Style {
+    color: bool,
+}
+
+impl Style {
+    pub(super) fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    /// Check if colors are enabled.
+    pub(super) fn color(&self) -> bool {
+        self.color
+    }
+
+    fn paint(&self, code: &str, s: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, s)
+        } else {
+            s.to_owned()
+        }
+    }
+
+    pub(super) fn bold(&self, s: &str) -> String {
+        self.paint("1", s)
+    }
+
+    pub(super) fn dim(&self, s: &str) -> String {
+        self.paint("2", s)
+    }
+
+    pub(super) fn red(&self, s: &str) -> String {
+        self.paint("31", s)
+    }
+
+    pub(super) fn green(&self, s: &str) -> String {
+        self.paint("32", s)
+    }
+
+    pub(super) fn yellow(&self, s: &str) -> String {
+        self.paint("33", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> ColorEnv {
+        ColorEnv::from_vars(|name| {
+            vars.iter()
+                .find(|&&(var_name, _)| var_name == name)
+                .map(|&(_, value)| value)
+        })
+    }
+
+    #[test]
+    fn env_vars() {
+        let e = env(&[]);
+        assert!(!e.no_color && !e.clicolor_force && !e.clicolor_disabled);
+
+        assert!(env(&[("NO_COLOR", "1")]).no_color);
+        // An empty `NO_COLOR` is ignored
+        assert!(!env(&[("NO_COLOR", "")]).no_color);
+
+        assert!(env(&[("CLICOLOR_FORCE", "1")]).clicolor_force);
+        assert!(!env(&[("CLICOLOR_FORCE", "0")]).clicolor_force);
+
+        assert!(env(&[("CLICOLOR", "0")]).clicolor_disabled);
+        assert!(!env(&[("CLICOLOR", "1")]).clicolor_disabled);
+    }
+
+    #[test]
+    fn decision_matrix() {
+        use ColorChoice::*;
+
+        let none = env(&[]);
+        let no_color = env(&[("NO_COLOR", "1")]);
+        let force = env(&[("CLICOLOR_FORCE", "1")]);
+        let both = env(&[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")]);
+        let disabled = env(&[("CLICOLOR", "0")]);
+        let force_disabled = env(&[("CLICOLOR", "0"), ("CLICOLOR_FORCE", "1")]);
+
+        // (choice, env, is_terminal, expected)
+        let cases = [
+            (Auto, none, true, true),
+            (Auto, none, false, false),
+            (Auto, no_color, true, false),
+            (Auto, force, false, true),
+            (Auto, both, false, false),
+            (Auto, both, true, false),
+            (Auto, disabled, true, false),
+            (Auto, force_disabled, false, true),
+            (Always, none, false, true),
+            (Always, no_color, false, true),
+            (Always, disabled, true, true),
+            (Never, none, true, false),
+            (Never, force, true, false),
+        ];
+        for &(choice, env, is_terminal, expected) in cases.iter() {
+            assert_eq!(
+                use_color(choice, env, is_terminal),
+                expected,
+                "{:?} {:?} is_terminal = {}",
+                choice,
+                env,
+                is_terminal
+            );
+        }
+    }
+
+    #[test]
+    fn paint() {
+        assert_eq!(Style::new(true).red("x"), "\x1b[31mx\x1b[0m");
+        assert_eq!(Style::new(false).red("x"), "x");
+    }
+}
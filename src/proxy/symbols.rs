@@ -0,0 +1,96 @@
+//! Symbol name utilities shared by the ELF analyses
+/// Decode a symbol name mangled by the legacy scheme into path components,
+/// excluding the trailing hash.
+pub(super) fn legacy_symbol_path(sym: &str) -> Option<Vec<String>> {
+    let mut rest = sym.strip_prefix("_ZN")?;
+    let mut out = Vec::new();
+    while !rest.starts_with('E') {
+        let num_digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        if num_digits == 0 {
+            return None;
+        }
+        let len: usize = rest[..num_digits].parse().ok()?;
+        let ident = rest.get(num_digits..num_digits + len)?;
+        out.push(decode_legacy_ident(ident));
+        rest = &rest[num_digits + len..];
+    }
+
+    let is_hash = |x: &String| {
+        x.len() == 17 && x.starts_with('h') && x[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    };
+    if matches!(out.last(), Some(x) if is_hash(x)) {
+        out.pop();
+    }
+
+    if out.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+fn decode_legacy_ident(ident: &str) -> String {
+    // A leading `_` is inserted if the identifier starts with `$`
+    let ident = if ident.starts_with("_$") {
+        &ident[1..]
+    } else {
+        ident
+    };
+
+    const ESCAPES: &[(&str, &str)] = &[
+        ("$SP$", "@"),
+        ("$BP$", "*"),
+        ("$RF$", "&"),
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$LP$", "("),
+        ("$RP$", ")"),
+        ("$C$", ","),
+        ("$u20$", " "),
+        ("$u22$", "\""),
+        ("$u27$", "'"),
+        ("$u2b$", "+"),
+        ("$u3b$", ";"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u7e$", "~"),
+        ("..", "::"),
+    ];
+
+    let mut out = ident.to_owned();
+    for (from, to) in ESCAPES {
+        out = out.replace(from, to);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_legacy_symbol() {
+        assert_eq!(
+            legacy_symbol_path(
+                "_ZN4sort19criterion_benchmark28_$u7b$$u7b$closure$u7d$$u7d$17h0123456789abcdefE"
+            ),
+            Some(vec![
+                "sort".to_owned(),
+                "criterion_benchmark".to_owned(),
+                "{{closure}}".to_owned(),
+            ])
+        );
+        assert_eq!(
+            legacy_symbol_path("_ZN4core5slice4sort7recurse17h0123456789abcdefE"),
+            Some(vec![
+                "core".to_owned(),
+                "slice".to_owned(),
+                "sort".to_owned(),
+                "recurse".to_owned(),
+            ])
+        );
+        assert_eq!(legacy_symbol_path("__aeabi_memcpy"), None);
+    }
+}
@@ -1,20 +1,139 @@
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use futures::future;
 use rand::Rng;
-use std::pin::Pin;
+use std::{collections::VecDeque, pin::Pin};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
     sync::oneshot,
     time::{self, Duration},
 };
 
+use super::{
+    budget::SuiteBudget, capacity, clock::ClockService, error::ProtocolViolation,
+    progress::Progress, targets::MeasurementFlag, timing::Timings,
+};
 use crate::{bencher::protocol, utils::async_buf_read_skip_until_pattern};
 
+#[cfg(test)]
+mod halfduplex;
 mod slip;
 
+/// How long to wait for the Target program to send the next message when it
+/// isn't running a benchmark
+pub(super) const REPLY_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Generate a handshake nonce. The nonce doesn't include any bytes from
+/// `HANDSHAKE_MAGIC` or `HANDSHAKE_END_MAGIC`, so no part of the nonce can be
+/// mistaken for (a part of) either of them, no matter where in the stream the
+/// receiver starts looking.
+fn generate_nonce(rng: &mut impl Rng) -> [u8; protocol::HANDSHAKE_NONCE_LEN] {
+    let mut nonce = [0u8; protocol::HANDSHAKE_NONCE_LEN];
+    for x in nonce.iter_mut() {
+        *x = loop {
+            let b: u8 = rng.gen();
+            if !protocol::HANDSHAKE_MAGIC.contains(&b)
+                && !protocol::HANDSHAKE_END_MAGIC.contains(&b)
+            {
+                break b;
+            }
+        };
+    }
+    nonce
+}
+
+/// The kind of a [`protocol::DownstreamMessage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReplyKind {
+    Greeting,
+    Continue,
+    Instant,
+    /// `EchoRequest`, or `Continue` to end the link check
+    Echo,
+}
+
+impl ReplyKind {
+    fn of(msg: &protocol::DownstreamMessage<String>) -> Self {
+        match msg {
+            protocol::DownstreamMessage::Greeting { .. } => Self::Greeting,
+            protocol::DownstreamMessage::Continue | protocol::DownstreamMessage::SkipRemaining => {
+                Self::Continue
+            }
+            protocol::DownstreamMessage::Instant(_) => Self::Instant,
+            protocol::DownstreamMessage::EchoRequest { .. } => Self::Echo,
+        }
+    }
+
+    /// Check if the Target program waiting for a message of kind `self`
+    /// accepts a message of kind `kind`.
+    fn accepts(self, kind: Self) -> bool {
+        self == kind || (self == Self::Echo && kind == Self::Continue)
+    }
+
+    /// Get the kind of the reply the Target program waits for after sending
+    /// `msg`. `link_check` indicates whether [`protocol::FEATURE_LINK_CHECK`]
+    /// was requested.
+    fn awaited_after(
+        msg: &protocol::UpstreamMessage<String, Vec<u64>>,
+        link_check: bool,
+    ) -> Option<Self> {
+        match msg {
+            protocol::UpstreamMessage::GreetingReply { .. } if link_check => Some(Self::Echo),
+            protocol::UpstreamMessage::EchoReply { .. } => Some(Self::Echo),
+            protocol::UpstreamMessage::GetInstant => Some(Self::Instant),
+            protocol::UpstreamMessage::MeasurementComplete { .. }
+            | protocol::UpstreamMessage::BenchmarkFailed { .. }
+            | protocol::UpstreamMessage::FinishedBenchmarkGroup => Some(Self::Continue),
+            _ => None,
+        }
+    }
+}
+
+/// An error indicating that the Target program stopped communicating
+/// properly, e.g., because it crashed
+#[derive(thiserror::Error, Debug)]
+pub(super) enum TargetFault {
+    #[error("Failed to receive a message from the Target program.")]
+    Link,
+    #[error("Timed out while waiting for a message from the Target program.")]
+    Timeout,
+    #[error("The benchmark '{id}' didn't complete within {limit:?}.")]
+    BenchTimeout { id: String, limit: Duration },
+    /// Reported by a Target program built with `panic_reset`
+    #[error("The Target program panicked: {message}")]
+    Panicked { message: String },
+}
+
 pub(super) struct TargetLink<Stream> {
     reader: BufReader<ReadHalf<Stream>>,
     writer: WriteHalf<Stream>,
+    timings: Timings,
+    clock: ClockService,
+    progress: Progress,
+    /// The capacities reported by `GreetingReply`
+    capacities: Option<protocol::TargetCapacities>,
+    /// The kind of the message the Target program is waiting for. We must
+    /// not send anything while this is `None` because the link might be
+    /// half-duplex. See the turn-taking rules in [`protocol`].
+    awaited: Option<ReplyKind>,
+    /// Whether the greeting sent to the Target program requested
+    /// [`protocol::FEATURE_LINK_CHECK`]
+    link_check: bool,
+    /// The messages deferred by [`Self::send`]
+    pending: VecDeque<protocol::DownstreamMessage<String>>,
+    /// The last message received was `FinishedBenchmarkGroup`. Another one
+    /// right after it is a duplicate because each group starts with
+    /// `BeginningBenchmarkGroup`.
+    after_group_end: bool,
+    /// The limit on the time a single benchmark may take, enforced by
+    /// [`Self::recv_timeout`]
+    bench_timeout: Option<Duration>,
+    /// The time budget of the whole run. See [`Self::set_suite_budget`].
+    suite_budget: Option<SuiteBudget>,
+    /// See [`Self::set_measurement_flag`].
+    measurement_flag: Option<MeasurementFlag>,
+    /// The values of the `SampleChunk`s received since the last
+    /// `MeasurementStart`, to be merged into the next `MeasurementComplete`
+    sample_chunks: Vec<u64>,
 }
 
 impl<Stream: AsyncRead + AsyncWrite> TargetLink<Stream> {
@@ -30,15 +149,7 @@ impl<Stream: AsyncRead + AsyncWrite> TargetLink<Stream> {
         //   Process 2: Send `handshake_packet` repeatedly until Process 1
         //              completes.
         log::debug!("Performing the handshake stage 1");
-        let mut nonce: [u8; protocol::HANDSHAKE_NONCE_LEN] = rand::thread_rng().gen();
-        for x in nonce.iter_mut() {
-            if *x == protocol::HANDSHAKE_MAGIC[0] || *x == protocol::HANDSHAKE_END_MAGIC[0] {
-                *x = 255;
-                assert!(
-                    *x != protocol::HANDSHAKE_MAGIC[0] && *x != protocol::HANDSHAKE_END_MAGIC[0]
-                );
-            }
-        }
+        let nonce = generate_nonce(&mut rand::thread_rng());
         let mut handshake_packet = protocol::HANDSHAKE_MAGIC.to_owned();
         handshake_packet.extend_from_slice(&nonce);
         log::trace!("handshake_packet = {:?}", handshake_packet);
@@ -148,7 +259,10 @@ impl<Stream: AsyncRead + AsyncWrite> TargetLink<Stream> {
                         return Ok::<(), anyhow::Error>(());
                     }
                     _ => {
-                        bail!("Unexpected handshake end response byte: {}", buf[0]);
+                        return Err(
+                            anyhow!("Unexpected handshake end response byte: {}", buf[0])
+                                .context(ProtocolViolation),
+                        );
                     }
                 }
             }
@@ -171,23 +285,527 @@ impl<Stream: AsyncRead + AsyncWrite> TargetLink<Stream> {
             .await
             .context("Timed out while waiting for handshake completion.")??;
 
-        Ok(Self { reader, writer })
+        Ok(Self {
+            reader,
+            writer,
+            timings: Timings::default(),
+            clock: ClockService::default(),
+            progress: Progress::default(),
+            capacities: None,
+            awaited: Some(ReplyKind::Greeting),
+            link_check: false,
+            pending: VecDeque::new(),
+            after_group_end: false,
+            bench_timeout: None,
+            suite_budget: None,
+            measurement_flag: None,
+            sample_chunks: Vec::new(),
+        })
     }
 
+    /// Receive a message. Fails with [`TargetFault::Link`] if the link is
+    /// broken or the Target program violated the protocol, or with
+    /// [`TargetFault::Panicked`] if the Target program reported a panic.
     pub(super) async fn recv(&mut self) -> Result<protocol::UpstreamMessage<String, Vec<u64>>> {
-        let frame = slip::read_frame(&mut self.reader).await?;
-        log::trace!("Received a SLIP frame {:?}", frame);
-        let msg = serde_cbor::from_slice(&frame)
-            .context("Failed to parse the received UpstreamMessage packet.")?;
-        log::debug!("recv: {:?}", msg);
+        match self.recv_inner().await.context(TargetFault::Link)? {
+            protocol::UpstreamMessage::Panicked { message } => {
+                Err(TargetFault::Panicked { message }.into())
+            }
+            msg => Ok(msg),
+        }
+    }
+
+    /// Receive a message like [`Self::recv`], failing with
+    /// [`TargetFault::Timeout`] if none arrives within `timeout`, or with
+    /// [`TargetFault::BenchTimeout`] if the current benchmark exceeds the
+    /// limit set by [`Self::set_bench_timeout`] first.
+    pub(super) async fn recv_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<protocol::UpstreamMessage<String, Vec<u64>>> {
+        let bench_remaining = match (self.bench_timeout, self.progress.current_since()) {
+            (Some(limit), Some(since)) => {
+                Some(limit.checked_sub(since.elapsed()).unwrap_or_default())
+            }
+            _ => None,
+        };
+
+        match bench_remaining {
+            Some(remaining) if remaining < timeout => {
+                match time::timeout(remaining, self.recv()).await {
+                    Ok(result) => result,
+                    Err(_) => Err(TargetFault::BenchTimeout {
+                        id: self.progress.current().unwrap().to_owned(),
+                        limit: self.bench_timeout.unwrap(),
+                    }
+                    .into()),
+                }
+            }
+            _ => time::timeout(timeout, self.recv())
+                .await
+                .map_err(|_| TargetFault::Timeout)?,
+        }
+    }
+
+    /// Set the limit on the time a single benchmark may take from
+    /// `RunningBenchmark` until the next benchmark starts or its group
+    /// finishes. `None` removes the limit.
+    pub(super) fn set_bench_timeout(&mut self, limit: Option<Duration>) {
+        self.bench_timeout = limit;
+    }
+
+    /// Set the time budget of the whole run. Once running another benchmark
+    /// would exceed it, `Continue` sent in reply to `MeasurementComplete`,
+    /// `BenchmarkFailed`, or `FinishedBenchmarkGroup` is replaced with
+    /// `SkipRemaining`.
+    pub(super) fn set_suite_budget(&mut self, budget: Option<SuiteBudget>) {
+        self.suite_budget = budget;
+    }
+
+    /// Remove the time budget set by [`Self::set_suite_budget`] and return
+    /// it.
+    pub(super) fn take_suite_budget(&mut self) -> Option<SuiteBudget> {
+        self.suite_budget.take()
+    }
+
+    /// Set a flag to keep updated with whether the Target program is taking
+    /// measurements.
+    pub(super) fn set_measurement_flag(&mut self, flag: Option<MeasurementFlag>) {
+        if let Some(flag) = &flag {
+            flag.set(false);
+        }
+        self.measurement_flag = flag;
+    }
+
+    async fn recv_inner(&mut self) -> Result<protocol::UpstreamMessage<String, Vec<u64>>> {
+        let (msg, received_at) = loop {
+            let frame = slip::read_frame(&mut self.reader).await?;
+            let received_at = std::time::Instant::now();
+            log::trace!("Received a SLIP frame {:?}", frame);
+            let msg = serde_cbor::from_slice(&frame)
+                .context("Failed to parse the received UpstreamMessage packet.")
+                .context(ProtocolViolation)?;
+            log::debug!("recv: {:?}", msg);
+
+            // Answering a duplicate `FinishedBenchmarkGroup` would leave the
+            // Target program with an extra `Continue`, and passing it to the
+            // caller would make it count the group twice, so drop it here.
+            // The original one has been or will be answered.
+            let group_end = matches!(msg, protocol::UpstreamMessage::FinishedBenchmarkGroup);
+            if group_end && self.after_group_end {
+                log::warn!("Ignoring a duplicate `FinishedBenchmarkGroup`");
+                continue;
+            }
+            self.after_group_end = group_end;
+
+            // Merge the streamed samples into `MeasurementComplete` so that
+            // the callers see the measurement in one piece
+            match msg {
+                protocol::UpstreamMessage::SampleChunk { values } => {
+                    self.sample_chunks.extend(values);
+                    continue;
+                }
+                protocol::UpstreamMessage::MeasurementStart { .. } => self.sample_chunks.clear(),
+                _ => {}
+            }
+            let mut msg = msg;
+            if let protocol::UpstreamMessage::MeasurementComplete { values, .. } = &mut msg {
+                if !self.sample_chunks.is_empty() {
+                    self.sample_chunks.append(values);
+                    std::mem::swap(values, &mut self.sample_chunks);
+                }
+            }
+
+            break (msg, received_at);
+        };
+        self.timings.record_message(&msg, received_at);
+        self.progress.record_message(&msg, received_at);
+        if let Some(suite_budget) = &mut self.suite_budget {
+            suite_budget.record_message(&msg, received_at);
+        }
+        self.clock.record_message(received_at);
+        if let protocol::UpstreamMessage::MeasurementStart {
+            warm_up_round_trips,
+            ..
+        } = &msg
+        {
+            self.clock.record_target_round_trips(warm_up_round_trips);
+        }
+
+        if let Some(flag) = &self.measurement_flag {
+            use protocol::UpstreamMessage::*;
+            match &msg {
+                MeasurementStart { .. } => flag.set(true),
+                // Sent during a measurement
+                Progress { .. } | SampleTimestamps { .. } | MeasurementWarning { .. } => {}
+                _ => flag.set(false),
+            }
+        }
+
+        match &msg {
+            protocol::UpstreamMessage::GreetingReply { capacities, .. } => {
+                self.capacities = Some(*capacities);
+            }
+            protocol::UpstreamMessage::RunningBenchmark { id } => {
+                if let Some(capacities) = &self.capacities {
+                    let components = capacity::possibly_truncated_components(capacities, id);
+                    if !components.is_empty() {
+                        log::warn!(
+                            "The {} of '{}' might have been truncated by the Target program.",
+                            components.join(" and "),
+                            id
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(kind) = ReplyKind::awaited_after(&msg, self.link_check) {
+            if let Some(awaited) = self.awaited {
+                return Err(anyhow!(
+                    "Received {:?} while the Target program is supposed to be \
+                    waiting for {:?}.",
+                    msg,
+                    awaited
+                )
+                .context(ProtocolViolation));
+            }
+            self.awaited = Some(kind);
+            self.flush_pending().await?;
+        }
+
         Ok(msg)
     }
 
+    /// Get the wall-clock accounting of the messages received so far.
+    pub(super) fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    pub(super) fn timings_mut(&mut self) -> &mut Timings {
+        &mut self.timings
+    }
+
+    /// Get the clock answering `GetInstant`. Move it to the next
+    /// `TargetLink` to keep the issued instants comparable.
+    pub(super) fn clock(&self) -> &ClockService {
+        &self.clock
+    }
+
+    pub(super) fn clock_mut(&mut self) -> &mut ClockService {
+        &mut self.clock
+    }
+
+    /// Answer `GetInstant`.
+    pub(super) async fn send_instant(&mut self) -> Result<()> {
+        let instant = self.clock.issue(std::time::Instant::now());
+        self.send(&protocol::DownstreamMessage::Instant(instant))
+            .await
+    }
+
+    /// Get the progress of the Target program.
+    pub(super) fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// Send a message. If the Target program isn't waiting for a `Continue`
+    /// yet, a `Continue` is deferred until it is. Sending any other message
+    /// out of turn is an error.
     pub(super) async fn send(&mut self, msg: &protocol::DownstreamMessage<String>) -> Result<()> {
+        let kind = ReplyKind::of(msg);
+        if !matches!(self.awaited, Some(awaited) if awaited.accepts(kind)) {
+            // A front-end may acknowledge a result before the Target program
+            // asks for it, while the `Instant`s for the `GetInstant`s in
+            // between overtake it. Nothing else can be reordered.
+            if kind != ReplyKind::Continue {
+                bail!(
+                    "Attempted to send {:?} while the Target program is \
+                    waiting for {:?}.",
+                    msg,
+                    self.awaited
+                );
+            }
+            log::debug!("Deferring {:?} until the Target program asks for it", msg);
+            self.pending.push_back(msg.clone());
+            return Ok(());
+        }
+
+        self.send_now(msg).await
+    }
+
+    /// Send the first deferred message matching `self.awaited`, if any.
+    async fn flush_pending(&mut self) -> Result<()> {
+        let awaited = self.awaited;
+        if let Some(i) = (self.pending.iter())
+            .position(|msg| matches!(awaited, Some(awaited) if awaited.accepts(ReplyKind::of(msg))))
+        {
+            let msg = self.pending.remove(i).unwrap();
+            self.send_now(&msg).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_now(&mut self, msg: &protocol::DownstreamMessage<String>) -> Result<()> {
+        let msg = match (msg, &self.suite_budget, self.awaited) {
+            (
+                protocol::DownstreamMessage::Continue,
+                Some(suite_budget),
+                Some(ReplyKind::Continue),
+            ) if suite_budget.is_exhausted(std::time::Instant::now()) => {
+                log::debug!("The time budget is exhausted; skipping the remaining benchmarks");
+                &protocol::DownstreamMessage::SkipRemaining
+            }
+            _ => msg,
+        };
+
         log::debug!("send: {:?}", msg);
         let frame = serde_cbor::to_vec(msg).unwrap();
         log::trace!("Sending a SLIP frame {:?}", frame);
         slip::write_frame(&mut self.writer, &frame).await?;
+        self.clock.record_reply(std::time::Instant::now());
+        if let protocol::DownstreamMessage::Greeting { config } = msg {
+            self.link_check = config.feature_bits & protocol::FEATURE_LINK_CHECK != 0;
+        }
+        self.awaited = None;
         Ok(())
     }
+
+    /// Get the number of messages deferred by [`Self::send`] and not sent
+    /// yet.
+    #[cfg(test)]
+    pub(super) fn num_pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tests::{fast_overrides, loopback_config, start_loopback, start_loopback_on};
+
+    /// Send `msgs` at once, ignoring the turn-taking rules. Used to simulate
+    /// replies that the Target program receives coalesced.
+    async fn send_out_of_turn<Stream: AsyncRead + AsyncWrite>(
+        target_link: &mut TargetLink<Stream>,
+        msgs: &[protocol::DownstreamMessage<String>],
+    ) -> Result<()> {
+        let mut bytes = Vec::new();
+        for msg in msgs {
+            log::debug!("send (out of turn): {:?}", msg);
+            slip::write_frame(&mut bytes, &serde_cbor::to_vec(msg).unwrap()).await?;
+        }
+        target_link.writer.write_all(&bytes).await?;
+        target_link.awaited = None;
+        Ok(())
+    }
+
+    /// Returns every byte value in turn, including those from the magic
+    /// sequences
+    fn adversarial_rng() -> impl Rng {
+        rand::rngs::mock::StepRng::new(0, 1)
+    }
+
+    #[test]
+    fn nonce_excludes_magic_bytes() {
+        let mut rng = adversarial_rng();
+        for _ in 0..256 {
+            let nonce = generate_nonce(&mut rng);
+            for b in nonce.iter() {
+                assert!(!protocol::HANDSHAKE_MAGIC.contains(b), "{:?}", nonce);
+                assert!(!protocol::HANDSHAKE_END_MAGIC.contains(b), "{:?}", nonce);
+            }
+        }
+    }
+
+    #[test]
+    fn nonce_no_false_match() {
+        let mut rng = adversarial_rng();
+        for _ in 0..256 {
+            let nonce = generate_nonce(&mut rng);
+            let mut handshake_packet = protocol::HANDSHAKE_MAGIC.to_owned();
+            handshake_packet.extend_from_slice(&nonce);
+
+            // Repeated handshake requests followed by a handshake end request,
+            // which is what the Target program sees
+            let mut stream = handshake_packet.repeat(3);
+            stream.extend_from_slice(protocol::HANDSHAKE_END_MAGIC);
+
+            // Each magic sequence must be found only where it was placed
+            for (i, window) in stream.windows(protocol::HANDSHAKE_MAGIC.len()).enumerate() {
+                assert_eq!(
+                    window == protocol::HANDSHAKE_MAGIC,
+                    i % handshake_packet.len() == 0 && i < handshake_packet.len() * 3,
+                    "{:?} at {}",
+                    nonce,
+                    i
+                );
+            }
+            for (i, window) in (stream.windows(protocol::HANDSHAKE_END_MAGIC.len())).enumerate() {
+                assert_eq!(
+                    window == protocol::HANDSHAKE_END_MAGIC,
+                    i == handshake_packet.len() * 3,
+                    "{:?} at {}",
+                    nonce,
+                    i
+                );
+            }
+
+            // The first byte of either magic sequence only appears at the
+            // start of each of them
+            for (i, &b) in stream.iter().enumerate() {
+                if b == protocol::HANDSHAKE_MAGIC[0] || b == protocol::HANDSHAKE_END_MAGIC[0] {
+                    assert_eq!(i % handshake_packet.len(), 0, "{:?} at {}", nonce, i);
+                }
+            }
+        }
+    }
+
+    /// The Target program's end of a link that delivers every
+    /// `FinishedBenchmarkGroup` twice
+    struct DuplicateGroupEnd(std::net::TcpStream);
+
+    impl std::io::Read for DuplicateGroupEnd {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl std::io::Write for DuplicateGroupEnd {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let name = b"FinishedBenchmarkGroup";
+            if buf.windows(name.len()).any(|w| w == name) {
+                self.0.write_all(buf)?;
+            }
+            self.0.write_all(buf)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_turn_instant_rejected() {
+        let (mut target_link, target_thread) =
+            start_loopback(protocol::Mode::List, Default::default(), 0, |_| {}).await;
+
+        // The Target program hasn't sent `GetInstant`
+        let result = target_link
+            .send(&protocol::DownstreamMessage::Instant(
+                protocol::Instant::from_nanos(0),
+            ))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(target_link.num_pending(), 0);
+
+        loop {
+            let msg = time::timeout(super::REPLY_TIMEOUT, target_link.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if let protocol::UpstreamMessage::End = msg {
+                break;
+            }
+        }
+        target_thread.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplicate_group_end_ignored() {
+        let (mut target_link, target_thread) = start_loopback_on(
+            loopback_config(protocol::Mode::Benchmark, fast_overrides(), 0),
+            DuplicateGroupEnd,
+            |stream| stream,
+            |c| {
+                for &name in &["group1", "group2"] {
+                    let mut group = c.benchmark_group(name);
+                    group.bench_function("a", |b| b.iter(|| ()));
+                    group.finish();
+                }
+            },
+        )
+        .await;
+
+        let mut num_group_ends = 0;
+        let mut num_measurements = 0;
+        loop {
+            let msg = time::timeout(super::REPLY_TIMEOUT, target_link.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            match msg {
+                protocol::UpstreamMessage::GetInstant => {
+                    target_link.send_instant().await.unwrap();
+                    continue;
+                }
+                protocol::UpstreamMessage::MeasurementComplete { .. } => num_measurements += 1,
+                protocol::UpstreamMessage::FinishedBenchmarkGroup => num_group_ends += 1,
+                protocol::UpstreamMessage::End => break,
+                _ => continue,
+            }
+            target_link
+                .send(&protocol::DownstreamMessage::Continue)
+                .await
+                .unwrap();
+        }
+
+        target_thread.join().unwrap();
+
+        assert_eq!(num_group_ends, 2);
+        assert_eq!(num_measurements, 2);
+        assert_eq!(target_link.num_pending(), 0);
+    }
+
+    /// Deliver the reply to `MeasurementComplete` late, coalesced with the
+    /// reply to the following `FinishedBenchmarkGroup`. The Target program
+    /// must not lose the latter while sending `FinishedBenchmarkGroup`.
+    #[tokio::test]
+    async fn coalesced_continues_tolerated() {
+        let (mut target_link, target_thread) =
+            start_loopback(protocol::Mode::Benchmark, fast_overrides(), 0, |c| {
+                let mut group = c.benchmark_group("coalesced");
+                group.bench_function("f", |b| b.iter(|| ()));
+                group.finish();
+            })
+            .await;
+
+        let mut messages = Vec::new();
+        loop {
+            let msg = time::timeout(super::REPLY_TIMEOUT, target_link.recv())
+                .await
+                .expect("the Target program hung")
+                .unwrap();
+            match &msg {
+                protocol::UpstreamMessage::GetInstant => {
+                    target_link.send_instant().await.unwrap();
+                    continue;
+                }
+                protocol::UpstreamMessage::MeasurementComplete { .. } => {
+                    time::delay_for(Duration::from_millis(50)).await;
+                    send_out_of_turn(
+                        &mut target_link,
+                        &[
+                            protocol::DownstreamMessage::Continue,
+                            protocol::DownstreamMessage::Continue,
+                        ],
+                    )
+                    .await
+                    .unwrap();
+                }
+                // Already answered
+                protocol::UpstreamMessage::FinishedBenchmarkGroup => {}
+                protocol::UpstreamMessage::End => break,
+                _ => {}
+            }
+            messages.push(msg);
+        }
+
+        target_thread.join().unwrap();
+
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, protocol::UpstreamMessage::FinishedBenchmarkGroup)));
+    }
 }
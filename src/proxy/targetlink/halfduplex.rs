@@ -0,0 +1,226 @@
+//! Simulated half-duplex link, used to test the turn-taking rules (see
+//! [`protocol`])
+use futures::ready;
+use std::{
+    io::{self, Read, Write},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time,
+};
+
+use crate::{
+    bencher::{protocol, BenchmarkId, Criterion},
+    proxy::{
+        targetlink::REPLY_TIMEOUT,
+        tests::{fast_overrides, loopback_config, start_loopback_on},
+    },
+};
+
+const TARGET: usize = 0;
+const PROXY: usize = 1;
+
+/// The state of the line shared by both ends
+#[derive(Debug, Default)]
+struct Line {
+    /// Report simultaneous writes as errors. The handshake is exempt from
+    /// the turn-taking rules, so this is set after the greeting.
+    strict: bool,
+    /// The number of bytes written by each end
+    written: [u64; 2],
+    /// The number of bytes read by each end
+    read: [u64; 2],
+}
+
+impl Line {
+    /// Reserve `len` bytes for writing by `side`. Fails if the data sent by
+    /// the other end hasn't been completely received yet, in which case
+    /// both ends would be driving the line at the same time.
+    fn begin_write(&mut self, side: usize, len: usize) -> io::Result<()> {
+        if self.strict && self.written[1 - side] != self.read[side] {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "simultaneous write on a half-duplex link",
+            ));
+        }
+        self.written[side] += len as u64;
+        Ok(())
+    }
+}
+
+/// The Target program's end of the line
+struct TargetEnd {
+    stream: std::net::TcpStream,
+    line: Arc<Mutex<Line>>,
+}
+
+impl Read for TargetEnd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = self.stream.read(buf)?;
+        self.line.lock().unwrap().read[TARGET] += num_bytes as u64;
+        Ok(num_bytes)
+    }
+}
+
+impl Write for TargetEnd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.lock().unwrap().begin_write(TARGET, buf.len())?;
+        self.stream.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// The Proxy program's end of the line
+struct ProxyEnd {
+    stream: TcpStream,
+    line: Arc<Mutex<Line>>,
+    /// The number of bytes reserved by an incomplete `poll_write`
+    reserved: usize,
+}
+
+impl AsyncRead for ProxyEnd {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let num_bytes = ready!(Pin::new(&mut this.stream).poll_read(cx, buf))?;
+        this.line.lock().unwrap().read[PROXY] += num_bytes as u64;
+        Poll::Ready(Ok(num_bytes))
+    }
+}
+
+impl AsyncWrite for ProxyEnd {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.reserved == 0 {
+            this.line.lock().unwrap().begin_write(PROXY, buf.len())?;
+            this.reserved = buf.len();
+        }
+
+        let result = ready!(Pin::new(&mut this.stream).poll_write(cx, &buf[..this.reserved]));
+
+        // Return the unused portion of the reservation
+        let num_bytes = *result.as_ref().unwrap_or(&0);
+        this.line.lock().unwrap().written[PROXY] -= (this.reserved - num_bytes) as u64;
+        this.reserved = 0;
+
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+fn session(c: &mut Criterion) {
+    let mut group = c.benchmark_group("group1");
+    group.bench_function("a", |b| b.iter(|| ()));
+    group.bench_function("b", |b| b.iter(|| [0u8; 64]));
+    group.finish();
+
+    let mut timestamps = [0; crate::bencher::MAX_SAMPLES];
+    let mut group = c.benchmark_group("group2");
+    group.record_sample_timestamps(&mut timestamps);
+    for i in 0..3 {
+        group.bench_with_input(BenchmarkId::from_parameter(&i), &i, |b, &i| {
+            b.iter(|| i * 2)
+        });
+    }
+    group.finish();
+
+    c.bench_function("standalone", |b| b.iter(|| ()));
+}
+
+/// Run a full session over a half-duplex link, with the front-end sending
+/// `Continue` before the Target program asks for it.
+#[tokio::test]
+async fn full_session() {
+    let line = Arc::new(Mutex::new(Line::default()));
+
+    let (mut target_link, target_thread) = start_loopback_on(
+        loopback_config(
+            protocol::Mode::Benchmark,
+            fast_overrides(),
+            protocol::FEATURE_LOOP_OVERHEAD,
+        ),
+        {
+            let line = Arc::clone(&line);
+            |stream| TargetEnd { stream, line }
+        },
+        |stream| ProxyEnd {
+            stream,
+            line: Arc::clone(&line),
+            reserved: 0,
+        },
+        session,
+    )
+    .await;
+
+    // `start_loopback_on` has sent the greeting, which the Target program
+    // was waiting for. Check everything after it.
+    line.lock().unwrap().strict = true;
+
+    let origin = std::time::Instant::now();
+    let mut num_groups = 0;
+    let mut num_measurements = 0;
+    loop {
+        let msg = time::timeout(REPLY_TIMEOUT, target_link.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match msg {
+            protocol::UpstreamMessage::GetInstant => {
+                let instant = protocol::Instant::from_nanos(origin.elapsed().as_nanos() as u64);
+                target_link
+                    .send(&protocol::DownstreamMessage::Instant(instant))
+                    .await
+                    .unwrap();
+            }
+            // Send `Continue`s early. They must be deferred until
+            // `FinishedBenchmarkGroup` and `MeasurementComplete`, letting
+            // the `Instant`s for the `GetInstant`s in between overtake them.
+            protocol::UpstreamMessage::BeginningBenchmarkGroup { .. } => {
+                num_groups += 1;
+                target_link
+                    .send(&protocol::DownstreamMessage::Continue)
+                    .await
+                    .unwrap();
+            }
+            protocol::UpstreamMessage::Warmup { .. } => {
+                target_link
+                    .send(&protocol::DownstreamMessage::Continue)
+                    .await
+                    .unwrap();
+            }
+            protocol::UpstreamMessage::MeasurementComplete { .. } => {
+                num_measurements += 1;
+            }
+            protocol::UpstreamMessage::End => break,
+            _ => {}
+        }
+    }
+
+    target_thread.join().unwrap();
+
+    assert_eq!(num_groups, 3);
+    assert_eq!(num_measurements, 6);
+    assert_eq!(target_link.num_pending(), 0);
+}
@@ -111,6 +111,14 @@ impl<T: AsyncBufRead + Unpin> Future for ReadFrame<'_, T> {
                 Err(e) => return Poll::Ready(Err(FrameExtractorError::Io(e))),
             };
 
+            if buffer.is_empty() {
+                // The stream was closed (e.g., the Target program panicked
+                // on the host)
+                break 'result Err(FrameExtractorError::Io(
+                    std::io::ErrorKind::UnexpectedEof.into(),
+                ));
+            }
+
             for &b in buffer {
                 consumed += 1;
 
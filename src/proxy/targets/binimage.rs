@@ -0,0 +1,189 @@
+//! Raw binary images (like `objcopy -O binary`) for targets that can't boot
+//! an ELF executable
+use anyhow::{bail, Context, Result};
+use std::{ffi::OsString, future::Future, pin::Pin};
+
+use super::{BuildSetup, MemoryRegion, MemoryX};
+use crate::cargo::{CompiledExecutable, ImageFormat};
+
+/// The maximum size of a binary image. The loadable segments of an
+/// executable placed in distant memory regions would produce a huge image.
+const MAX_IMAGE_LEN: u64 = 64 << 20;
+
+/// Wraps a [`BuildSetup`] to convert the built executable to a raw binary
+/// image, which is then programmed instead of the executable. The image
+/// starts at the origin of the memory region (of the wrapped setup's
+/// `memory.x`) containing the executable's first loadable segment.
+// Not used by the built-in targets, which can all program ELF executables
+#[allow(dead_code)]
+pub struct BinImageSetup {
+    inner: Box<dyn BuildSetup>,
+    dir: tempdir::TempDir,
+}
+
+#[allow(dead_code)]
+impl BinImageSetup {
+    pub async fn new(inner: Box<dyn BuildSetup>) -> Result<Self, std::io::Error> {
+        let dir = tokio::task::spawn_blocking(|| tempdir::TempDir::new("farcri-rs"))
+            .await
+            .unwrap()?;
+        Ok(Self { inner, dir })
+    }
+}
+
+impl BuildSetup for BinImageSetup {
+    fn build_envs(&self) -> Vec<(OsString, OsString)> {
+        self.inner.build_envs()
+    }
+
+    fn rustc_flags(&self) -> Vec<String> {
+        self.inner.rustc_flags()
+    }
+
+    fn memory_x(&self) -> Option<MemoryX> {
+        self.inner.memory_x()
+    }
+
+    fn disable_memory_x(&mut self) -> bool {
+        self.inner.disable_memory_x()
+    }
+
+    fn post_process(
+        &self,
+        exe: CompiledExecutable,
+    ) -> Pin<Box<dyn Future<Output = Result<CompiledExecutable>> + '_>> {
+        Box::pin(async move {
+            let exe = self.inner.post_process(exe).await?;
+
+            let elf_bytes = tokio::fs::read(&exe.path)
+                .await
+                .with_context(|| format!("Failed to read '{}'.", exe.path.display()))?;
+            let regions = self.inner.memory_x().map_or_else(Vec::new, |x| x.regions);
+            let (base_address, image) = elf_to_bin(&elf_bytes, &regions)
+                .with_context(|| format!("Failed to convert '{}'.", exe.path.display()))?;
+
+            let path = self.dir.path().join(format!("{}.bin", exe.name));
+            tokio::fs::write(&path, &image)
+                .await
+                .with_context(|| format!("Failed to write '{}'.", path.display()))?;
+            log::debug!(
+                "Converted '{}' to '{}' ({} bytes at 0x{:08x})",
+                exe.path.display(),
+                path.display(),
+                image.len(),
+                base_address
+            );
+
+            Ok(CompiledExecutable {
+                format: ImageFormat::Bin { path, base_address },
+                ..exe
+            })
+        })
+    }
+}
+
+/// Convert an ELF executable to a binary image. Returns the image and its
+/// base address, derived from `regions` as described in [`layout`].
+fn elf_to_bin(elf_bytes: &[u8], regions: &[MemoryRegion]) -> Result<(u64, Vec<u8>)> {
+    let elf = goblin::elf::Elf::parse(elf_bytes).context("Failed to parse the executable.")?;
+
+    let mut segments = Vec::new();
+    for ph in elf.program_headers.iter() {
+        if ph.p_type != goblin::elf::program_header::PT_LOAD || ph.p_filesz == 0 {
+            continue;
+        }
+        let data = (elf_bytes.get(ph.file_range()))
+            .context("A loadable segment extends past the end of the file.")?;
+        // The image is loaded at the load addresses (LMA), like
+        // `objcopy -O binary` does
+        segments.push((ph.p_paddr, data));
+    }
+
+    layout(&segments, regions)
+}
+
+/// Place `(address, data)` pairs in a binary image. Returns the image and its
+/// base address, which is the origin of the region in `regions` containing
+/// the lowest address, or the lowest address itself if `regions` is empty.
+fn layout(segments: &[(u64, &[u8])], regions: &[MemoryRegion]) -> Result<(u64, Vec<u8>)> {
+    let start_address = match segments.iter().map(|&(address, _)| address).min() {
+        Some(x) => x,
+        None => bail!("The executable has no loadable segments."),
+    };
+    let base_address = if regions.is_empty() {
+        start_address
+    } else {
+        match regions
+            .iter()
+            .find(|region| region.contains(start_address, 1))
+        {
+            Some(region) => region.origin,
+            None => bail!(
+                "The first loadable segment (0x{:08x}) is outside the memory map.",
+                start_address
+            ),
+        }
+    };
+    let end_address = (segments.iter())
+        .map(|&(address, data)| address + data.len() as u64)
+        .max()
+        .unwrap();
+
+    let len = end_address - base_address;
+    if len > MAX_IMAGE_LEN {
+        bail!(
+            "The loadable segments span {} bytes (0x{:08x}..0x{:08x}), which is \
+            too large for a binary image.",
+            len,
+            base_address,
+            end_address
+        );
+    }
+
+    // Fill the gaps with the erased state of flash memory
+    let mut image = vec![0xff; len as usize];
+    for &(address, data) in segments {
+        let offset = (address - base_address) as usize;
+        image[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    Ok((base_address, image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_with_gap() {
+        let (base_address, image) =
+            layout(&[(0x0800_0004, &[3, 4]), (0x0800_0000, &[1, 2])], &[]).unwrap();
+        assert_eq!(base_address, 0x0800_0000);
+        assert_eq!(image, [1, 2, 0xff, 0xff, 3, 4]);
+
+        assert!(layout(&[], &[]).is_err());
+        assert!(layout(&[(0x0800_0000, &[1]), (0x2000_0000, &[2])], &[]).is_err());
+    }
+
+    #[test]
+    fn base_address_from_memory_map() {
+        let regions = [
+            MemoryRegion {
+                name: "FLASH".to_owned(),
+                origin: 0x0800_0000,
+                length: 0x8_0000,
+            },
+            MemoryRegion {
+                name: "RAM".to_owned(),
+                origin: 0x2000_0000,
+                length: 0x1_8000,
+            },
+        ];
+
+        let (base_address, image) = layout(&[(0x0800_0002, &[1, 2])], &regions).unwrap();
+        assert_eq!(base_address, 0x0800_0000);
+        assert_eq!(image, [0xff, 0xff, 1, 2]);
+
+        assert!(layout(&[(0x0010_0000, &[1])], &regions).is_err());
+    }
+}
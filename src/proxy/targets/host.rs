@@ -0,0 +1,166 @@
+//! The host computer as a target
+//!
+//! The Target program is built with `target_std` and runs as a subprocess of
+//! the Proxy program, making it possible to run benchmarks without any
+//! hardware (e.g., to test FarCri.rs itself). The measured values are in
+//! nanoseconds (see `crate::target::std_time`).
+use anyhow::{bail, Context as _, Result};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    process::Stdio,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command},
+};
+
+use super::{
+    Arch, Backend, BuildSetup, CompiledExecutable, ConnectOptions, DebugProbe, DynAsyncReadWrite,
+    Target, TargetFactory, Transport, ValueUnit,
+};
+use crate::bencher::protocol;
+
+/// The backend providing the host computer as a target
+pub(super) struct HostBackend;
+
+impl Backend for HostBackend {
+    fn name(&self) -> &str {
+        "host"
+    }
+
+    fn targets(&self) -> &[(&'static str, TargetFactory)] {
+        &[("host", super::default_factory::<Host>)]
+    }
+}
+
+/// The host computer
+#[derive(Debug, Default)]
+pub struct Host;
+
+impl Target for Host {
+    fn target_arch(&self) -> Arch {
+        Arch::Host
+    }
+
+    fn cargo_features(&self) -> &[&str] {
+        &["target_std"]
+    }
+
+    fn prepare_build(&self) -> Pin<Box<dyn Future<Output = Result<Box<dyn BuildSetup>>>>> {
+        Box::pin(async { Ok(Box::new(()) as _) })
+    }
+
+    fn connect(
+        &self,
+        _opts: &ConnectOptions,
+    ) -> Pin<Box<dyn Future<Output = Result<Box<dyn DebugProbe>>>>> {
+        Box::pin(async { Ok(Box::new(HostDebugProbe) as _) })
+    }
+
+    fn value_unit(&self) -> ValueUnit {
+        ValueUnit::Nanoseconds
+    }
+
+    fn runs_without_hardware(&self) -> bool {
+        true
+    }
+}
+
+struct HostDebugProbe;
+
+#[derive(thiserror::Error, Debug)]
+enum RunError {
+    #[error("SWO is not supported by this target")]
+    SwoUnsupported,
+}
+
+impl DebugProbe for HostDebugProbe {
+    fn program_and_get_output(
+        &mut self,
+        exe: &CompiledExecutable,
+        transport: Transport,
+    ) -> Pin<Box<dyn Future<Output = Result<DynAsyncReadWrite<'_>>> + '_>> {
+        let exe = exe.path.clone();
+
+        Box::pin(async move {
+            if let Transport::Swo = transport {
+                return Err(RunError::SwoUnsupported.into());
+            }
+
+            // The Target program connects to this port (see
+            // `crate::target::comm_std`)
+            let mut listener = TcpListener::bind(("127.0.0.1", 0))
+                .await
+                .context("Failed to open a port for the Target program.")?;
+            let addr = listener.local_addr()?;
+
+            log::info!("Starting '{}'", exe.display());
+
+            // The log output goes to stderr
+            let mut child = Command::new(&exe)
+                .env(protocol::STD_LINK_ADDR_ENV, addr.to_string())
+                .stdin(Stdio::null())
+                .kill_on_drop(true)
+                .spawn()
+                .with_context(|| format!("Failed to start '{}'.", exe.display()))?;
+
+            let stream = tokio::select! {
+                result = listener.accept() => {
+                    result.context("Failed to accept a connection from the Target program.")?.0
+                }
+                status = &mut child => {
+                    bail!(
+                        "The Target program exited ({}) before connecting to the \
+                        Proxy program.",
+                        status?
+                    );
+                }
+            };
+            stream.set_nodelay(true)?;
+
+            Ok(Box::pin(HostLink {
+                _child: child,
+                stream,
+            }) as _)
+        })
+    }
+}
+
+/// The connection to a Target program running on the host. The process is
+/// killed when this is dropped.
+struct HostLink {
+    _child: Child,
+    stream: TcpStream,
+}
+
+impl AsyncRead for HostLink {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for HostLink {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,133 @@
+//! Decoder for the ITM packet stream captured through SWO
+//!
+//! See Armv7-M Architecture Reference Manual, Appendix D4 "Debug ITM and DWT
+//! Packet Protocol".
+
+/// Extracts the payload of software source packets of a specific stimulus
+/// port from an ITM packet stream. The stream can be supplied in arbitrary
+/// chunks.
+#[derive(Debug)]
+pub(super) struct ItmDecoder {
+    port: u8,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Expecting a header
+    Header,
+    /// Inside a synchronization packet (a run of zero bytes terminated by
+    /// `0x80`)
+    Sync,
+    /// Expecting the payload of a source packet. `keep` indicates whether the
+    /// payload should be included in the output.
+    Payload { remaining: u8, keep: bool },
+    /// Expecting continuation bytes of a protocol packet (e.g., a timestamp)
+    Continuation,
+}
+
+impl ItmDecoder {
+    pub(super) fn new(port: u8) -> Self {
+        Self {
+            port,
+            state: State::Header,
+        }
+    }
+
+    /// Decode `bytes` and append the extracted payload to `out`.
+    pub(super) fn decode(&mut self, bytes: &[u8], out: &mut Vec<u8>) {
+        for &b in bytes {
+            self.state = match self.state {
+                State::Header => self.header(b),
+                State::Sync => match b {
+                    0x00 => State::Sync,
+                    0x80 => State::Header,
+                    // Malformed synchronization packet. Treat `b` as a header.
+                    _ => self.header(b),
+                },
+                State::Payload { remaining, keep } => {
+                    if keep {
+                        out.push(b);
+                    }
+                    if remaining > 1 {
+                        State::Payload {
+                            remaining: remaining - 1,
+                            keep,
+                        }
+                    } else {
+                        State::Header
+                    }
+                }
+                State::Continuation => {
+                    if b & 0x80 != 0 {
+                        State::Continuation
+                    } else {
+                        State::Header
+                    }
+                }
+            };
+        }
+    }
+
+    fn header(&self, b: u8) -> State {
+        match (b, b & 0b11) {
+            (0x00, _) => State::Sync,
+            (0x70, _) => {
+                log::warn!("ITM overflow - some bytes were lost");
+                State::Header
+            }
+            // Protocol packets (timestamps and extension packets). Skip the
+            // continuation bytes if there are any.
+            (_, 0b00) if b & 0x80 != 0 => State::Continuation,
+            (_, 0b00) => State::Header,
+            // Source packets
+            (_, size) => State::Payload {
+                remaining: 1 << (size - 1),
+                // Bit 2 distinguishes hardware source packets
+                keep: b & 0b100 == 0 && b >> 3 == self.port,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_chunks(port: u8, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut decoder = ItmDecoder::new(port);
+        let mut out = Vec::new();
+        for chunk in chunks {
+            decoder.decode(chunk, &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn software_packets() {
+        let stream: &[u8] = &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // synchronization
+            0x09, b'a', // port 1, 1 byte
+            0x01, b'x', // port 0, 1 byte
+            0x0a, b'b', b'c', // port 1, 2 bytes
+            0xc0, 0x81, 0x01, // local timestamp with continuation
+            0x0b, b'd', b'e', b'f', b'g', // port 1, 4 bytes
+            0x30, // local timestamp without continuation
+            0x70, // overflow
+            0x0f, 0xc0, 0xc0, 0xc0, 0xc0, // hardware source packet, 4 bytes
+            0x94, 0x80, 0x00, // global timestamp
+            0x09, 0x00, // port 1, 1 byte (zero)
+        ];
+        let expected = b"abcdefg\0";
+
+        assert_eq!(decode_chunks(1, &[stream]), expected);
+
+        // The result shouldn't depend on how the stream is split
+        for i in 0..stream.len() {
+            let (a, b) = stream.split_at(i);
+            assert_eq!(decode_chunks(1, &[a, b]), expected, "split at {}", i);
+        }
+
+        assert_eq!(decode_chunks(0, &[stream]), b"x");
+    }
+}
@@ -1,31 +1,251 @@
-use super::BuildSetup;
+use super::{BuildSetup, MemoryRegion, MemoryX};
 use std::{ffi::OsString, io::Error};
 
+/// A linker script fragment placing the link sections emitted by FarCri.rs
+/// (`farcri_benches` of `crate::registry`, `farcri_ids` of `crate::idtable`,
+/// and the build note `.note.farcri`), to be used along with
+/// `cortex-m-rt`'s `link.x`.
+///
+/// `link.x` doesn't know these sections, and the linker is free to place
+/// orphan sections anywhere, e.g., right after `.vector_table`, where they
+/// overlap `.text`. `KEEP` also prevents the sections from being discarded
+/// when `__start_*` references don't retain them (`-z start-stop-gc`).
+///
+/// The sections are placed in `FLASH` after the initial contents of `.data`.
+/// They can't follow `.rodata` directly because `cortex-m-rt` 0.6 places
+/// the initial contents of `.data` there by `AT(__erodata)`. `INSERT AFTER`
+/// refers to `.uninit`, which older versions of `link.x` lack, hence the
+/// minimum version of `cortex-m-rt` in `Cargo.toml`.
+const FARCRI_X: &str = "
+SECTIONS
+{
+  farcri_benches ALIGN(LOADADDR(.data) + SIZEOF(.data), 4) :
+  {
+    KEEP(*(farcri_benches));
+  } > FLASH
+
+  farcri_ids : ALIGN(4)
+  {
+    KEEP(*(farcri_ids));
+  } > FLASH
+
+  .note.farcri : ALIGN(4)
+  {
+    KEEP(*(.note.farcri));
+  } > FLASH
+}
+INSERT AFTER .uninit;
+";
+
 /// Provides a `memory.x` file to be included by the linker script of
-/// `cortex-m-rt`.
+/// `cortex-m-rt`, as well as [`FARCRI_X`].
 pub struct RtLdscriptSetup {
     dir: tempdir::TempDir,
+    /// The directory containing `farcri.x`. `memory.x` is in a separate
+    /// directory so that `--farcri-no-memory-x` can hide it alone.
+    fragment_dir: tempdir::TempDir,
+    regions: Vec<MemoryRegion>,
+    /// `--farcri-no-memory-x`
+    disabled: bool,
 }
 
 impl RtLdscriptSetup {
     pub async fn new(memory_x_contents: &[u8]) -> Result<Self, Error> {
-        let dir = tokio::task::spawn_blocking(|| tempdir::TempDir::new("farcri-rs"))
-            .await
-            .unwrap()?;
-
-        tokio::fs::write(dir.path().join("memory.x"), memory_x_contents).await?;
-
-        Ok(Self { dir })
+        Ok(Self {
+            dir: make_dir_with_file("memory.x", memory_x_contents).await?,
+            fragment_dir: make_dir_with_file("farcri.x", FARCRI_X.as_bytes()).await?,
+            regions: parse_memory_regions(&String::from_utf8_lossy(memory_x_contents)),
+            disabled: false,
+        })
     }
 }
 
 impl BuildSetup for RtLdscriptSetup {
     fn rustc_flags(&self) -> Vec<String> {
         // `link.x` is provided by `cortex-m-rt`
-        vec!["-C".to_string(), "link-arg=-Tlink.x".to_string()]
+        vec![
+            "-C".to_string(),
+            "link-arg=-Tlink.x".to_string(),
+            "-C".to_string(),
+            "link-arg=-Tfarcri.x".to_string(),
+        ]
+    }
+
+    fn build_envs(&self) -> Vec<(OsString, OsString)> {
+        let dirs = if self.disabled {
+            vec![self.fragment_dir.path()]
+        } else {
+            vec![self.fragment_dir.path(), self.dir.path()]
+        };
+        // `build.rs` splits the list
+        let dirs = std::env::join_paths(dirs).expect("bad temporary directory path");
+        vec![("FARCRI_LINK_SEARCH".into(), dirs)]
+    }
+
+    fn memory_x(&self) -> Option<MemoryX> {
+        if self.disabled || self.regions.is_empty() {
+            None
+        } else {
+            Some(MemoryX {
+                dir: self.dir.path().to_owned(),
+                regions: self.regions.clone(),
+            })
+        }
+    }
+
+    fn disable_memory_x(&mut self) -> bool {
+        self.disabled = true;
+        true
+    }
+}
+
+/// Provides a complete linker script.
+pub struct LdscriptSetup {
+    dir: tempdir::TempDir,
+}
+
+impl LdscriptSetup {
+    pub async fn new(link_x_contents: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            dir: make_dir_with_file("farcri_link.x", link_x_contents).await?,
+        })
+    }
+}
+
+impl BuildSetup for LdscriptSetup {
+    fn rustc_flags(&self) -> Vec<String> {
+        vec!["-C".to_string(), "link-arg=-Tfarcri_link.x".to_string()]
     }
 
     fn build_envs(&self) -> Vec<(OsString, OsString)> {
         vec![("FARCRI_LINK_SEARCH".into(), self.dir.path().into())]
     }
 }
+
+/// Parse the regions in the `MEMORY` command of a linker script. Only the
+/// forms we write ourselves (`NAME : ORIGIN = 0x..., LENGTH = 512K`) are
+/// understood; other lines are ignored.
+fn parse_memory_regions(script: &str) -> Vec<MemoryRegion> {
+    fn parse_number(s: &str) -> Option<u64> {
+        let s = s.trim();
+        let (digits, scale) = if let Some(x) = s.strip_suffix('K') {
+            (x, 1 << 10)
+        } else if let Some(x) = s.strip_suffix('M') {
+            (x, 1 << 20)
+        } else {
+            (s, 1)
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16).ok()?
+        } else {
+            digits.parse().ok()?
+        };
+        value.checked_mul(scale)
+    }
+
+    let parse_line = |line: &str| -> Option<MemoryRegion> {
+        let mut parts = line.splitn(2, ':');
+        // Drop the attributes, e.g., `FLASH (rx)`
+        let name = parts.next()?.split('(').next()?.trim();
+        let mut fields = parts.next()?.splitn(2, ',');
+        let mut field = |key: &str| {
+            let mut key_value = fields.next()?.splitn(2, '=');
+            if key_value.next()?.trim() != key {
+                return None;
+            }
+            parse_number(key_value.next()?)
+        };
+        Some(MemoryRegion {
+            name: name.to_owned(),
+            origin: field("ORIGIN")?,
+            length: field("LENGTH")?,
+        })
+    };
+
+    let start = match script.find("MEMORY") {
+        Some(x) => x,
+        None => return Vec::new(),
+    };
+    let body = &script[start..];
+    let body = match (body.find('{'), body.find('}')) {
+        (Some(open), Some(close)) if open < close => &body[open + 1..close],
+        _ => return Vec::new(),
+    };
+
+    body.lines()
+        .filter_map(|line| parse_line(line.trim()))
+        .collect()
+}
+
+async fn make_dir_with_file(name: &str, contents: &[u8]) -> Result<tempdir::TempDir, Error> {
+    let dir = tokio::task::spawn_blocking(|| tempdir::TempDir::new("farcri-rs"))
+        .await
+        .unwrap()?;
+
+    tokio::fs::write(dir.path().join(name), contents).await?;
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_regions() {
+        let regions = parse_memory_regions(
+            "
+            MEMORY
+            {
+              /* NOTE K = KiBi = 1024 bytes */
+              FLASH : ORIGIN = 0x08000000, LENGTH = 512K
+              RAM (rwx) : ORIGIN = 536870912, LENGTH = 1M
+            }
+
+            _stack_start = ORIGIN(RAM) + LENGTH(RAM);
+            ",
+        );
+        assert_eq!(
+            regions,
+            [
+                MemoryRegion {
+                    name: "FLASH".to_owned(),
+                    origin: 0x0800_0000,
+                    length: 512 * 1024,
+                },
+                MemoryRegion {
+                    name: "RAM".to_owned(),
+                    origin: 0x2000_0000,
+                    length: 1024 * 1024,
+                },
+            ]
+        );
+        assert_eq!(parse_memory_regions("SECTIONS {}"), []);
+    }
+
+    #[tokio::test]
+    async fn link_search_paths() {
+        let mut setup = RtLdscriptSetup::new(b"MEMORY { FLASH : ORIGIN = 0, LENGTH = 1K }")
+            .await
+            .unwrap();
+        let search_dirs = |setup: &RtLdscriptSetup| -> Vec<std::path::PathBuf> {
+            let envs = setup.build_envs();
+            assert_eq!(envs.len(), 1);
+            assert_eq!(envs[0].0, "FARCRI_LINK_SEARCH");
+            std::env::split_paths(&envs[0].1).collect()
+        };
+        let has_file = |dirs: &[std::path::PathBuf], name: &str| {
+            dirs.iter().any(|dir| dir.join(name).is_file())
+        };
+
+        let dirs = search_dirs(&setup);
+        assert!(has_file(&dirs, "farcri.x"));
+        assert!(has_file(&dirs, "memory.x"));
+
+        // `farcri.x` is still needed without our `memory.x`
+        assert!(setup.disable_memory_x());
+        let dirs = search_dirs(&setup);
+        assert!(has_file(&dirs, "farcri.x"));
+        assert!(!has_file(&dirs, "memory.x"));
+    }
+}
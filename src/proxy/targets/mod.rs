@@ -1,15 +1,36 @@
 use anyhow::Result;
-use std::{ffi::OsString, fmt, future::Future, pin::Pin};
+use std::{
+    ffi::OsString,
+    fmt,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::cargo::CompiledExecutable;
+use crate::{
+    cargo::CompiledExecutable,
+    proxy::formatter::{
+        CyclesFormatter, MachineUnit, ValueFormatter, WallTimeFormatter, CYCLES_MACHINE_UNITS,
+        WALL_TIME_MACHINE_UNITS,
+    },
+};
 
 // TODO: Some things in this module were copied from `r3_test_runner`.
 //       Find a way to deduplicate
 
 // mod demux;
+mod binimage;
+mod host;
+mod itm;
 mod ldscript;
 mod probe_rs;
+mod qemu;
 
 pub trait Target: Send + Sync + fmt::Debug {
     /// Get the target architecture.
@@ -22,7 +43,174 @@ pub trait Target: Send + Sync + fmt::Debug {
     fn cargo_features(&self) -> &[&str];
 
     /// Connect to the target.
-    fn connect(&self) -> Pin<Box<dyn Future<Output = Result<Box<dyn DebugProbe>>>>>;
+    fn connect(
+        &self,
+        opts: &ConnectOptions,
+    ) -> Pin<Box<dyn Future<Output = Result<Box<dyn DebugProbe>>>>>;
+
+    /// Get the unit of the values measured on the target.
+    fn value_unit(&self) -> ValueUnit {
+        ValueUnit::Cycles
+    }
+
+    /// Get the clock frequency of the debug interface (in kilohertz) to use
+    /// unless specified by [`ConnectOptions::probe_speed_khz`]. `None` leaves
+    /// it to the debug probe.
+    fn default_probe_speed_khz(&self) -> Option<u32> {
+        None
+    }
+
+    /// Check if the target can only be attached to while it's held in reset
+    /// (e.g., because its firmware disables the debug pins or keeps the core
+    /// asleep). See [`ConnectOptions::connect_under_reset`].
+    fn requires_connect_under_reset(&self) -> bool {
+        false
+    }
+
+    /// Get the index of the core to run the Target program on unless
+    /// specified by [`ConnectOptions::core_index`].
+    fn core_index(&self) -> usize {
+        0
+    }
+
+    /// Check if the cores other than the selected one should be halted
+    /// during measurement so that they don't contend for the memory system.
+    /// See [`ConnectOptions::halt_other_cores`].
+    fn halt_other_cores(&self) -> bool {
+        false
+    }
+
+    /// Check if the Target program runs without any target hardware (e.g.,
+    /// on the host computer or in an emulator). `--farcri-list-static` runs
+    /// such a target to enumerate the benchmarks instead of guessing them
+    /// from the executable.
+    fn runs_without_hardware(&self) -> bool {
+        false
+    }
+}
+
+/// The options for [`Target::connect`]
+#[derive(Debug, Default, Clone)]
+pub struct ConnectOptions {
+    /// The clock frequency of the debug interface in kilohertz. `None`
+    /// leaves it to the debug probe. Ignored by the targets without a debug
+    /// probe.
+    pub probe_speed_khz: Option<u32>,
+    /// Attach to the target while holding it in reset. Even if this is
+    /// `false`, a target may fall back to it when flashing fails. Ignored by
+    /// the targets without a debug probe.
+    pub connect_under_reset: bool,
+    /// The index of the core to run the Target program on. Ignored by the
+    /// targets without a debug probe.
+    pub core_index: usize,
+    /// Halt the cores other than [`Self::core_index`] after resetting the
+    /// target. Ignored by the targets without a debug probe.
+    pub halt_other_cores: bool,
+    /// How to program the flash memory. Ignored by the targets without a
+    /// debug probe.
+    pub flash_mode: FlashMode,
+    /// Discard the Target program's log output while this flag is set.
+    /// Ignored by the targets without a debug probe.
+    pub mute_logs: Option<MeasurementFlag>,
+    /// Incremented each time the connection to the debug probe is
+    /// re-established by [`DebugProbe::reconnect`]
+    pub reconnects: ReconnectCounter,
+    /// Receives the durations of the steps taken by
+    /// [`DebugProbe::program_and_get_output`]. Ignored by the targets that
+    /// don't break it down.
+    pub phases: PhaseLog,
+}
+
+/// A flag set by the Proxy program while the Target program is taking
+/// measurements, i.e., between `MeasurementStart` and `MeasurementComplete`
+#[derive(Debug, Default, Clone)]
+pub struct MeasurementFlag(Arc<AtomicBool>);
+
+impl MeasurementFlag {
+    pub fn set(&self, value: bool) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The number of times a [`DebugProbe`] re-established the connection to
+/// the debug probe. Readable while the probe is in use.
+#[derive(Debug, Default, Clone)]
+pub struct ReconnectCounter(Arc<AtomicUsize>);
+
+impl ReconnectCounter {
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The durations of the steps a [`DebugProbe`] took to load and start the
+/// Target program, e.g., flashing and attaching to RTT. Readable while the
+/// probe is in use.
+#[derive(Debug, Default, Clone)]
+pub struct PhaseLog(Arc<Mutex<Vec<(&'static str, Duration)>>>);
+
+impl PhaseLog {
+    pub fn record(&self, name: &'static str, duration: Duration) {
+        self.0.lock().unwrap().push((name, duration));
+    }
+
+    /// Remove and return the recorded durations.
+    pub fn take(&self) -> Vec<(&'static str, Duration)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// How a debug probe programs the flash memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, arg_enum_proc_macro::ArgEnum)]
+pub enum FlashMode {
+    /// Erase and program the sectors covered by the executable.
+    Sectors,
+    /// Read back the flash memory first, and skip programming if it already
+    /// contains the executable. Otherwise, proceed like [`Self::Sectors`].
+    Preverify,
+}
+
+impl Default for FlashMode {
+    fn default() -> Self {
+        Self::Sectors
+    }
+}
+
+/// The unit of the values measured by the Target program, which depends on
+/// the time source of the port (see `crate::target`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueUnit {
+    /// Clock cycles
+    Cycles,
+    /// Nanoseconds
+    Nanoseconds,
+}
+
+impl ValueUnit {
+    /// Get the formatter to present values in this unit with.
+    pub(crate) fn formatter(self) -> &'static dyn ValueFormatter {
+        match self {
+            Self::Cycles => &CyclesFormatter,
+            Self::Nanoseconds => &WallTimeFormatter,
+        }
+    }
+
+    /// Get the units machine-readable values in this unit can be scaled to
+    /// (see [`MachineUnitFormatter`](crate::proxy::formatter::MachineUnitFormatter)).
+    pub(crate) fn machine_units(self) -> &'static [MachineUnit] {
+        match self {
+            Self::Cycles => CYCLES_MACHINE_UNITS,
+            Self::Nanoseconds => WALL_TIME_MACHINE_UNITS,
+        }
+    }
 }
 
 /// Represents a temporary setup on the host computer for compilation, such as a
@@ -37,12 +225,74 @@ pub trait BuildSetup: Send {
     fn rustc_flags(&self) -> Vec<String> {
         Vec::new()
     }
+
+    /// Get the `memory.x` provided to the build, if any. The linked
+    /// executable is checked against its memory map.
+    fn memory_x(&self) -> Option<MemoryX> {
+        None
+    }
+
+    /// Stop providing `memory.x` so that the one provided by the benchmark
+    /// crate or its dependencies is used (`--farcri-no-memory-x`). Returns
+    /// `false` if there's no `memory.x` to stop providing.
+    fn disable_memory_x(&mut self) -> bool {
+        false
+    }
+
+    /// Process the built executable before it's programmed into the target,
+    /// e.g., to convert it to another format or to sign it. The generated
+    /// files should be owned by `self`, which outlives the Target program.
+    fn post_process(
+        &self,
+        exe: CompiledExecutable,
+    ) -> Pin<Box<dyn Future<Output = Result<CompiledExecutable>> + '_>> {
+        Box::pin(async move { Ok(exe) })
+    }
+}
+
+/// A `memory.x` provided by [`BuildSetup`]
+#[derive(Debug, Clone)]
+pub struct MemoryX {
+    /// The directory containing `memory.x`, added to the linker's search
+    /// paths
+    pub dir: PathBuf,
+    pub regions: Vec<MemoryRegion>,
+}
+
+/// A region defined by the `MEMORY` command of a linker script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub origin: u64,
+    pub length: u64,
+}
+
+impl MemoryRegion {
+    /// Check if `[start, start + len)` is contained by this region.
+    pub fn contains(&self, start: u64, len: u64) -> bool {
+        start >= self.origin && (start - self.origin).saturating_add(len) <= self.length
+    }
+}
+
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<6} 0x{:08x}..0x{:08x} ({} KiB)",
+            self.name,
+            self.origin,
+            self.origin + self.length,
+            self.length / 1024
+        )
+    }
 }
 
 impl BuildSetup for () {}
 
 pub trait DebugProbe: Send {
     /// Program the specified ELF image and start its execution on the target.
+    /// The implementation may load the image by any means, e.g., by invoking
+    /// an external tool.
     ///
     /// Returns the target's input and output stream. It's allowed for the
     /// stream to fail to deliver some of the first bytes sent or received.
@@ -56,23 +306,170 @@ pub trait DebugProbe: Send {
     /// this is violated when a `Read` (`Write`) future returned by
     /// `AsyncReadExt::read` (`AsyncWriteExt::write`) is dropped before
     /// finishing.)
+    ///
+    /// `transport` specifies the data path the Target program was built to
+    /// use. Fails if it's unsupported by the target.
     fn program_and_get_output(
         &mut self,
         exe: &CompiledExecutable,
+        transport: Transport,
     ) -> Pin<Box<dyn Future<Output = Result<DynAsyncReadWrite<'_>>> + '_>>;
+
+    /// Restart the program loaded by the last call to
+    /// [`Self::program_and_get_output`], e.g., after it crashed. The
+    /// returned stream is subject to the same rules as
+    /// `program_and_get_output`'s.
+    ///
+    /// The default implementation loads the program again. Override it if
+    /// the target can be reset without doing so.
+    fn reset_and_get_output(
+        &mut self,
+        exe: &CompiledExecutable,
+        transport: Transport,
+    ) -> Pin<Box<dyn Future<Output = Result<DynAsyncReadWrite<'_>>> + '_>> {
+        self.program_and_get_output(exe, transport)
+    }
+
+    /// Re-establish the connection to the debug probe, e.g., after it
+    /// dropped off the USB bus. Used by the implementation to retry a failed
+    /// operation without starting over the whole run.
+    ///
+    /// The default implementation does nothing.
+    fn reconnect(&mut self) -> Pin<Box<dyn Future<Output = Result<()>> + '_>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The data path between the Proxy program and the Target program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, arg_enum_proc_macro::ArgEnum)]
+pub enum Transport {
+    /// Use RTT in both directions.
+    Rtt,
+    /// Use SWO (ITM stimulus port [`protocol::SWO_STIMULUS_PORT`]) for the
+    /// target-to-host direction and RTT for the other direction and logging.
+    ///
+    /// [`protocol::SWO_STIMULUS_PORT`]: crate::bencher::protocol::SWO_STIMULUS_PORT
+    Swo,
 }
 
-type DynAsyncReadWrite<'a> = Pin<Box<dyn AsyncReadWrite + 'a>>;
+impl Transport {
+    /// Get the additional Cargo features of `farcri` to enable when building
+    /// the target in Target mode
+    pub fn cargo_features(self) -> &'static [&'static str] {
+        match self {
+            Self::Rtt => &[],
+            Self::Swo => &["comm_swo"],
+        }
+    }
+}
+
+pub(super) type DynAsyncReadWrite<'a> = Pin<Box<dyn AsyncReadWrite + 'a>>;
 
 pub trait AsyncReadWrite: AsyncRead + AsyncWrite {}
 impl<T: AsyncRead + AsyncWrite + ?Sized> AsyncReadWrite for T {}
 
-pub static TARGETS: &[(&str, &dyn Target)] = &[("nucleo_f401re", &probe_rs::NucleoF401re)];
+/// A debug probe backend, which provides a set of [`Target`]s.
+///
+/// A backend doesn't have to use probe-rs. For example, a backend could
+/// implement [`DebugProbe::program_and_get_output`] by invoking an external
+/// tool (e.g., OpenOCD) to load the executable and connecting to a TCP port
+/// it exposes.
+pub trait Backend: Send + Sync {
+    /// Get the name of the backend, e.g., `"probe-rs"`.
+    fn name(&self) -> &str;
+
+    /// Get the names of the targets provided by this backend and their
+    /// constructors. The names must be unique among all registered backends.
+    fn targets(&self) -> &[(&'static str, TargetFactory)];
+
+    /// Print the information about the backend (e.g., the version and
+    /// connected debug probes) to stdout. Used by `--farcri-info`.
+    ///
+    /// `selected_target` is the name of the target specified by
+    /// `--farcri-target`, if any. Debug probes must not be opened unless
+    /// they are used by the selected target because they might be in use by
+    /// another program.
+    fn print_info(&self, _selected_target: Option<&str>) {}
+
+    /// Print the list of the targets known to the backend but not provided
+    /// as [`Target`]s (e.g., supported chip families) to stdout. Used by
+    /// `--farcri-list-targets`.
+    fn print_generic_targets(&self) {}
+}
+
+/// Constructs a [`Target`]. A target that needs runtime configuration (e.g.,
+/// a serial port) can read it from environment variables here and fail if
+/// it's missing or invalid.
+pub type TargetFactory = fn() -> Result<Arc<dyn Target>>;
+
+/// A [`TargetFactory`] for a target that doesn't need any configuration.
+pub fn default_factory<T: Target + Default + 'static>() -> Result<Arc<dyn Target>> {
+    Ok(Arc::new(T::default()))
+}
+
+/// The error type returned by [`register_backend`].
+#[allow(dead_code)]
+#[derive(thiserror::Error, Debug)]
+#[error("Target '{0}' is already registered.")]
+pub struct DuplicateTargetError(&'static str);
+
+lazy_static::lazy_static! {
+    static ref BACKENDS: RwLock<Vec<&'static dyn Backend>> =
+        RwLock::new(vec![&probe_rs::ProbeRsBackend, &qemu::QemuBackend, &host::HostBackend]);
+}
+
+/// Register a backend. Must be called before parsing command-line arguments.
+// The built-in backends are registered statically, so this is only used by
+// tests for now
+#[allow(dead_code)]
+pub fn register_backend(backend: &'static dyn Backend) -> Result<(), DuplicateTargetError> {
+    let mut backends = BACKENDS.write().unwrap();
+    for &(name, _) in backend.targets() {
+        if backends
+            .iter()
+            .any(|b| b.targets().iter().any(|&(n, _)| n == name))
+        {
+            return Err(DuplicateTargetError(name));
+        }
+    }
+    backends.push(backend);
+    Ok(())
+}
+
+/// Get the registered backends.
+pub fn backends() -> Vec<&'static dyn Backend> {
+    BACKENDS.read().unwrap().clone()
+}
+
+/// Find a target by name. Returns the name and the constructor of the target.
+pub fn find_target(name: &str) -> Option<(&'static str, TargetFactory)> {
+    backends()
+        .iter()
+        .flat_map(|b| b.targets().iter())
+        .find(|&&(n, _)| n == name)
+        .cloned()
+}
+
+/// Print the information about the debug probe backends to stdout. Used by
+/// `--farcri-info`.
+pub fn print_info(selected_target: Option<&str>) {
+    for backend in backends() {
+        backend.print_info(selected_target);
+    }
+}
+
+/// Print the list of the targets known to the debug probe backends to
+/// stdout. Used by `--farcri-list-targets`.
+pub fn print_generic_targets() {
+    for backend in backends() {
+        backend.print_generic_targets();
+    }
+}
 
 #[derive(Debug)]
-struct OverrideTargetArch<T>(Arch, T);
+struct OverrideTargetArch(Arch, Arc<dyn Target>);
 
-impl<T: Target> Target for OverrideTargetArch<T> {
+impl Target for OverrideTargetArch {
     fn target_arch(&self) -> Arch {
         self.0
     }
@@ -85,13 +482,42 @@ impl<T: Target> Target for OverrideTargetArch<T> {
         self.1.cargo_features()
     }
 
-    fn connect(&self) -> Pin<Box<dyn Future<Output = Result<Box<dyn DebugProbe>>>>> {
-        self.1.connect()
+    fn connect(
+        &self,
+        opts: &ConnectOptions,
+    ) -> Pin<Box<dyn Future<Output = Result<Box<dyn DebugProbe>>>>> {
+        self.1.connect(opts)
+    }
+
+    fn value_unit(&self) -> ValueUnit {
+        self.1.value_unit()
+    }
+
+    fn default_probe_speed_khz(&self) -> Option<u32> {
+        self.1.default_probe_speed_khz()
+    }
+
+    fn requires_connect_under_reset(&self) -> bool {
+        self.1.requires_connect_under_reset()
+    }
+
+    fn core_index(&self) -> usize {
+        self.1.core_index()
+    }
+
+    fn halt_other_cores(&self) -> bool {
+        self.1.halt_other_cores()
+    }
+
+    fn runs_without_hardware(&self) -> bool {
+        self.1.runs_without_hardware()
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Arch {
+    /// The host computer. The Target program is built without `--target`.
+    Host,
     /// Armv7-A
     Armv7A,
     /// Arm M-Profile
@@ -139,7 +565,8 @@ pub enum Xlen {
 /// target specified by [`Arch`].
 #[derive(Debug, Default)]
 pub struct BuildOpt {
-    pub target_triple: &'static str,
+    /// The target triple. `None` means the host.
+    pub target_triple: Option<&'static str>,
     pub target_features: String,
 }
 
@@ -268,8 +695,21 @@ impl Arch {
         d: true,
     };
 
+    /// Get a flag indicating whether the architecture has a hardware FPU.
+    pub fn has_fpu(&self) -> bool {
+        match self {
+            Self::Host => true,
+            // `armv7a-none-eabi` is a soft-float target
+            Self::Armv7A => false,
+            Self::ArmM { fpu, .. } => *fpu,
+            Self::Riscv { f, .. } => *f,
+        }
+    }
+
     pub fn build_opt(&self) -> Option<BuildOpt> {
         match self {
+            Self::Host => Some(BuildOpt::default()),
+
             // Arm A-Profile
             // -------------------------------------------------------------
             Self::Armv7A => Some(BuildOpt::from_target_triple("armv7a-none-eabi")),
@@ -434,7 +874,7 @@ impl Arch {
             }};
         }
         match self {
-            Self::Armv7A => None,
+            Self::Host | Self::Armv7A => None,
             Self::ArmM { fpu, dsp, version } => features!(Self::ArmM { fpu, dsp; version }),
             Self::Riscv {
                 e,
@@ -447,12 +887,83 @@ impl Arch {
             } => features!(Self::Riscv { e, m, a, c, f, d; xlen }),
         }
     }
+
+    /// Get the features that can be modified by [`Self::with_feature_by_name`]
+    /// and whether they are enabled.
+    fn features(&self) -> Vec<(&'static str, bool)> {
+        match *self {
+            Self::Host | Self::Armv7A => Vec::new(),
+            Self::ArmM { fpu, dsp, .. } => vec![("fpu", fpu), ("dsp", dsp)],
+            Self::Riscv {
+                e, m, a, c, f, d, ..
+            } => vec![("e", e), ("m", m), ("a", a), ("c", c), ("f", f), ("d", d)],
+        }
+    }
+
+    /// Describe the ways in which `self`, specified by `--farcri-arch`, is
+    /// inconsistent with `target_arch`, the architecture of the target.
+    pub fn override_conflicts(&self, target_arch: &Self) -> Vec<String> {
+        if std::mem::discriminant(self) != std::mem::discriminant(target_arch) {
+            return vec![format!(
+                "'{}' belongs to a different architecture family than the \
+                target's ('{}'). The executable will not run on the target.",
+                self, target_arch
+            )];
+        }
+
+        let mut conflicts = Vec::new();
+        match (self, target_arch) {
+            (
+                Self::ArmM { version, .. },
+                Self::ArmM {
+                    version: target, ..
+                },
+            ) if version != target => {
+                conflicts.push(format!(
+                    "It's {:?} whereas the target implements {:?}.",
+                    version, target
+                ));
+            }
+            (Self::Riscv { xlen, .. }, Self::Riscv { xlen: target, .. }) if xlen != target => {
+                conflicts.push(format!(
+                    "It's RV{} whereas the target implements RV{}.",
+                    *xlen as u8, *target as u8
+                ));
+            }
+            _ => {}
+        }
+
+        for (&(name, enabled), &(_, target_enabled)) in
+            self.features().iter().zip(target_arch.features().iter())
+        {
+            if enabled == target_enabled {
+                continue;
+            }
+            let sign = if enabled { '+' } else { '-' };
+            // `e` restricts the architecture rather than extending it
+            if (name != "e") == enabled {
+                conflicts.push(format!(
+                    "`{}{}` requires a feature the target lacks. The executable \
+                    might contain instructions unsupported by the target.",
+                    sign, name
+                ));
+            } else {
+                conflicts.push(format!(
+                    "`{}{}` drops a feature the target has. The code enabled by \
+                    the target's Cargo features (e.g., a HAL) might assume it.",
+                    sign, name
+                ));
+            }
+        }
+
+        conflicts
+    }
 }
 
 impl BuildOpt {
     fn from_target_triple(target_triple: &'static str) -> Self {
         Self {
-            target_triple,
+            target_triple: Some(target_triple),
             ..Default::default()
         }
     }
@@ -464,11 +975,20 @@ impl BuildOpt {
             ..self
         }
     }
+
+    /// Get a flag indicating whether `target_features` removes any features
+    /// from `target_triple`. If it does, the precompiled standard library for
+    /// `target_triple` may use instructions unsupported by the target, so the
+    /// standard library must be rebuilt with `target_features`.
+    pub fn requires_build_std(&self) -> bool {
+        self.target_features.split(',').any(|x| x.starts_with('-'))
+    }
 }
 
 impl fmt::Display for Arch {
     fn fmt(&self, fm: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Self::Host => write!(fm, "host"),
             Self::Armv7A => write!(fm, "cortex_a9"),
             Self::ArmM {
                 mut fpu,
@@ -601,4 +1121,99 @@ mod tests {
             assert_eq!(*arch, arch2);
         }
     }
+
+    #[test]
+    fn requires_build_std() {
+        let build_opt = |s: &str| s.parse::<Arch>().unwrap().build_opt().unwrap();
+        assert!(!build_opt("cortex_m4f").requires_build_std());
+        assert!(!build_opt("rv32i+m+a+c").requires_build_std());
+        assert!(!build_opt("rv32i+m+a+c+f").requires_build_std());
+        assert!(build_opt("rv32i+m").requires_build_std());
+    }
+
+    #[test]
+    fn has_fpu() {
+        let has_fpu = |s: &str| s.parse::<Arch>().unwrap().has_fpu();
+        assert!(has_fpu("cortex_m4f"));
+        assert!(has_fpu("cortex_m33+fpu"));
+        assert!(has_fpu("rv32i+m+a+c+f"));
+        assert!(!has_fpu("cortex_m0"));
+        assert!(!has_fpu("cortex_m4"));
+        assert!(!has_fpu("cortex_a9"));
+        assert!(!has_fpu("rv32i+m+a+c"));
+    }
+
+    #[test]
+    fn override_conflicts() {
+        let conflicts = |arch: &str, target_arch: &str| {
+            (arch.parse::<Arch>().unwrap()).override_conflicts(&target_arch.parse().unwrap())
+        };
+        assert!(conflicts("cortex_m4f", "cortex_m4f").is_empty());
+        assert!(conflicts("rv32i+m+a+c", "rv32i+m+a+c").is_empty());
+
+        let c = conflicts("cortex_m4", "cortex_m4f");
+        assert_eq!(c.len(), 1);
+        assert!(
+            c[0].starts_with("`-fpu` drops a feature the target has."),
+            "{:?}",
+            c
+        );
+
+        let c = conflicts("cortex_m4f", "cortex_m3");
+        assert_eq!(c.len(), 2);
+        assert!(c[0].starts_with("`+fpu` requires a feature"), "{:?}", c);
+        assert!(c[1].starts_with("`+dsp` requires a feature"), "{:?}", c);
+
+        let c = conflicts("cortex_m3", "cortex_m0");
+        assert_eq!(c, ["It's Armv7M whereas the target implements Armv6M."]);
+
+        let c = conflicts("rv32e+m", "rv32i+m");
+        assert_eq!(c.len(), 1);
+        assert!(c[0].starts_with("`+e` drops"), "{:?}", c);
+        let c = conflicts("rv64i", "rv32i");
+        assert_eq!(c, ["It's RV64 whereas the target implements RV32."]);
+
+        let c = conflicts("cortex_m4f", "rv32i");
+        assert_eq!(c.len(), 1);
+        assert!(c[0].contains("different architecture family"), "{:?}", c);
+    }
+
+    #[test]
+    fn builtin_targets_resolve() {
+        for name in ["nucleo_f401re", "qemu_vexpress_a9", "host"].iter() {
+            let (found_name, factory) = find_target(name).unwrap();
+            assert_eq!(found_name, *name);
+            factory().unwrap();
+        }
+        assert!(find_target("nonexistent").is_none());
+    }
+
+    #[test]
+    fn host_target() {
+        let target = find_target("host").unwrap().1().unwrap();
+        assert_eq!(target.target_arch(), Arch::Host);
+        assert_eq!(target.cargo_features(), ["target_std"]);
+        assert_eq!(target.value_unit(), ValueUnit::Nanoseconds);
+
+        let build_opt = Arch::Host.build_opt().unwrap();
+        assert_eq!(build_opt.target_triple, None);
+        assert!(!build_opt.requires_build_std());
+    }
+
+    #[test]
+    fn register_backend_rejects_duplicate_targets() {
+        struct DupBackend;
+        impl Backend for DupBackend {
+            fn name(&self) -> &str {
+                "dup"
+            }
+            fn targets(&self) -> &[(&'static str, TargetFactory)] {
+                &[("nucleo_f401re", default_factory::<probe_rs::NucleoF401re>)]
+            }
+        }
+
+        assert!(find_target("nucleo_f401re").is_some());
+        assert!(register_backend(&DupBackend).is_err());
+        assert!(backends().iter().all(|b| b.name() != "dup"));
+    }
 }
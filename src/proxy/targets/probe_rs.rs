@@ -1,12 +1,15 @@
 use anyhow::Result;
 use futures_core::ready;
 use std::{
+    collections::VecDeque,
     convert::TryInto,
+    fmt,
     future::Future,
     io::Write,
     mem::replace,
-    path::Path,
+    path::{Path, PathBuf},
     pin::Pin,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
     sync::{Arc, Mutex},
     task::{Context, Poll},
     time::{Duration, Instant},
@@ -17,12 +20,113 @@ use tokio::{
     time::{delay_for, Delay},
 };
 
-use super::{Arch, BuildSetup, CompiledExecutable, DebugProbe, DynAsyncReadWrite, Target};
-use crate::utils::Spmc;
+use super::{
+    itm::ItmDecoder, Arch, Backend, BuildSetup, CompiledExecutable, ConnectOptions, DebugProbe,
+    DynAsyncReadWrite, FlashMode, MeasurementFlag, PhaseLog, ReconnectCounter, Target,
+    TargetFactory, Transport,
+};
+use crate::{
+    bencher::protocol, cargo::ImageFormat, proxy::frametrace::FrameTraceDecoder, utils::Spmc,
+};
 
-#[derive(Debug)]
+/// The version of `probe-rs` we are built with, taken from `Cargo.lock` by
+/// `build.rs`
+const PROBE_RS_VERSION: &str = env!("FARCRI_PROBE_RS_VERSION");
+
+/// The backend providing the targets supported through `probe-rs`
+pub(super) struct ProbeRsBackend;
+
+impl Backend for ProbeRsBackend {
+    fn name(&self) -> &str {
+        "probe-rs"
+    }
+
+    fn targets(&self) -> &[(&'static str, TargetFactory)] {
+        &[("nucleo_f401re", super::default_factory::<NucleoF401re>)]
+    }
+
+    fn print_info(&self, selected_target: Option<&str>) {
+        print_info(selected_target);
+    }
+
+    fn print_generic_targets(&self) {
+        print_chip_families();
+    }
+}
+
+/// Get the debug probe selector used by the specified target of this
+/// backend.
+fn probe_selector(target_name: &str) -> Option<&'static str> {
+    match target_name {
+        "nucleo_f401re" => Some(NUCLEO_F401RE_PROBE),
+        _ => None,
+    }
+}
+
+/// Print the version of `probe-rs` and the list of the connected probes.
+/// Only the probes used by `selected_target` are opened.
+fn print_info(selected_target: Option<&str>) {
+    println!("probe-rs {}", PROBE_RS_VERSION);
+    let selector = selected_target.and_then(probe_selector);
+
+    let probes = probe_rs::Probe::list_all();
+    if probes.is_empty() {
+        println!("No debug probes found");
+        return;
+    }
+
+    println!("Debug probes:");
+    for (i, probe) in probes.iter().enumerate() {
+        println!(
+            "  [{}] {} ({:04x}:{:04x}, {:?}), serial number: {}",
+            i,
+            probe.identifier,
+            probe.vendor_id,
+            probe.product_id,
+            probe.probe_type,
+            probe.serial_number.as_deref().unwrap_or("unknown"),
+        );
+
+        let vid_pid = format!("{:04x}:{:04x}", probe.vendor_id, probe.product_id);
+        if selector != Some(vid_pid.as_str()) {
+            continue;
+        }
+
+        // `probe-rs` doesn't expose the firmware version. The name reported
+        // by the probe driver is the closest thing we can get. Opening the
+        // probe doesn't touch the target.
+        match probe.open() {
+            Ok(opened) => println!("      name reported by the probe: {}", opened.get_name()),
+            Err(e) => println!("      couldn't open the probe: {}", e),
+        }
+    }
+}
+
+/// Print the chip families known to `probe-rs`.
+fn print_chip_families() {
+    let families = match probe_rs::config::families() {
+        Ok(x) => x,
+        Err(e) => {
+            println!(
+                "Couldn't retrieve the chip families known to probe-rs: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    println!("Chip families known to probe-rs {}:", PROBE_RS_VERSION);
+    for family in families.iter() {
+        println!("  {} ({} variants)", family.name, family.variants.len());
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct NucleoF401re;
 
+/// The on-board ST-LINK/V2-1 of [`NucleoF401re`]
+const NUCLEO_F401RE_PROBE: &str = "0483:374b";
+
 impl Target for NucleoF401re {
     fn target_arch(&self) -> Arch {
         Arch::CORTEX_M4F
@@ -54,11 +158,29 @@ impl Target for NucleoF401re {
         })
     }
 
-    fn connect(&self) -> Pin<Box<dyn Future<Output = Result<Box<dyn DebugProbe>>>>> {
-        Box::pin(async {
-            spawn_blocking(|| {
-                ProbeRsDebugProbe::new("0483:374b".try_into().unwrap(), "stm32f401re".into())
-                    .map(|x| Box::new(x) as _)
+    fn default_probe_speed_khz(&self) -> Option<u32> {
+        // The on-board ST-LINK/V2-1 supports up to 4MHz, which is faster than
+        // probe-rs's default
+        Some(4000)
+    }
+
+    fn connect(
+        &self,
+        opts: &ConnectOptions,
+    ) -> Pin<Box<dyn Future<Output = Result<Box<dyn DebugProbe>>>>> {
+        let opts = opts.clone();
+        Box::pin(async move {
+            spawn_blocking(move || {
+                ProbeRsDebugProbe::new(
+                    NUCLEO_F401RE_PROBE.try_into().unwrap(),
+                    "stm32f401re".into(),
+                    &opts,
+                    // The Target program doesn't change the clock
+                    // configuration, so the core runs on the 16MHz HSI
+                    // oscillator
+                    Some(16_000_000),
+                )
+                .map(|x| Box::new(x) as _)
             })
             .await
             .unwrap()
@@ -67,7 +189,48 @@ impl Target for NucleoF401re {
 }
 
 struct ProbeRsDebugProbe {
-    session: Arc<Mutex<probe_rs::Session>>,
+    /// `None` only while the probe is being reopened
+    session: Option<Arc<Mutex<probe_rs::Session>>>,
+    probe_sel: probe_rs::DebugProbeSelector,
+    target_sel: probe_rs::config::TargetSelector,
+    speed_khz: Option<u32>,
+    attach_method: AttachMethod,
+    core: CoreSelection,
+    /// The frequency of the TPIU clock (usually the core clock) of the
+    /// target program. `None` if SWO is unsupported.
+    swo_tpiu_clock: Option<u32>,
+    flash_mode: FlashMode,
+    mute_logs: Option<MeasurementFlag>,
+    reconnects: ReconnectCounter,
+    phases: PhaseLog,
+}
+
+/// The maximum number of attempts to flash the executable when the debug
+/// probe fails
+const MAX_FLASH_ATTEMPTS: usize = 3;
+
+/// How [`ProbeRsDebugProbe`] attaches to the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttachMethod {
+    Normal,
+    UnderReset,
+}
+
+impl fmt::Display for AttachMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Normal => f.write_str("normal attach"),
+            Self::UnderReset => f.write_str("connect under reset"),
+        }
+    }
+}
+
+/// The core [`ProbeRsDebugProbe`] runs the Target program on
+#[derive(Debug, Clone, Copy)]
+struct CoreSelection {
+    index: usize,
+    /// Halt the other cores after resetting the target
+    halt_others: bool,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -76,6 +239,11 @@ enum OpenError {
     OpenProbe(#[source] probe_rs::DebugProbeError),
     #[error("Error while attaching to the probe")]
     Attach(#[source] probe_rs::Error),
+    #[error(
+        "The target has no core {index}. Available cores: {cores}. Choose \
+        one by `--farcri-core`."
+    )]
+    NoSuchCore { index: usize, cores: String },
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -84,61 +252,457 @@ enum RunError {
     Flash(#[source] probe_rs::flashing::FileDownloadError),
     #[error("Error while resetting the device")]
     Reset(#[source] probe_rs::Error),
+    #[error("Error while halting core {index}")]
+    HaltOtherCore {
+        index: usize,
+        #[source]
+        source: probe_rs::Error,
+    },
+    #[error("SWO is not supported by this target")]
+    SwoUnsupported,
 }
 
 impl ProbeRsDebugProbe {
     fn new(
         probe_sel: probe_rs::DebugProbeSelector,
         target_sel: probe_rs::config::TargetSelector,
+        opts: &ConnectOptions,
+        swo_tpiu_clock: Option<u32>,
     ) -> anyhow::Result<Self> {
-        let probe = probe_rs::Probe::open(probe_sel).map_err(OpenError::OpenProbe)?;
+        let attach_method = if opts.connect_under_reset {
+            AttachMethod::UnderReset
+        } else {
+            AttachMethod::Normal
+        };
+        let core = CoreSelection {
+            index: opts.core_index,
+            halt_others: opts.halt_other_cores,
+        };
+        let session = open_session(
+            probe_sel.clone(),
+            target_sel.clone(),
+            opts.probe_speed_khz,
+            attach_method,
+            core.index,
+        )?;
+
+        Ok(Self {
+            session: Some(Arc::new(Mutex::new(session))),
+            probe_sel,
+            target_sel,
+            speed_khz: opts.probe_speed_khz,
+            attach_method,
+            core,
+            swo_tpiu_clock,
+            flash_mode: opts.flash_mode,
+            mute_logs: opts.mute_logs.clone(),
+            reconnects: opts.reconnects.clone(),
+            phases: opts.phases.clone(),
+        })
+    }
 
-        let session = Arc::new(Mutex::new(
-            probe.attach(target_sel).map_err(OpenError::Attach)?,
-        ));
+    fn session(&self) -> Arc<Mutex<probe_rs::Session>> {
+        Arc::clone(self.session.as_ref().unwrap())
+    }
+
+    /// Close the session and open the probe again, attaching by
+    /// `attach_method`.
+    async fn reattach(&mut self, attach_method: AttachMethod) -> Result<()> {
+        // Release the probe first. We hold the only reference to the
+        // session at this point.
+        self.session = None;
+
+        let probe_sel = self.probe_sel.clone();
+        let target_sel = self.target_sel.clone();
+        let speed_khz = self.speed_khz;
+        let core_index = self.core.index;
+        let session = spawn_blocking(move || {
+            open_session(probe_sel, target_sel, speed_khz, attach_method, core_index)
+        })
+        .await
+        .unwrap()?;
+
+        self.session = Some(Arc::new(Mutex::new(session)));
+        self.attach_method = attach_method;
+        Ok(())
+    }
+
+    /// Flash `exe`, recovering from failures by reopening the session.
+    ///
+    /// probe-rs can't resume an interrupted download, so each retry
+    /// programs the whole executable again. The executable isn't rebuilt.
+    async fn flash_with_retries(&mut self, exe: &Path, format: &ImageFormat) -> Result<()> {
+        let mut num_attempts = 0;
+        loop {
+            num_attempts += 1;
+            let e = match flash(
+                self.session(),
+                exe.to_owned(),
+                format.clone(),
+                self.flash_mode,
+                self.core.index,
+            )
+            .await
+            {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+
+            if is_probe_failure(&e) && num_attempts < MAX_FLASH_ATTEMPTS {
+                log::warn!(
+                    "{:?}\n\nThe debug probe failed while flashing (attempt {} of \
+                    {}). Reconnecting and retrying.",
+                    anyhow::Error::from(e),
+                    num_attempts,
+                    MAX_FLASH_ATTEMPTS
+                );
+                self.reconnect().await?;
+                continue;
+            }
+
+            // probe-rs doesn't tell apart an unresponsive target from
+            // other causes reliably, so retry once on any other failure
+            if self.attach_method == AttachMethod::UnderReset {
+                return Err(e.into());
+            }
+            log::warn!(
+                "{:?}\n\nRetrying with connect under reset. If this \
+                succeeds, consider passing `--farcri-connect-under-reset`.",
+                anyhow::Error::from(e)
+            );
+            self.reattach(AttachMethod::UnderReset).await?;
+        }
+
+        if num_attempts > 1 {
+            log::info!("Flashing succeeded after {} attempts", num_attempts);
+        }
+        Ok(())
+    }
+}
+
+/// Check if `e` was caused by the debug probe (e.g., a USB error) rather
+/// than the target.
+fn is_probe_failure(e: &RunError) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e);
+    while let Some(e) = source {
+        if e.is::<probe_rs::DebugProbeError>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
 
-        Ok(Self { session })
+/// Open the probe and attach to the target by `attach_method`. Fails if the
+/// target doesn't have the core `core_index`.
+fn open_session(
+    probe_sel: probe_rs::DebugProbeSelector,
+    target_sel: probe_rs::config::TargetSelector,
+    speed_khz: Option<u32>,
+    attach_method: AttachMethod,
+    core_index: usize,
+) -> anyhow::Result<probe_rs::Session> {
+    let mut probe = probe_rs::Probe::open(probe_sel).map_err(OpenError::OpenProbe)?;
+
+    // This must be done before attaching
+    if let Some(speed_khz) = speed_khz {
+        if let Err(e) = probe.set_speed(speed_khz) {
+            log::warn!(
+                "Couldn't set the probe speed to {} kHz ({}). Using the \
+                probe's default instead.",
+                speed_khz,
+                e
+            );
+        }
+    }
+    log::info!("Probe speed: {} kHz", probe.speed_khz());
+
+    let session = match attach_method {
+        AttachMethod::Normal => probe.attach(target_sel),
+        AttachMethod::UnderReset => probe.attach_under_reset(target_sel),
+    }
+    .map_err(OpenError::Attach)?;
+    log::info!("Attached to the target ({})", attach_method);
+
+    let cores = session.list_cores();
+    if !cores.iter().any(|&(i, _)| i == core_index) {
+        let cores = cores
+            .iter()
+            .map(|(i, ty)| format!("{} ({:?})", i, ty))
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(OpenError::NoSuchCore {
+            index: core_index,
+            cores,
+        }
+        .into());
     }
+    if cores.len() > 1 {
+        log::info!("Using core {} of {}", core_index, cores.len());
+    }
+
+    Ok(session)
 }
 
 impl DebugProbe for ProbeRsDebugProbe {
     fn program_and_get_output(
         &mut self,
         exe: &CompiledExecutable,
+        transport: Transport,
     ) -> Pin<Box<dyn Future<Output = Result<DynAsyncReadWrite<'_>>> + '_>> {
-        let exe = exe.path.clone();
-        let session = Arc::clone(&self.session);
+        let (exe, format) = (exe.path.clone(), exe.format.clone());
 
         Box::pin(async move {
+            let swo_tpiu_clock = match transport {
+                Transport::Rtt => None,
+                Transport::Swo => Some(self.swo_tpiu_clock.ok_or(RunError::SwoUnsupported)?),
+            };
+
+            check_rtt_symbol_placement(self.session(), &exe).await;
+
             // Flash the executable
-            log::info!("Flashing '{0}'", exe.display());
+            match &format {
+                ImageFormat::Elf => log::info!("Flashing '{0}'", exe.display()),
+                ImageFormat::Bin { path, base_address } => {
+                    log::info!("Flashing '{0}' at 0x{1:08x}", path.display(), base_address)
+                }
+                ImageFormat::Hex { path } => log::info!("Flashing '{0}'", path.display()),
+            }
+            let flash_start = std::time::Instant::now();
+            self.flash_with_retries(&exe, &format).await?;
+            log::debug!("Flashing took {:?}", flash_start.elapsed());
+            self.phases.record("flash", flash_start.elapsed());
+
+            let attach_start = std::time::Instant::now();
+            let stream = reset_and_attach(
+                self.session(),
+                &exe,
+                swo_tpiu_clock,
+                self.core,
+                self.mute_logs.clone(),
+            )
+            .await?;
+            self.phases.record("RTT attach", attach_start.elapsed());
+            Ok(stream)
+        })
+    }
 
-            let session2 = Arc::clone(&session);
-            let exe2 = exe.clone();
-            spawn_blocking(move || {
-                let mut session_lock = session2.lock().unwrap();
-                probe_rs::flashing::download_file(
-                    &mut *session_lock,
-                    &exe2,
-                    probe_rs::flashing::Format::Elf,
-                )
-            })
-            .await
-            .unwrap()
-            .map_err(RunError::Flash)?;
+    fn reset_and_get_output(
+        &mut self,
+        exe: &CompiledExecutable,
+        transport: Transport,
+    ) -> Pin<Box<dyn Future<Output = Result<DynAsyncReadWrite<'_>>> + '_>> {
+        let exe = exe.path.clone();
+        let session = self.session();
+        let swo_tpiu_clock = self.swo_tpiu_clock;
+        let core = self.core;
+        let mute_logs = self.mute_logs.clone();
+
+        Box::pin(async move {
+            let swo_tpiu_clock = match transport {
+                Transport::Rtt => None,
+                Transport::Swo => Some(swo_tpiu_clock.ok_or(RunError::SwoUnsupported)?),
+            };
 
-            // Reset the core
-            (session.lock().unwrap().core(0))
-                .map_err(RunError::Reset)?
-                .reset()
-                .map_err(RunError::Reset)?;
+            // The executable is still in the flash memory
+            log::info!("Resetting the target");
+            reset_and_attach(session, &exe, swo_tpiu_clock, core, mute_logs).await
+        })
+    }
 
-            // Attach to RTT
-            Ok(attach_rtt(session, &exe, Default::default()).await?)
+    fn reconnect(&mut self) -> Pin<Box<dyn Future<Output = Result<()>> + '_>> {
+        Box::pin(async move {
+            log::info!("Reconnecting to the debug probe");
+            self.reattach(self.attach_method).await?;
+            self.reconnects.increment();
+            Ok(())
         })
     }
 }
 
+/// Download the image of `exe` in `format` to the target's flash memory as
+/// specified by `mode`. `core_index` specifies the core used to read back the
+/// flash memory.
+async fn flash(
+    session: Arc<Mutex<probe_rs::Session>>,
+    exe: PathBuf,
+    format: ImageFormat,
+    mode: FlashMode,
+    core_index: usize,
+) -> Result<(), RunError> {
+    spawn_blocking(move || {
+        let mut session_lock = session.lock().unwrap();
+
+        // The read-back is compared with the executable's segments, which
+        // may differ from a post-processed image (e.g., a signed one)
+        if mode == FlashMode::Preverify && format != ImageFormat::Elf {
+            log::debug!("Not verifying the flash memory because the image isn't an ELF executable");
+        } else if mode == FlashMode::Preverify {
+            let start = Instant::now();
+            match flash_contains_image(&mut session_lock, &exe, core_index) {
+                Ok(true) => {
+                    log::info!(
+                        "The flash memory already contains the executable \
+                        (verified in {:?}); skipping flashing",
+                        start.elapsed()
+                    );
+                    return Ok(());
+                }
+                Ok(false) => log::info!(
+                    "The flash memory differs from the executable (verified in {:?})",
+                    start.elapsed()
+                ),
+                Err(e) => log::warn!(
+                    "Couldn't compare the flash memory with the executable: {:?}",
+                    e
+                ),
+            }
+        }
+
+        // Count the sectors actually programmed
+        let num_sectors = Arc::new(AtomicUsize::new(0));
+        let sector_bytes = Arc::new(AtomicU32::new(0));
+        let progress = {
+            let num_sectors = Arc::clone(&num_sectors);
+            let sector_bytes = Arc::clone(&sector_bytes);
+            probe_rs::flashing::FlashProgress::new(move |event| {
+                if let probe_rs::flashing::ProgressEvent::SectorErased { size, .. } = event {
+                    num_sectors.fetch_add(1, Ordering::Relaxed);
+                    sector_bytes.fetch_add(size, Ordering::Relaxed);
+                }
+            })
+        };
+
+        let (image, format) = image_to_download(&exe, &format);
+
+        let start = Instant::now();
+        probe_rs::flashing::download_file_with_options(
+            &mut *session_lock,
+            image,
+            format,
+            probe_rs::flashing::DownloadOptions {
+                progress: Some(&progress),
+                keep_unwritten_bytes: false,
+                ..Default::default()
+            },
+        )
+        .map_err(RunError::Flash)?;
+        log::info!(
+            "Programmed {} sector(s) ({} KiB) in {:?}",
+            num_sectors.load(Ordering::Relaxed),
+            sector_bytes.load(Ordering::Relaxed) / 1024,
+            start.elapsed()
+        );
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Get the file to download and its format for the executable `exe` whose
+/// image is in `format`.
+fn image_to_download<'a>(
+    exe: &'a Path,
+    format: &'a ImageFormat,
+) -> (&'a Path, probe_rs::flashing::Format) {
+    match format {
+        ImageFormat::Elf => (exe, probe_rs::flashing::Format::Elf),
+        ImageFormat::Bin { path, base_address } => (
+            path,
+            probe_rs::flashing::Format::Bin(probe_rs::flashing::BinOptions {
+                // probe-rs only supports 32-bit targets
+                base_address: Some(*base_address as u32),
+                skip: 0,
+            }),
+        ),
+        ImageFormat::Hex { path } => (path, probe_rs::flashing::Format::Hex),
+    }
+}
+
+/// Check if the target's memory already contains the loadable segments of
+/// `exe` by reading it back through the core `core_index`.
+fn flash_contains_image(
+    session: &mut probe_rs::Session,
+    exe: &Path,
+    core_index: usize,
+) -> anyhow::Result<bool> {
+    use anyhow::Context;
+    use probe_rs::MemoryInterface;
+
+    let elf_bytes =
+        std::fs::read(exe).with_context(|| format!("Failed to read '{}'.", exe.display()))?;
+    let elf = goblin::elf::Elf::parse(&elf_bytes).context("Failed to parse the executable.")?;
+    let mut core = session.core(core_index)?;
+
+    let mut read_back = Vec::new();
+    for ph in elf.program_headers.iter() {
+        if ph.p_type != goblin::elf::program_header::PT_LOAD || ph.p_filesz == 0 {
+            continue;
+        }
+        let image = elf_bytes
+            .get(ph.file_range())
+            .context("A segment is out of the file's bounds.")?;
+        let address = ph
+            .p_paddr
+            .try_into()
+            .context("A segment is out of the 32-bit address space.")?;
+
+        read_back.resize(image.len(), 0);
+        core.read_8(address, &mut read_back)?;
+        if read_back != image {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Reset the core and attach to the output stream of the program.
+async fn reset_and_attach(
+    session: Arc<Mutex<probe_rs::Session>>,
+    exe: &Path,
+    swo_tpiu_clock: Option<u32>,
+    core: CoreSelection,
+    mute_logs: Option<MeasurementFlag>,
+) -> Result<DynAsyncReadWrite<'static>> {
+    {
+        let mut session = session.lock().unwrap();
+        (session.core(core.index))
+            .map_err(RunError::Reset)?
+            .reset()
+            .map_err(RunError::Reset)?;
+
+        // The reset usually restarts the other cores as well, so this must
+        // come after it
+        if core.halt_others {
+            for (index, _) in session.list_cores() {
+                if index == core.index {
+                    continue;
+                }
+                (session.core(index))
+                    .and_then(|mut other| other.halt(Duration::from_millis(100)))
+                    .map_err(|source| RunError::HaltOtherCore { index, source })?;
+                log::debug!("Halted core {}", index);
+            }
+        }
+    }
+
+    let rtt_options = RttOptions {
+        halt_on_access: false,
+        core_index: core.index,
+        mute_logs,
+    };
+
+    if let Some(tpiu_clock) = swo_tpiu_clock {
+        // Attach to SWO and RTT
+        Ok(attach_swo(session, exe, tpiu_clock, rtt_options).await?)
+    } else {
+        // Attach to RTT
+        Ok(attach_rtt(session, exe, rtt_options).await?)
+    }
+}
+
 const POLL_INTERVAL: Duration = Duration::from_millis(30);
 const RTT_ATTACH_TIMEOUT: Duration = Duration::from_millis(500);
 
@@ -148,14 +712,74 @@ enum AttachRttError {
     AttachRtt(#[source] probe_rs_rtt::Error),
     #[error("Error while halting or resuming the core to access the RTT channel")]
     HaltCore(#[source] probe_rs::Error),
-    #[error("Timeout while trying to attach to the RTT channel.")]
-    Timeout,
+    #[error(
+        "Couldn't find the RTT control block in {region} within {timeout:?}. \
+        Make sure the firmware initializes RTT (e.g., by `rtt_init!`) before \
+        anything that might block or crash and that the control block is \
+        placed in the scanned region."
+    )]
+    ControlBlockNotFound { region: String, timeout: Duration },
+    #[error(
+        "Found the RTT control block in {region}, but it had only {num_up} up \
+        channel(s) and {num_down} down channel(s) after {timeout:?}. The \
+        Target program needs at least one of each. Make sure the firmware \
+        finishes initializing RTT and that nothing else (e.g., another RTT \
+        host) is reconfiguring it."
+    )]
+    ChannelsMissing {
+        region: String,
+        num_up: usize,
+        num_down: usize,
+        timeout: Duration,
+    },
+}
+
+/// The outcome of an unsuccessful attempt to attach to RTT
+#[derive(Debug, Clone, Copy)]
+enum AttachRttPending {
+    ControlBlockNotFound,
+    ChannelsMissing { num_up: usize, num_down: usize },
+}
+
+impl AttachRttPending {
+    /// Convert the last unsuccessful outcome to the error reported on timeout.
+    fn into_timeout_error(self, region: &probe_rs_rtt::ScanRegion) -> AttachRttError {
+        let region = describe_scan_region(region);
+        let timeout = RTT_ATTACH_TIMEOUT;
+        match self {
+            Self::ControlBlockNotFound => AttachRttError::ControlBlockNotFound { region, timeout },
+            Self::ChannelsMissing { num_up, num_down } => AttachRttError::ChannelsMissing {
+                region,
+                num_up,
+                num_down,
+                timeout,
+            },
+        }
+    }
+}
+
+/// Describe the region scanned for the RTT control block for error messages.
+fn describe_scan_region(region: &probe_rs_rtt::ScanRegion) -> String {
+    match region {
+        probe_rs_rtt::ScanRegion::Exact(addr) => {
+            format!("the location of `_SEGGER_RTT` (0x{:08x})", addr)
+        }
+        probe_rs_rtt::ScanRegion::Range(range) => {
+            format!("0x{:08x}..0x{:08x}", range.start, range.end)
+        }
+        probe_rs_rtt::ScanRegion::Ram => "the target's RAM (the executable has no \
+            `_SEGGER_RTT` symbol, or it couldn't be read)"
+            .to_owned(),
+    }
 }
 
-#[derive(Default)]
 struct RttOptions {
     /// When set to `true`, the core is halted whenever accessing RTT.
     halt_on_access: bool,
+    /// The core running the Target program
+    core_index: usize,
+    /// See [`ConnectOptions::mute_logs`].
+    mute_logs: Option<MeasurementFlag>,
 }
 
 async fn attach_rtt(
@@ -194,40 +818,49 @@ async fn attach_rtt(
     let rtt = loop {
         let session = session.clone();
         let halt_on_access = options.halt_on_access;
-        let rtt_scan_region = rtt_scan_region.clone();
+        let core_index = options.core_index;
+        let scan_region = rtt_scan_region.clone();
 
         let result = spawn_blocking(move || {
             let _halt_guard = if halt_on_access {
-                Some(CoreHaltGuard::new(session.clone()).map_err(AttachRttError::HaltCore)?)
+                Some(
+                    CoreHaltGuard::new(session.clone(), core_index)
+                        .map_err(AttachRttError::HaltCore)?,
+                )
             } else {
                 None
             };
 
-            match probe_rs_rtt::Rtt::attach_region(session, &rtt_scan_region) {
+            match probe_rs_rtt::Rtt::attach_region(session, &scan_region) {
                 Ok(mut rtt) => {
-                    if rtt.up_channels().is_empty() || rtt.down_channels().is_empty() {
+                    let num_up = rtt.up_channels().len();
+                    let num_down = rtt.down_channels().len();
+                    if num_up == 0 || num_down == 0 {
                         log::trace!(
                             "The up or down chaneel is missing. Seems \
                             like the target needs some time to get ready"
                         );
-                        Ok(None)
+                        Ok(Err(AttachRttPending::ChannelsMissing { num_up, num_down }))
                     } else {
-                        Ok(Some(rtt))
+                        Ok(Ok(rtt))
                     }
                 }
-                Err(probe_rs_rtt::Error::ControlBlockNotFound) => Ok(None),
+                Err(probe_rs_rtt::Error::ControlBlockNotFound) => {
+                    Ok(Err(AttachRttPending::ControlBlockNotFound))
+                }
                 Err(e) => Err(AttachRttError::AttachRtt(e)),
             }
         })
         .await
         .unwrap()?;
 
-        if let Some(rtt) = result {
-            break rtt;
-        }
+        let pending = match result {
+            Ok(rtt) => break rtt,
+            Err(pending) => pending,
+        };
 
         if start.elapsed() > RTT_ATTACH_TIMEOUT {
-            return Err(AttachRttError::Timeout);
+            return Err(pending.into_timeout_error(&rtt_scan_region));
         }
 
         delay_for(POLL_INTERVAL).await;
@@ -237,6 +870,60 @@ async fn attach_rtt(
     Ok(Box::pin(ReadWriteRtt::new(session, rtt, options)) as DynAsyncReadWrite<'_>)
 }
 
+/// Warn if the RTT control block in `exe` is placed outside the RAM regions
+/// of the target's memory map, which are what `ScanRegion::Ram` covers.
+async fn check_rtt_symbol_placement(session: Arc<Mutex<probe_rs::Session>>, exe: &Path) {
+    let elf_bytes = match tokio::fs::read(exe).await {
+        Ok(x) => x,
+        // `attach_rtt` will report this
+        Err(_) => return,
+    };
+
+    let warning = spawn_blocking(move || {
+        let addr = find_rtt_symbol(&elf_bytes)?;
+        let session = session.lock().unwrap();
+        let ram: Vec<_> = (session.memory_map().iter())
+            .filter_map(|region| match region {
+                probe_rs::config::MemoryRegion::Ram(region) => Some(region.range.clone()),
+                _ => None,
+            })
+            .collect();
+        rtt_placement_warning(addr, &ram)
+    })
+    .await
+    .unwrap();
+
+    if let Some(warning) = warning {
+        log::warn!("{}", warning);
+    }
+}
+
+/// Explain the problem if `addr`, the address of `_SEGGER_RTT`, is outside
+/// all of `ram`. Returns `None` if `ram` is empty because the memory map is
+/// unknown then.
+fn rtt_placement_warning(addr: u64, ram: &[std::ops::Range<u32>]) -> Option<String> {
+    if ram.is_empty()
+        || ram
+            .iter()
+            .any(|range| (range.start as u64..range.end as u64).contains(&addr))
+    {
+        return None;
+    }
+
+    let ram = ram
+        .iter()
+        .map(|range| format!("0x{:08x}..0x{:08x}", range.start, range.end))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(format!(
+        "The RTT control block `_SEGGER_RTT` is placed at 0x{:08x}, which is \
+        outside the RAM regions probe-rs knows for this chip ({}). Attaching \
+        to RTT will probably time out. Check the RAM region in the linker \
+        script (`memory.x`).",
+        addr, ram
+    ))
+}
+
 fn find_rtt_symbol(elf_bytes: &[u8]) -> Option<u64> {
     let elf = match goblin::elf::Elf::parse(elf_bytes) {
         Ok(elf) => elf,
@@ -260,25 +947,28 @@ fn find_rtt_symbol(elf_bytes: &[u8]) -> Option<u64> {
     None
 }
 
-/// Halts the first core while this RAII guard is held.
-struct CoreHaltGuard(Arc<Mutex<probe_rs::Session>>);
+/// Halts the specified core while this RAII guard is held.
+struct CoreHaltGuard(Arc<Mutex<probe_rs::Session>>, usize);
 
 impl CoreHaltGuard {
-    fn new(session: Arc<Mutex<probe_rs::Session>>) -> Result<Self, probe_rs::Error> {
+    fn new(
+        session: Arc<Mutex<probe_rs::Session>>,
+        core_index: usize,
+    ) -> Result<Self, probe_rs::Error> {
         {
             let mut session = session.lock().unwrap();
-            let mut core = session.core(0)?;
+            let mut core = session.core(core_index)?;
             core.halt(std::time::Duration::from_millis(100))?;
         }
 
-        Ok(Self(session))
+        Ok(Self(session, core_index))
     }
 }
 
 impl Drop for CoreHaltGuard {
     fn drop(&mut self) {
         let mut session = self.0.lock().unwrap();
-        let mut core = match session.core(0) {
+        let mut core = match session.core(self.1) {
             Ok(x) => x,
             Err(e) => {
                 log::warn!(
@@ -308,6 +998,113 @@ struct Bufs {
     write: [u8; 1024],
     write_pos: usize,
     write_len: usize,
+    frame_trace: FrameTraceDecoder,
+    bulk: BulkMerger,
+    log: LogForwarder,
+}
+
+/// The maximum number of the bytes forwarded from the log channel to stdout
+/// per poll. The rest is dropped so that a benchmark flooding the log
+/// channel doesn't hog the debug probe.
+const LOG_BYTES_PER_POLL: usize = 512;
+
+/// Forwards the log channel's output to stdout
+#[derive(Debug, Default)]
+struct LogForwarder {
+    /// `--farcri-mute-target-logs-during-measurement`
+    mute: Option<MeasurementFlag>,
+    /// The log channel was muted when last polled
+    was_muted: bool,
+}
+
+impl LogForwarder {
+    fn new(mute: Option<MeasurementFlag>) -> Self {
+        Self {
+            mute,
+            was_muted: false,
+        }
+    }
+
+    /// Check if the log channel should be read. While muted, it's left
+    /// alone to minimize the debug probe's traffic, and the Target program
+    /// discards what doesn't fit in the channel.
+    fn reads_log(&mut self) -> bool {
+        let muted = matches!(&self.mute, Some(flag) if flag.get());
+        self.was_muted |= muted;
+        !muted
+    }
+
+    /// Forward the bytes read from the log channel (possibly none) to `out`.
+    fn forward(&mut self, bytes: &[u8], out: &mut impl Write) -> std::io::Result<()> {
+        if replace(&mut self.was_muted, false) {
+            // These were written while muted
+            if !bytes.is_empty() {
+                log::trace!("Discarding {} log byte(s) from a measurement", bytes.len());
+            }
+            return Ok(());
+        }
+
+        let (forwarded, dropped) = bytes.split_at(bytes.len().min(LOG_BYTES_PER_POLL));
+        out.write_all(forwarded)?;
+        if !dropped.is_empty() {
+            write!(out, "\n[{} log bytes dropped]\n", dropped.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Merges the data from the bulk channel into the terminal channel's stream
+/// in the order the Target program sent them. See
+/// [`protocol::BULK_CHANNEL_NAME`].
+#[derive(Debug, Default)]
+struct BulkMerger {
+    /// The bytes read from the bulk channel but not passed on yet
+    pending: VecDeque<u8>,
+    /// The terminal channel was found empty after the bulk data arrived, so
+    /// the bulk data can be passed on. Cleared when the Proxy program sends
+    /// something.
+    active: bool,
+}
+
+impl BulkMerger {
+    /// Accept the bytes read from the bulk channel.
+    fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend(bytes);
+    }
+
+    /// Check if the terminal channel should be read. It stays silent while
+    /// the Target program is using the bulk channel.
+    fn reads_terminal(&self) -> bool {
+        !self.active
+    }
+
+    /// Report the number of the bytes read from the terminal channel into a
+    /// non-empty buffer.
+    fn terminal_read(&mut self, num_bytes: usize) {
+        if num_bytes == 0 && !self.pending.is_empty() {
+            // Everything the Target program sent through the terminal
+            // channel before switching to the bulk channel has been read
+            self.active = true;
+        }
+    }
+
+    /// Report that some bytes were sent to the Target program.
+    fn downstream_written(&mut self) {
+        self.active = false;
+    }
+
+    /// Move the bytes that can be passed on to `out`. Returns the number of
+    /// the moved bytes.
+    fn drain_into(&mut self, out: &mut [u8]) -> usize {
+        if !self.active {
+            return 0;
+        }
+        let num_bytes = out.len().min(self.pending.len());
+        for (out, b) in out.iter_mut().zip(self.pending.drain(..num_bytes)) {
+            *out = b;
+        }
+        num_bytes
+    }
 }
 
 #[derive(Debug)]
@@ -341,6 +1138,7 @@ impl ReadWriteRtt {
         rtt: probe_rs_rtt::Rtt,
         options: RttOptions,
     ) -> Self {
+        let log = LogForwarder::new(options.mute_logs.clone());
         Self {
             session,
             options,
@@ -352,6 +1150,9 @@ impl ReadWriteRtt {
                     write: [0u8; 1024],
                     write_pos: 0,
                     write_len: 0,
+                    frame_trace: FrameTraceDecoder::default(),
+                    bulk: BulkMerger::default(),
+                    log,
                 }),
                 rtt: Box::new(rtt),
                 poll_delay: [None, None],
@@ -517,13 +1318,19 @@ impl ReadWriteRtt {
                 };
 
                 let halt_on_access = self.options.halt_on_access;
+                let core_index = self.options.core_index;
                 let session = self.session.clone();
 
                 // Accessing RTT is a blocking operation, so do it in a
                 // separate thread
                 let join_handle = spawn_blocking(move || {
-                    let stalled =
-                        Self::hit_rtt_inner(session, &mut rtt, &mut *bufs, halt_on_access)?;
+                    let stalled = Self::hit_rtt_inner(
+                        session,
+                        &mut rtt,
+                        &mut *bufs,
+                        halt_on_access,
+                        core_index,
+                    )?;
 
                     // Send the buffer back to the `ReadWriteRtt`
                     Ok((bufs, stalled, rtt))
@@ -571,10 +1378,11 @@ impl ReadWriteRtt {
         rtt: &mut probe_rs_rtt::Rtt,
         bufs: &mut Bufs,
         halt_on_access: bool,
+        core_index: usize,
     ) -> tokio::io::Result<[bool; 2]> {
         let _halt_guard = if halt_on_access {
             Some(
-                CoreHaltGuard::new(session)
+                CoreHaltGuard::new(session, core_index)
                     .map_err(|e| tokio::io::Error::new(tokio::io::ErrorKind::Other, e))?,
             )
         } else {
@@ -589,43 +1397,80 @@ impl ReadWriteRtt {
             bufs.read_len = 0;
         }
 
-        // Copy the up channels' received bytes to `bufs.read`
+        // Read the bulk channel before the terminal channel so that
+        // `BulkMerger` can tell if the latter has been drained since
+        let bulk_channel = (rtt.up_channels().iter())
+            .find(|channel| channel.name() == Some(protocol::BULK_CHANNEL_NAME));
+        if let Some(channel) = bulk_channel {
+            let mut buf = [0u8; 1024];
+            let num_ch_read_bytes = channel
+                .read(&mut buf)
+                .map_err(|e| tokio::io::Error::new(tokio::io::ErrorKind::Other, e))?;
+            bufs.bulk.push(&buf[..num_ch_read_bytes]);
+        }
+
+        // Copy the terminal channel's received bytes to `bufs.read`. This
+        // channel is serviced before the others so that heavy log traffic
+        // doesn't delay the protocol frames.
+        let buf = &mut bufs.read[bufs.read_len..];
+        if let Some(channel) = rtt.up_channels().iter().nth(1) {
+            if !buf.is_empty() && bufs.bulk.reads_terminal() {
+                let num_ch_read_bytes = channel
+                    .read(buf)
+                    .map_err(|e| tokio::io::Error::new(tokio::io::ErrorKind::Other, e))?;
+                bufs.bulk.terminal_read(num_ch_read_bytes);
+
+                if num_ch_read_bytes != 0 {
+                    log::trace!(
+                        "Read {:?} ({} bytes) from {:?}",
+                        &buf[..num_ch_read_bytes],
+                        buf.len(),
+                        (channel.number(), channel.name()),
+                    );
+                    bufs.read_len += num_ch_read_bytes;
+                }
+            }
+        }
+
+        // Service the other up channels, each with its own buffer
         for (i, channel) in rtt.up_channels().iter().enumerate() {
-            let buf = &mut bufs.read[bufs.read_len..];
-            if buf.is_empty() {
-                break;
+            let is_frame_trace = channel.name() == Some(protocol::FRAME_TRACE_CHANNEL_NAME);
+            if i == 1
+                || channel.name() == Some(protocol::BULK_CHANNEL_NAME)
+                || (!is_frame_trace && !bufs.log.reads_log())
+            {
+                continue;
             }
 
+            let mut buf = [0u8; 1024];
             let num_ch_read_bytes = channel
-                .read(buf)
+                .read(&mut buf)
                 .map_err(|e| tokio::io::Error::new(tokio::io::ErrorKind::Other, e))?;
+            let buf = &buf[..num_ch_read_bytes];
 
             if num_ch_read_bytes != 0 {
                 log::trace!(
                     "Read {:?} ({} bytes) from {:?}",
-                    &buf[..num_ch_read_bytes],
-                    buf.len(),
+                    buf,
+                    num_ch_read_bytes,
                     (channel.number(), channel.name()),
                 );
-
-                if i == 1 {
-                    // Terminal channel - send it to `ReadWriteRtt`.
-                    // Don't bother checking other channels because we don't
-                    // want `buf` to be overwritten with a log channel's payload.
-                    bufs.read_len += num_ch_read_bytes;
-                    break;
-                } else {
-                    // Log channel - send it to stdout
-                    // (Yes, it piggybacks upon the terminal channel's read buffer)
-                    std::io::stdout()
-                        .write_all(&buf[..num_ch_read_bytes])
-                        .unwrap();
-                }
             } else if i == 0 {
                 stalled[SPMC_CONSUMER_READ] = true;
             }
+
+            if is_frame_trace {
+                // Frame trace channel - decode and log it
+                bufs.frame_trace.decode_and_log(buf);
+            } else {
+                // Log channel - send it to stdout
+                bufs.log.forward(buf, &mut std::io::stdout()).unwrap();
+            }
         }
 
+        // Pass on the bulk data if the terminal channel has been drained
+        bufs.read_len += bufs.bulk.drain_into(&mut bufs.read[bufs.read_len..]);
+
         // Send bytes from `bufs.write` to the first down channel
         let buf = &bufs.write[bufs.write_pos..bufs.write_len];
         if !buf.is_empty() {
@@ -642,6 +1487,7 @@ impl ReadWriteRtt {
                         (channel.number(), channel.name()),
                     );
                     bufs.write_pos += num_ch_written_bytes;
+                    bufs.bulk.downstream_written();
                 }
 
                 stalled[SPMC_CONSUMER_WRITE] = bufs.write_pos < bufs.write_len;
@@ -658,3 +1504,285 @@ impl ReadWriteRtt {
         Ok(stalled)
     }
 }
+
+/// The baud rate of SWO. ST-LINK/V2-1 supports up to 2MHz.
+const SWO_BAUD_RATE: u32 = 2_000_000;
+
+#[derive(thiserror::Error, Debug)]
+enum AttachSwoError {
+    #[error("Error while configuring SWO")]
+    SetupSwv(#[source] probe_rs::Error),
+    #[error("Error while attaching to the RTT channel used with SWO")]
+    AttachRtt(#[source] AttachRttError),
+}
+
+/// Start capturing SWO output and attach to RTT. The returned stream reads
+/// from SWO and writes to RTT.
+async fn attach_swo(
+    session: Arc<Mutex<probe_rs::Session>>,
+    exe: &Path,
+    tpiu_clock: u32,
+    rtt_options: RttOptions,
+) -> Result<DynAsyncReadWrite<'static>, AttachSwoError> {
+    log::debug!(
+        "Configuring SWO (TPIU clock = {}Hz, baud rate = {})",
+        tpiu_clock,
+        SWO_BAUD_RATE
+    );
+    let session2 = Arc::clone(&session);
+    spawn_blocking(move || {
+        let config =
+            probe_rs::architecture::arm::SwoConfig::new(tpiu_clock).set_baud(SWO_BAUD_RATE);
+        session2.lock().unwrap().setup_swv(&config)
+    })
+    .await
+    .unwrap()
+    .map_err(AttachSwoError::SetupSwv)?;
+
+    // RTT is still used for the host-to-target direction and logging
+    let rtt = attach_rtt(Arc::clone(&session), exe, rtt_options)
+        .await
+        .map_err(AttachSwoError::AttachRtt)?;
+
+    Ok(Box::pin(ReadWriteSwo {
+        session,
+        rtt,
+        decoder: ItmDecoder::new(protocol::SWO_STIMULUS_PORT),
+        read: Vec::new(),
+        read_pos: 0,
+        st: ReadSwoSt::Idle,
+    }) as DynAsyncReadWrite<'_>)
+}
+
+/// Reads from SWO and writes to the wrapped RTT stream.
+///
+/// The RTT log channel is only serviced when writing to the RTT stream.
+struct ReadWriteSwo {
+    session: Arc<Mutex<probe_rs::Session>>,
+    rtt: DynAsyncReadWrite<'static>,
+    decoder: ItmDecoder,
+    /// `read[read_pos..]` contains the decoded bytes yet to be returned.
+    read: Vec<u8>,
+    read_pos: usize,
+    st: ReadSwoSt,
+}
+
+enum ReadSwoSt {
+    Idle,
+    /// `ReadWriteSwo` is currently reading SWO data.
+    Access(JoinHandle<Result<Vec<u8>, probe_rs::Error>>),
+    /// The last read returned nothing. Wait before trying again.
+    Delay(Delay),
+}
+
+impl AsyncRead for ReadWriteSwo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<tokio::io::Result<usize>> {
+        let this = Pin::into_inner(self);
+
+        loop {
+            if this.read_pos < this.read.len() {
+                let num_bytes_read = (this.read.len() - this.read_pos).min(buf.len());
+                buf[..num_bytes_read]
+                    .copy_from_slice(&this.read[this.read_pos..][..num_bytes_read]);
+                this.read_pos += num_bytes_read;
+                return Poll::Ready(Ok(num_bytes_read));
+            }
+
+            match &mut this.st {
+                ReadSwoSt::Idle => {
+                    // Reading SWO data is a blocking operation, so do it in a
+                    // separate thread
+                    let session = Arc::clone(&this.session);
+                    this.st = ReadSwoSt::Access(spawn_blocking(move || {
+                        session.lock().unwrap().read_swo()
+                    }));
+                }
+
+                ReadSwoSt::Access(join_handle) => {
+                    let swo_bytes = ready!(Pin::new(join_handle).poll(cx))
+                        .unwrap()
+                        .map_err(|e| tokio::io::Error::new(tokio::io::ErrorKind::Other, e))?;
+                    log::trace!("Read {:?} from SWO", swo_bytes);
+
+                    this.read.clear();
+                    this.read_pos = 0;
+                    this.decoder.decode(&swo_bytes, &mut this.read);
+
+                    this.st = if this.read.is_empty() {
+                        ReadSwoSt::Delay(delay_for(POLL_INTERVAL))
+                    } else {
+                        ReadSwoSt::Idle
+                    };
+                }
+
+                ReadSwoSt::Delay(delay) => {
+                    ready!(Pin::new(delay).poll(cx));
+                    this.st = ReadSwoSt::Idle;
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ReadWriteSwo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<tokio::io::Result<usize>> {
+        self.rtt.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<tokio::io::Result<()>> {
+        self.rtt.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<tokio::io::Result<()>> {
+        self.rtt.as_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_format_selection() {
+        let exe = Path::new("target/sort");
+        let (image, format) = image_to_download(exe, &ImageFormat::Elf);
+        assert_eq!(image, exe);
+        assert!(matches!(format, probe_rs::flashing::Format::Elf));
+
+        let bin = ImageFormat::Bin {
+            path: PathBuf::from("/tmp/sort.bin"),
+            base_address: 0x0800_0000,
+        };
+        let (image, format) = image_to_download(exe, &bin);
+        assert_eq!(image, Path::new("/tmp/sort.bin"));
+        assert!(matches!(
+            format,
+            probe_rs::flashing::Format::Bin(probe_rs::flashing::BinOptions {
+                base_address: Some(0x0800_0000),
+                skip: 0,
+            })
+        ));
+
+        let hex = ImageFormat::Hex {
+            path: PathBuf::from("/tmp/sort.hex"),
+        };
+        let (image, format) = image_to_download(exe, &hex);
+        assert_eq!(image, Path::new("/tmp/sort.hex"));
+        assert!(matches!(format, probe_rs::flashing::Format::Hex));
+    }
+
+    #[test]
+    fn attach_rtt_timeout_errors() {
+        let not_found = AttachRttPending::ControlBlockNotFound
+            .into_timeout_error(&probe_rs_rtt::ScanRegion::Exact(0x2000_0100))
+            .to_string();
+        assert!(
+            not_found.starts_with(
+                "Couldn't find the RTT control block in the location of \
+                `_SEGGER_RTT` (0x20000100) within 500ms."
+            ),
+            "{}",
+            not_found
+        );
+
+        let missing = AttachRttPending::ChannelsMissing {
+            num_up: 2,
+            num_down: 0,
+        }
+        .into_timeout_error(&probe_rs_rtt::ScanRegion::Ram)
+        .to_string();
+        assert!(
+            missing.starts_with(
+                "Found the RTT control block in the target's RAM (the executable \
+                has no `_SEGGER_RTT` symbol, or it couldn't be read), but it had \
+                only 2 up channel(s) and 0 down channel(s) after 500ms."
+            ),
+            "{}",
+            missing
+        );
+    }
+
+    #[test]
+    fn rtt_placement() {
+        let ram = [0x2000_0000..0x2001_8000, 0x1000_0000..0x1001_0000];
+        assert_eq!(rtt_placement_warning(0x2000_0100, &ram), None);
+        assert_eq!(rtt_placement_warning(0x1000_0000, &ram), None);
+        assert_eq!(rtt_placement_warning(0x0800_0000, &[]), None);
+
+        let warning = rtt_placement_warning(0x2001_8000, &ram).unwrap();
+        assert!(
+            warning.starts_with(
+                "The RTT control block `_SEGGER_RTT` is placed at 0x20018000, \
+                which is outside the RAM regions probe-rs knows for this chip \
+                (0x20000000..0x20018000, 0x10000000..0x10010000)."
+            ),
+            "{}",
+            warning
+        );
+    }
+
+    #[test]
+    fn log_forwarder() {
+        let flag = MeasurementFlag::default();
+        let mut log = LogForwarder::new(Some(flag.clone()));
+        let mut out = Vec::new();
+
+        assert!(log.reads_log());
+        log.forward(b"hello\n", &mut out).unwrap();
+        log.forward(&[b'a'; LOG_BYTES_PER_POLL + 3], &mut out)
+            .unwrap();
+        let mut expected = b"hello\n".to_vec();
+        expected.extend_from_slice(&[b'a'; LOG_BYTES_PER_POLL]);
+        expected.extend_from_slice(b"\n[3 log bytes dropped]\n");
+        assert_eq!(out, expected);
+
+        // The bytes written during a measurement are discarded
+        out.clear();
+        flag.set(true);
+        assert!(!log.reads_log());
+        flag.set(false);
+        assert!(log.reads_log());
+        log.forward(b"measuring\n", &mut out).unwrap();
+        log.forward(b"done\n", &mut out).unwrap();
+        assert_eq!(out, b"done\n");
+    }
+
+    #[test]
+    fn bulk_merger() {
+        let mut merger = BulkMerger::default();
+        let mut out = [0u8; 4];
+
+        // The terminal channel still has data sent before the bulk data
+        merger.push(b"bulk");
+        assert!(merger.reads_terminal());
+        merger.terminal_read(3);
+        assert_eq!(merger.drain_into(&mut out), 0);
+
+        // Drained
+        merger.push(b"!");
+        merger.terminal_read(0);
+        assert!(!merger.reads_terminal());
+        assert_eq!(merger.drain_into(&mut out[..3]), 3);
+        assert_eq!(&out[..3], b"bul");
+        merger.push(b"?");
+        assert_eq!(merger.drain_into(&mut out), 3);
+        assert_eq!(&out[..3], b"k!?");
+
+        // The Proxy program replied
+        merger.downstream_written();
+        assert!(merger.reads_terminal());
+        merger.terminal_read(0);
+        assert!(merger.reads_terminal());
+    }
+}
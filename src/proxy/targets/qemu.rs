@@ -0,0 +1,266 @@
+//! Targets emulated by QEMU
+use anyhow::{Context as _, Result};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    process::Stdio,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    process::{Child, ChildStdin, ChildStdout, Command},
+};
+
+use super::{
+    Arch, Backend, BuildSetup, CompiledExecutable, ConnectOptions, DebugProbe, DynAsyncReadWrite,
+    Target, TargetFactory, Transport,
+};
+
+const QEMU_SYSTEM_ARM: &str = "qemu-system-arm";
+
+/// The backend providing the targets emulated by QEMU
+pub(super) struct QemuBackend;
+
+impl Backend for QemuBackend {
+    fn name(&self) -> &str {
+        "qemu"
+    }
+
+    fn targets(&self) -> &[(&'static str, TargetFactory)] {
+        &[("qemu_vexpress_a9", super::default_factory::<QemuVexpressA9>)]
+    }
+
+    fn print_info(&self, _selected_target: Option<&str>) {
+        let version = std::process::Command::new(QEMU_SYSTEM_ARM)
+            .arg("--version")
+            .output();
+        match version {
+            Ok(output) if output.status.success() => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                println!("{}", stdout.lines().next().unwrap_or_default());
+            }
+            _ => println!("{} not found", QEMU_SYSTEM_ARM),
+        }
+    }
+}
+
+/// QEMU's `vexpress-a9` machine (Arm Versatile Express with a Cortex-A9
+/// daughterboard)
+#[derive(Debug, Default)]
+pub struct QemuVexpressA9;
+
+impl Target for QemuVexpressA9 {
+    fn target_arch(&self) -> Arch {
+        Arch::CORTEX_A9
+    }
+
+    fn cargo_features(&self) -> &[&str] {
+        &["target_qemu_vexpress_a9"]
+    }
+
+    fn prepare_build(&self) -> Pin<Box<dyn Future<Output = Result<Box<dyn BuildSetup>>>>> {
+        Box::pin(async {
+            // See `crate::target::armv7a_rt` for the symbols we must define
+            match super::ldscript::LdscriptSetup::new(
+                b"
+                ENTRY(_start);
+
+                MEMORY
+                {
+                  /* The first 32MiB of the motherboard's DRAM */
+                  RAM : ORIGIN = 0x60000000, LENGTH = 32M
+                }
+
+                SECTIONS
+                {
+                  .text : {
+                    KEEP(*(.text.start));
+                    *(.text .text.*);
+                  } > RAM
+
+                  .rodata : ALIGN(4) {
+                    *(.rodata .rodata.*);
+                  } > RAM
+
+                  /* See `crate::proxy::targets::ldscript::FARCRI_X` */
+                  farcri_benches : ALIGN(4) {
+                    KEEP(*(farcri_benches));
+                  } > RAM
+
+                  farcri_ids : ALIGN(4) {
+                    KEEP(*(farcri_ids));
+                  } > RAM
+
+                  .note.farcri : ALIGN(4) {
+                    KEEP(*(.note.farcri));
+                  } > RAM
+
+                  .ARM.exidx : ALIGN(4) {
+                    *(.ARM.exidx .ARM.exidx.*);
+                  } > RAM
+
+                  .data : ALIGN(4) {
+                    *(.data .data.*);
+                  } > RAM
+
+                  .bss (NOLOAD) : ALIGN(4) {
+                    __sbss = .;
+                    *(.bss .bss.*);
+                    . = ALIGN(4);
+                    __ebss = .;
+                  } > RAM
+                }
+
+                _stack_start = ORIGIN(RAM) + LENGTH(RAM);
+            ",
+            )
+            .await
+            {
+                Ok(x) => Ok(Box::new(x) as _),
+                Err(x) => Err(x.into()),
+            }
+        })
+    }
+
+    fn connect(
+        &self,
+        _opts: &ConnectOptions,
+    ) -> Pin<Box<dyn Future<Output = Result<Box<dyn DebugProbe>>>>> {
+        Box::pin(async {
+            Ok(Box::new(QemuDebugProbe {
+                machine: "vexpress-a9",
+            }) as _)
+        })
+    }
+
+    fn runs_without_hardware(&self) -> bool {
+        true
+    }
+}
+
+struct QemuDebugProbe {
+    /// The value passed to `-machine`
+    machine: &'static str,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum RunError {
+    #[error("SWO is not supported by this target")]
+    SwoUnsupported,
+}
+
+impl DebugProbe for QemuDebugProbe {
+    fn program_and_get_output(
+        &mut self,
+        exe: &CompiledExecutable,
+        transport: Transport,
+    ) -> Pin<Box<dyn Future<Output = Result<DynAsyncReadWrite<'_>>> + '_>> {
+        let exe = exe.path.clone();
+        let machine = self.machine;
+
+        Box::pin(async move {
+            if let Transport::Swo = transport {
+                return Err(RunError::SwoUnsupported.into());
+            }
+
+            log::info!("Starting QEMU with '{0}'", exe.display());
+
+            // UART0 is connected to the standard I/O, which we use to talk to
+            // the Target program. The log output (UART1) goes to stderr.
+            // `-icount` makes the virtual clock advance by 1ns per
+            // instruction, making the measurements deterministic.
+            let mut child = Command::new(QEMU_SYSTEM_ARM)
+                .args(&["-machine", machine])
+                .args(&["-nodefaults", "-display", "none"])
+                .args(&["-serial", "stdio", "-serial", "file:/dev/stderr"])
+                .args(&["-icount", "shift=0"])
+                .arg("-kernel")
+                .arg(&exe)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .with_context(|| format!("Failed to start '{}'.", QEMU_SYSTEM_ARM))?;
+
+            let stdin = child.stdin.take().unwrap();
+            let stdout = child.stdout.take().unwrap();
+
+            Ok(Box::pin(QemuStdio {
+                _child: child,
+                stdin,
+                stdout,
+            }) as _)
+        })
+    }
+}
+
+/// The standard I/O of a QEMU process. The process is killed when this is
+/// dropped.
+struct QemuStdio {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl AsyncRead for QemuStdio {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QemuStdio {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stdin).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stdin).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stdin).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    /// Boot the example benchmark crate on QEMU and list its benchmarks,
+    /// which requires the greeting exchange to succeed. Needs
+    /// `qemu-system-arm` and the `armv7a-none-eabi` target, so it's only run
+    /// on request (`cargo test --features role_proxy -- --ignored`).
+    #[test]
+    #[ignore]
+    fn example_boots() {
+        let output = Command::new(env!("CARGO"))
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .args(&["bench", "-p", "farcri_example", "--bench", "sort", "--"])
+            .arg("--list")
+            .env("FARCRI_TARGET", "qemu_vexpress_a9")
+            .env_remove("CARGO_CRITERION_PORT")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "{}\n{}",
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(
+            stdout.lines().any(|line| line == "noop: benchmark"),
+            "{}",
+            stdout
+        );
+    }
+}
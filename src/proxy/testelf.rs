@@ -0,0 +1,134 @@
+//! Builds minimal ELF images for testing the code reading the target
+//! executable
+use goblin::elf::section_header::{SHT_NULL, SHT_STRTAB};
+
+pub(super) struct ElfBuilder {
+    is_64: bool,
+    little_endian: bool,
+    /// `(name, sh_type, sh_addr, contents)`
+    sections: Vec<(String, u32, u64, Vec<u8>)>,
+}
+
+impl ElfBuilder {
+    pub(super) fn new(is_64: bool, little_endian: bool) -> Self {
+        Self {
+            is_64,
+            little_endian,
+            sections: Vec::new(),
+        }
+    }
+
+    /// Get the size of an address in the image.
+    pub(super) fn word_len(&self) -> usize {
+        if self.is_64 {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// Append `value` to `out` as an integer of `len` bytes in the image's
+    /// byte order.
+    pub(super) fn put(&self, out: &mut Vec<u8>, value: u64, len: usize) {
+        let bytes = value.to_le_bytes();
+        if self.little_endian {
+            out.extend_from_slice(&bytes[..len]);
+        } else {
+            out.extend(bytes[..len].iter().rev());
+        }
+    }
+
+    /// Add a section. `addr` is ignored unless the section is loadable.
+    pub(super) fn section(&mut self, name: &str, ty: u32, addr: u64, contents: Vec<u8>) {
+        self.sections.push((name.to_owned(), ty, addr, contents));
+    }
+
+    pub(super) fn build(&self) -> Vec<u8> {
+        let word_len = self.word_len();
+        let (ehdr_len, shdr_len) = if self.is_64 { (64, 64) } else { (52, 40) };
+
+        // The section header string table is the last section
+        let mut shstrtab = vec![0u8];
+        let mut name_offsets = Vec::new();
+        for (name, ..) in self.sections.iter() {
+            name_offsets.push(shstrtab.len() as u64);
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+        let shstrtab_name = shstrtab.len() as u64;
+        shstrtab.extend_from_slice(b".shstrtab\0");
+
+        let mut out = vec![0; ehdr_len];
+        let mut offsets = Vec::new();
+        for contents in self
+            .sections
+            .iter()
+            .map(|(_, _, _, contents)| contents)
+            .chain(std::iter::once(&shstrtab))
+        {
+            pad(&mut out, 8);
+            offsets.push(out.len() as u64);
+            out.extend_from_slice(contents);
+        }
+        pad(&mut out, 8);
+        let shdrs_offset = out.len() as u64;
+        let num_sections = self.sections.len() as u64 + 2;
+
+        // ELF header
+        let mut ehdr = b"\x7fELF".to_vec();
+        ehdr.push(if self.is_64 { 2 } else { 1 });
+        ehdr.push(if self.little_endian { 1 } else { 2 });
+        ehdr.push(1); // EI_VERSION
+        ehdr.resize(16, 0);
+        self.put(&mut ehdr, 2, 2); // e_type = ET_EXEC
+        self.put(&mut ehdr, 40, 2); // e_machine = EM_ARM
+        self.put(&mut ehdr, 1, 4); // e_version
+        self.put(&mut ehdr, 0, word_len); // e_entry
+        self.put(&mut ehdr, 0, word_len); // e_phoff
+        self.put(&mut ehdr, shdrs_offset, word_len); // e_shoff
+        self.put(&mut ehdr, 0, 4); // e_flags
+        self.put(&mut ehdr, ehdr_len as u64, 2); // e_ehsize
+        self.put(&mut ehdr, 0, 2); // e_phentsize
+        self.put(&mut ehdr, 0, 2); // e_phnum
+        self.put(&mut ehdr, shdr_len as u64, 2); // e_shentsize
+        self.put(&mut ehdr, num_sections, 2); // e_shnum
+        self.put(&mut ehdr, num_sections - 1, 2); // e_shstrndx
+        assert_eq!(ehdr.len(), ehdr_len);
+        out[..ehdr_len].copy_from_slice(&ehdr);
+
+        // Section headers
+        let mut shdr = |name: u64, ty: u32, addr: u64, offset: u64, size: usize| {
+            self.put(&mut out, name, 4);
+            self.put(&mut out, ty.into(), 4);
+            self.put(&mut out, 0, word_len); // sh_flags
+            self.put(&mut out, addr, word_len);
+            self.put(&mut out, offset, word_len);
+            self.put(&mut out, size as u64, word_len);
+            self.put(&mut out, 0, 4); // sh_link
+            self.put(&mut out, 0, 4); // sh_info
+            self.put(&mut out, 4, word_len); // sh_addralign
+            self.put(&mut out, 0, word_len); // sh_entsize
+        };
+        shdr(0, SHT_NULL, 0, 0, 0);
+        for (i, (_, ty, addr, contents)) in self.sections.iter().enumerate() {
+            shdr(name_offsets[i], *ty, *addr, offsets[i], contents.len());
+        }
+        shdr(
+            shstrtab_name,
+            SHT_STRTAB,
+            0,
+            offsets[self.sections.len()],
+            shstrtab.len(),
+        );
+
+        out
+    }
+}
+
+/// Append zeros to `out` until its length is a multiple of `align`.
+pub(super) fn pad(out: &mut Vec<u8>, align: usize) {
+    let rem = out.len() % align;
+    if rem != 0 {
+        out.resize(out.len() + align - rem, 0);
+    }
+}
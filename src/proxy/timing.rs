@@ -0,0 +1,306 @@
+//! Wall-clock accounting of a run
+use serde::{ser::SerializeSeq, Serialize, Serializer};
+use std::{
+    borrow::Borrow,
+    time::{Duration, Instant},
+};
+
+use crate::bencher::protocol;
+
+/// Records how long each phase of a run took. Serialized with the durations
+/// in seconds, e.g., `{"phases":[{"name":"build","duration":12.5}],
+/// "probe_reconnects":0,"benchmarks":[{"id":"sort/16","setup":0.01,
+/// "warm_up":3.0,"measurement":5.02,"overhead":0.1}]}`.
+#[derive(Debug, Default, Serialize)]
+pub(super) struct Timings {
+    /// The phases preceding the execution of benchmarks (build, flash, etc.)
+    #[serde(serialize_with = "serialize_phases")]
+    phases: Vec<(&'static str, Duration)>,
+    /// The number of times the connection to the debug probe was
+    /// re-established during the phases
+    probe_reconnects: usize,
+    benchmarks: Vec<BenchmarkTiming>,
+    /// The stage of the last benchmark in `benchmarks` and the time when it
+    /// was entered
+    #[serde(skip)]
+    current: Option<(Stage, Instant)>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub(super) struct BenchmarkTiming {
+    pub id: String,
+    /// `BeginningBenchmark` → `Warmup`
+    #[serde(serialize_with = "serialize_secs")]
+    pub setup: Duration,
+    /// `Warmup` → `MeasurementStart`
+    #[serde(serialize_with = "serialize_secs")]
+    pub warm_up: Duration,
+    /// `MeasurementStart` → `MeasurementComplete`
+    #[serde(serialize_with = "serialize_secs")]
+    pub measurement: Duration,
+    /// `MeasurementComplete` → the next benchmark. Includes the time taken
+    /// by the front-end to process the result.
+    #[serde(serialize_with = "serialize_secs")]
+    pub overhead: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Setup,
+    WarmUp,
+    Measurement,
+    Overhead,
+}
+
+impl BenchmarkTiming {
+    fn total(&self) -> Duration {
+        self.setup + self.warm_up + self.measurement + self.overhead
+    }
+}
+
+impl Timings {
+    /// Record the duration of a phase preceding the execution of benchmarks.
+    pub(super) fn record_phase(&mut self, name: &'static str, duration: Duration) {
+        self.phases.push((name, duration));
+    }
+
+    /// Record the number of times the connection to the debug probe was
+    /// re-established to recover from failures.
+    pub(super) fn record_probe_reconnects(&mut self, count: usize) {
+        self.probe_reconnects = count;
+    }
+
+    /// Update the per-benchmark durations based on a message received at
+    /// `at`.
+    pub(super) fn record_message<Str: Borrow<str>, Values>(
+        &mut self,
+        msg: &protocol::UpstreamMessage<Str, Values>,
+        at: Instant,
+    ) {
+        use protocol::UpstreamMessage::*;
+        let next_stage = match msg {
+            BeginningBenchmark { id } => {
+                self.close_stage(at);
+                self.benchmarks.push(BenchmarkTiming {
+                    id: id.to_string(),
+                    ..Default::default()
+                });
+                Some(Stage::Setup)
+            }
+            Warmup { .. } => Some(Stage::WarmUp),
+            MeasurementStart { .. } => Some(Stage::Measurement),
+            MeasurementComplete { .. } | BenchmarkFailed { .. } => Some(Stage::Overhead),
+            SkippingBenchmark { .. } | ListedBenchmark { .. } | FinishedBenchmarkGroup | End => {
+                None
+            }
+            _ => return,
+        };
+
+        self.close_stage(at);
+        self.current = next_stage.map(|stage| (stage, at));
+    }
+
+    /// Add the time spent in the current stage to the current benchmark.
+    fn close_stage(&mut self, at: Instant) {
+        if let (Some((stage, start)), Some(benchmark)) =
+            (self.current.take(), self.benchmarks.last_mut())
+        {
+            let duration = at.saturating_duration_since(start);
+            *match stage {
+                Stage::Setup => &mut benchmark.setup,
+                Stage::WarmUp => &mut benchmark.warm_up,
+                Stage::Measurement => &mut benchmark.measurement,
+                Stage::Overhead => &mut benchmark.overhead,
+            } += duration;
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn benchmarks(&self) -> &[BenchmarkTiming] {
+        &self.benchmarks
+    }
+
+    /// Print the timing breakdown to stderr.
+    pub(super) fn print(&self) {
+        eprintln!("Timing breakdown:");
+        for (name, duration) in self.phases.iter() {
+            eprintln!("  {:<30} {:>10}", name, format_duration(*duration));
+        }
+        if self.probe_reconnects > 0 {
+            eprintln!(
+                "  (reconnected to the debug probe {} time(s))",
+                self.probe_reconnects
+            );
+        }
+
+        if self.benchmarks.is_empty() {
+            return;
+        }
+
+        eprintln!();
+        eprintln!(
+            "  {:<30} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "benchmark", "setup", "warm-up", "measure", "overhead", "total"
+        );
+        let mut sum = BenchmarkTiming {
+            id: "(total)".to_owned(),
+            ..Default::default()
+        };
+        for benchmark in self.benchmarks.iter() {
+            print_benchmark_row(benchmark);
+            sum.setup += benchmark.setup;
+            sum.warm_up += benchmark.warm_up;
+            sum.measurement += benchmark.measurement;
+            sum.overhead += benchmark.overhead;
+        }
+        if self.benchmarks.len() > 1 {
+            print_benchmark_row(&sum);
+        }
+    }
+}
+
+fn print_benchmark_row(benchmark: &BenchmarkTiming) {
+    eprintln!(
+        "  {:<30} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        benchmark.id,
+        format_duration(benchmark.setup),
+        format_duration(benchmark.warm_up),
+        format_duration(benchmark.measurement),
+        format_duration(benchmark.overhead),
+        format_duration(benchmark.total()),
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn serialize_phases<S: Serializer>(
+    phases: &[(&'static str, Duration)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Phase {
+        name: &'static str,
+        #[serde(serialize_with = "serialize_secs")]
+        duration: Duration,
+    }
+
+    let mut seq = serializer.serialize_seq(Some(phases.len()))?;
+    for &(name, duration) in phases {
+        seq.serialize_element(&Phase { name, duration })?;
+    }
+    seq.end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_stages() {
+        use protocol::UpstreamMessage::*;
+        let origin = Instant::now();
+        let at = |ms| origin + Duration::from_millis(ms);
+        let id = |name: &'static str| protocol::RawBenchmarkId {
+            group_id: "group",
+            function_id: Some(name),
+            value_str: None,
+            throughput: None,
+        };
+        let config = protocol::BenchmarkConfig {
+            measurement_time: protocol::Duration::from_nanos(0),
+            nresamples: 100_000,
+            sample_size: 100,
+            warm_up_time: protocol::Duration::from_nanos(0),
+        };
+
+        let messages: Vec<(protocol::UpstreamMessage<&str, Vec<u64>>, u64)> = vec![
+            (BeginningBenchmarkGroup { group: "group" }, 0),
+            (BeginningBenchmark { id: id("a") }, 10),
+            (
+                Warmup {
+                    warm_up_goal_duration: protocol::Duration::from_nanos(0),
+                },
+                20,
+            ),
+            (GetInstant, 25),
+            (
+                MeasurementStart {
+                    warm_up_iter_count: 1,
+                    warm_up_duration: protocol::Duration::from_nanos(0),
+                    num_samples: 1,
+                    num_iters: 1,
+                    warm_up_round_trips: Default::default(),
+                },
+                120,
+            ),
+            (
+                MeasurementComplete {
+                    num_iters_per_sample: 1,
+                    values: vec![1],
+                    benchmark_config: config,
+                },
+                620,
+            ),
+            (
+                SkippingBenchmark {
+                    id: id("b"),
+                    reason: protocol::SkipReason::Resume,
+                },
+                650,
+            ),
+            (BeginningBenchmark { id: id("c") }, 700),
+            (FinishedBenchmarkGroup, 710),
+            (End, 800),
+        ];
+
+        let mut timings = Timings::default();
+        for (msg, ms) in messages.iter() {
+            timings.record_message(msg, at(*ms));
+        }
+
+        let ms = Duration::from_millis;
+        assert_eq!(
+            timings.benchmarks(),
+            [
+                BenchmarkTiming {
+                    id: "group/a".to_owned(),
+                    setup: ms(10),
+                    warm_up: ms(100),
+                    measurement: ms(500),
+                    overhead: ms(30),
+                },
+                BenchmarkTiming {
+                    id: "group/c".to_owned(),
+                    setup: ms(10),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn serialized() {
+        let mut timings = Timings::default();
+        timings.record_phase("build", Duration::from_millis(1500));
+        timings.record_phase("RTT attach", Duration::from_millis(250));
+        timings.record_probe_reconnects(1);
+        timings.benchmarks.push(BenchmarkTiming {
+            id: "group/a".to_owned(),
+            setup: Duration::from_millis(10),
+            warm_up: Duration::from_millis(100),
+            measurement: Duration::from_millis(500),
+            overhead: Duration::from_millis(30),
+        });
+
+        assert_eq!(
+            serde_json::to_string(&timings).unwrap(),
+            r#"{"phases":[{"name":"build","duration":1.5},{"name":"RTT attach","duration":0.25}],"probe_reconnects":1,"benchmarks":[{"id":"group/a","setup":0.01,"warm_up":0.1,"measurement":0.5,"overhead":0.03}]}"#
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Link-section-based benchmark registry used by `#[farcri::bench]`
+//!
+//! Each `#[farcri::bench]` function emits a [`BenchEntry`] into the link
+//! section `farcri_benches`. The linker places all input sections with the same
+//! name next to each other and defines the symbols `__start_farcri_benches` and
+//! `__stop_farcri_benches` at the boundaries (this is done for any section
+//! whose name is a valid C identifier), so the entries can be accessed as a
+//! slice without any runtime registration. This works without `std` or `alloc`
+//! and thus on bare-metal targets such as `thumbv7m-none-eabi`.
+//!
+//! The section must be non-empty for the boundary symbols to be defined, so
+//! [`crate::bench_harness!`] adds a sentinel entry.
+//!
+//! The linker scripts provided by the Proxy program place the section
+//! explicitly and `KEEP` it (`crate::proxy::targets::ldscript::FARCRI_X`).
+//! Otherwise, the linker could place it at an arbitrary address, or discard
+//! it under `-z start-stop-gc`.
+use crate::{Bencher, BenchmarkGroup, BenchmarkId, Criterion};
+
+#[doc(hidden)]
+pub struct BenchEntry {
+    /// The benchmark ID.
+    pub name: &'static str,
+    /// The benchmark function. `None` for the sentinel entry.
+    pub func: Option<fn(&mut Bencher<'_>)>,
+    /// Applies the settings specified by `#[farcri::bench(...)]` to the
+    /// benchmark group containing the benchmark.
+    pub configure: Option<fn(&mut BenchmarkGroup<'_, '_>)>,
+}
+
+/// Get the registered benchmarks, in an unspecified order.
+///
+/// The registry is only available in Target mode on ELF targets. This returns
+/// an empty slice otherwise.
+fn bench_entries() -> &'static [BenchEntry] {
+    match () {
+        #[cfg(all(feature = "role_target", any(target_os = "none", target_os = "linux")))]
+        () => {
+            extern "Rust" {
+                static __start_farcri_benches: BenchEntry;
+                static __stop_farcri_benches: BenchEntry;
+            }
+
+            // Safety: The linker defines these symbols at the boundaries of
+            //         the `farcri_benches` section, which only contains
+            //         `BenchEntry`s
+            unsafe {
+                let start = &__start_farcri_benches as *const BenchEntry;
+                let stop = &__stop_farcri_benches as *const BenchEntry;
+                let len = (stop as usize - start as usize) / core::mem::size_of::<BenchEntry>();
+                core::slice::from_raw_parts(start, len)
+            }
+        }
+
+        #[cfg(not(all(feature = "role_target", any(target_os = "none", target_os = "linux"))))]
+        () => &[],
+    }
+}
+
+/// Run all registered benchmarks. Called by the code generated by
+/// [`crate::bench_harness!`].
+#[doc(hidden)]
+pub fn run_bench_entries(c: &mut Criterion<'_>) {
+    for entry in bench_entries() {
+        if let Some(func) = entry.func {
+            let mut group = c.benchmark_group(entry.name);
+            if let Some(configure) = entry.configure {
+                configure(&mut group);
+            }
+            group.bench_function(BenchmarkId::no_function(), func);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Temporal quantifier for Cortex-A9 MPCore
+//!
+//! This port uses the global timer, a 64-bit up-counter shared by all
+//! cores. It's clocked by `PERIPHCLK`, which usually runs at a fraction of
+//! the core clock, so the resolution is a few core cycles. On the other hand,
+//! wrap-arounds are not a concern, unlike SysTick.
+use core::ptr;
+
+/// The offset of the global timer registers in the private memory region
+const GTIMER_OFFSET: usize = 0x200;
+
+const COUNTER_LO: usize = 0x0;
+const COUNTER_HI: usize = 0x4;
+const CONTROL: usize = 0x8;
+
+const CONTROL_TIMER_ENABLE: u32 = 1 << 0;
+
+fn reg(periphbase: usize, offset: usize) -> *mut u32 {
+    (periphbase + GTIMER_OFFSET + offset) as *mut u32
+}
+
+/// Start the global timer with the prescaler set to 1. `periphbase` is the
+/// base address of the private memory region.
+#[inline]
+pub fn init(periphbase: usize) {
+    // Safety: `periphbase` points to the private memory region
+    unsafe { ptr::write_volatile(reg(periphbase, CONTROL), CONTROL_TIMER_ENABLE) };
+}
+
+#[inline]
+pub fn now(periphbase: usize) -> u64 {
+    // Safety: `periphbase` points to the private memory region
+    let read = |offset| unsafe { ptr::read_volatile(reg(periphbase, offset)) };
+
+    // The counter can't be read atomically
+    loop {
+        let hi = read(COUNTER_HI);
+        let lo = read(COUNTER_LO);
+        let hi2 = read(COUNTER_HI);
+
+        if hi == hi2 {
+            return (lo as u64) | ((hi as u64) << 32);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Minimal runtime for Armv7-A targets
+//!
+//! The executable is expected to be loaded at its link address by a loader
+//! (e.g., QEMU's `-kernel` option) and entered at `_start` in ARM state and
+//! a privileged mode with the MMU, caches, and interrupts disabled. Exception
+//! vectors aren't set up, so exceptions are not recoverable.
+//!
+//! The linker script provided by the Proxy program must define the following
+//! symbols:
+//!
+//!  - `_stack_start`: The initial stack pointer
+//!  - `__sbss`, `__ebss`: The boundaries of `.bss` (4-byte aligned)
+//!
+//! The entry point of the user program (`farcri_main`) is defined by
+//! [`crate::criterion_main!`].
+use core::ptr;
+
+extern "C" {
+    static _stack_start: u32;
+    static mut __sbss: u32;
+    static mut __ebss: u32;
+    fn farcri_main() -> !;
+}
+
+/// The code and data located at the entry point
+#[repr(C)]
+struct Start {
+    /// Hand-assembled ARM instructions. Inline assembly isn't available on
+    /// stable Rust, so this is the only way to set up the stack pointer
+    /// before entering Rust code.
+    code: [u32; 2],
+    /// Loaded by `code[0]`
+    stack_top: *const u32,
+    /// Loaded by `code[1]`
+    reset: unsafe extern "C" fn() -> !,
+}
+
+// Safety: `Start` is never accessed as data by Rust code
+unsafe impl Sync for Start {}
+
+#[no_mangle]
+#[link_section = ".text.start"]
+static _start: Start = Start {
+    code: [
+        0xe59fd000, // ldr sp, [pc, #0]   ; sp = stack_top
+        0xe59ff000, // ldr pc, [pc, #0]   ; pc = reset
+    ],
+    // Safety: Only the address is taken
+    stack_top: unsafe { &_stack_start },
+    reset,
+};
+
+unsafe extern "C" fn reset() -> ! {
+    // Zero-fill `.bss`. Some loaders don't do this.
+    // Safety: The linker script defines `__sbss` and `__ebss` as above, and
+    //         nothing in `.bss` has been accessed yet
+    unsafe {
+        let mut p = ptr::addr_of_mut!(__sbss);
+        let end = ptr::addr_of_mut!(__ebss);
+        while p < end {
+            ptr::write_volatile(p, 0);
+            p = p.add(1);
+        }
+    }
+
+    // Safety: `farcri_main` is defined by `criterion_main!`
+    unsafe { farcri_main() }
+}
@@ -0,0 +1,69 @@
+//! Communication channel for the `std` port
+//!
+//! The Target program runs as a subprocess of the Proxy program, which
+//! listens on a TCP port on the loopback interface and passes its address
+//! through the environment variable [`protocol::STD_LINK_ADDR_ENV`]. The log
+//! output goes to stderr.
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use crate::bencher::protocol;
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        eprintln!(
+            "[{:5} {}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+pub struct Comm {
+    stream: TcpStream,
+}
+
+impl Comm {
+    pub fn new() -> Self {
+        log::set_logger(&Logger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let addr = std::env::var(protocol::STD_LINK_ADDR_ENV).unwrap_or_else(|_| {
+            panic!(
+                "`{}` is not set. This program must be started by the Proxy program.",
+                protocol::STD_LINK_ADDR_ENV
+            )
+        });
+        let stream = TcpStream::connect(&addr).unwrap_or_else(|e| {
+            panic!("could not connect to the Proxy program at {}: {}", addr, e)
+        });
+
+        // The messages are small and exchanged in lockstep
+        stream.set_nodelay(true).unwrap();
+
+        Self { stream }
+    }
+
+    pub fn write(&mut self, b: &[u8]) {
+        self.stream.write_all(b).unwrap();
+    }
+
+    pub fn read(&mut self, b: &mut [u8]) -> usize {
+        match self.stream.read(b).unwrap() {
+            // There's nothing left to do without the Proxy program
+            0 if !b.is_empty() => panic!("the Proxy program closed the connection"),
+            n => n,
+        }
+    }
+}
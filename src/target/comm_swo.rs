@@ -0,0 +1,69 @@
+//! Communication through ITM/SWO (target-to-host) and RTT (host-to-target)
+//!
+//! The debug probe is responsible for configuring the TPIU and enabling the
+//! ITM stimulus port. The SWO baud rate is derived from the TPIU clock, which
+//! is the core clock on most chips, so the Proxy program must know the core
+//! clock frequency the Target program runs at.
+use cortex_m::{itm, peripheral::ITM};
+
+use crate::bencher::protocol;
+
+pub struct Comm {
+    down: rtt_target::DownChannel,
+    itm: ITM,
+}
+
+impl Comm {
+    pub fn new(itm: ITM) -> Self {
+        let channels = rtt_target::rtt_init! {
+            up: {
+                0: {
+                    size: 1024
+                    mode: NoBlockSkip
+                    name: "Log"
+                }
+            }
+            down: {
+                0: {
+                    size: 512
+                    mode: BlockIfFull
+                    name: "Terminal"
+                }
+            }
+        };
+
+        super::logger_rtt::init_logger(channels.up.0);
+
+        // Route the trace output to the SWO pin
+        #[cfg(feature = "stm32f4xx-hal")]
+        unsafe {
+            (*stm32f4xx_hal::stm32::DBGMCU::ptr())
+                .cr
+                .modify(|_, w| w.trace_ioen().set_bit());
+        }
+
+        Self {
+            down: channels.down.0,
+            itm,
+        }
+    }
+
+    pub fn write(&mut self, b: &[u8]) {
+        itm::write_all(&mut self.itm.stim[protocol::SWO_STIMULUS_PORT as usize], b);
+    }
+
+    /// Read bytes from the host without blocking.
+    pub fn try_read(&mut self, b: &mut [u8]) -> usize {
+        self.down.read(b)
+    }
+
+    pub fn read(&mut self, b: &mut [u8]) -> usize {
+        loop {
+            let num_bytes_read = self.down.read(b);
+            if num_bytes_read > 0 {
+                return num_bytes_read;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Estimated instruction counts on Cortex-M (`dwt_instr_estimate`)
+//!
+//! See [`Counters`] for the estimation method. Only Armv7-M and Armv8-M
+//! Mainline processors implement the required DWT counters.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use farcri::{dwt, *};
+//!
+//! fn bench(c: &mut Criterion) {
+//!     assert!(dwt::enable(), "the profiling counters are unavailable");
+//!     c.bench_function("foo", |b| {
+//!         b.iter(|| {
+//!             let mut estimator = dwt::Estimator::new(dwt::read());
+//!             foo_step1();
+//!             estimator.update(dwt::read());
+//!             foo_step2();
+//!             estimator.update(dwt::read());
+//!             log::info!("~{} instructions", estimator.total());
+//!         })
+//!     });
+//! }
+//! ```
+use cortex_m::peripheral::{DCB, DWT};
+
+pub use super::instr_estimate::{Counters, Estimator};
+
+/// `DEMCR.TRCENA`
+const DEMCR_TRCENA: u32 = 1 << 24;
+
+/// `DWT_CTRL.CYCCNTENA`
+const CTRL_CYCCNTENA: u32 = 1 << 0;
+/// `DWT_CTRL.{CPI,EXC,SLEEP,LSU,FOLD}EVTENA`
+const CTRL_EVTENA: u32 = 0b11111 << 17;
+/// `DWT_CTRL.NOPRFCNT`
+const CTRL_NOPRFCNT: u32 = 1 << 24;
+/// `DWT_CTRL.NOCYCCNT`
+const CTRL_NOCYCCNT: u32 = 1 << 25;
+
+/// Enable the DWT counters used by [`read`]. Returns `false` if the
+/// processor doesn't implement them.
+pub fn enable() -> bool {
+    // Safety: The only other user of the DWT unit is the debugger, and we
+    //         only set bits. Read-modify-write races are harmless because
+    //         nothing clears these bits.
+    unsafe {
+        (*DCB::ptr()).demcr.modify(|x| x | DEMCR_TRCENA);
+
+        let dwt = &*DWT::ptr();
+        if dwt.ctrl.read() & (CTRL_NOPRFCNT | CTRL_NOCYCCNT) != 0 {
+            return false;
+        }
+        dwt.ctrl.modify(|x| x | CTRL_CYCCNTENA | CTRL_EVTENA);
+    }
+    true
+}
+
+/// Take a snapshot of the DWT counters. [`enable`] must have returned `true`.
+#[inline]
+pub fn read() -> Counters {
+    // Safety: Read-only access
+    let dwt = unsafe { &*DWT::ptr() };
+    Counters {
+        cyc: dwt.cyccnt.read(),
+        cpi: dwt.cpicnt.read() as u8,
+        exc: dwt.exccnt.read() as u8,
+        sleep: dwt.sleepcnt.read() as u8,
+        lsu: dwt.lsucnt.read() as u8,
+        fold: dwt.foldcnt.read() as u8,
+    }
+}
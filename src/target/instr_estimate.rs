@@ -0,0 +1,158 @@
+//! Instruction count estimation from the DWT profiling counters
+//!
+//! Armv7-M and Armv8-M Mainline don't have a retired instruction counter, but
+//! the DWT unit counts the cycles spent on things other than executing
+//! instructions:
+//!
+//!  - `CPICNT`: the additional cycles spent by multi-cycle instructions and
+//!    instruction fetch stalls
+//!  - `EXCCNT`: the cycles spent on exception entry and return
+//!  - `SLEEPCNT`: the cycles spent sleeping
+//!  - `LSUCNT`: the additional cycles spent by load-store operations
+//!  - `FOLDCNT`: the instructions that took no cycles ("folded")
+//!
+//! The number of executed instructions can be estimated by
+//! `CYCCNT - CPICNT - EXCCNT - SLEEPCNT - LSUCNT + FOLDCNT` (Armv7-M
+//! Architecture Reference Manual, C1.8.7). This is only an estimate because
+//! the counters are event-based and the processor might count some cycles in
+//! more than one of them.
+//!
+//! All but `CYCCNT` are 8-bit counters that silently wrap around, so an
+//! interval must be short enough for each of them to count fewer than 256
+//! events. [`Estimator`] accumulates shorter intervals to measure a longer
+//! one.
+//!
+//! This is separate from `dwt` so that it can be tested on the host.
+
+/// A snapshot of the DWT profiling counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub cyc: u32,
+    pub cpi: u8,
+    pub exc: u8,
+    pub sleep: u8,
+    pub lsu: u8,
+    pub fold: u8,
+}
+
+impl Counters {
+    /// Estimate the number of instructions executed since `start`. Each of
+    /// the 8-bit counters must have counted fewer than 256 events in the
+    /// meantime.
+    pub fn instructions_since(&self, start: &Self) -> u32 {
+        let delta8 = |now: u8, then: u8| now.wrapping_sub(then) as u32;
+        let cycles = self.cyc.wrapping_sub(start.cyc);
+        let overhead = delta8(self.cpi, start.cpi)
+            + delta8(self.exc, start.exc)
+            + delta8(self.sleep, start.sleep)
+            + delta8(self.lsu, start.lsu);
+        // The counters aren't sampled atomically, so the overhead might
+        // slightly exceed the cycle count
+        cycles.saturating_sub(overhead) + delta8(self.fold, start.fold)
+    }
+}
+
+/// Accumulates the estimated instruction counts of consecutive intervals
+#[derive(Debug, Clone, Copy)]
+pub struct Estimator {
+    last: Counters,
+    total: u64,
+}
+
+impl Estimator {
+    /// Start an estimation at `now`.
+    pub fn new(now: Counters) -> Self {
+        Self {
+            last: now,
+            total: 0,
+        }
+    }
+
+    /// Add the interval since the last call (or [`Self::new`]) ending at
+    /// `now`. See [`Counters::instructions_since`] for the interval length
+    /// limit.
+    pub fn update(&mut self, now: Counters) {
+        self.total += now.instructions_since(&self.last) as u64;
+        self.last = now;
+    }
+
+    /// Get the estimated number of instructions executed so far.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_stalls() {
+        let start = Counters::default();
+        let end = Counters { cyc: 100, ..start };
+        assert_eq!(end.instructions_since(&start), 100);
+    }
+
+    #[test]
+    fn stalls_and_folding() {
+        let start = Counters {
+            cyc: 1000,
+            cpi: 10,
+            exc: 0,
+            sleep: 0,
+            lsu: 250,
+            fold: 3,
+        };
+        let end = Counters {
+            cyc: 1100,
+            cpi: 30,
+            exc: 12,
+            sleep: 0,
+            // wrapped around
+            lsu: 4,
+            fold: 8,
+        };
+        // 100 - 20 - 12 - 0 - 10 + 5
+        assert_eq!(end.instructions_since(&start), 63);
+    }
+
+    #[test]
+    fn cycle_counter_wraparound() {
+        let start = Counters {
+            cyc: u32::MAX - 9,
+            ..Default::default()
+        };
+        let end = Counters {
+            cyc: 10,
+            cpi: 5,
+            ..Default::default()
+        };
+        assert_eq!(end.instructions_since(&start), 15);
+    }
+
+    #[test]
+    fn overhead_exceeding_cycles() {
+        let start = Counters::default();
+        let end = Counters {
+            cyc: 3,
+            lsu: 5,
+            fold: 1,
+            ..Default::default()
+        };
+        assert_eq!(end.instructions_since(&start), 1);
+    }
+
+    #[test]
+    fn accumulate() {
+        let mut estimator = Estimator::new(Counters::default());
+        for i in 1..=4u32 {
+            estimator.update(Counters {
+                cyc: i * 200,
+                lsu: (i * 100) as u8,
+                ..Default::default()
+            });
+        }
+        // 4 * (200 - 100), although `LSUCNT` wrapped around in total
+        assert_eq!(estimator.total(), 400);
+    }
+}
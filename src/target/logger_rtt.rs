@@ -0,0 +1,207 @@
+use core::{cell::RefCell, fmt::Write};
+use cortex_m::interrupt;
+
+static LOG_CHANNEL: interrupt::Mutex<RefCell<Option<rtt_target::UpChannel>>> =
+    interrupt::Mutex::new(RefCell::new(None));
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        interrupt::free(move |cs| {
+            let mut log_channel = LOG_CHANNEL.borrow(cs).borrow_mut();
+            if let Some(channel) = &mut *log_channel {
+                writeln!(
+                    channel,
+                    "[{:5} {}] {}",
+                    record.level(),
+                    record.target(),
+                    record.args()
+                )
+                .unwrap();
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger that outputs to `channel`.
+pub(super) fn init_logger(channel: rtt_target::UpChannel) {
+    interrupt::free(move |cs| {
+        *LOG_CHANNEL.borrow(cs).borrow_mut() = Some(channel);
+    });
+    log::set_logger(&Logger).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+#[cfg(not(feature = "comm_swo"))]
+pub struct Comm {
+    down: rtt_target::DownChannel,
+    up: rtt_target::UpChannel,
+    #[cfg(feature = "frame_trace")]
+    trace: rtt_target::UpChannel,
+    #[cfg(feature = "rtt_bulk_channel")]
+    bulk: rtt_target::UpChannel,
+}
+
+/// Initialize RTT with the log and terminal up channels, the terminal down
+/// channel, and the specified additional up channels.
+#[cfg(not(feature = "comm_swo"))]
+macro_rules! rtt_init_comm {
+    ($($extra_up:tt)*) => {
+        rtt_target::rtt_init! {
+            up: {
+                0: {
+                    size: 1024
+                    mode: NoBlockSkip
+                    name: "Log"
+                }
+                1: {
+                    size: 1024
+                    mode: BlockIfFull
+                    name: "Terminal"
+                }
+                $($extra_up)*
+            }
+            down: {
+                0: {
+                    size: 512
+                    mode: BlockIfFull
+                    name: "Terminal"
+                }
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "comm_swo"))]
+impl Comm {
+    #[cfg(not(any(feature = "frame_trace", feature = "rtt_bulk_channel")))]
+    pub fn new() -> Self {
+        let channels = rtt_init_comm! {};
+        let (up0, up1) = channels.up;
+
+        init_logger(up0);
+
+        Self {
+            up: up1,
+            down: channels.down.0,
+        }
+    }
+
+    #[cfg(all(feature = "frame_trace", not(feature = "rtt_bulk_channel")))]
+    pub fn new() -> Self {
+        let channels = rtt_init_comm! {
+            2: {
+                size: 1024
+                mode: BlockIfFull
+                // `protocol::FRAME_TRACE_CHANNEL_NAME`
+                name: "FrameTrace"
+            }
+        };
+        let (up0, up1, up2) = channels.up;
+
+        init_logger(up0);
+
+        Self {
+            up: up1,
+            down: channels.down.0,
+            trace: up2,
+        }
+    }
+
+    #[cfg(all(not(feature = "frame_trace"), feature = "rtt_bulk_channel"))]
+    pub fn new() -> Self {
+        let channels = rtt_init_comm! {
+            2: {
+                size: 4096
+                mode: BlockIfFull
+                // `protocol::BULK_CHANNEL_NAME`
+                name: "Bulk"
+            }
+        };
+        let (up0, up1, up2) = channels.up;
+
+        init_logger(up0);
+
+        Self {
+            up: up1,
+            down: channels.down.0,
+            bulk: up2,
+        }
+    }
+
+    #[cfg(all(feature = "frame_trace", feature = "rtt_bulk_channel"))]
+    pub fn new() -> Self {
+        let channels = rtt_init_comm! {
+            2: {
+                size: 1024
+                mode: BlockIfFull
+                // `protocol::FRAME_TRACE_CHANNEL_NAME`
+                name: "FrameTrace"
+            }
+            3: {
+                size: 4096
+                mode: BlockIfFull
+                // `protocol::BULK_CHANNEL_NAME`
+                name: "Bulk"
+            }
+        };
+        let (up0, up1, up2, up3) = channels.up;
+
+        init_logger(up0);
+
+        Self {
+            up: up1,
+            down: channels.down.0,
+            trace: up2,
+            bulk: up3,
+        }
+    }
+
+    pub fn write(&mut self, b: &[u8]) {
+        write_all(&mut self.up, b);
+    }
+
+    #[cfg(feature = "rtt_bulk_channel")]
+    pub fn write_bulk(&mut self, b: &[u8]) {
+        write_all(&mut self.bulk, b);
+    }
+
+    /// Emit a frame trace record. Blocks if the channel is full so that the
+    /// records don't get truncated.
+    #[cfg(feature = "frame_trace")]
+    pub fn trace_frame(&mut self, direction: u8, frame: &[u8]) {
+        let len = frame.len() as u16;
+        write_all(&mut self.trace, &[direction, len as u8, (len >> 8) as u8]);
+        write_all(&mut self.trace, frame);
+    }
+
+    /// Read bytes from the host without blocking.
+    pub fn try_read(&mut self, b: &mut [u8]) -> usize {
+        self.down.read(b)
+    }
+
+    pub fn read(&mut self, b: &mut [u8]) -> usize {
+        loop {
+            let num_bytes_read = self.down.read(b);
+            if num_bytes_read > 0 {
+                return num_bytes_read;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(not(feature = "comm_swo"))]
+fn write_all(channel: &mut rtt_target::UpChannel, mut b: &[u8]) {
+    while b.len() > 0 {
+        let bytes_written = channel.write(b);
+        b = &b[bytes_written..];
+    }
+}
@@ -0,0 +1,94 @@
+//! Loopback port, used to run the Target program on the host in unit tests
+//! and `--farcri-dry-run=simulate`
+//!
+//! The Target program runs on the calling thread and communicates with the
+//! test code through a stream (usually a TCP stream). The test code is
+//! expected to play the role of the Proxy program.
+use std::{
+    cell::RefCell,
+    io::{Read, Write},
+    sync::Mutex,
+    time::Instant,
+};
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+struct State {
+    stream: Box<dyn Stream>,
+    origin: Instant,
+    /// Set by [`tamper_with_time_source`]
+    time_source_tampered: bool,
+}
+
+thread_local! {
+    static STATE: RefCell<Option<State>> = RefCell::new(None);
+}
+
+lazy_static::lazy_static! {
+    /// Serializes the calls to `crate::bencher::main`, which uses a singleton
+    /// working area
+    static ref MAIN_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Run the Target program on the current thread, using `stream` to talk to
+/// the Proxy program.
+pub(crate) fn run(
+    stream: impl Read + Write + 'static,
+    groups: impl FnOnce(&mut crate::bencher::Criterion),
+) {
+    let _guard = MAIN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    STATE.with(|state| {
+        *state.borrow_mut() = Some(State {
+            stream: Box::new(stream),
+            origin: Instant::now(),
+            time_source_tampered: false,
+        })
+    });
+
+    // Safety: `MAIN_LOCK` ensures no other instances of `bencher::main` are
+    //         running at the same time. The previous instance, if any, has
+    //         relinquished the working area.
+    unsafe { crate::bencher::main(groups, &mut super::BencherIo {}) };
+
+    STATE.with(|state| *state.borrow_mut() = None);
+}
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    STATE.with(|state| f(state.borrow_mut().as_mut().expect("not in `loopback::run`")))
+}
+
+pub(super) fn write(b: &[u8]) {
+    with_state(|state| state.stream.write_all(b).unwrap());
+}
+
+pub(super) fn read(b: &mut [u8]) -> usize {
+    with_state(|state| state.stream.read(b).unwrap())
+}
+
+pub(super) fn now() -> u64 {
+    with_state(|state| state.origin.elapsed().as_nanos() as u64)
+}
+
+/// Simulate the panic handler of `panic_reset` reporting a panic with
+/// `message`. The caller should then end the Target program (e.g., by
+/// panicking), which stands in for the reset.
+#[cfg(test)]
+pub(crate) fn report_panic(message: &str) {
+    let mut buf = [0u8; crate::bencher::protocol::PANIC_REPORT_BUF_LEN];
+    write(crate::bencher::proxylink::encode_panic_report(
+        &mut buf, &message,
+    ));
+}
+
+/// Simulate the benchmark code reprogramming the time source, which is
+/// detected by the next call to `BencherIo::check_time_source`.
+#[cfg(test)]
+pub(crate) fn tamper_with_time_source() {
+    with_state(|state| state.time_source_tampered = true);
+}
+
+pub(super) fn check_time_source() -> bool {
+    with_state(|state| !std::mem::replace(&mut state.time_source_tampered, false))
+}
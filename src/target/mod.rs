@@ -0,0 +1,321 @@
+/// The entry point for Target mode.
+///
+/// Actually, this module is built in all modes. This is because `crate::
+/// bencher` depends on the hardware abstraction provided by this module, and
+/// user benchmark crates need the API provided by `crate::bencher` to
+/// successfully compile. Non-Target modes don't specfiy any target-specifying
+/// Cargo features, so we must be prepared to handle such cases even when
+/// this module isn't actually used at runtime.
+///
+/// > **Rationale:** It's possible to remove this redundant dependency by using
+/// > `#[cfg(...)]` in `crate::bencher`. However, this approach would introduce
+/// > a larger amount of noise to the code and hence more maintenance burdens.
+///
+
+// --------------------------------------------------------------------------
+
+// Panic handler
+#[cfg(all(feature = "panic-rtt-target", not(feature = "panic_reset")))]
+use panic_rtt_target as _;
+#[cfg(feature = "panic_reset")]
+mod panic_reset;
+
+// The panic handler reports through the RTT terminal channel and resets the
+// processor through the SCB
+#[cfg(all(
+    feature = "panic_reset",
+    not(all(feature = "cortex-m-rt", feature = "rtt-target"))
+))]
+compile_error!("`panic_reset` is only supported by the Cortex-M targets");
+#[cfg(all(feature = "panic_reset", feature = "comm_swo"))]
+compile_error!("`panic_reset` can't be used with `comm_swo`");
+
+// -------------------------------------------------------------------------
+
+// `cortex-m-rt` interrupt handlers
+#[cfg(feature = "stm32f4xx-hal")]
+use stm32f4xx_hal as _;
+
+// --------------------------------------------------------------------------
+
+// Runtime for Armv7-A targets
+#[cfg(feature = "armv7a_rt")]
+mod armv7a_rt;
+
+// --------------------------------------------------------------------------
+
+#[cfg(feature = "rtt-target")]
+mod logger_rtt;
+#[cfg(all(feature = "rtt-target", not(feature = "comm_swo")))]
+use self::logger_rtt::Comm;
+
+#[cfg(all(feature = "rtt-target", feature = "comm_swo"))]
+mod comm_swo;
+#[cfg(all(feature = "rtt-target", feature = "comm_swo"))]
+use self::comm_swo::Comm;
+
+// The frame trace channel is only serviced when the Proxy program writes to
+// RTT in this configuration, so the Target program could get stuck
+#[cfg(all(feature = "frame_trace", feature = "comm_swo"))]
+compile_error!("`frame_trace` can't be used with `comm_swo`");
+#[cfg(all(feature = "rtt_bulk_channel", feature = "comm_swo"))]
+compile_error!("`rtt_bulk_channel` can't be used with `comm_swo`");
+
+#[cfg(feature = "target_qemu_vexpress_a9")]
+mod pl011;
+#[cfg(feature = "target_qemu_vexpress_a9")]
+mod qemu_vexpress_a9;
+#[cfg(feature = "target_qemu_vexpress_a9")]
+use self::qemu_vexpress_a9::Comm;
+
+// There's no channel to send the frame trace through
+#[cfg(all(feature = "frame_trace", feature = "target_qemu_vexpress_a9"))]
+compile_error!("`frame_trace` isn't supported by `target_qemu_vexpress_a9`");
+#[cfg(all(feature = "rtt_bulk_channel", feature = "target_qemu_vexpress_a9"))]
+compile_error!("`rtt_bulk_channel` isn't supported by `target_qemu_vexpress_a9`");
+
+#[cfg(feature = "target_std")]
+mod comm_std;
+#[cfg(feature = "target_std")]
+use self::comm_std::Comm;
+
+#[cfg(all(feature = "frame_trace", feature = "target_std"))]
+compile_error!("`frame_trace` isn't supported by `target_std`");
+#[cfg(all(feature = "rtt_bulk_channel", feature = "target_std"))]
+compile_error!("`rtt_bulk_channel` isn't supported by `target_std`");
+
+// --------------------------------------------------------------------------
+
+// Temporal quantification
+#[cfg(feature = "cortex-m-rt")]
+mod cortex_m_time;
+#[cfg(any(feature = "cortex-m-rt", all(test, feature = "role_proxy")))]
+mod systick_check;
+
+// Instruction count estimation
+#[cfg(all(feature = "dwt_instr_estimate", feature = "cortex-m-rt"))]
+pub mod dwt;
+#[cfg(any(
+    all(feature = "dwt_instr_estimate", feature = "cortex-m-rt"),
+    all(test, feature = "role_proxy")
+))]
+mod instr_estimate;
+
+#[cfg(feature = "target_qemu_vexpress_a9")]
+mod a9_global_timer;
+
+#[cfg(feature = "target_std")]
+mod std_time;
+
+// --------------------------------------------------------------------------
+
+// Loopback port for testing and `--farcri-dry-run=simulate`
+#[cfg(feature = "role_proxy")]
+pub(crate) mod loopback;
+
+// --------------------------------------------------------------------------
+
+// Suppress the "dead code" warning in non-Target mode
+#[cfg(not(feature = "role_target"))]
+#[used]
+static _UNUSED: fn() = || main(|_| {});
+
+pub fn main(groups: impl FnOnce(&mut crate::bencher::Criterion)) -> ! {
+    #[cfg(feature = "cortex-m-rt")]
+    let p = cortex_m::Peripherals::take().unwrap();
+    #[cfg(feature = "cortex-m-rt")]
+    cortex_m_time::init(p.SYST);
+
+    #[cfg(all(feature = "rtt-target", not(feature = "comm_swo")))]
+    let comm = Comm::new();
+    #[cfg(all(feature = "rtt-target", feature = "comm_swo"))]
+    let comm = Comm::new(p.ITM);
+
+    #[cfg(feature = "target_qemu_vexpress_a9")]
+    a9_global_timer::init(qemu_vexpress_a9::PERIPHBASE);
+    #[cfg(feature = "target_qemu_vexpress_a9")]
+    let comm = Comm::new();
+
+    #[cfg(feature = "target_std")]
+    let comm = Comm::new();
+
+    // Safety: We call this function only once throught the program's lifetime
+    unsafe {
+        crate::bencher::main(
+            groups,
+            &mut BencherIo {
+                #[cfg(any(
+                    feature = "rtt-target",
+                    feature = "target_qemu_vexpress_a9",
+                    feature = "target_std"
+                ))]
+                comm,
+            },
+        );
+    }
+
+    match () {
+        // Let the Proxy program know we are done
+        #[cfg(feature = "target_std")]
+        () => std::process::exit(0),
+        #[cfg(not(feature = "target_std"))]
+        () => loop {
+            core::hint::spin_loop();
+        },
+    }
+}
+
+/// Reset the target. Used by the link watchdog (`Criterion::on_link_lost`).
+/// The targets that can't reset themselves stop in a busy loop instead.
+pub(crate) fn reset() -> ! {
+    match () {
+        #[cfg(feature = "cortex-m-rt")]
+        () => cortex_m::peripheral::SCB::sys_reset(),
+        #[cfg(feature = "target_std")]
+        () => std::process::exit(1),
+        #[allow(unreachable_patterns)]
+        _ => loop {
+            core::hint::spin_loop();
+        },
+    }
+}
+
+/// Stores state variables maintained by this module and provides methods to be
+/// called by `crate::bencher`.
+pub(crate) struct BencherIo {
+    #[cfg(any(
+        feature = "rtt-target",
+        feature = "target_qemu_vexpress_a9",
+        feature = "target_std"
+    ))]
+    comm: Comm,
+}
+
+impl BencherIo {
+    pub fn write(&mut self, b: &[u8]) {
+        let _ = b;
+        match () {
+            #[cfg(any(
+                feature = "rtt-target",
+                feature = "target_qemu_vexpress_a9",
+                feature = "target_std"
+            ))]
+            () => self.comm.write(b),
+            #[cfg(feature = "role_proxy")]
+            () => loopback::write(b),
+            #[cfg(not(any(
+                feature = "rtt-target",
+                feature = "target_qemu_vexpress_a9",
+                feature = "target_std",
+                feature = "role_proxy"
+            )))]
+            () => unimplemented!(),
+        }
+    }
+
+    /// Read bytes from the host, blocking the execution until at least one byte
+    /// is read.
+    pub fn read(&mut self, b: &mut [u8]) -> usize {
+        let _ = b;
+        match () {
+            #[cfg(any(
+                feature = "rtt-target",
+                feature = "target_qemu_vexpress_a9",
+                feature = "target_std"
+            ))]
+            () => self.comm.read(b),
+            #[cfg(feature = "role_proxy")]
+            () => loopback::read(b),
+            #[cfg(not(any(
+                feature = "rtt-target",
+                feature = "target_qemu_vexpress_a9",
+                feature = "target_std",
+                feature = "role_proxy"
+            )))]
+            () => unimplemented!(),
+        }
+    }
+
+    /// Indicates whether [`Self::poll_read`] can give up waiting.
+    pub const CAN_POLL_READ: bool = cfg!(feature = "rtt-target");
+
+    /// Read bytes from the host like [`Self::read`], calling `poll` with the
+    /// current time while waiting. Gives up and returns zero if `poll`
+    /// returns `true`. If [`Self::CAN_POLL_READ`] is `false`, this is
+    /// equivalent to `read`.
+    pub fn poll_read(&mut self, b: &mut [u8], mut poll: impl FnMut(u64) -> bool) -> usize {
+        match () {
+            #[cfg(feature = "rtt-target")]
+            () => loop {
+                let num_bytes_read = self.comm.try_read(b);
+                if num_bytes_read > 0 {
+                    return num_bytes_read;
+                }
+                if poll(self.now()) {
+                    return 0;
+                }
+                core::hint::spin_loop();
+            },
+            #[cfg(not(feature = "rtt-target"))]
+            () => {
+                let _ = &mut poll;
+                self.read(b)
+            }
+        }
+    }
+
+    /// Send bytes to the host through the bulk channel. See
+    /// [`crate::bencher::protocol::BULK_CHANNEL_NAME`].
+    #[cfg(feature = "rtt_bulk_channel")]
+    pub fn write_bulk(&mut self, b: &[u8]) {
+        match () {
+            #[cfg(feature = "rtt-target")]
+            () => self.comm.write_bulk(b),
+            #[cfg(not(feature = "rtt-target"))]
+            () => self.write(b),
+        }
+    }
+
+    /// Emit a frame trace record. See [`crate::bencher::protocol::
+    /// FRAME_TRACE_CHANNEL_NAME`] for the format.
+    #[cfg(feature = "frame_trace")]
+    pub fn trace_frame(&mut self, direction: u8, frame: &[u8]) {
+        let _ = (direction, frame);
+        match () {
+            #[cfg(feature = "rtt-target")]
+            () => self.comm.trace_frame(direction, frame),
+            #[cfg(not(feature = "rtt-target"))]
+            () => {}
+        }
+    }
+
+    /// Check that the time source is still configured as it was initially.
+    /// If it isn't (e.g., because the benchmark code reprogrammed it),
+    /// configure it again and return `false`.
+    pub fn check_time_source(&mut self) -> bool {
+        match () {
+            #[cfg(feature = "cortex-m-rt")]
+            () => cortex_m_time::check(),
+            #[cfg(feature = "role_proxy")]
+            () => loopback::check_time_source(),
+            #[allow(unreachable_patterns)]
+            _ => true,
+        }
+    }
+
+    #[inline(never)]
+    pub fn now(&mut self) -> u64 {
+        match () {
+            #[cfg(feature = "cortex-m-rt")]
+            () => cortex_m_time::now(),
+            #[cfg(feature = "target_qemu_vexpress_a9")]
+            () => a9_global_timer::now(qemu_vexpress_a9::PERIPHBASE),
+            #[cfg(feature = "target_std")]
+            () => std_time::now(),
+            #[cfg(feature = "role_proxy")]
+            () => loopback::now(),
+            #[allow(unreachable_patterns)]
+            _ => unimplemented!(),
+        }
+    }
+}
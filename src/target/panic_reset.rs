@@ -0,0 +1,45 @@
+//! The panic handler that reports a panic to the Proxy program and resets
+//! the target (`panic_reset`)
+//!
+//! A panicking benchmark can't be recovered from in place. Panics don't
+//! unwind on `no_std` targets (`panic = "abort"` is the only supported
+//! strategy), and the stack frames between the benchmark loop and the panic
+//! site may hold locks, half-updated state, or peripherals in an unknown
+//! configuration, so jumping back to the loop (`setjmp`/`longjmp`-style)
+//! isn't sound in general. Instead, the handler reports the panic and resets
+//! the whole target. The Proxy program then restarts the session, skipping
+//! the benchmarks that were started, if `--farcri-keep-going` is given.
+use core::{fmt::Write, panic::PanicInfo};
+use rtt_target::{ChannelMode, UpChannel};
+
+use crate::bencher::{protocol, proxylink};
+
+/// The number of processor cycles to wait before resetting the target so
+/// that the debug probe can read the report before the startup code
+/// reinitializes the RTT control block
+const RESET_DELAY_CYCLES: u32 = 50_000_000;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    // Safety: Interrupts are disabled, and the code owning these channels
+    //         will never resume. The channels aren't used concurrently.
+    if let Some(mut channel) = unsafe { UpChannel::conjure(0) } {
+        channel.set_mode(ChannelMode::BlockIfFull);
+        let _ = writeln!(channel, "{}", info);
+    }
+
+    if let Some(mut channel) = unsafe { UpChannel::conjure(1) } {
+        channel.set_mode(ChannelMode::BlockIfFull);
+        let mut buf = [0u8; protocol::PANIC_REPORT_BUF_LEN];
+        let mut frame = proxylink::encode_panic_report(&mut buf, info);
+        while !frame.is_empty() {
+            let num_bytes_written = channel.write(frame);
+            frame = &frame[num_bytes_written..];
+        }
+    }
+
+    cortex_m::asm::delay(RESET_DELAY_CYCLES);
+    cortex_m::peripheral::SCB::sys_reset()
+}
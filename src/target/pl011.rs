@@ -0,0 +1,85 @@
+//! Driver for Arm PrimeCell UART (PL011)
+//!
+//! The baud rate and the frame format are left as configured by the
+//! firmware (or the emulator). Interrupts and DMA aren't used.
+use core::{fmt, ptr};
+
+const UARTDR: usize = 0x000;
+const UARTFR: usize = 0x018;
+const UARTCR: usize = 0x030;
+
+/// Receive FIFO empty
+const UARTFR_RXFE: u32 = 1 << 4;
+/// Transmit FIFO full
+const UARTFR_TXFF: u32 = 1 << 5;
+
+const UARTCR_UARTEN: u32 = 1 << 0;
+const UARTCR_TXE: u32 = 1 << 8;
+const UARTCR_RXE: u32 = 1 << 9;
+
+#[derive(Clone, Copy)]
+pub struct Pl011 {
+    base: usize,
+}
+
+impl Pl011 {
+    /// Construct a `Pl011` to access the PL011 register block at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must point to a PL011 register block that is not used for
+    /// anything else.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn read_reg(self, offset: usize) -> u32 {
+        // Safety: `self.base` points to a PL011 register block
+        unsafe { ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write_reg(self, offset: usize, value: u32) {
+        // Safety: `self.base` points to a PL011 register block
+        unsafe { ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// Enable the transmitter and the receiver.
+    pub fn enable(self) {
+        self.write_reg(UARTCR, UARTCR_UARTEN | UARTCR_TXE | UARTCR_RXE);
+    }
+
+    /// Write bytes, blocking the execution until all of them are pushed to
+    /// the transmit FIFO.
+    pub fn write(self, b: &[u8]) {
+        for &byte in b {
+            while self.read_reg(UARTFR) & UARTFR_TXFF != 0 {
+                core::hint::spin_loop();
+            }
+            self.write_reg(UARTDR, byte as u32);
+        }
+    }
+
+    /// Read bytes, blocking the execution until at least one byte is read.
+    pub fn read(self, b: &mut [u8]) -> usize {
+        let mut i = 0;
+        while i < b.len() {
+            if self.read_reg(UARTFR) & UARTFR_RXFE != 0 {
+                if i > 0 {
+                    break;
+                }
+                core::hint::spin_loop();
+                continue;
+            }
+            b[i] = self.read_reg(UARTDR) as u8;
+            i += 1;
+        }
+        i
+    }
+}
+
+impl fmt::Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
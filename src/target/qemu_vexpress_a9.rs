@@ -0,0 +1,81 @@
+//! Support for QEMU's `vexpress-a9` machine
+//!
+//! The Proxy program talks to the Target program through UART0. UART1 is used
+//! for logging and panic messages.
+use core::fmt::Write;
+
+use super::pl011::Pl011;
+
+/// The base address of the Cortex-A9 MPCore private memory region
+pub(super) const PERIPHBASE: usize = 0x1e00_0000;
+
+/// UART0, connected to the Proxy program
+const UART_COMM: usize = 0x1000_9000;
+/// UART1, used for logging
+const UART_LOG: usize = 0x1000_a000;
+
+fn log_uart() -> Pl011 {
+    // Safety: UART1 is only used for logging
+    unsafe { Pl011::new(UART_LOG) }
+}
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        // There's only one thread, and interrupts are never enabled, so we
+        // don't need a lock
+        writeln!(
+            log_uart(),
+            "[{:5} {}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        )
+        .unwrap();
+    }
+
+    fn flush(&self) {}
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let _ = writeln!(log_uart(), "{}", info);
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+pub struct Comm {
+    uart: Pl011,
+}
+
+impl Comm {
+    pub fn new() -> Self {
+        log_uart().enable();
+
+        // Safety: There's only one thread. (`set_logger` uses an atomic
+        // read-modify-write operation, which isn't guaranteed to work while
+        // the MMU is disabled.)
+        unsafe { log::set_logger_racy(&Logger) }.unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        // Safety: UART0 is only used by `Comm`, which is only created once
+        let uart = unsafe { Pl011::new(UART_COMM) };
+        uart.enable();
+
+        Self { uart }
+    }
+
+    pub fn write(&mut self, b: &[u8]) {
+        self.uart.write(b);
+    }
+
+    pub fn read(&mut self, b: &mut [u8]) -> usize {
+        self.uart.read(b)
+    }
+}
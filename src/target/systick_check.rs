@@ -0,0 +1,50 @@
+//! Detects tampering with the SysTick configuration made by `cortex_m_time`
+//!
+//! This is separate from `cortex_m_time` so that it can be tested on the
+//! host.
+
+/// The reload value programmed by `cortex_m_time::init`
+pub(super) const RELOAD: u32 = 0xff_ffff;
+
+/// `SYST_CSR.ENABLE`
+const CSR_ENABLE: u32 = 1 << 0;
+/// `SYST_CSR.TICKINT`
+const CSR_TICKINT: u32 = 1 << 1;
+/// `SYST_CSR.CLKSOURCE` (set = the processor clock)
+const CSR_CLKSOURCE: u32 = 1 << 2;
+
+/// The `SYST_CSR` bits set by `cortex_m_time::init`
+pub(super) const CSR_EXPECTED: u32 = CSR_ENABLE | CSR_TICKINT | CSR_CLKSOURCE;
+
+/// Check if SysTick is still configured as `cortex_m_time::init` left it,
+/// given the values of `SYST_CSR` and `SYST_RVR`.
+pub(super) fn is_intact(csr: u32, rvr: u32) -> bool {
+    csr & CSR_EXPECTED == CSR_EXPECTED && rvr & RELOAD == RELOAD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intact() {
+        assert!(is_intact(CSR_EXPECTED, RELOAD));
+        // `COUNTFLAG` and the reserved bits don't matter
+        assert!(is_intact(CSR_EXPECTED | 1 << 16, RELOAD | 0xff00_0000));
+    }
+
+    #[test]
+    fn tampered() {
+        // A typical HAL delay implementation: 1-ms ticks, no interrupt,
+        // external clock
+        assert!(!is_intact(CSR_ENABLE, 84_000 - 1));
+        // Stopped
+        assert!(!is_intact(CSR_TICKINT | CSR_CLKSOURCE, RELOAD));
+        // Interrupt disabled
+        assert!(!is_intact(CSR_ENABLE | CSR_CLKSOURCE, RELOAD));
+        // Clock source switched
+        assert!(!is_intact(CSR_ENABLE | CSR_TICKINT, RELOAD));
+        // Reload value changed
+        assert!(!is_intact(CSR_EXPECTED, RELOAD - 1));
+    }
+}
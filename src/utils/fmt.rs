@@ -60,3 +60,37 @@ where
         write!(f, "{}{}", self.0 .0, self.0 .1)
     }
 }
+
+/// Formats a frequency in hertz with the largest SI prefix that represents
+/// it exactly (e.g., `84 MHz`, `32768 Hz`).
+pub struct Frequency(pub u32);
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            hz if hz != 0 && hz % 1_000_000_000 == 0 => write!(f, "{} GHz", hz / 1_000_000_000),
+            hz if hz != 0 && hz % 1_000_000 == 0 => write!(f, "{} MHz", hz / 1_000_000),
+            hz if hz != 0 && hz % 1_000 == 0 => write!(f, "{} kHz", hz / 1_000),
+            hz => write!(f, "{} Hz", hz),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequency() {
+        let cases: &[(u32, &str)] = &[
+            (0, "0 Hz"),
+            (32_768, "32768 Hz"),
+            (16_000, "16 kHz"),
+            (84_000_000, "84 MHz"),
+            (84_500_000, "84500 kHz"),
+            (2_000_000_000, "2 GHz"),
+        ];
+        for &(hz, expected) in cases {
+            assert_eq!(Frequency(hz).to_string(), expected);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! A hash function whose output doesn't change between Rust releases
+
+/// A 64-bit FNV-1a hasher. Unlike `std::collections::hash_map::DefaultHasher`
+/// (whose algorithm is unspecified) and `std::hash::Hash` (whose encoding of
+/// values is unspecified), the output only depends on the input bytes, so it
+/// can be persisted or compared across builds of the Proxy program.
+///
+/// Each input is length-prefixed so that, e.g., `["ab", "c"]` and
+/// `["a", "bc"]` produce different hashes.
+#[derive(Debug, Clone)]
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub fn write_u64(&mut self, x: u64) {
+        self.write_raw(&x.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.write_raw(bytes);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    /// Get the hash as a 16-digit hexadecimal string.
+    pub fn finish_hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_reference() {
+        // The unprefixed FNV-1a test vectors
+        let hash = |bytes: &[u8]| {
+            let mut hasher = StableHasher::new();
+            hasher.write_raw(bytes);
+            hasher.0
+        };
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn length_prefixed() {
+        let hash = |strs: &[&str]| {
+            let mut hasher = StableHasher::new();
+            for s in strs {
+                hasher.write_str(s);
+            }
+            hasher.finish_hex()
+        };
+        assert_ne!(hash(&["ab", "c"]), hash(&["a", "bc"]));
+        assert_eq!(hash(&["ab", "c"]), hash(&["ab", "c"]));
+        assert_eq!(hash(&["ab", "c"]).len(), 16);
+    }
+}
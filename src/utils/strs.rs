@@ -1,25 +1,25 @@
 //! `str` utilities
 
-/// Find the byte offset of the first scalar value before `i` in a given byte
-/// slice assumed to be a UTF-8 string. Returns `0` if there is no such
-/// scalar value.
-///
-/// `i` must be on a scalar boundary.
-pub fn utf8_str_prev(s: &[u8], mut i: usize) -> usize {
-    debug_assert!(i <= s.len());
-
-    // `i` must be on a scalar boundary
-    debug_assert!(i >= s.len() || !is_utf8_continuation(s[i]));
+/// Find the largest byte offset not greater than `i` that is on a scalar
+/// boundary in a given byte slice assumed to be a UTF-8 string. Returns
+/// `s.len()` if `i` is greater than that.
+pub fn utf8_str_floor(s: &[u8], mut i: usize) -> usize {
+    if i >= s.len() {
+        return s.len();
+    }
 
-    if i > 0 {
-        while {
-            i -= 1;
-            i > 0 && is_utf8_continuation(s[i])
-        } {}
+    while i > 0 && is_utf8_continuation(s[i]) {
+        i -= 1;
     }
     i
 }
 
+/// Get the longest prefix of `s` that is not longer than `len` bytes and
+/// doesn't split a scalar value.
+pub fn truncate_str(s: &str, len: usize) -> &str {
+    &s[..utf8_str_floor(s.as_bytes(), len)]
+}
+
 fn is_utf8_continuation(x: u8) -> bool {
     (x as i8) < -0x40
 }